@@ -0,0 +1,34 @@
+//! Crash-consistency harness, running the workload of `phdb::crash_test` over many seeds with random faults
+//! Usage: `crash_test [first seed] [number of runs]`. A failing run is replayed by passing its seed
+//! Built with the `fault-injection` feature: `cargo run --release --features fault-injection --bin crash_test`
+
+use std::{env, process::ExitCode};
+
+use phdb::crash_test;
+
+fn main() -> ExitCode {
+    let mut args = env::args().skip(1).map(|arg| arg.parse::<u64>());
+    let (first, runs) = match (args.next(), args.next()) {
+        (None, _) => (0, 100),
+        (Some(Ok(first)), None) => (first, 1),
+        (Some(Ok(first)), Some(Ok(runs))) => (first, runs),
+        _ => {
+            eprintln!("Usage: crash_test [first seed] [number of runs]");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut failures = 0;
+    for seed in first..first + runs {
+        if let Err(err) = crash_test::run(seed, crash_test::random_faults) {
+            eprintln!("seed {seed}: {err}");
+            failures += 1;
+        }
+    }
+
+    println!("{runs} runs, {failures} failed");
+    match failures {
+        0 => ExitCode::SUCCESS,
+        _ => ExitCode::FAILURE,
+    }
+}
@@ -0,0 +1,114 @@
+//! Compares the synchronous I/O path with the io_uring one on batched page I/O: committing a transaction that dirtied
+//! many pages, which writes them back as a single batch, and a full table scan from a cold cache, which prefetches the
+//! leaves of the tree in batches. The OS page cache is dropped for the file before each scan
+//! Usage: `io_bench [rows]`. Without the `io-uring` feature only the synchronous path is measured
+//! Three runs of `cargo run --release --features io-uring --bin io_bench` with the default 50000 rows, on a VM with a
+//! single Intel Xeon CPU, 5 GB of memory, Linux 6.18 and ext4 on a virtio disk:
+//! | run | sync commit | sync cold scan | io_uring commit | io_uring cold scan |
+//! |   1 |    235.74ms |       292.34ms |        268.50ms |           328.23ms |
+//! |   2 |    249.66ms |       323.10ms |        243.77ms |           319.16ms |
+//! |   3 |    237.89ms |       326.33ms |        266.67ms |           307.11ms |
+//! With one CPU there is nothing to overlap the I/O with, io_uring ends up from 6% faster to 14% slower
+
+use std::{
+    env,
+    fs::File,
+    io,
+    os::fd::AsRawFd,
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use phdb::{
+    btree::{BTree, TreeKind},
+    connection::Connection,
+    pager::JournalMode,
+    vfs::{UnixVfs, Vfs},
+};
+
+const PATH: &str = "io_bench.phdb";
+const PAGE_SIZE: u32 = 4096;
+const RECORD_SIZE: usize = 400;
+// Large enough for a whole transaction to stay in memory until it is committed
+const WRITE_CACHE_SIZE: usize = 1 << 16;
+const SCAN_CACHE_SIZE: usize = 64;
+const ROUNDS: u32 = 5;
+
+fn main() -> io::Result<()> {
+    let rows = match env::args().nth(1) {
+        Some(rows) => rows
+            .parse()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Usage: io_bench [rows]"))?,
+        None => 50_000,
+    };
+
+    println!("{rows} rows of {RECORD_SIZE} bytes, {PAGE_SIZE} byte pages, best of {ROUNDS} rounds");
+    println!("{:<8} {:>12} {:>12}", "backend", "commit", "cold scan");
+    bench("sync", Arc::new(UnixVfs), rows)?;
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    bench("io_uring", Arc::new(phdb::vfs::UringVfs), rows)?;
+
+    remove_files()
+}
+
+fn bench(name: &str, vfs: Arc<dyn Vfs>, rows: i64) -> io::Result<()> {
+    let mut commit = Duration::MAX;
+    let mut scan = Duration::MAX;
+
+    for round in 0..ROUNDS {
+        remove_files()?;
+        let mut connection =
+            Connection::open_with_vfs(vfs.clone(), PATH, PAGE_SIZE, WRITE_CACHE_SIZE)?;
+        // Without a journal, the commit only measures writing the pages back
+        connection.set_journal_mode(JournalMode::Off)?;
+
+        connection.begin()?;
+        let pager = connection.pager_mut();
+        let tree = BTree::create(pager, TreeKind::Table)?;
+        let record = vec![round as u8; RECORD_SIZE];
+        for rowid in 0..rows {
+            tree.insert(pager, rowid, &record)?;
+        }
+        let start = Instant::now();
+        connection.commit()?;
+        commit = commit.min(start.elapsed());
+        drop(connection);
+
+        drop_os_cache(Path::new(PATH))?;
+        let mut connection =
+            Connection::open_with_vfs(vfs.clone(), PATH, PAGE_SIZE, SCAN_CACHE_SIZE)?;
+        let start = Instant::now();
+        let pager = connection.pager_mut();
+        let count = tree
+            .scan(pager, ..)?
+            .try_fold(0, |count, row| row.map(|_| count + 1))?;
+        scan = scan.min(start.elapsed());
+        assert_eq!(count, rows, "Rows went missing");
+    }
+
+    println!("{name:<8} {:>12.2?} {:>12.2?}", commit, scan);
+    Ok(())
+}
+
+// Evicts the clean pages of the file from the OS page cache, so that reads hit the disk
+fn drop_os_cache(path: &Path) -> io::Result<()> {
+    let file = File::open(path)?;
+    // SAFETY: plain system call on a file descriptor that stays open during the call
+    let result = unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED) };
+    match result {
+        0 => Ok(()),
+        err => Err(io::Error::from_raw_os_error(err)),
+    }
+}
+
+fn remove_files() -> io::Result<()> {
+    for suffix in ["", "-wal", "-journal"] {
+        match std::fs::remove_file(format!("{PATH}{suffix}")) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
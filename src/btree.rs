@@ -0,0 +1,1919 @@
+//! B+trees stored in the heap file, one per table and one per index. Every page of a tree is a slotted page
+//! Leaf pages of table trees hold the rows, as (rowid, record) cells sorted by rowid, while leaf pages of index trees
+//! hold index entries, see `index`. Interior pages only hold (child, key) cells
+//! routing the searches: every key stored under the child is lower than or equal to the key of its cell, and the keys
+//! that are greater than the last cell live under the right-most child, which is kept in the page header
+//! The root page of a tree never moves, so a tree is identified by its root page number alone. When the root has to
+//! split, its content is moved to a new page which becomes its first child
+//! Records too large to fit in a cell only keep their beginning in the leaf, the rest being moved to a chain of
+//! overflow pages, see `overflow`
+//! In auto-vacuum databases, every page written records its children, or the overflow pages of its cells, in the
+//! pointer map, see `pointer_map`
+//! Keys are handled as byte strings that compare with memcmp. Rowids are stored big-endian with their sign bit flipped
+//! so that this ordering matches the ordering of the numbers, and index keys are encoded likewise, see `key`
+
+use std::{
+    cmp::Ordering,
+    io::{self, Read},
+    iter, mem,
+    ops::{Bound, RangeBounds},
+};
+
+use crate::{
+    index,
+    overflow::{self, OverflowReader, OverflowWriter},
+    pager::{PageNumber, Pager},
+    pointer_map::PointerMapEntry,
+    slotted_page::{SlottedPage, PAGE_HEADER_SIZE, SLOT_SIZE},
+};
+
+pub const TABLE_INTERIOR_PAGE: u8 = 0x05;
+pub const TABLE_LEAF_PAGE: u8 = 0x0d;
+pub const INDEX_INTERIOR_PAGE: u8 = 0x02;
+pub const INDEX_LEAF_PAGE: u8 = 0x0a;
+// Pages read ahead by cursors that prefetch, starting with the child they go down to
+pub const PREFETCH_PAGES: usize = 16;
+
+pub type RowId = i64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TreeKind {
+    Table,
+    Index,
+}
+impl TreeKind {
+    fn leaf_page_type(self) -> u8 {
+        match self {
+            TreeKind::Table => TABLE_LEAF_PAGE,
+            TreeKind::Index => INDEX_LEAF_PAGE,
+        }
+    }
+
+    fn interior_page_type(self) -> u8 {
+        match self {
+            TreeKind::Table => TABLE_INTERIOR_PAGE,
+            TreeKind::Index => INDEX_INTERIOR_PAGE,
+        }
+    }
+
+    fn from_page_type(page_type: u8) -> Option<Self> {
+        match page_type {
+            TABLE_INTERIOR_PAGE | TABLE_LEAF_PAGE => Some(TreeKind::Table),
+            INDEX_INTERIOR_PAGE | INDEX_LEAF_PAGE => Some(TreeKind::Index),
+            _ => None,
+        }
+    }
+
+    // Two keys are equal when one of them is a prefix of the other, which lets searches use partial index keys
+    pub(crate) fn compare(self, a: &[u8], b: &[u8]) -> Ordering {
+        match self {
+            TreeKind::Table => a.cmp(b),
+            TreeKind::Index => index::compare_keys(a, b),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BTree {
+    root: PageNumber,
+    kind: TreeKind,
+}
+
+// Emitted by a page that could not hold its cells anymore: its upper half was moved to the `right` page, and `key` is
+// the greatest key left in the original page
+struct Split {
+    key: Vec<u8>,
+    right: PageNumber,
+}
+
+enum Node {
+    Leaf {
+        cells: Vec<Vec<u8>>,
+    },
+    // `children` holds one more page than `keys`, the last one being the right-most child
+    Interior {
+        children: Vec<PageNumber>,
+        keys: Vec<Vec<u8>>,
+    },
+}
+
+impl BTree {
+    // Allocates the root page of a new, empty tree
+    pub fn create(pager: &mut Pager, kind: TreeKind) -> io::Result<Self> {
+        let root = pager.allocate_page()?;
+        pager.set_pointer_map_entry(root, PointerMapEntry::Root)?;
+        let tree = Self { root, kind };
+        tree.store_node(pager, root, &Node::Leaf { cells: vec![] })?;
+        Ok(tree)
+    }
+
+    // The kind of the tree is found out from its root page
+    pub fn open(pager: &mut Pager, root: PageNumber) -> io::Result<Self> {
+        let buf = load_page(pager, root)?;
+        let page = SlottedPage::new(&buf[..pager.usable_size()]);
+        let kind = TreeKind::from_page_type(page.page_type()).ok_or_else(|| {
+            corrupted(
+                root,
+                &format!("Unexpected page type {:#04x}", page.page_type()),
+            )
+        })?;
+
+        Ok(Self { root, kind })
+    }
+
+    pub fn root_page(&self) -> PageNumber {
+        self.root
+    }
+
+    pub fn kind(&self) -> TreeKind {
+        self.kind
+    }
+
+    // Largest record that is stored entirely in its cell. Cells are kept small enough for any page to hold at least 4
+    // of them, so that splitting a page always produces two valid pages
+    pub fn max_payload_size(pager: &Pager) -> usize {
+        max_local_size(pager.usable_size(), mem::size_of::<RowId>())
+    }
+
+    // Largest index key that fits in a cell. Keys never overflow as interior pages need copies of them
+    pub fn max_key_size(pager: &Pager) -> usize {
+        max_cell_size(pager.usable_size()) - leaf_cell_size(0, 0)
+    }
+
+    // Inserts a row, replacing the previous record stored under `rowid` if any
+    pub fn insert(&self, pager: &mut Pager, rowid: RowId, record: &[u8]) -> io::Result<()> {
+        self.insert_from(pager, rowid, record.len() as u64, record)
+    }
+
+    // Inserts a row whose record of `len` bytes is read from `reader`. Only the part of the record kept in the leaf is
+    // held in memory, the rest is streamed to overflow pages
+    pub fn insert_from<R: Read>(
+        &self,
+        pager: &mut Pager,
+        rowid: RowId,
+        len: u64,
+        reader: R,
+    ) -> io::Result<()> {
+        if len > u32::MAX as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Record of {len} bytes is too large"),
+            ));
+        }
+
+        let key = rowid_key(rowid);
+        let cell = build_leaf_cell(pager, &key, len, reader)?;
+        self.insert_cell(pager, &key, cell)
+    }
+
+    pub fn get(&self, pager: &mut Pager, rowid: RowId) -> io::Result<Option<Vec<u8>>> {
+        let mut cursor = self.cursor();
+        let key = rowid_key(rowid);
+
+        if cursor.seek(pager, &key)? && cursor.key() == key {
+            return Ok(Some(cursor.read_payload(pager)?));
+        }
+
+        Ok(None)
+    }
+
+    // Streams the record of a row instead of loading it in memory
+    pub fn reader<'a>(
+        &self,
+        pager: &'a mut Pager,
+        rowid: RowId,
+    ) -> io::Result<Option<PayloadReader<'a>>> {
+        let mut cursor = self.cursor();
+        let key = rowid_key(rowid);
+
+        if cursor.seek(pager, &key)? && cursor.key() == key {
+            return Ok(Some(cursor.payload_reader(pager)));
+        }
+
+        Ok(None)
+    }
+
+    // Returns whether a row was deleted
+    pub fn delete(&self, pager: &mut Pager, rowid: RowId) -> io::Result<bool> {
+        self.delete_entry(pager, &rowid_key(rowid))
+    }
+
+    pub fn max_rowid(&self, pager: &mut Pager) -> io::Result<Option<RowId>> {
+        let mut cursor = self.cursor();
+
+        if cursor.last(pager)? {
+            return Ok(Some(cursor.rowid()));
+        }
+
+        Ok(None)
+    }
+
+    pub fn cursor(&self) -> Cursor {
+        Cursor::new(self.root, self.kind)
+    }
+
+    // Iterates over the rows whose rowid falls into `range`, in rowid order
+    pub fn scan<'a, R: RangeBounds<RowId>>(
+        &self,
+        pager: &'a mut Pager,
+        range: R,
+    ) -> io::Result<Scan<'a>> {
+        let mut cursor = self.cursor();
+        cursor.set_prefetch(true);
+
+        match range.start_bound() {
+            Bound::Included(&start) => cursor.seek(pager, &rowid_key(start))?,
+            Bound::Excluded(&start) => match start.checked_add(1) {
+                Some(start) => cursor.seek(pager, &rowid_key(start))?,
+                None => false,
+            },
+            Bound::Unbounded => cursor.first(pager)?,
+        };
+
+        Ok(Scan {
+            pager,
+            cursor,
+            end: range.end_bound().cloned(),
+        })
+    }
+
+    // Removes every row, keeping the root page
+    pub fn clear(&self, pager: &mut Pager) -> io::Result<()> {
+        match self.load_node(pager, self.root)? {
+            Node::Interior { children, .. } => {
+                for child in children {
+                    self.free_subtree(pager, child)?;
+                }
+            }
+            Node::Leaf { cells } => {
+                for cell in cells {
+                    self.free_overflow(pager, &cell)?;
+                }
+            }
+        }
+
+        self.store_node(pager, self.root, &Node::Leaf { cells: vec![] })?;
+        Ok(())
+    }
+
+    // Pages used by the tree, overflow pages included
+    pub fn page_count(&self, pager: &mut Pager) -> io::Result<u32> {
+        self.subtree_page_count(pager, self.root)
+    }
+
+    // Checks the structure of the tree: the type of its pages, keys sorted and within the separators leading to their
+    // page, leaves all at the same depth and overflow chains as long as their payload. Returns the pages of the tree,
+    // overflow pages included, along with the pointer-map entry each of them should have
+    pub fn check(&self, pager: &mut Pager) -> io::Result<Vec<(PageNumber, PointerMapEntry)>> {
+        let mut pages = vec![(self.root, PointerMapEntry::Root)];
+        self.check_subtree(pager, self.root, (None, None), 0, &mut pages)?;
+        Ok(pages)
+    }
+
+    // Copies every entry of the tree to `target`, which may use another page size. The copy is rooted at the same page
+    // number, which must already be allocated in `target`. Pages are filled up one after the other instead of being
+    // split as entries come, so the copy takes as few pages as possible, laid out in key order
+    pub fn copy_to(&self, pager: &mut Pager, target: &mut Pager) -> io::Result<()> {
+        let mut loader = Loader::new(*self, target);
+        let mut cursor = self.cursor();
+        cursor.set_prefetch(true);
+
+        let mut found = cursor.first(pager)?;
+        while found {
+            let key = cursor.key();
+            if key.len() > Self::max_key_size(target) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "Key of {} bytes does not fit in pages of {} bytes",
+                        key.len(),
+                        target.page_size()
+                    ),
+                ));
+            }
+            let cell = build_leaf_cell(
+                target,
+                key,
+                cursor.payload_len(),
+                cursor.payload_reader(pager),
+            )?;
+            loader.push(target, cell)?;
+            found = cursor.next(pager)?;
+        }
+
+        loader.finish(target)
+    }
+
+    // Gives every page of the tree back to the pager, root included. The tree must not be used afterwards
+    pub fn destroy(self, pager: &mut Pager) -> io::Result<()> {
+        self.free_subtree(pager, self.root)
+    }
+
+    // Inserts an entry small enough to never overflow
+    pub(crate) fn insert_entry(
+        &self,
+        pager: &mut Pager,
+        key: &[u8],
+        payload: &[u8],
+    ) -> io::Result<()> {
+        self.insert_cell(pager, key, leaf_cell(key, payload, payload.len() as u64, 0))
+    }
+
+    fn insert_cell(&self, pager: &mut Pager, key: &[u8], cell: Vec<u8>) -> io::Result<()> {
+        let mut replaced = None;
+
+        if let Some(split) = self.insert_into(pager, self.root, key, cell, &mut replaced)? {
+            self.split_root(pager, split)?;
+        }
+
+        // The overflow pages of the record that was replaced are only released once the new one is in place
+        if let Some(replaced) = replaced {
+            self.free_overflow(pager, &replaced)?;
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn delete_entry(&self, pager: &mut Pager, key: &[u8]) -> io::Result<bool> {
+        let (found, split) = self.delete_from(pager, self.root, key)?;
+
+        if let Some(split) = split {
+            self.split_root(pager, split)?;
+        }
+
+        // An interior root left with a single child is replaced by that child, making the tree one level shorter
+        while let Node::Interior { children, keys } = self.load_node(pager, self.root)? {
+            if !keys.is_empty() {
+                break;
+            }
+            let child = self.load_node(pager, children[0])?;
+            self.store_node(pager, self.root, &child)?;
+            pager.free_page(children[0])?;
+        }
+
+        Ok(found)
+    }
+
+    fn insert_into(
+        &self,
+        pager: &mut Pager,
+        page_no: PageNumber,
+        key: &[u8],
+        cell: Vec<u8>,
+        replaced: &mut Option<Vec<u8>>,
+    ) -> io::Result<Option<Split>> {
+        let mut buf = load_page(pager, page_no)?;
+        let usable = pager.usable_size();
+        let mut page = SlottedPage::new(&mut buf[..usable]);
+
+        if page.page_type() == self.kind.leaf_page_type() {
+            let position = search_leaf(self.kind, &page, key);
+            if let Ok(index) = position {
+                *replaced = Some(page.cell(index).to_vec());
+            }
+            let done = match position {
+                Ok(index) => page.update(index, &cell).is_ok(),
+                Err(index) => page.insert(index, &cell).is_ok(),
+            };
+            if done {
+                pager.write(page_no, &buf)?;
+                set_overflow_parent(pager, page_no, &cell)?;
+                return Ok(None);
+            }
+
+            let mut cells: Vec<Vec<u8>> = page.cells().map(<[u8]>::to_vec).collect();
+            match position {
+                Ok(index) => cells[index] = cell,
+                Err(index) => cells.insert(index, cell),
+            }
+            return self.store_node(pager, page_no, &Node::Leaf { cells });
+        }
+
+        let Node::Interior {
+            mut children,
+            mut keys,
+        } = self.parse_node(page_no, &page)?
+        else {
+            unreachable!()
+        };
+        let index = search_interior(self.kind, &keys, key);
+
+        match self.insert_into(pager, children[index], key, cell, replaced)? {
+            Some(split) => {
+                children.insert(index + 1, split.right);
+                keys.insert(index, split.key);
+                self.store_node(pager, page_no, &Node::Interior { children, keys })
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn delete_from(
+        &self,
+        pager: &mut Pager,
+        page_no: PageNumber,
+        key: &[u8],
+    ) -> io::Result<(bool, Option<Split>)> {
+        let mut buf = load_page(pager, page_no)?;
+        let usable = pager.usable_size();
+        let mut page = SlottedPage::new(&mut buf[..usable]);
+
+        if page.page_type() == self.kind.leaf_page_type() {
+            return match search_leaf(self.kind, &page, key) {
+                Ok(index) => {
+                    let cell = page.cell(index).to_vec();
+                    page.delete(index);
+                    pager.write(page_no, &buf)?;
+                    self.free_overflow(pager, &cell)?;
+                    Ok((true, None))
+                }
+                Err(_) => Ok((false, None)),
+            };
+        }
+
+        let Node::Interior {
+            mut children,
+            mut keys,
+        } = self.parse_node(page_no, &page)?
+        else {
+            unreachable!()
+        };
+        let index = search_interior(self.kind, &keys, key);
+
+        let (found, split) = self.delete_from(pager, children[index], key)?;
+        if !found {
+            return Ok((false, None));
+        }
+
+        match split {
+            Some(split) => {
+                children.insert(index + 1, split.right);
+                keys.insert(index, split.key);
+            }
+            None if children.len() > 1 && self.is_underfull(pager, children[index])? => {
+                self.rebalance(pager, &mut children, &mut keys, index)?;
+            }
+            None => return Ok((true, None)),
+        }
+
+        Ok((
+            true,
+            self.store_node(pager, page_no, &Node::Interior { children, keys })?,
+        ))
+    }
+
+    // Merges the child at `index` with one of its siblings, or moves cells between them when they do not fit in a
+    // single page. `children` and `keys` are the content of their parent and are updated accordingly
+    fn rebalance(
+        &self,
+        pager: &mut Pager,
+        children: &mut Vec<PageNumber>,
+        keys: &mut Vec<Vec<u8>>,
+        index: usize,
+    ) -> io::Result<()> {
+        let left = if index + 1 < children.len() {
+            index
+        } else {
+            index - 1
+        };
+        let (left_page, right_page) = (children[left], children[left + 1]);
+        let capacity = capacity(pager.usable_size());
+
+        match (
+            self.load_node(pager, left_page)?,
+            self.load_node(pager, right_page)?,
+        ) {
+            (Node::Leaf { mut cells }, Node::Leaf { cells: right_cells }) => {
+                cells.extend(right_cells);
+
+                if cells_size(&cells) <= capacity {
+                    self.store_node(pager, left_page, &Node::Leaf { cells })?;
+                    pager.free_page(right_page)?;
+                    children.remove(left + 1);
+                    keys.remove(left);
+                    return Ok(());
+                }
+
+                let right_cells = cells.split_off(split_point(&cells, capacity));
+                keys[left] = leaf_cell_key(cells.last().expect("Empty leaf")).to_vec();
+                self.store_node(pager, left_page, &Node::Leaf { cells })?;
+                self.store_node(pager, right_page, &Node::Leaf { cells: right_cells })?;
+            }
+            (
+                Node::Interior {
+                    children: mut merged_children,
+                    keys: mut merged_keys,
+                },
+                Node::Interior {
+                    children: right_children,
+                    keys: right_keys,
+                },
+            ) => {
+                // The separator goes down between the two halves
+                merged_keys.push(keys[left].clone());
+                merged_keys.extend(right_keys);
+                merged_children.extend(right_children);
+
+                if interior_size(&merged_keys) <= capacity {
+                    self.store_node(
+                        pager,
+                        left_page,
+                        &Node::Interior {
+                            children: merged_children,
+                            keys: merged_keys,
+                        },
+                    )?;
+                    pager.free_page(right_page)?;
+                    children.remove(left + 1);
+                    keys.remove(left);
+                    return Ok(());
+                }
+
+                let cells: Vec<Vec<u8>> = merged_keys
+                    .iter()
+                    .map(|key| interior_cell(0, key))
+                    .collect();
+                let middle = split_point(&cells, capacity).min(merged_keys.len() - 1);
+                let mut right_keys = merged_keys.split_off(middle);
+                keys[left] = right_keys.remove(0);
+                let right_children = merged_children.split_off(middle + 1);
+
+                self.store_node(
+                    pager,
+                    left_page,
+                    &Node::Interior {
+                        children: merged_children,
+                        keys: merged_keys,
+                    },
+                )?;
+                self.store_node(
+                    pager,
+                    right_page,
+                    &Node::Interior {
+                        children: right_children,
+                        keys: right_keys,
+                    },
+                )?;
+            }
+            _ => {
+                return Err(corrupted(left_page, "Siblings are at different depths"));
+            }
+        }
+
+        Ok(())
+    }
+
+    // Moves the content of the root to a new page and turns the root into an interior page pointing to both halves
+    fn split_root(&self, pager: &mut Pager, split: Split) -> io::Result<()> {
+        let left = pager.allocate_page()?;
+        let node = self.load_node(pager, self.root)?;
+        self.store_node(pager, left, &node)?;
+
+        let root = Node::Interior {
+            children: vec![left, split.right],
+            keys: vec![split.key],
+        };
+        self.store_node(pager, self.root, &root)?;
+        Ok(())
+    }
+
+    fn is_underfull(&self, pager: &mut Pager, page_no: PageNumber) -> io::Result<bool> {
+        let buf = load_page(pager, page_no)?;
+        let page = SlottedPage::new(&buf[..pager.usable_size()]);
+        let capacity = capacity(pager.usable_size());
+
+        Ok(capacity - page.free_space() < capacity / 3)
+    }
+
+    fn free_subtree(&self, pager: &mut Pager, page_no: PageNumber) -> io::Result<()> {
+        match self.load_node(pager, page_no)? {
+            Node::Interior { children, .. } => {
+                for child in children {
+                    self.free_subtree(pager, child)?;
+                }
+            }
+            Node::Leaf { cells } => {
+                for cell in cells {
+                    self.free_overflow(pager, &cell)?;
+                }
+            }
+        }
+
+        pager.free_page(page_no)
+    }
+
+    fn subtree_page_count(&self, pager: &mut Pager, page_no: PageNumber) -> io::Result<u32> {
+        match self.load_node(pager, page_no)? {
+            Node::Interior { children, .. } => children.into_iter().try_fold(1, |count, child| {
+                Ok(count + self.subtree_page_count(pager, child)?)
+            }),
+            Node::Leaf { cells } => {
+                let usable = pager.usable_size();
+                let per_page = overflow::overflow_data_size(pager) as u64;
+                Ok(cells.iter().fold(1, |count, cell| {
+                    let len = leaf_cell_payload_len(cell);
+                    let local = local_size(usable, leaf_cell_key_len(cell), len) as u64;
+                    count + (len - local).div_ceil(per_page) as u32
+                }))
+            }
+        }
+    }
+
+    // Returns the height of the subtree rooted at `page_no`, whose keys must be greater than the lower bound and lower
+    // than or equal to the upper one
+    fn check_subtree(
+        &self,
+        pager: &mut Pager,
+        page_no: PageNumber,
+        (low, high): (Option<&[u8]>, Option<&[u8]>),
+        depth: usize,
+        pages: &mut Vec<(PageNumber, PointerMapEntry)>,
+    ) -> io::Result<usize> {
+        // Every interior page having at least two children, a deeper tree would need more pages than a file can hold
+        if depth > 32 {
+            return Err(corrupted(
+                page_no,
+                "Tree too deep, its pages may form a loop",
+            ));
+        }
+        let sorted = |keys: &[&[u8]]| {
+            keys.windows(2)
+                .all(|pair| self.kind.compare(pair[0], pair[1]) == Ordering::Less)
+        };
+        let in_bounds = |key: &[u8]| {
+            low.is_none_or(|low| self.kind.compare(key, low) == Ordering::Greater)
+                && high.is_none_or(|high| self.kind.compare(key, high) != Ordering::Greater)
+        };
+
+        match self.load_node(pager, page_no)? {
+            Node::Leaf { cells } => {
+                let keys: Vec<&[u8]> = cells.iter().map(|cell| leaf_cell_key(cell)).collect();
+                if !sorted(&keys) || !keys.iter().all(|key| in_bounds(key)) {
+                    return Err(corrupted(page_no, "Keys out of order"));
+                }
+
+                let usable = pager.usable_size();
+                let per_page = overflow::overflow_data_size(pager) as u64;
+                for cell in &cells {
+                    let len = leaf_cell_payload_len(cell);
+                    let local = local_size(usable, leaf_cell_key_len(cell), len) as u64;
+                    let chain = match leaf_cell_overflow(usable, cell) {
+                        0 => vec![],
+                        first => overflow::chain_pages(pager, first)?,
+                    };
+                    if chain.len() as u64 != (len - local).div_ceil(per_page) {
+                        return Err(corrupted(
+                            page_no,
+                            &format!("Overflow chain of {} pages for {len} bytes", chain.len()),
+                        ));
+                    }
+                    let parents = iter::once(PointerMapEntry::FirstOverflow(page_no)).chain(
+                        chain
+                            .iter()
+                            .map(|&previous| PointerMapEntry::Overflow(previous)),
+                    );
+                    pages.extend(chain.iter().copied().zip(parents));
+                }
+
+                Ok(1)
+            }
+            Node::Interior { children, keys } => {
+                let keys: Vec<&[u8]> = keys.iter().map(Vec::as_slice).collect();
+                if keys.is_empty() || !sorted(&keys) || !keys.iter().all(|key| in_bounds(key)) {
+                    return Err(corrupted(page_no, "Keys out of order"));
+                }
+
+                let mut heights = vec![];
+                for (i, &child) in children.iter().enumerate() {
+                    if child == 0 || child >= pager.page_count() {
+                        return Err(corrupted(page_no, &format!("Invalid child page {child}")));
+                    }
+                    pages.push((child, PointerMapEntry::Child(page_no)));
+                    let bounds = (
+                        i.checked_sub(1).map_or(low, |i| Some(keys[i])),
+                        keys.get(i).copied().or(high),
+                    );
+                    heights.push(self.check_subtree(pager, child, bounds, depth + 1, pages)?);
+                }
+                if heights.windows(2).any(|pair| pair[0] != pair[1]) {
+                    return Err(corrupted(page_no, "Leaves at different depths"));
+                }
+
+                Ok(heights[0] + 1)
+            }
+        }
+    }
+
+    fn free_overflow(&self, pager: &mut Pager, cell: &[u8]) -> io::Result<()> {
+        match leaf_cell_overflow(pager.usable_size(), cell) {
+            0 => Ok(()),
+            first => overflow::free_chain(pager, first),
+        }
+    }
+
+    fn load_node(&self, pager: &mut Pager, page_no: PageNumber) -> io::Result<Node> {
+        let buf = load_page(pager, page_no)?;
+        self.parse_node(page_no, &SlottedPage::new(&buf[..pager.usable_size()]))
+    }
+
+    fn parse_node<B: AsRef<[u8]>>(
+        &self,
+        page_no: PageNumber,
+        page: &SlottedPage<B>,
+    ) -> io::Result<Node> {
+        match page.page_type() {
+            page_type if page_type == self.kind.leaf_page_type() => Ok(Node::Leaf {
+                cells: page.cells().map(<[u8]>::to_vec).collect(),
+            }),
+            page_type if page_type == self.kind.interior_page_type() => {
+                let mut children: Vec<PageNumber> = page.cells().map(interior_cell_child).collect();
+                children.push(page.right_pointer());
+                let keys = page
+                    .cells()
+                    .map(|cell| interior_cell_key(cell).to_vec())
+                    .collect();
+                Ok(Node::Interior { children, keys })
+            }
+            page_type => Err(corrupted(
+                page_no,
+                &format!("Unexpected page type {page_type:#04x}"),
+            )),
+        }
+    }
+
+    // Writes `node` to `page_no`, splitting it in two if it does not fit
+    fn store_node(
+        &self,
+        pager: &mut Pager,
+        page_no: PageNumber,
+        node: &Node,
+    ) -> io::Result<Option<Split>> {
+        let capacity = capacity(pager.usable_size());
+
+        let split = match node {
+            Node::Leaf { cells } if cells_size(cells) > capacity => {
+                let middle = split_point(cells, capacity);
+                let right = pager.allocate_page()?;
+                self.write_leaf(pager, page_no, &cells[..middle])?;
+                self.write_leaf(pager, right, &cells[middle..])?;
+                Some(Split {
+                    key: leaf_cell_key(&cells[middle - 1]).to_vec(),
+                    right,
+                })
+            }
+            Node::Leaf { cells } => {
+                self.write_leaf(pager, page_no, cells)?;
+                None
+            }
+            Node::Interior { children, keys } if interior_size(keys) > capacity => {
+                // The middle key moves up to the parent instead of staying in one of the halves
+                let cells: Vec<Vec<u8>> = keys.iter().map(|key| interior_cell(0, key)).collect();
+                let middle = split_point(&cells, capacity).min(keys.len() - 1);
+                let right = pager.allocate_page()?;
+                self.write_interior(pager, page_no, &children[..=middle], &keys[..middle])?;
+                self.write_interior(pager, right, &children[middle + 1..], &keys[middle + 1..])?;
+                Some(Split {
+                    key: keys[middle].clone(),
+                    right,
+                })
+            }
+            Node::Interior { children, keys } => {
+                self.write_interior(pager, page_no, children, keys)?;
+                None
+            }
+        };
+
+        Ok(split)
+    }
+
+    fn write_leaf(
+        &self,
+        pager: &mut Pager,
+        page_no: PageNumber,
+        cells: &[Vec<u8>],
+    ) -> io::Result<()> {
+        let mut buf = vec![0; pager.page_size() as usize];
+        let usable = pager.usable_size();
+        let mut page = SlottedPage::init(&mut buf[..usable], self.kind.leaf_page_type());
+        for cell in cells {
+            page.push(cell).expect("Leaf cells overflow their page");
+        }
+
+        pager.write(page_no, &buf)?;
+        if pager.has_pointer_map() {
+            for cell in cells {
+                set_overflow_parent(pager, page_no, cell)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_interior(
+        &self,
+        pager: &mut Pager,
+        page_no: PageNumber,
+        children: &[PageNumber],
+        keys: &[Vec<u8>],
+    ) -> io::Result<()> {
+        let mut buf = vec![0; pager.page_size() as usize];
+        let usable = pager.usable_size();
+        let mut page = SlottedPage::init(&mut buf[..usable], self.kind.interior_page_type());
+        for (&child, key) in children.iter().zip(keys) {
+            page.push(&interior_cell(child, key))
+                .expect("Interior cells overflow their page");
+        }
+        page.set_right_pointer(*children.last().expect("Interior page without children"));
+
+        pager.write(page_no, &buf)?;
+        if pager.has_pointer_map() {
+            for &child in children {
+                pager.set_pointer_map_entry(child, PointerMapEntry::Child(page_no))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Records `page_no` as the parent of every page it points to, for pages that were just moved there
+pub(crate) fn update_pointer_map(pager: &mut Pager, page_no: PageNumber) -> io::Result<()> {
+    let buf = load_page(pager, page_no)?;
+    let page = SlottedPage::new(&buf[..pager.usable_size()]);
+
+    if is_interior_page(page_no, &page)? {
+        for child in page.cells().map(interior_cell_child) {
+            pager.set_pointer_map_entry(child, PointerMapEntry::Child(page_no))?;
+        }
+        pager.set_pointer_map_entry(page.right_pointer(), PointerMapEntry::Child(page_no))
+    } else {
+        for cell in page.cells() {
+            set_overflow_parent(pager, page_no, cell)?;
+        }
+        Ok(())
+    }
+}
+
+// Makes `page_no` point to `to` instead of `from`, which is either one of its children or the first overflow page of
+// one of its cells
+pub(crate) fn replace_pointer(
+    pager: &mut Pager,
+    page_no: PageNumber,
+    from: PageNumber,
+    to: PageNumber,
+) -> io::Result<()> {
+    let mut buf = load_page(pager, page_no)?;
+    let usable = pager.usable_size();
+    let mut page = SlottedPage::new(&mut buf[..usable]);
+
+    let interior = is_interior_page(page_no, &page)?;
+    let index = page.cells().position(|cell| match interior {
+        true => interior_cell_child(cell) == from,
+        false => leaf_cell_overflow(usable, cell) == from,
+    });
+    match index {
+        Some(index) => {
+            // The pointer takes the first 4 bytes of interior cells and the last 4 bytes of leaf cells
+            let mut cell = page.cell(index).to_vec();
+            let offset = if interior { 0 } else { cell.len() - 4 };
+            cell[offset..offset + 4].copy_from_slice(&to.to_le_bytes());
+            page.update(index, &cell).expect("Cell of the same size");
+        }
+        None if interior && page.right_pointer() == from => page.set_right_pointer(to),
+        None => return Err(corrupted(page_no, &format!("No pointer to page {from}"))),
+    }
+
+    pager.write(page_no, &buf)?;
+    Ok(())
+}
+
+// Position in a tree. A cursor does not borrow the pager, which lets several of them be used at the same time, but it
+// has to be repositioned after the tree is modified
+#[derive(Debug, Clone)]
+pub struct Cursor {
+    root: PageNumber,
+    kind: TreeKind,
+    // Usable size of the pages, which tells how records are split between their cell and overflow pages
+    usable: usize,
+    // Pages from the root down to the current leaf, along with the index of the child (or cell, for the leaf) the
+    // cursor went through
+    stack: Vec<(PageNumber, usize)>,
+    // Cells of the current leaf
+    cells: Vec<Vec<u8>>,
+    prefetch: bool,
+}
+
+impl Cursor {
+    fn new(root: PageNumber, kind: TreeKind) -> Self {
+        Self {
+            root,
+            kind,
+            usable: 0,
+            stack: vec![],
+            cells: vec![],
+            prefetch: false,
+        }
+    }
+
+    // Whether moving to a child page also loads the next siblings in a single batch, which pays off when walking
+    // through many entries in order
+    pub fn set_prefetch(&mut self, enabled: bool) {
+        self.prefetch = enabled;
+    }
+
+    // Whether the cursor points to an entry
+    pub fn is_valid(&self) -> bool {
+        !self.stack.is_empty()
+    }
+
+    pub fn key(&self) -> &[u8] {
+        leaf_cell_key(self.cell())
+    }
+
+    // Size of the whole record, overflow included
+    pub fn payload_len(&self) -> u64 {
+        leaf_cell_payload_len(self.cell())
+    }
+
+    pub fn read_payload(&self, pager: &mut Pager) -> io::Result<Vec<u8>> {
+        let mut payload = Vec::with_capacity(self.payload_len() as usize);
+        self.payload_reader(pager).read_to_end(&mut payload)?;
+        Ok(payload)
+    }
+
+    pub fn payload_reader<'a>(&self, pager: &'a mut Pager) -> PayloadReader<'a> {
+        let cell = self.cell();
+        let len = leaf_cell_payload_len(cell);
+        let local = leaf_cell_local_payload(self.usable, cell);
+
+        PayloadReader {
+            len,
+            inner: io::Cursor::new(local.to_vec()).chain(OverflowReader::new(
+                pager,
+                leaf_cell_overflow(self.usable, cell),
+                len - local.len() as u64,
+            )),
+        }
+    }
+
+    pub fn rowid(&self) -> RowId {
+        key_rowid(self.key())
+    }
+
+    // Moves to the first entry. Returns false when the tree is empty
+    pub fn first(&mut self, pager: &mut Pager) -> io::Result<bool> {
+        self.stack.clear();
+        self.descend(pager, self.root, false)
+    }
+
+    // Moves to the last entry. Returns false when the tree is empty
+    pub fn last(&mut self, pager: &mut Pager) -> io::Result<bool> {
+        self.stack.clear();
+        self.descend(pager, self.root, true)
+    }
+
+    // Moves to the first entry whose key is greater than or equal to `key`. Returns false when there is none
+    pub fn seek(&mut self, pager: &mut Pager, key: &[u8]) -> io::Result<bool> {
+        self.seek_to(pager, key, false)
+    }
+
+    // Moves to the first entry whose key is strictly greater than `key`. Returns false when there is none
+    pub fn seek_after(&mut self, pager: &mut Pager, key: &[u8]) -> io::Result<bool> {
+        self.seek_to(pager, key, true)
+    }
+
+    fn seek_to(&mut self, pager: &mut Pager, key: &[u8], after: bool) -> io::Result<bool> {
+        self.stack.clear();
+        let mut page_no = self.root;
+        let kind = self.kind;
+        let before = |other: &[u8]| match kind.compare(other, key) {
+            Ordering::Less => true,
+            Ordering::Equal => after,
+            Ordering::Greater => false,
+        };
+
+        loop {
+            match self.load(pager, page_no)? {
+                Node::Interior { children, keys } => {
+                    let index = keys.partition_point(|separator| before(separator));
+                    self.stack.push((page_no, index));
+                    page_no = children[index];
+                }
+                Node::Leaf { cells } => {
+                    let index = cells.partition_point(|cell| before(leaf_cell_key(cell)));
+                    self.cells = cells;
+                    self.stack.push((page_no, index));
+                    if index == self.cells.len() {
+                        return self.next_leaf(pager, false);
+                    }
+                    return Ok(true);
+                }
+            }
+        }
+    }
+
+    // Moves to the following entry. Returns false once the end of the tree is reached
+    pub fn next(&mut self, pager: &mut Pager) -> io::Result<bool> {
+        let Some((_, index)) = self.stack.last_mut() else {
+            return Ok(false);
+        };
+
+        *index += 1;
+        if *index < self.cells.len() {
+            return Ok(true);
+        }
+        self.next_leaf(pager, false)
+    }
+
+    // Moves to the preceding entry. Returns false once the beginning of the tree is reached
+    pub fn prev(&mut self, pager: &mut Pager) -> io::Result<bool> {
+        let Some((_, index)) = self.stack.last_mut() else {
+            return Ok(false);
+        };
+
+        if *index > 0 {
+            *index -= 1;
+            return Ok(true);
+        }
+        self.next_leaf(pager, true)
+    }
+
+    fn cell(&self) -> &[u8] {
+        let &(_, index) = self
+            .stack
+            .last()
+            .expect("Cursor does not point to an entry");
+        &self.cells[index]
+    }
+
+    // Moves to the closest non-empty leaf after (or before, when `backwards` is set) the current one
+    fn next_leaf(&mut self, pager: &mut Pager, backwards: bool) -> io::Result<bool> {
+        self.stack.pop();
+
+        while let Some((page_no, index)) = self.stack.pop() {
+            let Node::Interior { children, .. } = self.load(pager, page_no)? else {
+                unreachable!()
+            };
+
+            let sibling = match backwards {
+                false if index + 1 < children.len() => index + 1,
+                true if index > 0 => index - 1,
+                _ => continue,
+            };
+            self.stack.push((page_no, sibling));
+            self.prefetch_children(pager, &children, sibling, backwards);
+            if self.descend(pager, children[sibling], backwards)? {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    // Goes down to the first (or last) entry of the subtree rooted at `page_no`
+    fn descend(
+        &mut self,
+        pager: &mut Pager,
+        mut page_no: PageNumber,
+        backwards: bool,
+    ) -> io::Result<bool> {
+        loop {
+            match self.load(pager, page_no)? {
+                Node::Interior { children, .. } => {
+                    let index = if backwards { children.len() - 1 } else { 0 };
+                    self.stack.push((page_no, index));
+                    self.prefetch_children(pager, &children, index, backwards);
+                    page_no = children[index];
+                }
+                Node::Leaf { cells } => {
+                    if cells.is_empty() {
+                        // Only an empty root leaf can be met here, every other leaf is merged once it empties
+                        self.stack.push((page_no, 0));
+                        self.cells = cells;
+                        return self.next_leaf(pager, backwards);
+                    }
+                    let index = if backwards { cells.len() - 1 } else { 0 };
+                    self.stack.push((page_no, index));
+                    self.cells = cells;
+                    return Ok(true);
+                }
+            }
+        }
+    }
+
+    // Prefetching is only a hint: should it fail, the pages are read one at a time, which reports the error if it
+    // persists
+    fn prefetch_children(
+        &self,
+        pager: &mut Pager,
+        children: &[PageNumber],
+        index: usize,
+        backwards: bool,
+    ) {
+        if !self.prefetch {
+            return;
+        }
+
+        let pages = match backwards {
+            false => &children[index..children.len().min(index + PREFETCH_PAGES)],
+            true => &children[(index + 1).saturating_sub(PREFETCH_PAGES)..=index],
+        };
+        let _ = pager.prefetch(pages);
+    }
+
+    fn load(&mut self, pager: &mut Pager, page_no: PageNumber) -> io::Result<Node> {
+        self.usable = pager.usable_size();
+        BTree {
+            root: self.root,
+            kind: self.kind,
+        }
+        .load_node(pager, page_no)
+    }
+}
+
+pub struct Scan<'a> {
+    pager: &'a mut Pager,
+    cursor: Cursor,
+    end: Bound<RowId>,
+}
+
+impl Iterator for Scan<'_> {
+    type Item = io::Result<(RowId, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.cursor.is_valid() {
+            return None;
+        }
+
+        let rowid = self.cursor.rowid();
+        let in_range = match self.end {
+            Bound::Included(end) => rowid <= end,
+            Bound::Excluded(end) => rowid < end,
+            Bound::Unbounded => true,
+        };
+        if !in_range {
+            self.cursor.stack.clear();
+            return None;
+        }
+
+        let row = match self.cursor.read_payload(self.pager) {
+            Ok(payload) => (rowid, payload),
+            Err(err) => {
+                self.cursor.stack.clear();
+                return Some(Err(err));
+            }
+        };
+        if let Err(err) = self.cursor.next(self.pager) {
+            self.cursor.stack.clear();
+            return Some(Err(err));
+        }
+
+        Some(Ok(row))
+    }
+}
+
+// Reads a record stored in the tree, first from its cell and then from its overflow pages
+pub struct PayloadReader<'a> {
+    len: u64,
+    inner: io::Chain<io::Cursor<Vec<u8>>, OverflowReader<'a>>,
+}
+
+impl PayloadReader<'_> {
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl Read for PayloadReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+// Builds a tree from the bottom up out of leaf cells given in key order. Each page is written once full, and its
+// greatest key is handed to the level above, whose pages are built the same way. The top page is written last, to the
+// root page of the tree
+struct Loader {
+    tree: BTree,
+    capacity: usize,
+    leaf: Vec<Vec<u8>>,
+    // Interior pages being filled, from the one right above the leaves to the top one
+    levels: Vec<PendingInterior>,
+}
+
+// Children of an interior page that is not written yet, along with their greatest key. The key of the last child is
+// not stored in the page, but it becomes part of it once another child is added
+#[derive(Default)]
+struct PendingInterior {
+    children: Vec<PageNumber>,
+    keys: Vec<Vec<u8>>,
+    // Size taken by the cells of all the keys
+    size: usize,
+}
+
+impl Loader {
+    fn new(tree: BTree, pager: &Pager) -> Self {
+        Self {
+            tree,
+            capacity: capacity(pager.usable_size()),
+            leaf: vec![],
+            levels: vec![],
+        }
+    }
+
+    fn push(&mut self, pager: &mut Pager, cell: Vec<u8>) -> io::Result<()> {
+        if cells_size(&self.leaf) + cell.len() + SLOT_SIZE > self.capacity {
+            self.write_leaf(pager)?;
+        }
+
+        self.leaf.push(cell);
+        Ok(())
+    }
+
+    fn write_leaf(&mut self, pager: &mut Pager) -> io::Result<()> {
+        let cells = mem::take(&mut self.leaf);
+        let page_no = pager.allocate_page()?;
+        self.tree.write_leaf(pager, page_no, &cells)?;
+
+        let key = leaf_cell_key(cells.last().expect("Empty leaf")).to_vec();
+        self.push_child(pager, 0, page_no, key)
+    }
+
+    fn push_child(
+        &mut self,
+        pager: &mut Pager,
+        level: usize,
+        child: PageNumber,
+        key: Vec<u8>,
+    ) -> io::Result<()> {
+        if level == self.levels.len() {
+            self.levels.push(PendingInterior::default());
+        }
+        if self.levels[level].size > self.capacity {
+            let node = mem::take(&mut self.levels[level]);
+            let page_no = pager.allocate_page()?;
+            let key = self.write_interior(pager, page_no, node)?;
+            self.push_child(pager, level + 1, page_no, key)?;
+        }
+
+        let node = &mut self.levels[level];
+        node.size += interior_cell_size(key.len()) + SLOT_SIZE;
+        node.children.push(child);
+        node.keys.push(key);
+        Ok(())
+    }
+
+    // Returns the greatest key under the page
+    fn write_interior(
+        &self,
+        pager: &mut Pager,
+        page_no: PageNumber,
+        mut node: PendingInterior,
+    ) -> io::Result<Vec<u8>> {
+        let key = node.keys.pop().expect("Interior page without children");
+        self.tree
+            .write_interior(pager, page_no, &node.children, &node.keys)?;
+        Ok(key)
+    }
+
+    fn finish(mut self, pager: &mut Pager) -> io::Result<()> {
+        if self.levels.is_empty() {
+            return self.tree.write_leaf(pager, self.tree.root, &self.leaf);
+        }
+
+        // Every level above the leaves received at least two children, the top one can become the root
+        self.write_leaf(pager)?;
+        let mut level = 0;
+        while level + 1 < self.levels.len() {
+            let node = mem::take(&mut self.levels[level]);
+            let page_no = pager.allocate_page()?;
+            let key = self.write_interior(pager, page_no, node)?;
+            self.push_child(pager, level + 1, page_no, key)?;
+            level += 1;
+        }
+
+        let top = self.levels.pop().expect("No interior level");
+        self.write_interior(pager, self.tree.root, top)?;
+        Ok(())
+    }
+}
+
+// Leaf cell holding a payload of `len` bytes read from `reader`, the part that does not fit in the cell being written
+// to overflow pages
+fn build_leaf_cell<R: Read>(
+    pager: &mut Pager,
+    key: &[u8],
+    len: u64,
+    mut reader: R,
+) -> io::Result<Vec<u8>> {
+    let mut local = vec![0; local_size(pager.usable_size(), key.len(), len)];
+    reader.read_exact(&mut local)?;
+
+    let remaining = len - local.len() as u64;
+    let mut overflow = 0;
+    if remaining > 0 {
+        let mut writer = OverflowWriter::new(pager);
+        let copied = io::copy(&mut reader.take(remaining), &mut writer);
+        overflow = writer.finish()?;
+        // The pages already written are given back when the reader fails or runs out early
+        let err = match copied {
+            Ok(copied) if copied == remaining => None,
+            Ok(_) => Some(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Record shorter than announced",
+            )),
+            Err(err) => Some(err),
+        };
+        if let Some(err) = err {
+            overflow::free_chain(pager, overflow)?;
+            return Err(err);
+        }
+    }
+
+    Ok(leaf_cell(key, &local, len, overflow))
+}
+
+pub fn rowid_key(rowid: RowId) -> [u8; mem::size_of::<RowId>()] {
+    ((rowid as u64) ^ (1 << 63)).to_be_bytes()
+}
+
+pub fn key_rowid(key: &[u8]) -> RowId {
+    (u64::from_be_bytes(key.try_into().expect("Invalid size")) ^ (1 << 63)) as RowId
+}
+
+// Records `page_no` as the page pointing to the overflow pages of `cell`, if it has any
+fn set_overflow_parent(pager: &mut Pager, page_no: PageNumber, cell: &[u8]) -> io::Result<()> {
+    match leaf_cell_overflow(pager.usable_size(), cell) {
+        0 => Ok(()),
+        first => pager.set_pointer_map_entry(first, PointerMapEntry::FirstOverflow(page_no)),
+    }
+}
+
+fn is_interior_page<B: AsRef<[u8]>>(
+    page_no: PageNumber,
+    page: &SlottedPage<B>,
+) -> io::Result<bool> {
+    match page.page_type() {
+        TABLE_INTERIOR_PAGE | INDEX_INTERIOR_PAGE => Ok(true),
+        TABLE_LEAF_PAGE | INDEX_LEAF_PAGE => Ok(false),
+        page_type => Err(corrupted(
+            page_no,
+            &format!("Unexpected page type {page_type:#04x}"),
+        )),
+    }
+}
+
+fn load_page(pager: &mut Pager, page_no: PageNumber) -> io::Result<Vec<u8>> {
+    let mut buf = vec![0; pager.page_size() as usize];
+    pager.read(page_no, &mut buf)?;
+    Ok(buf)
+}
+
+fn corrupted(page_no: PageNumber, reason: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Corrupted B-tree page {page_no}: {reason}"),
+    )
+}
+
+// Index of the first cell whose key is `key`, or of where it would be inserted
+fn search_leaf<B: AsRef<[u8]>>(
+    kind: TreeKind,
+    page: &SlottedPage<B>,
+    key: &[u8],
+) -> Result<usize, usize> {
+    let (mut low, mut high) = (0, page.len());
+
+    while low < high {
+        let middle = (low + high) / 2;
+        match kind.compare(leaf_cell_key(page.cell(middle)), key) {
+            Ordering::Less => low = middle + 1,
+            Ordering::Greater => high = middle,
+            Ordering::Equal => return Ok(middle),
+        }
+    }
+
+    Err(low)
+}
+
+// Index of the child under which `key` lives
+fn search_interior(kind: TreeKind, keys: &[Vec<u8>], key: &[u8]) -> usize {
+    keys.partition_point(|separator| kind.compare(separator, key) == Ordering::Less)
+}
+
+// Index at which `cells` can be split so that both halves are about the same size and fit in a page
+fn split_point(cells: &[Vec<u8>], capacity: usize) -> usize {
+    let total = cells_size(cells);
+    let mut size = 0;
+
+    for (index, cell) in cells.iter().enumerate() {
+        size += cell.len() + SLOT_SIZE;
+        if size >= total / 2 {
+            let index = if size > capacity { index } else { index + 1 };
+            return index.clamp(1, cells.len() - 1);
+        }
+    }
+
+    cells.len() - 1
+}
+
+fn cells_size(cells: &[Vec<u8>]) -> usize {
+    cells.iter().map(|cell| cell.len() + SLOT_SIZE).sum()
+}
+
+fn interior_size(keys: &[Vec<u8>]) -> usize {
+    keys.iter()
+        .map(|key| interior_cell_size(key.len()) + SLOT_SIZE)
+        .sum()
+}
+
+// Bytes of a page available to cells and their slots
+fn capacity(usable: usize) -> usize {
+    usable - PAGE_HEADER_SIZE
+}
+
+fn max_cell_size(usable: usize) -> usize {
+    capacity(usable) / 4 - SLOT_SIZE
+}
+
+// Largest payload kept entirely in a leaf cell along with a key of `key_len` bytes
+fn max_local_size(usable: usize, key_len: usize) -> usize {
+    max_cell_size(usable) - leaf_cell_size(key_len, 0)
+}
+
+// Part of a payload of `payload_len` bytes that is kept in its leaf cell. Like SQLite, a payload that overflows keeps
+// as much as needed in the cell for its last overflow page to be full, or a minimal part when that would be too much
+fn local_size(usable: usize, key_len: usize, payload_len: u64) -> usize {
+    let max_local = max_local_size(usable, key_len);
+    if payload_len <= max_local as u64 {
+        return payload_len as usize;
+    }
+
+    let min_local = capacity(usable) / 16;
+    let per_page = (usable - mem::size_of::<PageNumber>()) as u64;
+    let local = min_local + ((payload_len - min_local as u64) % per_page) as usize;
+    if local + mem::size_of::<PageNumber>() <= max_local {
+        local
+    } else {
+        min_local
+    }
+}
+
+// Leaf cells are laid out as
+// | key length (2) | payload length (4) | key | local payload | first overflow page (4), if the payload overflows |
+fn leaf_cell(key: &[u8], local: &[u8], payload_len: u64, overflow: PageNumber) -> Vec<u8> {
+    let mut cell = Vec::with_capacity(leaf_cell_size(key.len(), local.len()) + 4);
+    cell.extend_from_slice(&(key.len() as u16).to_le_bytes());
+    cell.extend_from_slice(&(payload_len as u32).to_le_bytes());
+    cell.extend_from_slice(key);
+    cell.extend_from_slice(local);
+    if overflow != 0 {
+        cell.extend_from_slice(&overflow.to_le_bytes());
+    }
+    cell
+}
+
+const LEAF_CELL_HEADER_SIZE: usize = mem::size_of::<u16>() + mem::size_of::<u32>();
+
+fn leaf_cell_size(key_len: usize, local_len: usize) -> usize {
+    LEAF_CELL_HEADER_SIZE + key_len + local_len
+}
+
+fn leaf_cell_key_len(cell: &[u8]) -> usize {
+    u16::from_le_bytes(cell[..2].try_into().expect("Invalid size")) as usize
+}
+
+fn leaf_cell_key(cell: &[u8]) -> &[u8] {
+    &cell[LEAF_CELL_HEADER_SIZE..LEAF_CELL_HEADER_SIZE + leaf_cell_key_len(cell)]
+}
+
+fn leaf_cell_payload_len(cell: &[u8]) -> u64 {
+    u32::from_le_bytes(cell[2..6].try_into().expect("Invalid size")) as u64
+}
+
+fn leaf_cell_local_payload(usable: usize, cell: &[u8]) -> &[u8] {
+    let key_len = leaf_cell_key_len(cell);
+    let start = LEAF_CELL_HEADER_SIZE + key_len;
+    &cell[start..start + local_size(usable, key_len, leaf_cell_payload_len(cell))]
+}
+
+// First overflow page of the payload, 0 when it is stored entirely in the cell
+fn leaf_cell_overflow(usable: usize, cell: &[u8]) -> PageNumber {
+    let key_len = leaf_cell_key_len(cell);
+    let payload_len = leaf_cell_payload_len(cell);
+    if payload_len <= max_local_size(usable, key_len) as u64 {
+        return 0;
+    }
+
+    PageNumber::from_le_bytes(cell[cell.len() - 4..].try_into().expect("Invalid size"))
+}
+
+// Interior cells are laid out as | child page (4) | key |
+fn interior_cell(child: PageNumber, key: &[u8]) -> Vec<u8> {
+    let mut cell = Vec::with_capacity(interior_cell_size(key.len()));
+    cell.extend_from_slice(&child.to_le_bytes());
+    cell.extend_from_slice(key);
+    cell
+}
+
+fn interior_cell_size(key_len: usize) -> usize {
+    mem::size_of::<PageNumber>() + key_len
+}
+
+fn interior_cell_child(cell: &[u8]) -> PageNumber {
+    PageNumber::from_le_bytes(cell[..4].try_into().expect("Invalid size"))
+}
+
+fn interior_cell_key(cell: &[u8]) -> &[u8] {
+    &cell[4..]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, sync::Arc};
+
+    use super::*;
+    use crate::vfs::MemoryVfs;
+
+    // Small pages, so that a few thousand rows make trees of several levels
+    const PAGE_SIZE: u32 = 512;
+    const ROWS: i64 = 3000;
+
+    fn open() -> Pager {
+        let vfs = Arc::new(MemoryVfs::new());
+        let mut pager = Pager::open_with_vfs(vfs, "test.db", PAGE_SIZE, 32).unwrap();
+        pager.init().unwrap();
+        pager
+    }
+
+    fn record(rowid: RowId, len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|i| (rowid as u8).wrapping_add(i as u8))
+            .collect()
+    }
+
+    // Rowids from 0 to `ROWS` excluded, in an order scattering them across the tree
+    fn shuffled() -> impl Iterator<Item = RowId> {
+        (0..ROWS).map(|i| i * 1237 % ROWS)
+    }
+
+    // Checks that keys are sorted and fall within the separators leading to their page, and that every leaf is at the
+    // same depth. Returns the number of levels of the tree
+    fn check(tree: &BTree, pager: &mut Pager) -> usize {
+        fn walk(
+            tree: &BTree,
+            pager: &mut Pager,
+            page_no: PageNumber,
+            low: Option<&[u8]>,
+            high: Option<&[u8]>,
+        ) -> usize {
+            let in_bounds =
+                |key: &[u8]| low.is_none_or(|low| key > low) && high.is_none_or(|high| key <= high);
+            match tree.load_node(pager, page_no).unwrap() {
+                Node::Leaf { cells } => {
+                    let keys: Vec<&[u8]> = cells.iter().map(|cell| leaf_cell_key(cell)).collect();
+                    assert!(keys.is_sorted_by(|a, b| a < b), "page {page_no}");
+                    assert!(keys.iter().all(|key| in_bounds(key)), "page {page_no}");
+                    1
+                }
+                Node::Interior { children, keys } => {
+                    assert!(!keys.is_empty(), "page {page_no}");
+                    assert!(keys.is_sorted_by(|a, b| a < b), "page {page_no}");
+                    assert!(keys.iter().all(|key| in_bounds(key)), "page {page_no}");
+                    let depths: Vec<usize> = children
+                        .iter()
+                        .enumerate()
+                        .map(|(i, &child)| {
+                            let low = match i {
+                                0 => low,
+                                i => Some(&keys[i - 1][..]),
+                            };
+                            let high = keys.get(i).map(|key| &key[..]).or(high);
+                            walk(tree, pager, child, low, high)
+                        })
+                        .collect();
+                    assert!(
+                        depths.iter().all(|&depth| depth == depths[0]),
+                        "page {page_no}"
+                    );
+                    depths[0] + 1
+                }
+            }
+        }
+
+        walk(tree, pager, tree.root_page(), None, None)
+    }
+
+    fn rows(tree: &BTree, pager: &mut Pager) -> Vec<(RowId, Vec<u8>)> {
+        tree.scan(pager, ..).unwrap().map(Result::unwrap).collect()
+    }
+
+    #[test]
+    fn insert_seek_delete_across_levels() {
+        let mut pager = open();
+        let tree = BTree::create(&mut pager, TreeKind::Table).unwrap();
+        let mut model = BTreeMap::new();
+        for rowid in shuffled() {
+            let record = record(rowid, rowid as usize % 100);
+            tree.insert(&mut pager, rowid, &record).unwrap();
+            model.insert(rowid, record);
+        }
+        assert!(check(&tree, &mut pager) >= 3);
+        let full = tree.page_count(&mut pager).unwrap();
+
+        for rowid in [0, 1, ROWS / 2, ROWS - 1] {
+            assert_eq!(
+                tree.get(&mut pager, rowid).unwrap(),
+                model.get(&rowid).cloned()
+            );
+        }
+        assert_eq!(tree.get(&mut pager, ROWS).unwrap(), None);
+        let mut cursor = tree.cursor();
+        assert!(cursor.seek(&mut pager, &rowid_key(-5)).unwrap());
+        assert_eq!(cursor.rowid(), 0);
+        assert!(cursor.seek_after(&mut pager, &rowid_key(ROWS / 2)).unwrap());
+        assert_eq!(cursor.rowid(), ROWS / 2 + 1);
+        assert!(!cursor.seek(&mut pager, &rowid_key(ROWS)).unwrap());
+
+        // Replacing records keeps the tree as it is
+        for rowid in (0..ROWS).step_by(7) {
+            let record = record(rowid, 30);
+            tree.insert(&mut pager, rowid, &record).unwrap();
+            model.insert(rowid, record);
+        }
+        check(&tree, &mut pager);
+
+        // Deleting all but a few rows merges pages on every level
+        for rowid in shuffled().filter(|rowid| rowid % 50 != 0) {
+            assert!(tree.delete(&mut pager, rowid).unwrap());
+            assert!(!tree.delete(&mut pager, rowid).unwrap());
+            model.remove(&rowid);
+            if rowid % 97 == 0 {
+                check(&tree, &mut pager);
+            }
+        }
+        assert!(check(&tree, &mut pager) <= 2);
+        assert!(tree.page_count(&mut pager).unwrap() < full / 10);
+        assert_eq!(
+            rows(&tree, &mut pager),
+            model.into_iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn delete_down_to_empty_tree() {
+        let mut pager = open();
+        let tree = BTree::create(&mut pager, TreeKind::Table).unwrap();
+        let max_payload = BTree::max_payload_size(&pager);
+        for rowid in shuffled() {
+            // Some records spill to overflow pages
+            let len = match rowid % 10 {
+                0 => max_payload * 3,
+                _ => 40,
+            };
+            tree.insert(&mut pager, rowid, &record(rowid, len)).unwrap();
+        }
+        assert!(check(&tree, &mut pager) >= 3);
+        let used = pager.page_count() - pager.freelist_count();
+        // Pages in use before the tree was created
+        let reserved = tree.root_page();
+
+        for rowid in shuffled() {
+            assert!(tree.delete(&mut pager, rowid).unwrap());
+        }
+        assert_eq!(check(&tree, &mut pager), 1);
+        assert_eq!(tree.page_count(&mut pager).unwrap(), 1);
+        assert!(rows(&tree, &mut pager).is_empty());
+        assert!(!tree.cursor().first(&mut pager).unwrap());
+        assert!(!tree.cursor().last(&mut pager).unwrap());
+        assert_eq!(tree.max_rowid(&mut pager).unwrap(), None);
+        // Every page but the root went back to the free-list, overflow pages included
+        assert!(used > reserved + 1);
+        assert_eq!(pager.page_count() - pager.freelist_count(), reserved + 1);
+
+        // The tree is still usable
+        tree.insert(&mut pager, 42, b"again").unwrap();
+        assert_eq!(rows(&tree, &mut pager), vec![(42, b"again".to_vec())]);
+    }
+
+    #[test]
+    fn cursor_after_deletes() {
+        let mut pager = open();
+        let tree = BTree::create(&mut pager, TreeKind::Table).unwrap();
+        for rowid in shuffled() {
+            tree.insert(&mut pager, rowid, &record(rowid, 50)).unwrap();
+        }
+        // Removes whole leaves as well as single cells
+        for rowid in shuffled().filter(|rowid| rowid % 3 == 0 || (1000..1500).contains(rowid)) {
+            tree.delete(&mut pager, rowid).unwrap();
+        }
+        let expected: Vec<RowId> = (0..ROWS)
+            .filter(|rowid| rowid % 3 != 0 && !(1000..1500).contains(rowid))
+            .collect();
+
+        let mut cursor = tree.cursor();
+        let mut forward = vec![];
+        let mut found = cursor.first(&mut pager).unwrap();
+        while found {
+            assert_eq!(
+                cursor.read_payload(&mut pager).unwrap(),
+                record(cursor.rowid(), 50)
+            );
+            forward.push(cursor.rowid());
+            found = cursor.next(&mut pager).unwrap();
+        }
+        assert_eq!(forward, expected);
+
+        let mut backward = vec![];
+        let mut found = cursor.last(&mut pager).unwrap();
+        while found {
+            backward.push(cursor.rowid());
+            found = cursor.prev(&mut pager).unwrap();
+        }
+        backward.reverse();
+        assert_eq!(backward, expected);
+
+        // Seeking a deleted rowid lands on the next one left
+        assert!(cursor.seek(&mut pager, &rowid_key(999)).unwrap());
+        assert_eq!(cursor.rowid(), 1501);
+        assert!(cursor.prev(&mut pager).unwrap());
+        assert_eq!(cursor.rowid(), 998);
+        let range: Vec<RowId> = tree
+            .scan(&mut pager, 990..1510)
+            .unwrap()
+            .map(|row| row.unwrap().0)
+            .collect();
+        assert_eq!(
+            range,
+            [991, 992, 994, 995, 997, 998, 1501, 1502, 1504, 1505, 1507, 1508]
+        );
+    }
+
+    #[test]
+    fn loader_matches_incremental_inserts() {
+        let mut pager = open();
+        let tree = BTree::create(&mut pager, TreeKind::Table).unwrap();
+        let max_payload = BTree::max_payload_size(&pager);
+        for rowid in shuffled() {
+            let len = match rowid % 25 {
+                0 => max_payload * 2 + 7,
+                n => n as usize * 9,
+            };
+            tree.insert(&mut pager, rowid, &record(rowid, len)).unwrap();
+        }
+        let expected = rows(&tree, &mut pager);
+        let levels = check(&tree, &mut pager);
+
+        let mut target = open();
+        let reserved = target.page_count();
+        let root = target.allocate_page().unwrap();
+        assert_eq!(root, tree.root_page());
+        tree.copy_to(&mut pager, &mut target).unwrap();
+        let copy = BTree::open(&mut target, root).unwrap();
+        assert_eq!(rows(&copy, &mut target), expected);
+        // Pages are filled up, so the copy is no larger nor higher than the tree built by inserts
+        assert!(check(&copy, &mut target) <= levels);
+        assert!(copy.page_count(&mut target).unwrap() < tree.page_count(&mut pager).unwrap());
+        assert_eq!(
+            copy.page_count(&mut target).unwrap() + reserved,
+            target.page_count()
+        );
+
+        // Bulk loading through `Loader` directly, with any number of cells
+        for count in [0, 1, 5, 200, 2000] {
+            let mut target = open();
+            let loaded = BTree {
+                root: target.allocate_page().unwrap(),
+                kind: TreeKind::Table,
+            };
+            let mut loader = Loader::new(loaded, &target);
+            for (rowid, record) in &expected[..count] {
+                let cell = build_leaf_cell(
+                    &mut target,
+                    &rowid_key(*rowid),
+                    record.len() as u64,
+                    &record[..],
+                )
+                .unwrap();
+                loader.push(&mut target, cell).unwrap();
+            }
+            loader.finish(&mut target).unwrap();
+            check(&loaded, &mut target);
+            assert_eq!(rows(&loaded, &mut target), expected[..count]);
+
+            // Inserting after the bulk load splits the loaded pages as usual
+            loaded.insert(&mut target, -1, b"first").unwrap();
+            loaded.insert(&mut target, ROWS, b"last").unwrap();
+            check(&loaded, &mut target);
+            assert_eq!(rows(&loaded, &mut target).len(), count + 2);
+        }
+    }
+
+    // Yields `len` bytes of a pattern that doesn't repeat with the page size, failing once `fail_at` bytes were read
+    struct PatternReader {
+        position: u64,
+        len: u64,
+        fail_at: Option<u64>,
+    }
+
+    impl PatternReader {
+        fn new(len: u64) -> Self {
+            Self {
+                position: 0,
+                len,
+                fail_at: None,
+            }
+        }
+    }
+
+    impl Read for PatternReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let end = self.fail_at.unwrap_or(self.len).min(self.len);
+            if self.position == end && self.fail_at.is_some() {
+                return Err(io::Error::other("Reader failed"));
+            }
+
+            let len = buf.len().min((end - self.position) as usize);
+            for byte in &mut buf[..len] {
+                *byte = (self.position % 251) as u8;
+                self.position += 1;
+            }
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn failed_reader_frees_overflow_pages() {
+        let mut pager = open();
+        let tree = BTree::create(&mut pager, TreeKind::Table).unwrap();
+        tree.insert(&mut pager, 1, b"row").unwrap();
+        let len = 100 * PAGE_SIZE as u64;
+
+        let used = pager.page_count() - pager.freelist_count();
+        let mut reader = PatternReader::new(len);
+        reader.fail_at = Some(len / 2);
+        let err = tree.insert_from(&mut pager, 2, len, reader).unwrap_err();
+        assert_eq!(err.to_string(), "Reader failed");
+        assert!(pager.freelist_count() > 40);
+        assert_eq!(pager.page_count() - pager.freelist_count(), used);
+
+        // As does a reader ending before the announced length
+        let err = tree
+            .insert_from(&mut pager, 2, len, PatternReader::new(len - 1))
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(pager.page_count() - pager.freelist_count(), used);
+
+        // The freed pages are reused by the next record
+        let page_count = pager.page_count();
+        tree.insert_from(&mut pager, 2, len, PatternReader::new(len))
+            .unwrap();
+        assert_eq!(pager.page_count(), page_count);
+        assert_eq!(rows(&tree, &mut pager).len(), 2);
+    }
+
+    #[test]
+    fn streamed_multi_megabyte_records() {
+        let mut pager = open();
+        let tree = BTree::create(&mut pager, TreeKind::Table).unwrap();
+        let lens = [3 << 20, (5 << 20) + 123];
+        for (rowid, &len) in lens.iter().enumerate() {
+            tree.insert_from(&mut pager, rowid as RowId, len, PatternReader::new(len))
+                .unwrap();
+        }
+        let used = pager.page_count() - pager.freelist_count();
+        assert!(used as u64 > (8 << 20) / PAGE_SIZE as u64);
+
+        for (rowid, &len) in lens.iter().enumerate() {
+            let mut cursor = tree.cursor();
+            assert!(cursor.seek(&mut pager, &rowid_key(rowid as RowId)).unwrap());
+            assert_eq!(cursor.payload_len(), len);
+
+            // Compared chunk by chunk, without holding the whole record
+            let mut expected = PatternReader::new(len);
+            let mut reader = cursor.payload_reader(&mut pager);
+            let (mut chunk, mut expected_chunk) = (vec![0; 65536], vec![0; 65536]);
+            let mut read = 0;
+            loop {
+                let n = reader.read(&mut chunk).unwrap();
+                if n == 0 {
+                    break;
+                }
+                expected.read_exact(&mut expected_chunk[..n]).unwrap();
+                assert_eq!(chunk[..n], expected_chunk[..n], "record {rowid} at {read}");
+                read += n as u64;
+            }
+            assert_eq!(read, len);
+        }
+
+        // Deleting the records gives all their overflow pages back
+        let free = pager.freelist_count();
+        for rowid in 0..lens.len() {
+            tree.delete(&mut pager, rowid as RowId).unwrap();
+        }
+        assert!(pager.freelist_count() - free > (8 << 20) / PAGE_SIZE);
+        assert_eq!(pager.page_count() - pager.freelist_count(), 3);
+    }
+}
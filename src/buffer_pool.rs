@@ -0,0 +1,347 @@
+//! A bounded cache of page frames sitting between the pager and the heap file
+//! Frames are looked up by page number. A frame can be pinned, in which case it is never evicted, and is flagged as
+//! dirty once its content diverges from what is on disk. When a new page has to be loaded and every frame is in use,
+//! the least recently unpinned frame is evicted, after having its content written back if it was dirty
+
+use std::{collections::HashMap, fmt, io};
+
+use crate::{pager::PageNumber, vfs::AlignedBuf};
+
+pub type FrameId = usize;
+
+// Whatever the pool loads pages from and writes them back to
+pub trait PageStore {
+    fn read_page(&mut self, page_no: PageNumber, buf: &mut [u8]) -> io::Result<()>;
+    fn write_page(&mut self, page_no: PageNumber, buf: &[u8]) -> io::Result<()>;
+
+    // Reads several pages at once. Stores able to have these reads in flight together override it
+    fn read_pages(&mut self, pages: &mut [(PageNumber, &mut [u8])]) -> io::Result<()> {
+        for (page_no, buf) in pages {
+            self.read_page(*page_no, buf)?;
+        }
+
+        Ok(())
+    }
+
+    // Writes several pages at once. Stores able to have these writes in flight together override it
+    fn write_pages(&mut self, pages: &[(PageNumber, &[u8])]) -> io::Result<()> {
+        for &(page_no, buf) in pages {
+            self.write_page(page_no, buf)?;
+        }
+
+        Ok(())
+    }
+}
+
+struct Frame {
+    page_no: PageNumber,
+    // Aligned, so that frames can be read into and written from directly with direct I/O
+    data: AlignedBuf,
+    pin_count: u32,
+    dirty: bool,
+    // Links of the LRU list. Only unpinned frames are part of it
+    prev: Option<FrameId>,
+    next: Option<FrameId>,
+}
+
+pub struct BufferPool {
+    page_size: usize,
+    capacity: usize,
+    frames: Vec<Frame>,
+    page_table: HashMap<PageNumber, FrameId>,
+    // Least recently used end of the list, first candidate for eviction
+    lru_head: Option<FrameId>,
+    // Most recently used end of the list
+    lru_tail: Option<FrameId>,
+}
+
+impl BufferPool {
+    pub fn new(page_size: usize, capacity: usize) -> Self {
+        assert!(capacity > 0, "A buffer pool needs at least one frame");
+
+        Self {
+            page_size,
+            capacity,
+            frames: Vec::with_capacity(capacity),
+            page_table: HashMap::with_capacity(capacity),
+            lru_head: None,
+            lru_tail: None,
+        }
+    }
+
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    // Number of pages currently held in memory
+    pub fn len(&self) -> usize {
+        self.page_table.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.page_table.is_empty()
+    }
+
+    pub fn contains(&self, page_no: PageNumber) -> bool {
+        self.page_table.contains_key(&page_no)
+    }
+
+    // Returns the frame holding `page_no`, loading it from `store` if needed. The frame stays pinned until a matching
+    // call to `unpin`
+    pub fn pin<S: PageStore>(&mut self, page_no: PageNumber, store: &mut S) -> io::Result<FrameId> {
+        if let Some(&frame_id) = self.page_table.get(&page_no) {
+            self.pin_frame(frame_id);
+            return Ok(frame_id);
+        }
+
+        let frame_id = self.free_frame(store)?;
+        let frame = &mut self.frames[frame_id];
+        if let Err(err) = store.read_page(page_no, &mut frame.data) {
+            // The frame is left unmapped so a later pin retries the read
+            self.lru_push(frame_id);
+            return Err(err);
+        }
+        frame.page_no = page_no;
+        frame.pin_count = 1;
+        frame.dirty = false;
+        self.page_table.insert(page_no, frame_id);
+
+        Ok(frame_id)
+    }
+
+    pub fn unpin(&mut self, frame_id: FrameId, dirty: bool) {
+        let frame = &mut self.frames[frame_id];
+        assert!(
+            frame.pin_count > 0,
+            "Unpinning frame {frame_id} which is not pinned"
+        );
+
+        frame.dirty |= dirty;
+        frame.pin_count -= 1;
+        if frame.pin_count == 0 {
+            self.lru_push(frame_id);
+        }
+    }
+
+    pub fn page(&self, frame_id: FrameId) -> &[u8] {
+        &self.frames[frame_id].data
+    }
+
+    // Callers must flag the frame as dirty when unpinning it if they modified it
+    pub fn page_mut(&mut self, frame_id: FrameId) -> &mut [u8] {
+        &mut self.frames[frame_id].data
+    }
+
+    pub fn mark_dirty(&mut self, frame_id: FrameId) {
+        self.frames[frame_id].dirty = true;
+    }
+
+    pub fn page_no(&self, frame_id: FrameId) -> PageNumber {
+        self.frames[frame_id].page_no
+    }
+
+    pub fn is_dirty(&self, frame_id: FrameId) -> bool {
+        self.frames[frame_id].dirty
+    }
+
+    // Writes every dirty frame back to `store`, as a single batch. Frames stay cached
+    pub fn flush_all<S: PageStore>(&mut self, store: &mut S) -> io::Result<()> {
+        let dirty = self.dirty_frames();
+        let pages: Vec<(PageNumber, &[u8])> = dirty
+            .iter()
+            .map(|&(page_no, frame_id)| (page_no, &*self.frames[frame_id].data))
+            .collect();
+        store.write_pages(&pages)?;
+
+        for (_, frame_id) in dirty {
+            self.frames[frame_id].dirty = false;
+        }
+
+        Ok(())
+    }
+
+    // Loads the pages of `pages` that are not cached yet with a single batch read, leaving them unpinned. At most half
+    // of the pool is filled this way, so that prefetching does not evict the whole working set
+    pub fn prefetch<S: PageStore>(
+        &mut self,
+        pages: &[PageNumber],
+        store: &mut S,
+    ) -> io::Result<()> {
+        let mut missing: Vec<PageNumber> = vec![];
+        for &page_no in pages {
+            if !self.contains(page_no) && !missing.contains(&page_no) {
+                missing.push(page_no);
+            }
+        }
+        missing.truncate(self.capacity / 2);
+
+        let mut frame_ids = Vec::with_capacity(missing.len());
+        for _ in &missing {
+            match self.free_frame(store) {
+                Ok(frame_id) => frame_ids.push(frame_id),
+                Err(err) => {
+                    for &frame_id in &frame_ids {
+                        self.lru_push(frame_id);
+                    }
+                    return Err(err);
+                }
+            }
+        }
+
+        let mut targets = vec![None; self.frames.len()];
+        for (&frame_id, &page_no) in frame_ids.iter().zip(&missing) {
+            targets[frame_id] = Some(page_no);
+        }
+        let mut reads: Vec<(PageNumber, &mut [u8])> = self
+            .frames
+            .iter_mut()
+            .zip(targets)
+            .filter_map(|(frame, page_no)| Some((page_no?, &mut *frame.data)))
+            .collect();
+        let result = store.read_pages(&mut reads);
+
+        // On failure, the frames are left unmapped like after a failed `pin`
+        for (&frame_id, &page_no) in frame_ids.iter().zip(&missing) {
+            if result.is_ok() {
+                let frame = &mut self.frames[frame_id];
+                frame.page_no = page_no;
+                frame.pin_count = 0;
+                frame.dirty = false;
+                self.page_table.insert(page_no, frame_id);
+            }
+            self.lru_push(frame_id);
+        }
+
+        result
+    }
+
+    // Pages that are dirty along with their frame, sorted by page number so that writing them back is sequential
+    pub fn dirty_frames(&self) -> Vec<(PageNumber, FrameId)> {
+        let mut dirty: Vec<(PageNumber, FrameId)> = self
+            .page_table
+            .iter()
+            .filter(|(_, &frame_id)| self.frames[frame_id].dirty)
+            .map(|(&page_no, &frame_id)| (page_no, frame_id))
+            .collect();
+        dirty.sort_unstable();
+        dirty
+    }
+
+    // To be called once the content of a frame has been written back by the caller
+    pub fn mark_clean(&mut self, frame_id: FrameId) {
+        self.frames[frame_id].dirty = false;
+    }
+
+    pub fn flush_page<S: PageStore>(
+        &mut self,
+        page_no: PageNumber,
+        store: &mut S,
+    ) -> io::Result<()> {
+        if let Some(&frame_id) = self.page_table.get(&page_no) {
+            let frame = &mut self.frames[frame_id];
+            if frame.dirty {
+                store.write_page(page_no, &frame.data)?;
+                frame.dirty = false;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Drops `page_no` from the pool without writing it back, whatever its state
+    pub fn discard(&mut self, page_no: PageNumber) {
+        if let Some(frame_id) = self.page_table.remove(&page_no) {
+            let frame = &mut self.frames[frame_id];
+            assert!(
+                frame.pin_count == 0,
+                "Discarding page {page_no} which is still pinned"
+            );
+            frame.dirty = false;
+        }
+    }
+
+    // Finds a frame that can receive a new page: either a never used one or the least recently used unpinned one.
+    // The returned frame is unmapped and out of the LRU list
+    fn free_frame<S: PageStore>(&mut self, store: &mut S) -> io::Result<FrameId> {
+        if self.frames.len() < self.capacity {
+            self.frames.push(Frame {
+                page_no: 0,
+                data: AlignedBuf::zeroed(self.page_size),
+                pin_count: 0,
+                dirty: false,
+                prev: None,
+                next: None,
+            });
+            return Ok(self.frames.len() - 1);
+        }
+
+        let Some(victim) = self.lru_head else {
+            return Err(io::Error::new(
+                io::ErrorKind::OutOfMemory,
+                "All buffer pool frames are pinned",
+            ));
+        };
+
+        let frame = &self.frames[victim];
+        let page_no = frame.page_no;
+        if self.page_table.get(&page_no) == Some(&victim) {
+            if frame.dirty {
+                store.write_page(page_no, &frame.data)?;
+                self.frames[victim].dirty = false;
+            }
+            self.page_table.remove(&page_no);
+        }
+        self.lru_remove(victim);
+
+        Ok(victim)
+    }
+
+    fn pin_frame(&mut self, frame_id: FrameId) {
+        if self.frames[frame_id].pin_count == 0 {
+            self.lru_remove(frame_id);
+        }
+        self.frames[frame_id].pin_count += 1;
+    }
+
+    fn lru_push(&mut self, frame_id: FrameId) {
+        self.frames[frame_id].prev = self.lru_tail;
+        self.frames[frame_id].next = None;
+        match self.lru_tail {
+            Some(tail) => self.frames[tail].next = Some(frame_id),
+            None => self.lru_head = Some(frame_id),
+        }
+        self.lru_tail = Some(frame_id);
+    }
+
+    fn lru_remove(&mut self, frame_id: FrameId) {
+        let (prev, next) = (self.frames[frame_id].prev, self.frames[frame_id].next);
+        match prev {
+            Some(prev) => self.frames[prev].next = next,
+            None => self.lru_head = next,
+        }
+        match next {
+            Some(next) => self.frames[next].prev = prev,
+            None => self.lru_tail = prev,
+        }
+        self.frames[frame_id].prev = None;
+        self.frames[frame_id].next = None;
+    }
+}
+
+impl fmt::Debug for BufferPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BufferPool")
+            .field("page_size", &self.page_size)
+            .field("capacity", &self.capacity)
+            .field("cached", &self.page_table.len())
+            .field(
+                "dirty",
+                &self.frames.iter().filter(|frame| frame.dirty).count(),
+            )
+            .finish()
+    }
+}
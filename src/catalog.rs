@@ -0,0 +1,268 @@
+//! The catalog describes the schema of the database: every table, index, view and trigger, along with the SQL text
+//! defining it. Like SQLite's `sqlite_schema`, it is itself a table, rooted at page 1 so that it is found without
+//! anything else to go by. Each of its rows is a record of
+//! | kind (text) | name (text) | table (text) | root page (integer) | definition (text) |
+//! The table of an index or a trigger is the one it belongs to, that of a table or a view is the object itself. Views
+//! and triggers have no tree, their root page is 0
+//! The pager loads the catalog when it opens the database and keeps it in memory. Every change to the catalog bumps the
+//! schema cookie of the header, which tells that the copy in memory is stale. Names are case-insensitive
+
+use std::io;
+
+use crate::{
+    btree::{BTree, RowId, TreeKind},
+    pager::{PageNumber, Pager},
+    record::{self, Record},
+    value::ValueRef,
+};
+
+pub const CATALOG_ROOT: PageNumber = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectKind {
+    Table,
+    Index,
+    View,
+    Trigger,
+}
+
+impl ObjectKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Table => "table",
+            Self::Index => "index",
+            Self::View => "view",
+            Self::Trigger => "trigger",
+        }
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        match bytes {
+            b"table" => Some(Self::Table),
+            b"index" => Some(Self::Index),
+            b"view" => Some(Self::View),
+            b"trigger" => Some(Self::Trigger),
+            _ => None,
+        }
+    }
+
+    // Whether objects of this kind store their content in a tree of their own
+    pub fn has_tree(self) -> bool {
+        matches!(self, Self::Table | Self::Index)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaObject {
+    pub kind: ObjectKind,
+    pub name: String,
+    pub table: String,
+    // 0 for views and triggers
+    pub root: PageNumber,
+    pub sql: String,
+}
+
+impl SchemaObject {
+    fn to_record(&self) -> Vec<u8> {
+        record::encode_refs([
+            ValueRef::Text(self.kind.as_str().as_bytes()),
+            ValueRef::Text(self.name.as_bytes()),
+            ValueRef::Text(self.table.as_bytes()),
+            ValueRef::Integer(self.root as i64),
+            ValueRef::Text(self.sql.as_bytes()),
+        ])
+    }
+
+    fn from_record(rowid: RowId, data: &[u8]) -> io::Result<Self> {
+        let malformed = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Malformed catalog entry {rowid}"),
+            )
+        };
+        let record = Record::parse(data)?;
+        if record.len() != 5 {
+            return Err(malformed());
+        }
+        let text = |index: usize| match record.column(index) {
+            ValueRef::Text(text) => String::from_utf8(text.to_vec()).map_err(|_| malformed()),
+            _ => Err(malformed()),
+        };
+
+        let kind = match record.column(0) {
+            ValueRef::Text(kind) => ObjectKind::from_bytes(kind).ok_or_else(malformed)?,
+            _ => return Err(malformed()),
+        };
+        let root = match record.column(3) {
+            ValueRef::Integer(root) => PageNumber::try_from(root).map_err(|_| malformed())?,
+            _ => return Err(malformed()),
+        };
+
+        Ok(Self {
+            kind,
+            name: text(1)?,
+            table: text(2)?,
+            root,
+            sql: text(4)?,
+        })
+    }
+}
+
+// The content of the catalog as of a given schema cookie
+#[derive(Debug, Clone)]
+pub struct Catalog {
+    schema_cookie: u32,
+    // Whether the root page of the catalog was allocated, see `load`
+    created: bool,
+    // In the order they were added
+    objects: Vec<(RowId, SchemaObject)>,
+}
+
+impl Catalog {
+    // Allocates the root of the catalog, which has to be the first page of the database
+    pub(crate) fn create(pager: &mut Pager) -> io::Result<()> {
+        if pager.page_count() > CATALOG_ROOT {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The catalog can only be created in an empty database",
+            ));
+        }
+
+        BTree::create(pager, TreeKind::Table)?;
+        Ok(())
+    }
+
+    pub(crate) fn load(pager: &mut Pager) -> io::Result<Self> {
+        let mut objects = vec![];
+
+        // Databases created before the catalog existed get one along with their first schema object
+        let created = pager.page_count() > CATALOG_ROOT;
+        if created {
+            let tree = BTree::open(pager, CATALOG_ROOT)?;
+            if tree.kind() != TreeKind::Table {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Page {CATALOG_ROOT} does not hold the catalog"),
+                ));
+            }
+            for row in tree.scan(pager, ..)? {
+                let (rowid, data) = row?;
+                objects.push((rowid, SchemaObject::from_record(rowid, &data)?));
+            }
+        }
+
+        Ok(Self {
+            schema_cookie: pager.schema_cookie(),
+            created,
+            objects,
+        })
+    }
+
+    pub fn schema_cookie(&self) -> u32 {
+        self.schema_cookie
+    }
+
+    pub fn objects(&self) -> impl Iterator<Item = &SchemaObject> {
+        self.objects.iter().map(|(_, object)| object)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&SchemaObject> {
+        self.find(name).map(|(_, object)| object)
+    }
+
+    pub fn tables(&self) -> impl Iterator<Item = &SchemaObject> {
+        self.objects()
+            .filter(|object| object.kind == ObjectKind::Table)
+    }
+
+    // The indexes of `table`
+    pub fn indexes<'a>(&'a self, table: &'a str) -> impl Iterator<Item = &'a SchemaObject> {
+        self.objects().filter(move |object| {
+            object.kind == ObjectKind::Index && object.table.eq_ignore_ascii_case(table)
+        })
+    }
+
+    // Root pages of the trees described by the catalog, the catalog's own included
+    pub fn roots(&self) -> impl Iterator<Item = PageNumber> + '_ {
+        self.created.then_some(CATALOG_ROOT).into_iter().chain(
+            self.objects()
+                .filter(|object| object.kind.has_tree())
+                .map(|object| object.root),
+        )
+    }
+
+    fn find(&self, name: &str) -> Option<&(RowId, SchemaObject)> {
+        self.objects
+            .iter()
+            .find(|(_, object)| object.name.eq_ignore_ascii_case(name))
+    }
+}
+
+// Records a new object in the catalog. Its name must not be taken, and indexes and triggers must belong to an existing
+// table, or view for triggers. The tree of a table or an index is up to the caller
+pub fn add(pager: &mut Pager, object: SchemaObject) -> io::Result<()> {
+    let catalog = pager.catalog()?;
+    if catalog.get(&object.name).is_some() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("There is already an object called {}", object.name),
+        ));
+    }
+    match object.kind {
+        ObjectKind::Table | ObjectKind::View if object.table != object.name => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "The table of {} {} has to be itself",
+                    object.kind.as_str(),
+                    object.name
+                ),
+            ))
+        }
+        ObjectKind::Index | ObjectKind::Trigger => {
+            let table = catalog.get(&object.table).map(|table| table.kind);
+            let valid = match object.kind {
+                ObjectKind::Index => table == Some(ObjectKind::Table),
+                _ => matches!(table, Some(ObjectKind::Table | ObjectKind::View)),
+            };
+            if !valid {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("No such table: {}", object.table),
+                ));
+            }
+        }
+        _ => {}
+    }
+    if object.kind.has_tree() == (object.root == 0) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Invalid root page {} for {} {}",
+                object.root,
+                object.kind.as_str(),
+                object.name
+            ),
+        ));
+    }
+
+    if pager.page_count() <= CATALOG_ROOT {
+        Catalog::create(pager)?;
+    }
+    let tree = BTree::open(pager, CATALOG_ROOT)?;
+    let rowid = tree.max_rowid(pager)?.map_or(1, |rowid| rowid + 1);
+    tree.insert(pager, rowid, &object.to_record())?;
+    pager.bump_schema_cookie()
+}
+
+// Removes `name` from the catalog and returns what it described. Its tree, and the objects that belong to it, are up to
+// the caller
+pub fn remove(pager: &mut Pager, name: &str) -> io::Result<SchemaObject> {
+    let (rowid, object) = pager.catalog()?.find(name).cloned().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, format!("No such object: {name}"))
+    })?;
+
+    BTree::open(pager, CATALOG_ROOT)?.delete(pager, rowid)?;
+    pager.bump_schema_cookie()?;
+    Ok(object)
+}
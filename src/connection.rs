@@ -0,0 +1,241 @@
+//! Connections group page modifications into transactions
+//! Every change made between `begin` and `commit` becomes durable at once when committing, or not at all: pages are
+//! only made visible in the heap file through the journal mode of the database, which makes a crash in the middle of
+//! a commit look like the transaction never happened. `rollback` throws the changes away instead
+//! Without a journal (`JournalMode::Off`), commits are not atomic and pages written back before a rollback stay changed
+//! Savepoints mark points within a transaction that it can partially roll back to. Setting one outside of a
+//! transaction starts a transaction, which releasing that savepoint commits
+//! SQL statements run in a transaction of their own unless one is ongoing. A statement that fails undoes its own
+//! changes, and only those
+
+use std::{io, path::Path, sync::Arc};
+
+use crate::{
+    catalog::Catalog,
+    pager::{AutoVacuum, JournalMode, PageNumber, Pager, DEFAULT_CACHE_SIZE, DEFAULT_PAGE_SIZE},
+    sql::{self, Program, Vm},
+    vacuum,
+    value::Value,
+    vfs::{UnixVfs, Vfs},
+    wal::{CheckpointMode, CheckpointResult},
+};
+
+// Savepoint set around each statement
+const STATEMENT_SAVEPOINT: &str = "statement";
+
+#[derive(Debug)]
+pub struct Connection {
+    pager: Pager,
+    in_transaction: bool,
+    // Whether the ongoing transaction was started by setting a savepoint
+    savepoint_transaction: bool,
+}
+
+impl Connection {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::open_with(path, DEFAULT_PAGE_SIZE, DEFAULT_CACHE_SIZE)
+    }
+
+    // `page_size` is only used if the database has to be created
+    pub fn open_with<P: AsRef<Path>>(
+        path: P,
+        page_size: u32,
+        cache_size: usize,
+    ) -> io::Result<Self> {
+        Self::open_with_vfs(Arc::new(UnixVfs), path, page_size, cache_size)
+    }
+
+    pub fn open_with_vfs<P: AsRef<Path>>(
+        vfs: Arc<dyn Vfs>,
+        path: P,
+        page_size: u32,
+        cache_size: usize,
+    ) -> io::Result<Self> {
+        let mut pager = Pager::open_with_vfs(vfs, path, page_size, cache_size)?;
+        pager.init()?;
+
+        Ok(Self {
+            pager,
+            in_transaction: false,
+            savepoint_transaction: false,
+        })
+    }
+
+    pub fn pager(&self) -> &Pager {
+        &self.pager
+    }
+
+    // Changes made through the pager outside of a transaction are committed along with the next one
+    pub fn pager_mut(&mut self) -> &mut Pager {
+        &mut self.pager
+    }
+
+    pub fn in_transaction(&self) -> bool {
+        self.in_transaction
+    }
+
+    pub fn begin(&mut self) -> io::Result<()> {
+        if self.in_transaction {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Cannot start a transaction within a transaction",
+            ));
+        }
+
+        self.in_transaction = true;
+        Ok(())
+    }
+
+    // In `AutoVacuum::Full` mode, free pages are given back before committing
+    pub fn commit(&mut self) -> io::Result<()> {
+        if self.in_transaction && self.pager.auto_vacuum() == AutoVacuum::Full {
+            vacuum::incremental_vacuum(&mut self.pager, u32::MAX)?;
+        }
+        self.end_transaction()?;
+        self.pager.flush()
+    }
+
+    pub fn rollback(&mut self) -> io::Result<()> {
+        self.end_transaction()?;
+        self.pager.rollback()
+    }
+
+    pub fn savepoint(&mut self, name: &str) -> io::Result<()> {
+        if !self.in_transaction {
+            self.begin()?;
+            self.savepoint_transaction = true;
+        }
+
+        self.pager.savepoint(name);
+        Ok(())
+    }
+
+    // Releasing the outermost savepoint of a transaction it started commits it
+    pub fn release(&mut self, name: &str) -> io::Result<()> {
+        self.pager.release(name)?;
+
+        match self.savepoint_transaction && self.pager.savepoint_count() == 0 {
+            true => self.commit(),
+            false => Ok(()),
+        }
+    }
+
+    pub fn rollback_to(&mut self, name: &str) -> io::Result<()> {
+        self.pager.rollback_to(name)
+    }
+
+    // Runs `f` in its own transaction, committed if it succeeds and rolled back otherwise. Within a transaction that is
+    // already ongoing, `f` just becomes part of it
+    pub fn transaction<T, F>(&mut self, f: F) -> io::Result<T>
+    where
+        F: FnOnce(&mut Pager) -> io::Result<T>,
+    {
+        if self.in_transaction {
+            return f(&mut self.pager);
+        }
+
+        self.begin()?;
+        match f(&mut self.pager) {
+            Ok(value) => {
+                self.commit()?;
+                Ok(value)
+            }
+            Err(err) => {
+                self.rollback()?;
+                Err(err)
+            }
+        }
+    }
+
+    // Compiles a single statement against the current schema. The program fails to run once the schema changed
+    pub fn prepare(&mut self, sql: &str) -> io::Result<Program> {
+        let statement = sql::parse_statement(sql)?;
+        sql::compile(&mut self.pager, sql, &statement)
+    }
+
+    // Runs the statements of `sql` one after the other, and returns the rows of all of them
+    pub fn execute(&mut self, sql: &str, parameters: &[Value]) -> io::Result<Vec<Vec<Value>>> {
+        let mut rows = vec![];
+        for statement in sql::parse(sql)? {
+            let program = sql::compile(&mut self.pager, sql, &statement)?;
+            rows.extend(self.run(&program, parameters)?);
+        }
+        Ok(rows)
+    }
+
+    // Runs `program` to completion, and returns its rows. `Vm` runs programs a step at a time instead
+    pub fn run(&mut self, program: &Program, parameters: &[Value]) -> io::Result<Vec<Vec<Value>>> {
+        self.transaction(|pager| {
+            pager.savepoint(STATEMENT_SAVEPOINT);
+            let result = Vm::new(program, parameters).run(pager);
+            if result.is_err() {
+                pager.rollback_to(STATEMENT_SAVEPOINT)?;
+            }
+            pager.release(STATEMENT_SAVEPOINT)?;
+            result
+        })
+    }
+
+    pub fn journal_mode(&self) -> JournalMode {
+        self.pager.journal_mode()
+    }
+
+    // Switching commits, so it is refused in the middle of a transaction
+    pub fn set_journal_mode(&mut self, mode: JournalMode) -> io::Result<()> {
+        self.ensure_no_transaction()?;
+        self.pager.set_journal_mode(mode)
+    }
+
+    pub fn checkpoint(&mut self, mode: CheckpointMode) -> io::Result<CheckpointResult> {
+        self.ensure_no_transaction()?;
+        self.pager.checkpoint(mode)
+    }
+
+    // Rebuilds the database into as few pages as possible, optionally changing its page size. `roots` lists the trees
+    // that are not in the catalog, see `vacuum`
+    pub fn vacuum(&mut self, roots: &[PageNumber], page_size: Option<u32>) -> io::Result<()> {
+        self.ensure_no_transaction()?;
+        vacuum::vacuum(&mut self.pager, roots, page_size)
+    }
+
+    pub fn catalog(&mut self) -> io::Result<&Catalog> {
+        self.pager.catalog()
+    }
+
+    pub fn auto_vacuum(&self) -> AutoVacuum {
+        self.pager.auto_vacuum()
+    }
+
+    // Auto-vacuum can only be turned on or off while the database is empty, see `Pager::set_auto_vacuum`
+    pub fn set_auto_vacuum(&mut self, mode: AutoVacuum) -> io::Result<()> {
+        self.transaction(|pager| pager.set_auto_vacuum(mode))
+    }
+
+    // Gives up to `pages` free pages back to the file system, see `vacuum::incremental_vacuum`
+    pub fn incremental_vacuum(&mut self, pages: u32) -> io::Result<u32> {
+        self.transaction(|pager| vacuum::incremental_vacuum(pager, pages))
+    }
+
+    fn end_transaction(&mut self) -> io::Result<()> {
+        if !self.in_transaction {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "No transaction is active",
+            ));
+        }
+
+        self.in_transaction = false;
+        self.savepoint_transaction = false;
+        Ok(())
+    }
+
+    fn ensure_no_transaction(&self) -> io::Result<()> {
+        match self.in_transaction {
+            true => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Cannot be done within a transaction",
+            )),
+            false => Ok(()),
+        }
+    }
+}
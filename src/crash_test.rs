@@ -0,0 +1,210 @@
+//! Crash-consistency workload. Replays random transactions against a database stored on a `FaultVfs`, injecting the
+//! faults picked for each step, and simulates a power loss whenever an operation fails as well as at random points in
+//! between. After each power loss the database is reopened and must hold exactly the rows of the last committed
+//! transaction, or of the transaction being committed when the failure happened. Its structure must be sound as well,
+//! see `integrity`, and the heap file as long as its page count says once the log is checkpointed
+//! The seed picks the journal mode and whether the database uses full auto-vacuum, so that commits also move pages
+//! around and shrink the file
+//! Shared by the `crash_test` binary and the `crash` integration tests, behind the `fault-injection` feature
+
+use std::{collections::BTreeMap, io, path::Path, sync::Arc};
+
+use crate::{
+    btree::{BTree, RowId, TreeKind},
+    connection::Connection,
+    integrity,
+    pager::{AutoVacuum, JournalMode, PageNumber},
+    vfs::{FaultConfig, FaultVfs, MemoryVfs, Rng, Vfs},
+    wal::CheckpointMode,
+};
+
+const PATH: &str = "crash.phdb";
+const PAGE_SIZE: u32 = 512;
+// Small enough for dirty pages to be written back in the middle of transactions
+const CACHE_SIZE: usize = 8;
+const STEPS: usize = 40;
+const MAX_OPERATIONS: u64 = 16;
+const MAX_ROWID: u64 = 400;
+// Larger than a page, so that overflow chains are exercised too
+const MAX_RECORD_SIZE: u64 = 1200;
+
+type Rows = BTreeMap<RowId, Vec<u8>>;
+
+// Faults of the long randomized runs: any of them may hit any step
+pub fn random_faults(rng: &mut Rng) -> FaultConfig {
+    FaultConfig {
+        drop_unsynced: true,
+        tear_writes: true,
+        fail_sync: rng.below(10) == 0,
+        fail_after: match rng.below(3) {
+            0 => Some(rng.below(200)),
+            _ => None,
+        },
+    }
+}
+
+// Runs the workload, with `faults` picking the faults injected during each step
+pub fn run(seed: u64, faults: impl Fn(&mut Rng) -> FaultConfig) -> Result<(), String> {
+    let mut rng = Rng::new(seed);
+    let vfs = FaultVfs::new(Arc::new(MemoryVfs::new()), seed);
+    let mode = match seed % 2 {
+        0 => JournalMode::Rollback,
+        _ => JournalMode::Wal,
+    };
+
+    let mut connection = open(&vfs).map_err(|err| format!("Cannot create the database: {err}"))?;
+    connection
+        .set_journal_mode(mode)
+        .map_err(|err| format!("Cannot set the journal mode: {err}"))?;
+    if seed % 4 >= 2 {
+        connection
+            .set_auto_vacuum(AutoVacuum::Full)
+            .map_err(|err| format!("Cannot enable auto-vacuum: {err}"))?;
+    }
+    let root = connection
+        .transaction(|pager| BTree::create(pager, TreeKind::Table))
+        .map_err(|err| format!("Cannot create the table: {err}"))?
+        .root_page();
+
+    let mut committed = Rows::new();
+    for step in 0..STEPS {
+        vfs.set_config(faults(&mut rng));
+
+        let mut pending = committed.clone();
+        let mut committing = false;
+        let result = transaction(
+            &mut connection,
+            root,
+            &mut rng,
+            &mut pending,
+            &mut committing,
+        )
+        .and_then(
+            |committed| match mode == JournalMode::Wal && rng.below(4) == 0 {
+                true => connection
+                    .checkpoint(CheckpointMode::Truncate)
+                    .map(|_| committed),
+                false => Ok(committed),
+            },
+        );
+
+        let candidates = match result {
+            Ok(true) => {
+                committed = pending;
+                if rng.below(4) != 0 {
+                    continue;
+                }
+                vec![committed.clone()]
+            }
+            Ok(false) if rng.below(4) != 0 => continue,
+            Ok(false) => vec![committed.clone()],
+            // The failure may have hit the commit after its commit point
+            Err(_) if committing => vec![committed.clone(), pending],
+            Err(_) => vec![committed.clone()],
+        };
+
+        drop(connection);
+        // The power loss still drops or tears writes as configured, but recovering must not fail
+        vfs.set_config(FaultConfig {
+            fail_sync: false,
+            fail_after: None,
+            ..vfs.config()
+        });
+        vfs.crash()
+            .map_err(|err| format!("step {step}: cannot crash: {err}"))?;
+
+        connection = open(&vfs).map_err(|err| format!("step {step}: cannot recover: {err}"))?;
+        check_structure(&vfs, &mut connection, root)
+            .map_err(|err| format!("step {step}: inconsistent after recovery: {err}"))?;
+        let rows = read_rows(&mut connection, root)
+            .map_err(|err| format!("step {step}: cannot read after recovery: {err}"))?;
+        committed = candidates
+            .into_iter()
+            .find(|candidate| *candidate == rows)
+            .ok_or_else(|| {
+                format!(
+                    "step {step}: {} rows after recovery do not match any committed state",
+                    rows.len()
+                )
+            })?;
+    }
+
+    Ok(())
+}
+
+fn open(vfs: &FaultVfs) -> io::Result<Connection> {
+    Connection::open_with_vfs(Arc::new(vfs.clone()), PATH, PAGE_SIZE, CACHE_SIZE)
+}
+
+fn check_structure(
+    vfs: &FaultVfs,
+    connection: &mut Connection,
+    root: PageNumber,
+) -> io::Result<()> {
+    integrity::check(connection.pager_mut(), &[root])?;
+
+    // Pages committed to the log are not in the heap file yet
+    if connection.journal_mode() == JournalMode::Wal {
+        connection.checkpoint(CheckpointMode::Truncate)?;
+    }
+    let pager = connection.pager();
+    let len = vfs.open(Path::new(PATH))?.size()?;
+    match len == pager.page_count() as u64 * pager.page_size() as u64 {
+        true => Ok(()),
+        false => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "The heap file holds {len} bytes for {} pages",
+                pager.page_count()
+            ),
+        )),
+    }
+}
+
+// Runs a random transaction, applying it to `rows` as well. Returns whether it was committed rather than rolled back
+fn transaction(
+    connection: &mut Connection,
+    root: u32,
+    rng: &mut Rng,
+    rows: &mut Rows,
+    committing: &mut bool,
+) -> io::Result<bool> {
+    connection.begin()?;
+    let tree = BTree::open(connection.pager_mut(), root)?;
+
+    for _ in 0..1 + rng.below(MAX_OPERATIONS) {
+        let rowid = rng.below(MAX_ROWID) as RowId;
+        match rng.below(3) {
+            0 => {
+                tree.delete(connection.pager_mut(), rowid)?;
+                rows.remove(&rowid);
+            }
+            _ => {
+                let record = record(rng);
+                tree.insert(connection.pager_mut(), rowid, &record)?;
+                rows.insert(rowid, record);
+            }
+        }
+    }
+
+    if rng.below(5) == 0 {
+        connection.rollback()?;
+        return Ok(false);
+    }
+
+    *committing = true;
+    connection.commit()?;
+    Ok(true)
+}
+
+fn record(rng: &mut Rng) -> Vec<u8> {
+    let len = rng.below(MAX_RECORD_SIZE) as usize;
+    let fill = rng.next_u64() as u8;
+    (0..len).map(|i| fill.wrapping_add(i as u8)).collect()
+}
+
+fn read_rows(connection: &mut Connection, root: u32) -> io::Result<Rows> {
+    let pager = connection.pager_mut();
+    let tree = BTree::open(pager, root)?;
+    tree.scan(pager, ..)?.collect()
+}
@@ -0,0 +1,233 @@
+//! The database header, stored at the beginning of page 0. All fields are little-endian:
+//! | offset | size | field                                                                 |
+//! |      0 |    4 | magic number                                                          |
+//! |      4 |    2 | page size, a power of two from 512 to 65536, which is stored as 1     |
+//! |      6 |    1 | write version, files with a newer one can only be read               |
+//! |      7 |    1 | read version, files with a newer one cannot be opened at all          |
+//! |      8 |    1 | reserved bytes at the end of each page                                |
+//! |      9 |    1 | journal mode                                                          |
+//! |     10 |    1 | text encoding                                                         |
+//! |     11 |    1 | auto-vacuum mode                                                      |
+//! |     12 |    4 | page count                                                            |
+//! |     16 |    4 | first free-list trunk page                                            |
+//! |     20 |    4 | free page count                                                       |
+//! |     24 |    4 | schema cookie, bumped whenever the schema changes                     |
+//! |     28 |    4 | change counter, bumped by every transaction                           |
+//! |     32 |    4 | application id                                                        |
+//! |     36 |    4 | user version                                                          |
+//! |     40 |    8 | creation time, in seconds since the Unix epoch                        |
+//! |     48 |    4 | version of the library that created the file                          |
+//! |     52 |   48 | unused, zeroed so that future versions can give them a meaning        |
+//! A change that older versions can read but would break by writing bumps the write version, a change that they
+//! cannot even read bumps the read version
+
+use std::{
+    io,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::pager::PageNumber;
+
+pub const MAGIC_NUMBER: u32 = 0x50484442;
+pub const HEADER_SIZE: usize = 100;
+pub const FORMAT_WRITE_VERSION: u8 = 2;
+// Auto-vacuum databases keep pointer maps up to date, which older versions would not do
+pub const AUTO_VACUUM_WRITE_VERSION: u8 = 2;
+pub const FORMAT_READ_VERSION: u8 = 1;
+pub const TEXT_ENCODING_UTF8: u8 = 1;
+pub const MIN_PAGE_SIZE: u32 = 512;
+pub const MAX_PAGE_SIZE: u32 = 65536;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DbHeader {
+    pub magic: u32,
+    pub page_size: u32,
+    pub write_version: u8,
+    pub read_version: u8,
+    // Bytes at the end of each page that are not usable by the layers above the pager, holding the page checksum
+    pub reserved_size: u8,
+    pub journal_mode: u8,
+    pub text_encoding: u8,
+    pub auto_vacuum: u8,
+    pub page_count: u32,
+    // First trunk page of the free-list, 0 when the free-list is empty
+    pub freelist_head: PageNumber,
+    pub freelist_count: u32,
+    pub schema_cookie: u32,
+    pub change_counter: u32,
+    // Free for applications to tell their files apart from other phdb databases
+    pub application_id: u32,
+    // Free for applications to track the version of their own schema
+    pub user_version: u32,
+    pub created_at: u64,
+    pub created_by: u32,
+}
+
+impl DbHeader {
+    pub fn to_buf(&self) -> [u8; HEADER_SIZE] {
+        let mut buf = [0_u8; HEADER_SIZE];
+        let mut offset = 0;
+
+        put(&mut buf, &mut offset, &self.magic.to_le_bytes());
+        // 65536 does not fit in the field
+        let page_size = match self.page_size {
+            MAX_PAGE_SIZE => 1,
+            page_size => page_size as u16,
+        };
+        put(&mut buf, &mut offset, &page_size.to_le_bytes());
+        put(&mut buf, &mut offset, &[self.write_version]);
+        put(&mut buf, &mut offset, &[self.read_version]);
+        put(&mut buf, &mut offset, &[self.reserved_size]);
+        put(&mut buf, &mut offset, &[self.journal_mode]);
+        put(&mut buf, &mut offset, &[self.text_encoding]);
+        put(&mut buf, &mut offset, &[self.auto_vacuum]);
+        put(&mut buf, &mut offset, &self.page_count.to_le_bytes());
+        put(&mut buf, &mut offset, &self.freelist_head.to_le_bytes());
+        put(&mut buf, &mut offset, &self.freelist_count.to_le_bytes());
+        put(&mut buf, &mut offset, &self.schema_cookie.to_le_bytes());
+        put(&mut buf, &mut offset, &self.change_counter.to_le_bytes());
+        put(&mut buf, &mut offset, &self.application_id.to_le_bytes());
+        put(&mut buf, &mut offset, &self.user_version.to_le_bytes());
+        put(&mut buf, &mut offset, &self.created_at.to_le_bytes());
+        put(&mut buf, &mut offset, &self.created_by.to_le_bytes());
+
+        buf
+    }
+
+    pub fn from(buf: &[u8]) -> Self {
+        let mut offset = 0;
+
+        let magic = u32::from_le_bytes(take(buf, &mut offset));
+        let page_size = match u16::from_le_bytes(take(buf, &mut offset)) {
+            1 => MAX_PAGE_SIZE,
+            page_size => page_size as u32,
+        };
+        let [write_version] = take(buf, &mut offset);
+        let [read_version] = take(buf, &mut offset);
+        let [reserved_size] = take(buf, &mut offset);
+        let [journal_mode] = take(buf, &mut offset);
+        let [text_encoding] = take(buf, &mut offset);
+        let [auto_vacuum] = take(buf, &mut offset);
+        let page_count = u32::from_le_bytes(take(buf, &mut offset));
+        let freelist_head = u32::from_le_bytes(take(buf, &mut offset));
+        let freelist_count = u32::from_le_bytes(take(buf, &mut offset));
+        let schema_cookie = u32::from_le_bytes(take(buf, &mut offset));
+        let change_counter = u32::from_le_bytes(take(buf, &mut offset));
+        let application_id = u32::from_le_bytes(take(buf, &mut offset));
+        let user_version = u32::from_le_bytes(take(buf, &mut offset));
+        let created_at = u64::from_le_bytes(take(buf, &mut offset));
+        let created_by = u32::from_le_bytes(take(buf, &mut offset));
+
+        Self {
+            magic,
+            page_size,
+            write_version,
+            read_version,
+            reserved_size,
+            journal_mode,
+            text_encoding,
+            auto_vacuum,
+            page_count,
+            freelist_head,
+            freelist_count,
+            schema_cookie,
+            change_counter,
+            application_id,
+            user_version,
+            created_at,
+            created_by,
+        }
+    }
+
+    // Header of a database being created, of which `journal_mode`, `reserved_size` and `auto_vacuum` are left to the
+    // caller. Files only get a newer write version once they use the features requiring it
+    pub fn alloc(page_size: u32) -> Self {
+        Self {
+            magic: MAGIC_NUMBER,
+            page_size,
+            write_version: 1,
+            read_version: FORMAT_READ_VERSION,
+            reserved_size: 0,
+            journal_mode: 0,
+            text_encoding: TEXT_ENCODING_UTF8,
+            auto_vacuum: 0,
+            // The header page itself
+            page_count: 1,
+            freelist_head: 0,
+            freelist_count: 0,
+            schema_cookie: 0,
+            change_counter: 0,
+            application_id: 0,
+            user_version: 0,
+            created_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_secs()),
+            created_by: library_version(),
+        }
+    }
+
+    // Checks that a header read from disk can be handled by this version of the library. Returns whether the file can
+    // be written to as well
+    pub fn check_compatibility(&self) -> io::Result<bool> {
+        if self.magic != MAGIC_NUMBER {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Not a phdb database",
+            ));
+        }
+        // The first format stopped after the free-list fields, every file of the current one records its creator
+        if self.created_by == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Database created by an older phdb format, which this version cannot open",
+            ));
+        }
+        // Everything above the pager sizes its buffers after the page size, a bogus one must not get that far
+        if !is_valid_page_size(self.page_size) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid page size {}", self.page_size),
+            ));
+        }
+        if self.read_version > FORMAT_READ_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!(
+                    "Database written in format {}, which cannot be read by this version",
+                    self.read_version
+                ),
+            ));
+        }
+        if self.text_encoding != TEXT_ENCODING_UTF8 {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("Unsupported text encoding {}", self.text_encoding),
+            ));
+        }
+
+        Ok(self.write_version <= FORMAT_WRITE_VERSION)
+    }
+}
+
+pub fn is_valid_page_size(page_size: u32) -> bool {
+    page_size.is_power_of_two() && (MIN_PAGE_SIZE..=MAX_PAGE_SIZE).contains(&page_size)
+}
+
+// Version of this library as `major * 1_000_000 + minor * 1_000 + patch`
+pub fn library_version() -> u32 {
+    let part = |part: &str| part.parse::<u32>().unwrap_or(0);
+    part(env!("CARGO_PKG_VERSION_MAJOR")) * 1_000_000
+        + part(env!("CARGO_PKG_VERSION_MINOR")) * 1_000
+        + part(env!("CARGO_PKG_VERSION_PATCH"))
+}
+
+fn put(buf: &mut [u8], offset: &mut usize, bytes: &[u8]) {
+    buf[*offset..*offset + bytes.len()].copy_from_slice(bytes);
+    *offset += bytes.len();
+}
+
+fn take<const N: usize>(buf: &[u8], offset: &mut usize) -> [u8; N] {
+    let bytes = buf[*offset..*offset + N].try_into().expect("Invalid size");
+    *offset += N;
+    bytes
+}
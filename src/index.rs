@@ -0,0 +1,290 @@
+//! Secondary indexes, stored as B+trees whose pages are flagged as index pages
+//! The key of an index entry is made of the indexed column values of a row followed by its rowid, which makes every
+//! entry unique even when the index is not, and lets the row be found back in its table. Values are encoded so that
+//! keys compare with memcmp, see `key`, and the rowid is stored like table keys are. A key made of the first values
+//! only is a prefix of the full keys, which compare equal to it, so that it can be used to search a composite index
+//! The encoding of the keys cannot be decoded back, so the values are also stored as a record in the entry payload
+
+use std::{cmp::Ordering, io, mem, ops::Bound};
+
+use crate::{
+    btree::{self, BTree, Cursor, RowId, TreeKind},
+    key::{self, SortOrder},
+    pager::{PageNumber, Pager},
+    record,
+    value::Value,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Forward,
+    Backward,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Index {
+    tree: BTree,
+    unique: bool,
+    // Sort order of each column, missing ones being ascending
+    orders: Vec<SortOrder>,
+}
+
+impl Index {
+    pub fn create(pager: &mut Pager, unique: bool) -> io::Result<Self> {
+        Self::create_with(pager, unique, &[])
+    }
+
+    pub fn create_with(pager: &mut Pager, unique: bool, orders: &[SortOrder]) -> io::Result<Self> {
+        Ok(Self {
+            tree: BTree::create(pager, TreeKind::Index)?,
+            unique,
+            orders: orders.to_vec(),
+        })
+    }
+
+    pub fn open(pager: &mut Pager, root: PageNumber, unique: bool) -> io::Result<Self> {
+        Self::open_with(pager, root, unique, &[])
+    }
+
+    // The sort orders are not stored in the index, they must be the ones it was created with
+    pub fn open_with(
+        pager: &mut Pager,
+        root: PageNumber,
+        unique: bool,
+        orders: &[SortOrder],
+    ) -> io::Result<Self> {
+        let tree = BTree::open(pager, root)?;
+        if tree.kind() != TreeKind::Index {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Page {root} is not the root of an index"),
+            ));
+        }
+
+        Ok(Self {
+            tree,
+            unique,
+            orders: orders.to_vec(),
+        })
+    }
+
+    pub fn root_page(&self) -> PageNumber {
+        self.tree.root_page()
+    }
+
+    pub fn is_unique(&self) -> bool {
+        self.unique
+    }
+
+    pub fn orders(&self) -> &[SortOrder] {
+        &self.orders
+    }
+
+    // Adds the entry of a row. Unique indexes refuse a row whose values are already indexed for another row, unless one
+    // of them is NULL as NULLs never equal each other
+    pub fn insert(&self, pager: &mut Pager, values: &[Value], rowid: RowId) -> io::Result<()> {
+        let key = self.entry_key(values, rowid);
+        let payload = record::encode(values);
+        if key.len() + payload.len() > BTree::max_key_size(pager) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Index entry of {} bytes is too large",
+                    key.len() + payload.len()
+                ),
+            ));
+        }
+
+        if self.unique && !values.iter().any(Value::is_null) {
+            if let Some(other) = self
+                .lookup(pager, values)?
+                .into_iter()
+                .find(|&other| other != rowid)
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("UNIQUE constraint failed: values already indexed for row {other}"),
+                ));
+            }
+        }
+
+        self.tree.insert_entry(pager, &key, &payload)
+    }
+
+    // Removes the entry of a row. Returns whether it was found
+    pub fn delete(&self, pager: &mut Pager, values: &[Value], rowid: RowId) -> io::Result<bool> {
+        self.tree
+            .delete_entry(pager, &self.entry_key(values, rowid))
+    }
+
+    // Moves the entry of a row whose indexed values changed from `old` to `new`
+    pub fn update(
+        &self,
+        pager: &mut Pager,
+        old: &[Value],
+        new: &[Value],
+        rowid: RowId,
+    ) -> io::Result<()> {
+        if old.len() == new.len()
+            && old
+                .iter()
+                .zip(new)
+                .all(|(a, b)| a.compare(b) == Ordering::Equal)
+        {
+            return Ok(());
+        }
+
+        self.insert(pager, new, rowid)?;
+        self.delete(pager, old, rowid)?;
+        Ok(())
+    }
+
+    // Rowids of the entries whose first columns equal `values`
+    pub fn lookup(&self, pager: &mut Pager, values: &[Value]) -> io::Result<Vec<RowId>> {
+        self.scan(
+            pager,
+            Bound::Included(values),
+            Bound::Included(values),
+            Direction::Forward,
+        )?
+        .map(|entry| entry.map(|(_, rowid)| rowid))
+        .collect()
+    }
+
+    // Iterates over the entries between `lower` and `upper`. Bounds can hold fewer values than the index has columns,
+    // in which case only the leading columns of the entries are compared to them. Bounds follow the order of the
+    // index, so the lower bound of a descending column is its greatest value
+    pub fn scan<'a>(
+        &self,
+        pager: &'a mut Pager,
+        lower: Bound<&[Value]>,
+        upper: Bound<&[Value]>,
+        direction: Direction,
+    ) -> io::Result<IndexScan<'a>> {
+        let lower = lower.map(|values| key::encode(values, &self.orders));
+        let upper = upper.map(|values| key::encode(values, &self.orders));
+        let mut cursor = self.tree.cursor();
+
+        match direction {
+            Direction::Forward => match &lower {
+                Bound::Included(key) => cursor.seek(pager, key)?,
+                Bound::Excluded(key) => cursor.seek_after(pager, key)?,
+                Bound::Unbounded => cursor.first(pager)?,
+            },
+            // Going backwards starts right before the first entry past the upper bound
+            Direction::Backward => {
+                let past_end = match &upper {
+                    Bound::Included(key) => cursor.seek_after(pager, key)?,
+                    Bound::Excluded(key) => cursor.seek(pager, key)?,
+                    Bound::Unbounded => false,
+                };
+                match past_end {
+                    true => cursor.prev(pager)?,
+                    false => cursor.last(pager)?,
+                }
+            }
+        };
+
+        let end = match direction {
+            Direction::Forward => upper,
+            Direction::Backward => lower,
+        };
+
+        Ok(IndexScan {
+            pager,
+            done: !cursor.is_valid(),
+            cursor,
+            direction,
+            end,
+        })
+    }
+
+    pub fn destroy(self, pager: &mut Pager) -> io::Result<()> {
+        self.tree.destroy(pager)
+    }
+
+    fn entry_key(&self, values: &[Value], rowid: RowId) -> Vec<u8> {
+        let mut key = key::encode(values, &self.orders);
+        key.extend_from_slice(&btree::rowid_key(rowid));
+        key
+    }
+}
+
+pub struct IndexScan<'a> {
+    pager: &'a mut Pager,
+    cursor: Cursor,
+    done: bool,
+    direction: Direction,
+    // Bound at which the scan stops, the upper one when going forward
+    end: Bound<Vec<u8>>,
+}
+
+impl Iterator for IndexScan<'_> {
+    type Item = io::Result<(Vec<Value>, RowId)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let key = self.cursor.key();
+        let in_range = match (&self.end, self.direction) {
+            (Bound::Unbounded, _) => true,
+            (Bound::Included(end), Direction::Forward) => {
+                compare_keys(key, end) != Ordering::Greater
+            }
+            (Bound::Excluded(end), Direction::Forward) => compare_keys(key, end) == Ordering::Less,
+            (Bound::Included(end), Direction::Backward) => compare_keys(key, end) != Ordering::Less,
+            (Bound::Excluded(end), Direction::Backward) => {
+                compare_keys(key, end) == Ordering::Greater
+            }
+        };
+        if !in_range {
+            self.done = true;
+            return None;
+        }
+
+        let Some(rowid) = key
+            .len()
+            .checked_sub(mem::size_of::<RowId>())
+            .map(|start| btree::key_rowid(&key[start..]))
+        else {
+            self.done = true;
+            return Some(Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Index entry without rowid",
+            )));
+        };
+        let values = match self
+            .cursor
+            .read_payload(self.pager)
+            .and_then(|payload| record::decode(&payload))
+        {
+            Ok(values) => values,
+            Err(err) => {
+                self.done = true;
+                return Some(Err(err));
+            }
+        };
+
+        let moved = match self.direction {
+            Direction::Forward => self.cursor.next(self.pager),
+            Direction::Backward => self.cursor.prev(self.pager),
+        };
+        match moved {
+            Ok(more) => self.done = !more,
+            Err(err) => {
+                self.done = true;
+                return Some(Err(err));
+            }
+        }
+
+        Some(Ok((values, rowid)))
+    }
+}
+
+// Keys compare byte-wise, up to the length of the shorter one
+pub(crate) fn compare_keys(a: &[u8], b: &[u8]) -> Ordering {
+    let len = a.len().min(b.len());
+    a[..len].cmp(&b[..len])
+}
@@ -0,0 +1,78 @@
+//! Integrity check of the structure of the heap file: every B-tree must be well formed, see `BTree::check`, and every
+//! page must be used exactly once, by the header, a tree, the free-list or the pointer map. The free-list must hold as
+//! many pages as the header says, and in auto-vacuum databases the pointer map must record the actual parent of each
+//! page
+//! Like for `vacuum`, the trees are those of the catalog along with the ones the caller lists
+
+use std::io;
+
+use crate::{
+    btree::BTree,
+    catalog::CATALOG_ROOT,
+    pager::{PageNumber, Pager},
+    pointer_map::PointerMapEntry,
+};
+
+// Fails with `InvalidData` describing the first problem found
+pub fn check(pager: &mut Pager, roots: &[PageNumber]) -> io::Result<()> {
+    let corrupted = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    let page_count = pager.page_count();
+
+    let mut roots: Vec<PageNumber> = pager
+        .catalog()?
+        .roots()
+        .chain(roots.iter().copied())
+        .collect();
+    roots.sort_unstable();
+    roots.dedup();
+
+    // The pages of the trees and the free-list, with the pointer-map entry they should have
+    let free_pages = pager.free_pages()?;
+    if free_pages.len() != pager.freelist_count() as usize {
+        return Err(corrupted(format!(
+            "The free-list holds {} pages instead of {}",
+            free_pages.len(),
+            pager.freelist_count()
+        )));
+    }
+    let mut pages: Vec<(PageNumber, PointerMapEntry)> = free_pages
+        .into_iter()
+        .map(|page_no| (page_no, PointerMapEntry::Free))
+        .collect();
+    for root in roots {
+        if root == 0 || root >= page_count {
+            return Err(corrupted(format!("Invalid root page {root}")));
+        }
+        pages.extend(BTree::open(pager, root)?.check(pager)?);
+    }
+
+    // Page 0 holds the header
+    let mut used = vec![false; page_count as usize];
+    used[0] = true;
+    for page_no in 0..page_count {
+        used[page_no as usize] |= pager.is_pointer_map_page(page_no);
+    }
+    for (page_no, entry) in pages {
+        if used[page_no as usize] {
+            return Err(corrupted(format!("Page {page_no} is used twice")));
+        }
+        used[page_no as usize] = true;
+
+        // The root of the catalog has no entry, as it never moves
+        if pager.has_pointer_map() && page_no != CATALOG_ROOT {
+            let recorded = pager.pointer_map_entry(page_no)?;
+            if recorded != entry {
+                return Err(corrupted(format!(
+                    "The pointer map records {recorded:?} for page {page_no} instead of {entry:?}"
+                )));
+            }
+        }
+    }
+
+    match used.iter().position(|&used| !used) {
+        Some(page_no) => Err(corrupted(format!(
+            "Page {page_no} is neither in a tree nor free"
+        ))),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,188 @@
+//! Rollback journal. In rollback mode, the original content of a page is copied to a `<db>-journal` file next to the
+//! heap file, and synced, before the page is overwritten for the first time in a transaction. Once every page of the
+//! transaction has reached the heap file and has been synced, deleting the journal commits it
+//! A journal still present when the database is opened is *hot*: the transaction that created it never committed, and
+//! copying its pages back restores the database as it was before that transaction
+//! The journal starts with a header followed by records, each holding one page:
+//! header: | magic (4) | version (4) | page size (4) | db size (4) | nonce (4) | checksum (4) |
+//! record: | page number (4) | page data | checksum (4) |
+//! Records are checksummed with the nonce of their journal, so that a record torn by a crash is told apart from a
+//! complete one. Such a record is never needed: the page it was saving had not been overwritten yet
+
+use std::{
+    collections::{hash_map::RandomState, HashSet},
+    hash::{BuildHasher, Hasher},
+    io, mem,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{
+    pager::PageNumber,
+    vfs::{AlignedBuf, Vfs, VfsFile},
+    wal::checksum,
+};
+
+pub const JOURNAL_MAGIC_NUMBER: u32 = 0x5048444a;
+pub const JOURNAL_VERSION: u32 = 1;
+pub const JOURNAL_HEADER_SIZE: usize = 24;
+const RECORD_OVERHEAD: usize = 2 * mem::size_of::<u32>();
+
+#[derive(Debug)]
+pub struct Journal {
+    vfs: Arc<dyn Vfs>,
+    file: Box<dyn VfsFile>,
+    path: PathBuf,
+    page_size: usize,
+    // Page count of the database when the transaction started. Pages past it did not exist and need no saving
+    db_size: u32,
+    nonce: u32,
+    pages: HashSet<PageNumber>,
+}
+
+impl Journal {
+    // Creates the journal of a transaction starting on a database of `db_size` pages. The header is synced before
+    // returning, so that records appended later are recognized as belonging to this journal
+    pub fn create(
+        vfs: Arc<dyn Vfs>,
+        path: &Path,
+        page_size: usize,
+        db_size: u32,
+    ) -> io::Result<Self> {
+        let file = vfs.open(path)?;
+        file.truncate(0)?;
+        let nonce = RandomState::new().build_hasher().finish() as u32;
+
+        let mut header = [0_u8; JOURNAL_HEADER_SIZE];
+        header[0..4].copy_from_slice(&JOURNAL_MAGIC_NUMBER.to_le_bytes());
+        header[4..8].copy_from_slice(&JOURNAL_VERSION.to_le_bytes());
+        header[8..12].copy_from_slice(&(page_size as u32).to_le_bytes());
+        header[12..16].copy_from_slice(&db_size.to_le_bytes());
+        header[16..20].copy_from_slice(&nonce.to_le_bytes());
+        let [sum, _] = checksum(&header[..16], [nonce, 0]);
+        header[20..24].copy_from_slice(&sum.to_le_bytes());
+
+        file.write_all_at(&header, 0)?;
+        file.sync()?;
+
+        Ok(Self {
+            vfs,
+            file,
+            path: path.to_path_buf(),
+            page_size,
+            db_size,
+            nonce,
+            pages: HashSet::new(),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn db_size(&self) -> u32 {
+        self.db_size
+    }
+
+    // Whether the original content of `page_no` has to be saved before it is overwritten
+    pub fn needs(&self, page_no: PageNumber) -> bool {
+        page_no < self.db_size && !self.pages.contains(&page_no)
+    }
+
+    // Saves `data` as the original content of `page_no`. It is only safe to overwrite the page once the journal has
+    // been synced
+    pub fn append(&mut self, page_no: PageNumber, data: &[u8]) -> io::Result<()> {
+        let offset = record_offset(self.pages.len() as u64, self.page_size);
+        let [sum, _] = checksum(data, [self.nonce, page_no]);
+
+        self.file.write_all_at(&page_no.to_le_bytes(), offset)?;
+        self.file.write_all_at(data, offset + 4)?;
+        self.file
+            .write_all_at(&sum.to_le_bytes(), offset + 4 + self.page_size as u64)?;
+        self.pages.insert(page_no);
+
+        Ok(())
+    }
+
+    pub fn sync(&self) -> io::Result<()> {
+        self.file.sync()
+    }
+
+    // Commits the transaction. The heap file must have been synced beforehand. The removal is durable, otherwise the
+    // journal could come back after a crash and roll back a committed transaction
+    pub fn delete(self) -> io::Result<()> {
+        self.vfs.delete(&self.path)
+    }
+
+    // Aborts the transaction, copying the saved pages back to `db`
+    pub fn rollback(self, db: &dyn VfsFile) -> io::Result<()> {
+        drop(self.file);
+        rollback_hot_journal(&*self.vfs, &self.path, db).map(|_| ())
+    }
+}
+
+// Restores `db` from the hot journal at `path`, if there is one, and deletes it. Returns whether a rollback happened
+pub fn rollback_hot_journal(vfs: &dyn Vfs, path: &Path, db: &dyn VfsFile) -> io::Result<bool> {
+    if !vfs.exists(path)? {
+        return Ok(false);
+    }
+
+    let file = vfs.open(path)?;
+    let len = file.size()?;
+    let mut header = [0_u8; JOURNAL_HEADER_SIZE];
+    if len < JOURNAL_HEADER_SIZE as u64 {
+        // The crash happened before the header was synced, so the heap file was never touched
+        vfs.delete(path)?;
+        return Ok(false);
+    }
+    file.read_exact_at(&mut header, 0)?;
+
+    let word = |offset: usize| {
+        u32::from_le_bytes(header[offset..offset + 4].try_into().expect("Invalid size"))
+    };
+    let nonce = word(16);
+    if word(0) != JOURNAL_MAGIC_NUMBER || word(20) != checksum(&header[..16], [nonce, 0])[0] {
+        vfs.delete(path)?;
+        return Ok(false);
+    }
+    if word(4) != JOURNAL_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Unsupported journal version {}", word(4)),
+        ));
+    }
+    let page_size = word(8) as usize;
+    let db_size = word(12);
+
+    let mut page_no = [0_u8; 4];
+    let mut data = AlignedBuf::zeroed(page_size);
+    let mut sum = [0_u8; 4];
+    let mut record = 0;
+    loop {
+        let offset = record_offset(record, page_size);
+        if offset + (RECORD_OVERHEAD + page_size) as u64 > len {
+            break;
+        }
+        file.read_exact_at(&mut page_no, offset)?;
+        file.read_exact_at(&mut data, offset + 4)?;
+        file.read_exact_at(&mut sum, offset + 4 + page_size as u64)?;
+
+        let page_no = PageNumber::from_le_bytes(page_no);
+        if u32::from_le_bytes(sum) != checksum(&data, [nonce, page_no])[0] {
+            break;
+        }
+        db.write_all_at(&data, page_no as u64 * page_size as u64)?;
+        record += 1;
+    }
+
+    // Pages allocated by the transaction go away with it
+    db.truncate(db_size as u64 * page_size as u64)?;
+    db.sync()?;
+    vfs.delete(path)?;
+
+    Ok(true)
+}
+
+fn record_offset(record: u64, page_size: usize) -> u64 {
+    JOURNAL_HEADER_SIZE as u64 + record * (RECORD_OVERHEAD + page_size) as u64
+}
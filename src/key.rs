@@ -0,0 +1,218 @@
+//! Memcomparable encoding of index keys: a key is a byte string whose memcmp order is the order of the values it
+//! encodes, as `Value::compare` defines it, so that index pages are searched without decoding anything
+//! Each value is a tag giving the place of its type in the order, followed by its content:
+//! | NULL (1) | number (2) real (8) adjustment (2) | text (3) escaped bytes 0x00 0x01 | blob (4) same as text |
+//! Numbers are encoded as reals whose bits are made to sort as unsigned integers: the sign bit is flipped for positive
+//! numbers and every bit is flipped for negative ones. Integers past 2^53 lose precision along the way, which the
+//! adjustment makes up for: it is the difference between the integer and the real, a big-endian i16 with its sign bit
+//! flipped, and 0 for reals. Integers and reals thus compare by their numeric value
+//! In text and blobs, each 0x00 byte is escaped as 0x00 0xff and the terminator 0x00 0x01 makes a string sort before
+//! the longer strings it is a prefix of
+//! Every bit of the values of descending columns is inverted. No value is the prefix of another, so the key made of
+//! the first values of another key is a prefix of it
+
+use crate::value::{Value, ValueRef};
+
+const NULL_TAG: u8 = 1;
+const NUMBER_TAG: u8 = 2;
+const TEXT_TAG: u8 = 3;
+const BLOB_TAG: u8 = 4;
+const ESCAPE: u8 = 0x00;
+const ESCAPED_ZERO: u8 = 0xff;
+const TERMINATOR: u8 = 0x01;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SortOrder {
+    #[default]
+    Ascending,
+    Descending,
+}
+
+// Values past the end of `orders` are ascending
+pub fn encode(values: &[Value], orders: &[SortOrder]) -> Vec<u8> {
+    let mut key = vec![];
+    for (index, value) in values.iter().enumerate() {
+        let order = orders.get(index).copied().unwrap_or_default();
+        encode_value(&mut key, value.as_ref(), order);
+    }
+
+    key
+}
+
+pub fn encode_value(key: &mut Vec<u8>, value: ValueRef, order: SortOrder) {
+    let start = key.len();
+
+    match value {
+        ValueRef::Null => key.push(NULL_TAG),
+        ValueRef::Integer(integer) => {
+            let real = integer as f64;
+            // At most half of the gap between two consecutive reals, which is 2^11 right below 2^64
+            let adjustment = (integer as i128 - real as i128) as i16;
+            encode_number(key, real, adjustment);
+        }
+        ValueRef::Real(real) => encode_number(key, real, 0),
+        ValueRef::Text(text) => encode_bytes(key, TEXT_TAG, text),
+        ValueRef::Blob(blob) => encode_bytes(key, BLOB_TAG, blob),
+    }
+
+    if order == SortOrder::Descending {
+        for byte in &mut key[start..] {
+            *byte = !*byte;
+        }
+    }
+}
+
+fn encode_number(key: &mut Vec<u8>, real: f64, adjustment: i16) {
+    // NaN sorts before every other number, and -0 equals 0
+    let bits = match real {
+        real if real.is_nan() => 0,
+        0.0 => 1 << 63,
+        real if real.is_sign_negative() => !real.to_bits(),
+        real => real.to_bits() | 1 << 63,
+    };
+
+    key.push(NUMBER_TAG);
+    key.extend_from_slice(&bits.to_be_bytes());
+    key.extend_from_slice(&((adjustment as u16) ^ (1 << 15)).to_be_bytes());
+}
+
+fn encode_bytes(key: &mut Vec<u8>, tag: u8, bytes: &[u8]) {
+    key.reserve(bytes.len() + 3);
+    key.push(tag);
+    for &byte in bytes {
+        key.push(byte);
+        if byte == ESCAPE {
+            key.push(ESCAPED_ZERO);
+        }
+    }
+    key.extend_from_slice(&[ESCAPE, TERMINATOR]);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::slice;
+
+    use super::*;
+
+    const TWO_POW_53: i64 = 1 << 53;
+
+    // Values around the limits of integers, of reals, and of the integers that reals represent exactly
+    fn values() -> Vec<Value> {
+        let mut values = vec![
+            Value::Null,
+            Value::Text(String::new()),
+            Value::Text("a".into()),
+            Value::Text("a\0".into()),
+            Value::Text("ab".into()),
+            Value::Blob(vec![]),
+            Value::Blob(vec![0]),
+            Value::Blob(vec![0, 0]),
+            Value::Blob(vec![1]),
+        ];
+        for integer in [
+            i64::MIN,
+            i64::MIN + 1,
+            -TWO_POW_53 - 1,
+            -TWO_POW_53,
+            -TWO_POW_53 + 1,
+            -1,
+            0,
+            1,
+            TWO_POW_53 - 1,
+            TWO_POW_53,
+            TWO_POW_53 + 1,
+            i64::MAX - 1,
+            i64::MAX,
+        ] {
+            values.push(Value::Integer(integer));
+        }
+        for real in [
+            f64::NEG_INFINITY,
+            f64::MIN,
+            i64::MIN as f64,
+            -TWO_POW_53 as f64 - 2.0,
+            -TWO_POW_53 as f64,
+            -1.5,
+            -0.0,
+            0.0,
+            f64::MIN_POSITIVE,
+            0.5,
+            TWO_POW_53 as f64,
+            TWO_POW_53 as f64 + 2.0,
+            i64::MAX as f64,
+            f64::MAX,
+            f64::INFINITY,
+        ] {
+            values.push(Value::Real(real));
+        }
+        values
+    }
+
+    #[test]
+    fn byte_order_is_value_order() {
+        let values = values();
+        for a in &values {
+            for b in &values {
+                let expected = a.compare(b);
+                let ascending =
+                    encode(slice::from_ref(a), &[]).cmp(&encode(slice::from_ref(b), &[]));
+                assert_eq!(ascending, expected, "{a:?} and {b:?}");
+                let orders = [SortOrder::Descending];
+                let descending =
+                    encode(slice::from_ref(a), &orders).cmp(&encode(slice::from_ref(b), &orders));
+                assert_eq!(descending, expected.reverse(), "{a:?} and {b:?} descending");
+            }
+        }
+    }
+
+    #[test]
+    fn equal_numbers_encode_the_same() {
+        let key = |value: Value| encode(&[value], &[]);
+        assert_eq!(key(Value::Real(-0.0)), key(Value::Real(0.0)));
+        assert_eq!(key(Value::Integer(0)), key(Value::Real(-0.0)));
+        assert_eq!(
+            key(Value::Integer(TWO_POW_53)),
+            key(Value::Real(TWO_POW_53 as f64))
+        );
+        assert_eq!(
+            key(Value::Integer(-TWO_POW_53)),
+            key(Value::Real(-TWO_POW_53 as f64))
+        );
+        // The closest real is 2^63, one past the largest integer
+        assert_ne!(
+            key(Value::Integer(i64::MAX)),
+            key(Value::Real(i64::MAX as f64))
+        );
+        assert_eq!(
+            key(Value::Integer(i64::MIN)),
+            key(Value::Real(i64::MIN as f64))
+        );
+    }
+
+    // Columns compare one after the other, each in its own order, and a key sorts before the longer keys it is a
+    // prefix of
+    #[test]
+    fn composite_keys() {
+        let orders = [SortOrder::Ascending, SortOrder::Descending];
+        let values = values();
+        for a in values.iter().step_by(3) {
+            for b in values.iter().step_by(2) {
+                for c in &values {
+                    let first = encode(&[a.clone(), b.clone()], &orders);
+                    let second = encode(&[a.clone(), c.clone()], &orders);
+                    assert_eq!(
+                        first.cmp(&second),
+                        b.compare(c).reverse(),
+                        "{a:?}, {b:?} and {c:?}"
+                    );
+
+                    let second = encode(&[c.clone(), b.clone()], &orders);
+                    assert_eq!(first.cmp(&second), a.compare(c), "{a:?}, {b:?} and {c:?}");
+                }
+                let prefix = encode(slice::from_ref(a), &orders);
+                let key = encode(&[a.clone(), b.clone()], &orders);
+                assert!(key.starts_with(&prefix) && prefix < key);
+            }
+        }
+    }
+}
@@ -0,0 +1,30 @@
+//! Our system uses a single binary file to store an entire database
+//! This file in an unordered collection of fixed-size blocks of data called *pages*
+//! A page stores an entity of our database (table, index, etc.)
+//! This particular db file organization is commonly referred to as a heap file
+//! Each page has a unique identifier #pageid. Since an entire db fits into a single heap file, we can precisely retrieve any page
+//! by reading our file at an offset o = pageid * pagesize, assuming that all pages have the same size {pagesize}
+//! SQlite uses this approach and keeps a special (sub)page at the top of the heap file to keep track of the page
+//! organization as well as other metadata. See https://www.sqlite.org/fileformat.html
+
+pub mod btree;
+pub mod buffer_pool;
+pub mod catalog;
+pub mod connection;
+#[cfg(feature = "fault-injection")]
+pub mod crash_test;
+pub mod header;
+pub mod index;
+pub mod integrity;
+pub mod journal;
+pub mod key;
+pub mod overflow;
+pub mod pager;
+pub mod pointer_map;
+pub mod record;
+pub mod slotted_page;
+pub mod sql;
+pub mod vacuum;
+pub mod value;
+pub mod vfs;
+pub mod wal;
@@ -0,0 +1,197 @@
+//! Chains of overflow pages holding the part of a record that does not fit in its B-tree cell
+//! Each page of a chain starts with the number of the next page, 0 for the last one, and is filled with data after it:
+//! | next page (4) | data ... |
+//! In auto-vacuum databases, each page of a chain but the first records the previous one as its parent in the pointer
+//! map, the first one being recorded by the B-tree page holding the cell
+//! Chains are written and read through `io::Write` and `io::Read`, one page at a time, so that large records never
+//! have to be held in memory as a whole
+
+use std::{
+    cmp,
+    io::{self, Read, Write},
+    mem,
+};
+
+use crate::{
+    pager::{PageNumber, Pager},
+    pointer_map::PointerMapEntry,
+};
+
+const NEXT_PAGE_SIZE: usize = mem::size_of::<PageNumber>();
+
+// Bytes of data held by each overflow page
+pub fn overflow_data_size(pager: &Pager) -> usize {
+    pager.usable_size() - NEXT_PAGE_SIZE
+}
+
+// Writes data into a chain of freshly allocated overflow pages. Pages are only allocated once there is data to put in
+// them, and `finish` must be called to write the last one
+pub struct OverflowWriter<'a> {
+    pager: &'a mut Pager,
+    first: PageNumber,
+    current: PageNumber,
+    buf: Vec<u8>,
+    filled: usize,
+}
+
+impl<'a> OverflowWriter<'a> {
+    pub fn new(pager: &'a mut Pager) -> Self {
+        let buf = vec![0; pager.page_size() as usize];
+        Self {
+            pager,
+            first: 0,
+            current: 0,
+            buf,
+            filled: 0,
+        }
+    }
+
+    // Writes the last page of the chain and returns the first one, 0 if nothing was written
+    pub fn finish(mut self) -> io::Result<PageNumber> {
+        if self.current != 0 {
+            self.buf[..NEXT_PAGE_SIZE].copy_from_slice(&0_u32.to_le_bytes());
+            self.pager.write(self.current, &self.buf)?;
+        }
+
+        Ok(self.first)
+    }
+}
+
+impl Write for OverflowWriter<'_> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if data.is_empty() {
+            return Ok(0);
+        }
+
+        let capacity = overflow_data_size(self.pager);
+        if self.current == 0 || self.filled == capacity {
+            let page_no = self.pager.allocate_page()?;
+            if self.current == 0 {
+                self.first = page_no;
+            } else {
+                self.buf[..NEXT_PAGE_SIZE].copy_from_slice(&page_no.to_le_bytes());
+                self.pager.write(self.current, &self.buf)?;
+                self.pager
+                    .set_pointer_map_entry(page_no, PointerMapEntry::Overflow(self.current))?;
+            }
+            self.buf.fill(0);
+            self.current = page_no;
+            self.filled = 0;
+        }
+
+        let len = cmp::min(data.len(), capacity - self.filled);
+        let offset = NEXT_PAGE_SIZE + self.filled;
+        self.buf[offset..offset + len].copy_from_slice(&data[..len]);
+        self.filled += len;
+
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Reads `len` bytes out of the chain starting at `first`
+pub struct OverflowReader<'a> {
+    pager: &'a mut Pager,
+    next: PageNumber,
+    remaining: u64,
+    buf: Vec<u8>,
+    // Range of `buf` that has not been read yet
+    position: usize,
+    end: usize,
+}
+
+impl<'a> OverflowReader<'a> {
+    pub fn new(pager: &'a mut Pager, first: PageNumber, len: u64) -> Self {
+        let buf = vec![0; pager.page_size() as usize];
+        Self {
+            pager,
+            next: first,
+            remaining: len,
+            buf,
+            position: 0,
+            end: 0,
+        }
+    }
+}
+
+impl Read for OverflowReader<'_> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if self.position == self.end {
+            if self.remaining == 0 {
+                return Ok(0);
+            }
+            if self.next == 0 || self.next >= self.pager.page_count() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Overflow chain broken with {} bytes left", self.remaining),
+                ));
+            }
+
+            self.pager.read(self.next, &mut self.buf)?;
+            self.next = PageNumber::from_le_bytes(
+                self.buf[..NEXT_PAGE_SIZE].try_into().expect("Invalid size"),
+            );
+            let len = cmp::min(overflow_data_size(self.pager) as u64, self.remaining) as usize;
+            self.position = NEXT_PAGE_SIZE;
+            self.end = NEXT_PAGE_SIZE + len;
+            self.remaining -= len as u64;
+        }
+
+        let len = cmp::min(out.len(), self.end - self.position);
+        out[..len].copy_from_slice(&self.buf[self.position..self.position + len]);
+        self.position += len;
+
+        Ok(len)
+    }
+}
+
+// Page following `page_no` in its chain, 0 for the last one
+pub(crate) fn next_page(pager: &mut Pager, page_no: PageNumber) -> io::Result<PageNumber> {
+    let mut header = [0_u8; NEXT_PAGE_SIZE];
+    pager.read(page_no, &mut header)?;
+    Ok(PageNumber::from_le_bytes(header))
+}
+
+pub(crate) fn set_next_page(
+    pager: &mut Pager,
+    page_no: PageNumber,
+    next: PageNumber,
+) -> io::Result<()> {
+    pager.write(page_no, &next.to_le_bytes())?;
+    Ok(())
+}
+
+// Gives every page of the chain starting at `first` back to the pager
+pub fn free_chain(pager: &mut Pager, first: PageNumber) -> io::Result<()> {
+    for page_no in chain_pages(pager, first)? {
+        pager.free_page(page_no)?;
+    }
+
+    Ok(())
+}
+
+// Pages of the chain starting at `first`, in order
+pub fn chain_pages(pager: &mut Pager, first: PageNumber) -> io::Result<Vec<PageNumber>> {
+    let mut pages = vec![];
+    let mut next = first;
+
+    while next != 0 {
+        // A corrupted chain could loop forever, while a valid one cannot be longer than the file
+        if pages.len() >= pager.page_count() as usize || next >= pager.page_count() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid overflow page {next}"),
+            ));
+        }
+
+        let mut header = [0_u8; NEXT_PAGE_SIZE];
+        pager.read(next, &mut header)?;
+        pages.push(next);
+        next = PageNumber::from_le_bytes(header);
+    }
+
+    Ok(pages)
+}
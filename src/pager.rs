@@ -0,0 +1,1444 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    error::Error,
+    ffi::OsString,
+    fmt,
+    io::{self},
+    mem,
+    ops::Deref,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{
+    buffer_pool::{BufferPool, FrameId, PageStore},
+    catalog::{Catalog, CATALOG_ROOT},
+    header::{
+        self, DbHeader, AUTO_VACUUM_WRITE_VERSION, HEADER_SIZE, MAX_PAGE_SIZE, MIN_PAGE_SIZE,
+    },
+    journal::{self, Journal},
+    pointer_map::{self, PointerMapEntry, ENTRY_SIZE},
+    vfs::{AlignedBuf, LockMode, UnixVfs, Vfs, VfsFile, VfsMap, BUFFER_ALIGNMENT},
+    wal::{CheckpointMode, CheckpointResult, Wal, DEFAULT_AUTOCHECKPOINT},
+};
+
+pub type PageNumber = u32;
+pub const DEFAULT_PAGE_SIZE: u32 = 1024;
+pub const DEFAULT_CACHE_SIZE: usize = 256;
+// Size of the CRC32C stored at the end of each page when page checksums are enabled
+pub const CHECKSUM_SIZE: usize = 4;
+
+// A page whose checksum does not match its content, typically because it was only partially written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CorruptPage {
+    pub page_no: PageNumber,
+}
+impl fmt::Display for CorruptPage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Checksum mismatch on page {}", self.page_no)
+    }
+}
+impl Error for CorruptPage {}
+
+// How page writes are made durable
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JournalMode {
+    // Pages are overwritten in place, a crash in the middle of a flush can leave the database corrupted
+    Off,
+    // Pages are appended to a write-ahead log, see `wal`
+    Wal,
+    // Original pages are saved to a rollback journal before being overwritten, see `journal`
+    Rollback,
+}
+impl JournalMode {
+    fn from_u8(mode: u8) -> Option<Self> {
+        match mode {
+            0 => Some(JournalMode::Off),
+            1 => Some(JournalMode::Wal),
+            2 => Some(JournalMode::Rollback),
+            _ => None,
+        }
+    }
+
+    fn to_u8(self) -> u8 {
+        match self {
+            JournalMode::Off => 0,
+            JournalMode::Wal => 1,
+            JournalMode::Rollback => 2,
+        }
+    }
+}
+
+// When free pages are given back to the file system by moving the pages at the end of the file into them, see
+// `vacuum::incremental_vacuum`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AutoVacuum {
+    // Free pages stay in the file until the database is vacuumed
+    None,
+    // Free pages are given back whenever a transaction commits
+    Full,
+    // Free pages are only given back by `incremental_vacuum`
+    Incremental,
+}
+impl AutoVacuum {
+    fn from_u8(mode: u8) -> Option<Self> {
+        match mode {
+            0 => Some(AutoVacuum::None),
+            1 => Some(AutoVacuum::Full),
+            2 => Some(AutoVacuum::Incremental),
+            _ => None,
+        }
+    }
+
+    fn to_u8(self) -> u8 {
+        match self {
+            AutoVacuum::None => 0,
+            AutoVacuum::Full => 1,
+            AutoVacuum::Incremental => 2,
+        }
+    }
+}
+
+// The heap file, along with its write-ahead log in WAL mode or the journal of the ongoing transaction in rollback
+// mode. Pages that lie past the end of the file read as zeroes
+#[derive(Debug)]
+struct Storage {
+    vfs: Arc<dyn Vfs>,
+    file: Box<dyn VfsFile>,
+    // Set when the file was written in a newer format that this version could break by writing to it
+    read_only: bool,
+    checksums: bool,
+    // Copy of the page being written, sealed with its checksum
+    scratch: AlignedBuf,
+    mode: JournalMode,
+    wal: Option<Wal>,
+    journal: Option<Journal>,
+    journal_path: PathBuf,
+    // Maximum number of bytes of the heap file mapped into memory, 0 when reads go through `read_at`
+    mmap_size: u64,
+    map: Option<Box<dyn VfsMap>>,
+}
+impl Storage {
+    // Whether `page_no` can be read from the memory mapping of the heap file, mapping more of the file if needed.
+    // Pages living in the log, past the end of the file or past `mmap_size` cannot
+    fn map_page(&mut self, page_no: PageNumber, page_size: usize) -> io::Result<bool> {
+        let end = (page_no as u64 + 1) * page_size as u64;
+        if end > self.mmap_size
+            || self
+                .wal
+                .as_ref()
+                .is_some_and(|wal| wal.find(page_no).is_some())
+        {
+            return Ok(false);
+        }
+        if self
+            .map
+            .as_ref()
+            .is_some_and(|map| map.bytes().len() as u64 >= end)
+        {
+            return Ok(true);
+        }
+
+        // The mapping grows along with the file, a page at a time would remap far too often
+        let len = self.file.size()?.min(self.mmap_size) / page_size as u64 * page_size as u64;
+        if len < end {
+            return Ok(false);
+        }
+        self.map = None;
+        self.map = self.file.map(len)?;
+        if self.map.is_none() {
+            // The VFS cannot map files, which is not worth asking again
+            self.mmap_size = 0;
+        }
+
+        Ok(self.map.is_some())
+    }
+
+    // Only valid once `map_page` said so
+    fn mapped_page(&self, page_no: PageNumber, page_size: usize) -> &[u8] {
+        let offset = page_no as usize * page_size;
+        &self.map.as_ref().expect("Page not mapped").bytes()[offset..offset + page_size]
+    }
+
+    // To be called whenever the heap file may have shrunk, as touching a mapping past the end of its file crashes
+    fn unmap(&mut self) {
+        self.map = None;
+    }
+
+    // Saves the original content of the pages that are about to be overwritten for the first time in the transaction,
+    // starting its journal if needed, and waits for the disk to acknowledge them. The journal is started by the first
+    // write of the transaction even when it only appends pages, as it also records the size to cut the file back to
+    // after a crash. Committing writes the header anyway, which needs the journal
+    fn journal_pages(&mut self, pages: &[PageNumber], page_size: usize) -> io::Result<()> {
+        let journal = match &mut self.journal {
+            Some(journal) => journal,
+            None => {
+                let db_size = (self.file.size()? / page_size as u64) as u32;
+                self.journal.insert(Journal::create(
+                    self.vfs.clone(),
+                    &self.journal_path,
+                    page_size,
+                    db_size,
+                )?)
+            }
+        };
+
+        let mut buf = AlignedBuf::zeroed(page_size);
+        let mut appended = false;
+        for &page_no in pages {
+            if journal.needs(page_no) {
+                read_heap_page(&*self.file, page_no, &mut buf)?;
+                journal.append(page_no, &buf)?;
+                appended = true;
+            }
+        }
+        if appended {
+            journal.sync()?;
+        }
+
+        Ok(())
+    }
+}
+impl PageStore for Storage {
+    fn read_page(&mut self, page_no: PageNumber, buf: &mut [u8]) -> io::Result<()> {
+        if self.map_page(page_no, buf.len())? {
+            buf.copy_from_slice(self.mapped_page(page_no, buf.len()));
+        } else {
+            match self
+                .wal
+                .as_ref()
+                .and_then(|wal| Some((wal, wal.find(page_no)?)))
+            {
+                Some((wal, frame)) => wal.read_frame(frame, buf)?,
+                None => read_heap_page(&*self.file, page_no, buf)?,
+            }
+        }
+
+        check_page(self.checksums, page_no, buf)
+    }
+
+    // In WAL mode, pages written back before a commit are logged as uncommitted frames. In rollback mode, they are
+    // journaled before being overwritten
+    fn write_page(&mut self, page_no: PageNumber, buf: &[u8]) -> io::Result<()> {
+        if self.read_only {
+            return Err(read_only_error());
+        }
+        if self.mode == JournalMode::Rollback {
+            self.journal_pages(&[page_no], buf.len())?;
+        }
+
+        let buf = sealed(self.checksums, &mut self.scratch, page_no, buf);
+        if let Some(wal) = &mut self.wal {
+            return wal.append(page_no, buf, 0);
+        }
+
+        self.file
+            .write_all_at(buf, page_no as u64 * buf.len() as u64)
+    }
+
+    // Pages that are not in the log or mapped into memory are read from the heap file as a single batch
+    fn read_pages(&mut self, pages: &mut [(PageNumber, &mut [u8])]) -> io::Result<()> {
+        let mut batch = vec![];
+        let mut batch_pages = vec![];
+        for (page_no, buf) in pages.iter_mut() {
+            let logged = self
+                .wal
+                .as_ref()
+                .is_some_and(|wal| wal.find(*page_no).is_some());
+            if logged || self.map_page(*page_no, buf.len())? {
+                self.read_page(*page_no, buf)?;
+                continue;
+            }
+            batch.push((*page_no as u64 * buf.len() as u64, &mut **buf));
+            batch_pages.push(*page_no);
+        }
+
+        self.file.read_batch(&mut batch)?;
+        for ((_, buf), page_no) in batch.iter().zip(batch_pages) {
+            check_page(self.checksums, page_no, buf)?;
+        }
+
+        Ok(())
+    }
+
+    // Outside of WAL mode, the pages are journaled together then written to the heap file as a single batch
+    fn write_pages(&mut self, pages: &[(PageNumber, &[u8])]) -> io::Result<()> {
+        if self.read_only {
+            return Err(read_only_error());
+        }
+        if self.wal.is_some() {
+            for &(page_no, buf) in pages {
+                self.write_page(page_no, buf)?;
+            }
+            return Ok(());
+        }
+        if self.mode == JournalMode::Rollback {
+            let page_nos: Vec<PageNumber> = pages.iter().map(|&(page_no, _)| page_no).collect();
+            if let Some(&(_, buf)) = pages.first() {
+                self.journal_pages(&page_nos, buf.len())?;
+            }
+        }
+
+        let sealed_pages: Vec<AlignedBuf> = match self.checksums {
+            true => pages
+                .iter()
+                .map(|&(page_no, buf)| {
+                    let mut page = AlignedBuf::zeroed(buf.len());
+                    page.copy_from_slice(buf);
+                    seal(page_no, &mut page);
+                    page
+                })
+                .collect(),
+            false => vec![],
+        };
+        let writes: Vec<(u64, &[u8])> = pages
+            .iter()
+            .enumerate()
+            .map(|(i, &(page_no, buf))| {
+                let page = sealed_pages.get(i).map_or(buf, |page| page);
+                (page_no as u64 * buf.len() as u64, page)
+            })
+            .collect();
+
+        self.file.write_batch(&writes)
+    }
+}
+
+// The checksum covers the page number, so that a page written at the wrong place is detected as well
+fn page_checksum(page_no: PageNumber, page: &[u8]) -> u32 {
+    let data = &page[..page.len() - CHECKSUM_SIZE];
+    !crc32c(crc32c(!0, &page_no.to_le_bytes()), data)
+}
+
+// The page as it is written to disk, copied to `scratch` to receive its checksum when they are enabled
+fn sealed<'a>(
+    checksums: bool,
+    scratch: &'a mut AlignedBuf,
+    page_no: PageNumber,
+    page: &'a [u8],
+) -> &'a [u8] {
+    if !checksums {
+        return page;
+    }
+
+    if scratch.len() != page.len() {
+        *scratch = AlignedBuf::zeroed(page.len());
+    }
+    scratch.copy_from_slice(page);
+    seal(page_no, scratch);
+    scratch
+}
+
+// Stores the checksum of `page` in its last bytes
+fn seal(page_no: PageNumber, page: &mut [u8]) {
+    let checksum = page_checksum(page_no, page);
+    let len = page.len();
+    page[len - CHECKSUM_SIZE..].copy_from_slice(&checksum.to_le_bytes());
+}
+
+fn check_page(checksums: bool, page_no: PageNumber, page: &[u8]) -> io::Result<()> {
+    match checksums && !verify_checksum(page_no, page) {
+        true => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            CorruptPage { page_no },
+        )),
+        false => Ok(()),
+    }
+}
+
+// Pages that were allocated but never written read as zeroes, and have no checksum yet
+fn verify_checksum(page_no: PageNumber, page: &[u8]) -> bool {
+    let stored = &page[page.len() - CHECKSUM_SIZE..];
+    stored == page_checksum(page_no, page).to_le_bytes() || page.iter().all(|&byte| byte == 0)
+}
+
+// CRC-32C (Castagnoli), which unlike the WAL checksum catches any error affecting a few bits
+fn crc32c(mut crc: u32, data: &[u8]) -> u32 {
+    for &byte in data {
+        crc = CRC32C_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    crc
+}
+
+const CRC32C_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = match crc & 1 {
+                1 => (crc >> 1) ^ 0x82f63b78,
+                _ => crc >> 1,
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+fn read_only_error() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        "The database was written in a newer format and can only be read",
+    )
+}
+
+fn read_heap_page(file: &dyn VfsFile, page_no: PageNumber, buf: &mut [u8]) -> io::Result<()> {
+    let offset = page_no as u64 * buf.len() as u64;
+    let mut read = 0;
+
+    while read < buf.len() {
+        match file.read_at(&mut buf[read..], offset + read as u64) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    buf[read..].fill(0);
+
+    Ok(())
+}
+
+// This struct implements an in-memory cache representation of a database heap file. Pages are served from a bounded
+// buffer pool and only hit the disk when they are missing from it, evicted while dirty or flushed.
+#[derive(Debug)]
+pub struct Pager {
+    path: PathBuf,
+    store: Storage,
+    page_size: u32,
+    header: DbHeader,
+    pool: BufferPool,
+    savepoints: Vec<Savepoint>,
+    // Loaded by `init`, and again whenever the schema cookie no longer matches
+    catalog: Option<Catalog>,
+    // Whether a database created by `init` reserves room for page checksums
+    page_checksums: bool,
+    // Whether `init` switches the heap file to direct I/O
+    direct_io: bool,
+}
+impl Pager {
+    // Opens the heap file at `path`, creating it if needed. `page_size` is only used if the database has to be created,
+    // and must be a power of two between `MIN_PAGE_SIZE` and `MAX_PAGE_SIZE`
+    pub fn open<P: AsRef<Path>>(path: P, page_size: u32, cache_size: usize) -> io::Result<Self> {
+        Self::open_with_vfs(Arc::new(UnixVfs), path, page_size, cache_size)
+    }
+
+    // Same as `open`, with every file going through `vfs`
+    pub fn open_with_vfs<P: AsRef<Path>>(
+        vfs: Arc<dyn Vfs>,
+        path: P,
+        page_size: u32,
+        cache_size: usize,
+    ) -> io::Result<Self> {
+        if !header::is_valid_page_size(page_size) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Page size {page_size} is not a power of two between {MIN_PAGE_SIZE} and {MAX_PAGE_SIZE}"
+                ),
+            ));
+        }
+
+        let path = path.as_ref().to_path_buf();
+        let file = vfs.open(&path)?;
+        // The pager assumes that nobody else touches the files of the database while it is open
+        file.lock(LockMode::Exclusive)
+            .map_err(|err| match err.kind() {
+                io::ErrorKind::WouldBlock => {
+                    io::Error::new(io::ErrorKind::WouldBlock, "The database is already open")
+                }
+                _ => err,
+            })?;
+
+        Ok(Self {
+            store: Storage {
+                vfs,
+                file,
+                read_only: false,
+                checksums: false,
+                scratch: AlignedBuf::zeroed(0),
+                mode: JournalMode::Off,
+                wal: None,
+                journal: None,
+                journal_path: sidecar_path(&path, "-journal"),
+                mmap_size: 0,
+                map: None,
+            },
+            path,
+            page_size,
+            header: DbHeader::alloc(page_size),
+            pool: BufferPool::new(page_size as usize, cache_size),
+            savepoints: vec![],
+            catalog: None,
+            page_checksums: true,
+            direct_io: false,
+        })
+    }
+
+    pub fn init(&mut self) -> io::Result<()> {
+        // A transaction that never committed may have left pages half-written, including the header
+        journal::rollback_hot_journal(
+            &*self.store.vfs,
+            &self.store.journal_path,
+            &*self.store.file,
+        )?;
+
+        // Only an empty file is turned into a new database, anything else has to be a database already. The fresh
+        // header is committed right away, so that rolling back the first transaction finds it on disk
+        let len = self.store.file.size()?;
+        if len == 0 {
+            self.header = DbHeader::alloc(self.page_size);
+            self.header.journal_mode = JournalMode::Rollback.to_u8();
+            if self.page_checksums {
+                self.header.reserved_size = CHECKSUM_SIZE as u8;
+            }
+            self.store.checksums = self.page_checksums;
+            self.store.mode = JournalMode::Rollback;
+            self.enable_direct_io()?;
+            self.write_header()?;
+            Catalog::create(self)?;
+            self.catalog = Some(Catalog::load(self)?);
+            return self.flush();
+        }
+
+        // The page size is not known until the header has been read, so it bypasses the pool
+        if len < HEADER_SIZE as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Not a phdb database",
+            ));
+        }
+        let mut header = [0_u8; HEADER_SIZE];
+        self.store.file.read_exact_at(&mut header, 0)?;
+        let header = DbHeader::from(&header);
+        self.store.read_only = !header.check_compatibility()?;
+
+        self.page_size = header.page_size;
+        self.pool = BufferPool::new(self.page_size as usize, self.pool.capacity());
+        self.enable_direct_io()?;
+        self.store.checksums = match header.reserved_size as usize {
+            0 => false,
+            CHECKSUM_SIZE => true,
+            reserved_size => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Unsupported reserved size {reserved_size}"),
+                ))
+            }
+        };
+        let journal_mode = JournalMode::from_u8(header.journal_mode).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unknown journal mode {}", header.journal_mode),
+            )
+        })?;
+        if AutoVacuum::from_u8(header.auto_vacuum).is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unknown auto-vacuum mode {}", header.auto_vacuum),
+            ));
+        }
+
+        self.store.mode = journal_mode;
+
+        // A log left behind holds committed transactions that the heap file may be missing
+        let wal_path = self.wal_path();
+        if journal_mode == JournalMode::Wal || self.store.vfs.exists(&wal_path)? {
+            let mut wal = Wal::open(self.store.vfs.clone(), &wal_path, self.page_size as usize)?;
+            if journal_mode != JournalMode::Wal {
+                wal.checkpoint(&*self.store.file, CheckpointMode::Truncate)?;
+                wal.delete()?;
+            } else {
+                self.store.wal = Some(wal);
+            }
+        }
+
+        // The latest version of the header may live in the log
+        self.load_header()?;
+        self.catalog = Some(Catalog::load(self)?);
+
+        Ok(())
+    }
+
+    // Marks the current state of the pages so that `rollback_to` can come back to it
+    pub fn savepoint(&mut self, name: &str) {
+        self.savepoints.push(Savepoint {
+            name: name.to_string(),
+            pages: HashMap::new(),
+        });
+    }
+
+    pub fn savepoint_count(&self) -> usize {
+        self.savepoints.len()
+    }
+
+    // Forgets the latest savepoint called `name` along with the ones set after it, keeping their changes
+    pub fn release(&mut self, name: &str) -> io::Result<()> {
+        let index = self.find_savepoint(name)?;
+
+        // The savepoint below inherits the pages it did not save itself, as they have not changed since it was set
+        for released in self.savepoints.split_off(index) {
+            if let Some(savepoint) = self.savepoints.last_mut() {
+                for (page_no, image) in released.pages {
+                    savepoint.pages.entry(page_no).or_insert(image);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Restores the pages modified since the latest savepoint called `name` was set. The savepoint itself stays, the
+    // ones set after it are forgotten
+    pub fn rollback_to(&mut self, name: &str) -> io::Result<()> {
+        let index = self.find_savepoint(name)?;
+
+        // Older savepoints saved older images, so they are applied last
+        let savepoints = self.savepoints.split_off(index);
+        for savepoint in savepoints.into_iter().rev() {
+            for (page_no, image) in savepoint.pages {
+                let frame_id = self.pool.pin(page_no, &mut self.store)?;
+                self.pool.page_mut(frame_id).copy_from_slice(&image);
+                self.pool.unpin(frame_id, true);
+            }
+        }
+        self.savepoint(name);
+        self.load_header()?;
+
+        // Pages allocated since the savepoint do not exist anymore
+        for (page_no, _) in self.pool.dirty_frames() {
+            if page_no >= self.header.page_count {
+                self.pool.discard(page_no);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn find_savepoint(&self, name: &str) -> io::Result<usize> {
+        self.savepoints
+            .iter()
+            .rposition(|savepoint| savepoint.name == name)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("No such savepoint: {name}"),
+                )
+            })
+    }
+
+    // Discards every change made since the last flush, including the pages that were already written back. Without
+    // a journal, such pages cannot be restored and stay as they are
+    pub fn rollback(&mut self) -> io::Result<()> {
+        self.savepoints.clear();
+        // Clean pages may have been read back after being written back, so none of the cached pages can be trusted
+        self.pool = BufferPool::new(self.page_size as usize, self.pool.capacity());
+
+        if let Some(wal) = &mut self.store.wal {
+            wal.rollback();
+        }
+        if let Some(journal) = self.store.journal.take() {
+            self.store.unmap();
+            journal.rollback(&*self.store.file)?;
+        }
+
+        self.load_header()
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub(crate) fn vfs(&self) -> Arc<dyn Vfs> {
+        self.store.vfs.clone()
+    }
+
+    // Whether a database created by `init` stores a checksum in each page. Existing databases keep their setting
+    pub fn set_page_checksums(&mut self, enabled: bool) {
+        self.page_checksums = enabled;
+    }
+
+    pub fn has_page_checksums(&self) -> bool {
+        self.store.checksums
+    }
+
+    // Whether `init` opens the heap file for direct I/O, which bypasses the OS page cache as the buffer pool already
+    // caches pages. The page size must then be a multiple of the block size of the device, which `init` checks
+    pub fn set_direct_io(&mut self, enabled: bool) {
+        self.direct_io = enabled;
+    }
+
+    pub fn is_direct_io(&self) -> bool {
+        self.direct_io
+    }
+
+    fn enable_direct_io(&mut self) -> io::Result<()> {
+        if !self.direct_io {
+            return Ok(());
+        }
+
+        self.check_direct_io(self.page_size)?;
+        self.store.file.set_direct_io(true)
+    }
+
+    // Whether the heap file can hold pages of `page_size` bytes with direct I/O
+    fn check_direct_io(&self, page_size: u32) -> io::Result<()> {
+        let alignment = self.store.file.direct_io_alignment()?.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Direct I/O is not supported by this file system",
+            )
+        })?;
+        if alignment > BUFFER_ALIGNMENT {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("Direct I/O requires buffers aligned to {alignment} bytes"),
+            ));
+        }
+        if !(page_size as usize).is_multiple_of(alignment) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Page size {page_size} is not a multiple of the {alignment} byte blocks required by direct I/O"
+                ),
+            ));
+        }
+
+        Ok(())
+    }
+
+    // Maps up to `size` bytes of the heap file into memory, so that reads are served from the mapping rather than
+    // copied by `read_at`. 0, the default, disables memory mapping, as do VFS that cannot map files. Writes keep going
+    // through the buffer pool
+    pub fn set_mmap_size(&mut self, size: u64) {
+        self.store.mmap_size = size;
+        self.store.unmap();
+    }
+
+    pub fn mmap_size(&self) -> u64 {
+        self.store.mmap_size
+    }
+
+    // Whether the database was written in a newer format, which this version can read but not write
+    pub fn is_read_only(&self) -> bool {
+        self.store.read_only
+    }
+
+    pub fn header(&self) -> &DbHeader {
+        &self.header
+    }
+
+    pub fn schema_cookie(&self) -> u32 {
+        self.header.schema_cookie
+    }
+
+    // The schema of the database, see `catalog`
+    pub fn catalog(&mut self) -> io::Result<&Catalog> {
+        let stale = self
+            .catalog
+            .as_ref()
+            .is_none_or(|catalog| catalog.schema_cookie() != self.header.schema_cookie);
+        if stale {
+            self.catalog = Some(Catalog::load(self)?);
+        }
+
+        Ok(self.catalog.as_ref().expect("The catalog was just loaded"))
+    }
+
+    // To be called whenever the schema changes, so that anything derived from it knows that it is stale
+    pub fn bump_schema_cookie(&mut self) -> io::Result<()> {
+        self.header.schema_cookie = self.header.schema_cookie.wrapping_add(1);
+        self.write_header()
+    }
+
+    pub fn set_application_id(&mut self, application_id: u32) -> io::Result<()> {
+        self.header.application_id = application_id;
+        self.write_header()
+    }
+
+    pub fn set_user_version(&mut self, user_version: u32) -> io::Result<()> {
+        self.header.user_version = user_version;
+        self.write_header()
+    }
+
+    pub fn journal_mode(&self) -> JournalMode {
+        self.store.mode
+    }
+
+    // Switches the database to another journal mode. Pending changes are flushed first
+    pub fn set_journal_mode(&mut self, mode: JournalMode) -> io::Result<()> {
+        if mode == self.journal_mode() {
+            return Ok(());
+        }
+        self.flush()?;
+
+        // The new mode is committed with the current one, so that a crash in between leaves a database that is still
+        // usable
+        self.header.journal_mode = mode.to_u8();
+        self.write_header()?;
+        if self.store.wal.is_some() {
+            // Every frame has to reach the heap file before the log can go away
+            self.checkpoint(CheckpointMode::Truncate)?;
+            if let Some(wal) = self.store.wal.take() {
+                wal.delete()?;
+            }
+        } else {
+            self.flush()?;
+        }
+
+        if mode == JournalMode::Wal {
+            self.store.wal = Some(Wal::open(
+                self.store.vfs.clone(),
+                &self.wal_path(),
+                self.page_size as usize,
+            )?);
+        }
+        self.store.mode = mode;
+
+        Ok(())
+    }
+
+    // Copies the pages of the write-ahead log back to the heap file. Pending changes are committed first
+    pub fn checkpoint(&mut self, mode: CheckpointMode) -> io::Result<CheckpointResult> {
+        self.flush()?;
+
+        self.store.unmap();
+        match &mut self.store.wal {
+            Some(wal) => wal.checkpoint(&*self.store.file, mode),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The database is not in WAL mode",
+            )),
+        }
+    }
+
+    // Replaces every page of the database with the pages of `other`, which may use another page size. The database
+    // keeps its own identity though: application id, user version, schema cookie and creation details. All of its
+    // pages are journaled beforehand, so that a crash leaves either the old or the new database. Pending changes of
+    // both pagers are flushed first, and the rollback journal mode is required
+    pub fn replace_with(&mut self, mut other: Pager) -> io::Result<()> {
+        if self.store.mode != JournalMode::Rollback {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Replacing the database requires the rollback journal mode",
+            ));
+        }
+        if self.store.read_only {
+            return Err(read_only_error());
+        }
+        if self.direct_io {
+            self.check_direct_io(other.page_size)?;
+        }
+        self.flush()?;
+        other.flush()?;
+
+        let db_size = (self.store.file.size()? / self.page_size as u64) as u32;
+        let pages: Vec<PageNumber> = (0..db_size).collect();
+        self.store.journal_pages(&pages, self.page_size as usize)?;
+        self.store.unmap();
+        if let Err(err) = self.overwrite(&other) {
+            let _ = self.rollback();
+            return Err(err);
+        }
+
+        // The new pages are durable, deleting the journal commits them
+        if let Some(journal) = self.store.journal.take() {
+            journal.delete()?;
+        }
+        self.page_size = other.page_size;
+        self.store.checksums = other.store.checksums;
+        self.pool = BufferPool::new(self.page_size as usize, self.pool.capacity());
+        self.load_header()
+    }
+
+    // Copies the heap file of `other` over the heap file, bypassing the buffer pool as the page size may change
+    fn overwrite(&mut self, other: &Pager) -> io::Result<()> {
+        let header = DbHeader {
+            page_size: other.page_size,
+            reserved_size: other.header.reserved_size,
+            page_count: other.header.page_count,
+            freelist_head: other.header.freelist_head,
+            freelist_count: other.header.freelist_count,
+            auto_vacuum: other.header.auto_vacuum,
+            write_version: other.header.write_version,
+            change_counter: self.header.change_counter.wrapping_add(1),
+            ..self.header.clone()
+        };
+
+        let page_size = other.page_size as u64;
+        let mut buf = AlignedBuf::zeroed(other.page_size as usize);
+        for page_no in 0..header.page_count {
+            read_heap_page(&*other.store.file, page_no, &mut buf)?;
+            if page_no == 0 {
+                buf[..HEADER_SIZE].copy_from_slice(&header.to_buf());
+                if other.store.checksums {
+                    seal(page_no, &mut buf);
+                }
+            }
+            self.store
+                .file
+                .write_all_at(&buf, page_no as u64 * page_size)?;
+        }
+        self.store
+            .file
+            .truncate(header.page_count as u64 * page_size)?;
+        self.store.file.sync()
+    }
+
+    fn wal_path(&self) -> PathBuf {
+        sidecar_path(&self.path, "-wal")
+    }
+
+    pub fn page_size(&self) -> u32 {
+        self.page_size
+    }
+
+    // Bytes of each page that can be used by the layers above the pager
+    pub fn usable_size(&self) -> usize {
+        self.page_size as usize - self.header.reserved_size as usize
+    }
+
+    pub fn page_count(&self) -> u32 {
+        self.header.page_count
+    }
+
+    pub fn freelist_count(&self) -> u32 {
+        self.header.freelist_count
+    }
+
+    pub fn auto_vacuum(&self) -> AutoVacuum {
+        AutoVacuum::from_u8(self.header.auto_vacuum).unwrap_or(AutoVacuum::None)
+    }
+
+    // Pages only have pointer-map entries when the database has had them from the start, so auto-vacuum can only be
+    // turned on or off while the database is empty, with nothing past the root of the catalog, which has no entry.
+    // Switching between `Full` and `Incremental` is always possible
+    pub fn set_auto_vacuum(&mut self, mode: AutoVacuum) -> io::Result<()> {
+        let current = self.auto_vacuum();
+        if mode == current {
+            return Ok(());
+        }
+        if (mode == AutoVacuum::None) != (current == AutoVacuum::None)
+            && self.header.page_count > CATALOG_ROOT + 1
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Auto-vacuum can only be turned on or off while the database is empty",
+            ));
+        }
+        if self.store.read_only {
+            return Err(read_only_error());
+        }
+
+        self.header.auto_vacuum = mode.to_u8();
+        if mode != AutoVacuum::None {
+            self.header.write_version = self.header.write_version.max(AUTO_VACUUM_WRITE_VERSION);
+        }
+        self.write_header()
+    }
+
+    // Whether the database keeps track of what each page is used for, see `pointer_map`
+    pub fn has_pointer_map(&self) -> bool {
+        self.auto_vacuum() != AutoVacuum::None
+    }
+
+    pub fn is_pointer_map_page(&self, page_no: PageNumber) -> bool {
+        self.has_pointer_map() && pointer_map::is_map_page(page_no, self.usable_size())
+    }
+
+    pub fn pointer_map_page_count(&self) -> u32 {
+        match self.has_pointer_map() {
+            true => pointer_map::map_page_count(self.header.page_count, self.usable_size()),
+            false => 0,
+        }
+    }
+
+    pub fn pointer_map_entry(&mut self, page_no: PageNumber) -> io::Result<PointerMapEntry> {
+        let location = match self.has_pointer_map() && page_no < self.header.page_count {
+            true => pointer_map::locate(page_no, self.usable_size()),
+            false => None,
+        };
+        let (map, offset) = location.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Page {page_no} has no pointer map entry"),
+            )
+        })?;
+
+        let page = self.page_ref(map)?;
+        PointerMapEntry::from_bytes(page_no, &page[offset..offset + ENTRY_SIZE])
+    }
+
+    // Records what `page_no` is used for. Does nothing unless the database keeps pointer maps
+    pub fn set_pointer_map_entry(
+        &mut self,
+        page_no: PageNumber,
+        entry: PointerMapEntry,
+    ) -> io::Result<()> {
+        if !self.has_pointer_map() {
+            return Ok(());
+        }
+        let Some((map, offset)) = pointer_map::locate(page_no, self.usable_size()) else {
+            return Ok(());
+        };
+
+        let bytes = entry.to_bytes();
+        let frame_id = self.pin(map)?;
+        let changed = self.pool.page(frame_id)[offset..offset + ENTRY_SIZE] != bytes;
+        if changed {
+            self.page_mut(frame_id)[offset..offset + ENTRY_SIZE].copy_from_slice(&bytes);
+        }
+        self.unpin(frame_id, changed);
+
+        Ok(())
+    }
+
+    // Hands out a zeroed page, reusing a page from the free-list when there is one and growing the heap file otherwise
+    pub fn allocate_page(&mut self) -> io::Result<PageNumber> {
+        let page_no = match self.header.freelist_head {
+            0 => {
+                let mut page_no = self.header.page_count;
+                // Pointer-map pages are skipped over, they start out empty
+                if self.is_pointer_map_page(page_no) {
+                    let frame_id = self.pin(page_no)?;
+                    self.page_mut(frame_id).fill(0);
+                    self.unpin(frame_id, true);
+                    page_no += 1;
+                }
+                self.header.page_count = page_no.checked_add(1).ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::StorageFull, "Maximum page count reached")
+                })?;
+                page_no
+            }
+            trunk_no => {
+                // The same checks as `take_free_pages`, a corrupted free-list must not hand out pages in use
+                let corrupted =
+                    || io::Error::new(io::ErrorKind::InvalidData, "Corrupted free-list");
+                if trunk_no >= self.header.page_count || self.header.freelist_count == 0 {
+                    return Err(corrupted());
+                }
+                let frame_id = self.pin(trunk_no)?;
+                let trunk = FreelistTrunk::from(self.pool.page(frame_id));
+                let page_no = match trunk.leaf_count {
+                    // The trunk has no leaves left, it is handed out and the next trunk becomes the head
+                    0 => {
+                        self.unpin(frame_id, false);
+                        self.header.freelist_head = trunk.next;
+                        trunk_no
+                    }
+                    leaf_count => {
+                        let max_leaves = FreelistTrunk::max_leaves(self.usable_size());
+                        let leaf = match leaf_count <= max_leaves {
+                            true => FreelistTrunk::leaf(self.pool.page(frame_id), leaf_count - 1),
+                            false => 0,
+                        };
+                        if leaf == 0
+                            || leaf >= self.header.page_count
+                            || self.is_pointer_map_page(leaf)
+                        {
+                            self.unpin(frame_id, false);
+                            return Err(corrupted());
+                        }
+                        FreelistTrunk::set_leaf_count(self.page_mut(frame_id), leaf_count - 1);
+                        self.unpin(frame_id, true);
+                        leaf
+                    }
+                };
+                self.header.freelist_count -= 1;
+                page_no
+            }
+        };
+
+        let frame_id = self.pin(page_no)?;
+        self.page_mut(frame_id).fill(0);
+        self.unpin(frame_id, true);
+        self.write_header()?;
+
+        Ok(page_no)
+    }
+
+    // Gives `page_no` back to the free-list so that a later allocation can reuse it
+    pub fn free_page(&mut self, page_no: PageNumber) -> io::Result<()> {
+        if page_no == 0 || page_no >= self.header.page_count || self.is_pointer_map_page(page_no) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Cannot free page {page_no}"),
+            ));
+        }
+
+        let head = self.header.freelist_head;
+        let max_leaves = FreelistTrunk::max_leaves(self.usable_size());
+        let frame_id = match head {
+            0 => None,
+            head => Some(self.pin(head)?),
+        };
+
+        match frame_id {
+            // There is room left in the head trunk, the page becomes one of its leaves. Leaf content is meaningless so
+            // the page itself is not touched
+            Some(frame_id)
+                if FreelistTrunk::from(self.pool.page(frame_id)).leaf_count < max_leaves =>
+            {
+                let page = self.page_mut(frame_id);
+                let leaf_count = FreelistTrunk::from(page).leaf_count;
+                FreelistTrunk::set_leaf(page, leaf_count, page_no);
+                FreelistTrunk::set_leaf_count(page, leaf_count + 1);
+                self.unpin(frame_id, true);
+            }
+            // Otherwise the page becomes the new head trunk
+            _ => {
+                if let Some(frame_id) = frame_id {
+                    self.unpin(frame_id, false);
+                }
+                let frame_id = self.pin(page_no)?;
+                let page = self.page_mut(frame_id);
+                page.fill(0);
+                FreelistTrunk {
+                    next: head,
+                    leaf_count: 0,
+                }
+                .write(page);
+                self.unpin(frame_id, true);
+                self.header.freelist_head = page_no;
+            }
+        }
+
+        self.header.freelist_count += 1;
+        self.set_pointer_map_entry(page_no, PointerMapEntry::Free)?;
+        self.write_header()
+    }
+
+    // Pages of the free-list, trunks included, without taking them off the list
+    pub fn free_pages(&mut self) -> io::Result<Vec<PageNumber>> {
+        let corrupted = || io::Error::new(io::ErrorKind::InvalidData, "Corrupted free-list");
+        let max_leaves = FreelistTrunk::max_leaves(self.usable_size());
+        let page_count = self.header.page_count;
+
+        let mut pages = Vec::with_capacity(self.header.freelist_count as usize);
+        let mut trunk_no = self.header.freelist_head;
+        while trunk_no != 0 {
+            // A list looping back on itself would grow past the page count
+            if trunk_no >= page_count || pages.len() >= page_count as usize {
+                return Err(corrupted());
+            }
+            let page = self.page_ref(trunk_no)?;
+            let trunk = FreelistTrunk::from(&page);
+            if trunk.leaf_count > max_leaves {
+                return Err(corrupted());
+            }
+            pages.extend((0..trunk.leaf_count).map(|index| FreelistTrunk::leaf(&page, index)));
+            pages.push(trunk_no);
+            trunk_no = trunk.next;
+        }
+        if pages
+            .iter()
+            .any(|&page_no| page_no == 0 || page_no >= page_count)
+        {
+            return Err(corrupted());
+        }
+
+        Ok(pages)
+    }
+
+    // Empties the free-list, returning the pages it held, trunks included
+    pub(crate) fn take_free_pages(&mut self) -> io::Result<Vec<PageNumber>> {
+        let pages = self.free_pages()?;
+        if pages.len() != self.header.freelist_count as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Corrupted free-list",
+            ));
+        }
+
+        self.header.freelist_head = 0;
+        self.header.freelist_count = 0;
+        self.write_header()?;
+
+        Ok(pages)
+    }
+
+    // Cuts the database down to its first `page_count` pages, none of the others being in use anymore. The heap file
+    // shrinks on the next flush
+    pub(crate) fn truncate(&mut self, page_count: u32) -> io::Result<()> {
+        for page_no in page_count..self.header.page_count {
+            self.pool.discard(page_no);
+        }
+        self.header.page_count = page_count;
+        self.write_header()
+    }
+
+    fn load_header(&mut self) -> io::Result<()> {
+        let mut header = [0_u8; HEADER_SIZE];
+        self.read(0, &mut header)?;
+        self.header = DbHeader::from(&header);
+
+        Ok(())
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        self.write(0, &self.header.to_buf())?;
+        Ok(())
+    }
+
+    pub fn read(&mut self, page_no: PageNumber, buf: &mut [u8]) -> io::Result<usize> {
+        let page = self.page_ref(page_no)?;
+        let len = buf.len().min(page.len());
+        buf[..len].copy_from_slice(&page[..len]);
+
+        Ok(len)
+    }
+
+    // Loads `pages` into the buffer pool ahead of their use, reading those that are not cached yet all at once. Meant
+    // for sequential scans, which know which pages come next
+    pub fn prefetch(&mut self, pages: &[PageNumber]) -> io::Result<()> {
+        self.pool.prefetch(pages, &mut self.store)
+    }
+
+    // Borrows the content of a page for reading. Pages that are not cached are served straight from the memory mapping
+    // of the heap file when there is one, instead of being loaded into the pool
+    pub fn page_ref(&mut self, page_no: PageNumber) -> io::Result<PageRef<'_>> {
+        let page_size = self.page_size as usize;
+        if !self.pool.contains(page_no) && self.store.map_page(page_no, page_size)? {
+            let page = self.store.mapped_page(page_no, page_size);
+            check_page(self.store.checksums, page_no, page)?;
+            return Ok(PageRef(PageSource::Mapped(page)));
+        }
+
+        let frame_id = self.pin(page_no)?;
+        Ok(PageRef(PageSource::Pinned {
+            pool: &mut self.pool,
+            frame_id,
+        }))
+    }
+
+    // Overwrites the beginning of the page with `buf`. The change only reaches the disk once the page is evicted or
+    // the pager is flushed
+    pub fn write(&mut self, page_no: PageNumber, buf: &[u8]) -> io::Result<usize> {
+        let frame_id = self.pin(page_no)?;
+        let len = buf.len().min(self.page_size as usize);
+        self.page_mut(frame_id)[..len].copy_from_slice(&buf[..len]);
+        self.unpin(frame_id, true);
+
+        Ok(len)
+    }
+
+    pub fn pin(&mut self, page_no: PageNumber) -> io::Result<FrameId> {
+        self.pool.pin(page_no, &mut self.store)
+    }
+
+    pub fn unpin(&mut self, frame_id: FrameId, dirty: bool) {
+        self.pool.unpin(frame_id, dirty)
+    }
+
+    pub fn page(&self, frame_id: FrameId) -> &[u8] {
+        self.pool.page(frame_id)
+    }
+
+    // The page is about to be modified, so its current content is saved for the latest savepoint if it has not been
+    // already
+    pub fn page_mut(&mut self, frame_id: FrameId) -> &mut [u8] {
+        if let Some(savepoint) = self.savepoints.last_mut() {
+            if let Entry::Vacant(entry) = savepoint.pages.entry(self.pool.page_no(frame_id)) {
+                entry.insert(self.pool.page(frame_id).into());
+            }
+        }
+
+        self.pool.page_mut(frame_id)
+    }
+
+    // Writes every dirty page back to the heap file and waits for the disk to acknowledge them. In WAL mode, pages are
+    // appended to the log instead, the last one committing everything written since the previous flush
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.savepoints.clear();
+
+        if self.store.read_only {
+            return match self.pool.dirty_frames().is_empty() {
+                true => Ok(()),
+                false => Err(read_only_error()),
+            };
+        }
+        if self.has_pending_changes() {
+            self.header.change_counter = self.header.change_counter.wrapping_add(1);
+            self.write_header()?;
+        }
+        if self.store.wal.is_some() {
+            return self.commit_wal();
+        }
+
+        // Journaling every page at once only takes a single sync. Pages cut off the end of the file are saved as well
+        if self.store.mode == JournalMode::Rollback {
+            let file_pages = (self.store.file.size()? / self.page_size as u64) as u32;
+            let mut pages: Vec<PageNumber> = self
+                .pool
+                .dirty_frames()
+                .into_iter()
+                .map(|(page_no, _)| page_no)
+                .collect();
+            pages.extend(self.header.page_count..file_pages);
+            self.store.journal_pages(&pages, self.page_size as usize)?;
+        }
+
+        self.pool.flush_all(&mut self.store)?;
+
+        // Allocated pages that were never written still have to exist in the file, and truncated ones do not
+        let len = self.header.page_count as u64 * self.page_size as u64;
+        if self.store.file.size()? != len {
+            self.store.unmap();
+            self.store.file.truncate(len)?;
+        }
+        self.store.file.sync()?;
+
+        // The heap file is now durable, deleting the journal commits the transaction
+        match self.store.journal.take() {
+            Some(journal) => journal.delete(),
+            None => Ok(()),
+        }
+    }
+
+    // Whether anything was modified since the last flush
+    fn has_pending_changes(&self) -> bool {
+        !self.pool.dirty_frames().is_empty()
+            || self.store.journal.is_some()
+            || self
+                .store
+                .wal
+                .as_ref()
+                .is_some_and(Wal::has_uncommitted_frames)
+    }
+
+    fn commit_wal(&mut self) -> io::Result<()> {
+        let has_uncommitted_frames = self
+            .store
+            .wal
+            .as_ref()
+            .is_some_and(Wal::has_uncommitted_frames);
+
+        let mut dirty = self.pool.dirty_frames();
+        if dirty.is_empty() {
+            if !has_uncommitted_frames {
+                return Ok(());
+            }
+            // Frames written back by evictions still need a commit frame, which the header page provides
+            let frame_id = self.pin(0)?;
+            self.unpin(frame_id, true);
+            dirty = self.pool.dirty_frames();
+        }
+
+        let store = &mut self.store;
+        let wal = store.wal.as_mut().expect("Not in WAL mode");
+        let db_size = self.header.page_count;
+        for (i, &(page_no, frame_id)) in dirty.iter().enumerate() {
+            let commit = if i + 1 == dirty.len() { db_size } else { 0 };
+            let page = sealed(
+                store.checksums,
+                &mut store.scratch,
+                page_no,
+                self.pool.page(frame_id),
+            );
+            wal.append(page_no, page, commit)?;
+        }
+        wal.sync()?;
+        for (_, frame_id) in dirty {
+            self.pool.mark_clean(frame_id);
+        }
+
+        if wal.frame_count() >= DEFAULT_AUTOCHECKPOINT {
+            store.map = None;
+            wal.checkpoint(&*store.file, CheckpointMode::Passive)?;
+        }
+
+        Ok(())
+    }
+}
+
+// A page borrowed from the pager by `page_ref`. A page coming from the buffer pool stays pinned until the reference is
+// dropped
+#[derive(Debug)]
+pub struct PageRef<'a>(PageSource<'a>);
+
+#[derive(Debug)]
+enum PageSource<'a> {
+    Mapped(&'a [u8]),
+    Pinned {
+        pool: &'a mut BufferPool,
+        frame_id: FrameId,
+    },
+}
+
+impl Deref for PageRef<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match &self.0 {
+            PageSource::Mapped(page) => page,
+            PageSource::Pinned { pool, frame_id } => pool.page(*frame_id),
+        }
+    }
+}
+
+impl Drop for PageRef<'_> {
+    fn drop(&mut self) {
+        if let PageSource::Pinned { pool, frame_id } = &mut self.0 {
+            pool.unpin(*frame_id, false);
+        }
+    }
+}
+
+// Content of the pages modified since a savepoint was set, as it was at that point
+#[derive(Debug)]
+struct Savepoint {
+    name: String,
+    pages: HashMap<PageNumber, Box<[u8]>>,
+}
+
+// Free pages are chained into a list of trunk pages, each of them holding the page numbers of a batch of other free
+// pages called leaves. A trunk page is laid out as
+// | next trunk (4 bytes) | leaf count (4 bytes) | leaf page numbers (4 bytes each) ... |
+struct FreelistTrunk {
+    next: PageNumber,
+    leaf_count: usize,
+}
+impl FreelistTrunk {
+    const HEADER_SIZE: usize = 2 * mem::size_of::<u32>();
+
+    fn max_leaves(usable_size: usize) -> usize {
+        (usable_size - Self::HEADER_SIZE) / mem::size_of::<PageNumber>()
+    }
+
+    fn from(page: &[u8]) -> Self {
+        Self {
+            next: u32::from_le_bytes(page[0..4].try_into().expect("Invalid size")),
+            leaf_count: u32::from_le_bytes(page[4..8].try_into().expect("Invalid size")) as usize,
+        }
+    }
+
+    fn write(&self, page: &mut [u8]) {
+        page[0..4].copy_from_slice(&self.next.to_le_bytes());
+        Self::set_leaf_count(page, self.leaf_count);
+    }
+
+    fn set_leaf_count(page: &mut [u8], leaf_count: usize) {
+        page[4..8].copy_from_slice(&(leaf_count as u32).to_le_bytes());
+    }
+
+    fn leaf(page: &[u8], index: usize) -> PageNumber {
+        let offset = Self::HEADER_SIZE + index * mem::size_of::<PageNumber>();
+        u32::from_le_bytes(page[offset..offset + 4].try_into().expect("Invalid size"))
+    }
+
+    fn set_leaf(page: &mut [u8], index: usize, page_no: PageNumber) {
+        let offset = Self::HEADER_SIZE + index * mem::size_of::<PageNumber>();
+        page[offset..offset + 4].copy_from_slice(&page_no.to_le_bytes());
+    }
+}
+
+// Path of a file living next to the heap file, such as its write-ahead log
+pub(crate) fn sidecar_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = OsString::from(path.as_os_str());
+    path.push(suffix);
+    PathBuf::from(path)
+}
@@ -0,0 +1,101 @@
+//! Pointer maps, kept by auto-vacuum databases so that any page can be moved: moving a page means updating the page
+//! that points to it, which the pointer map records for every page along with what the page is used for
+//! Pointer-map pages are interleaved with the other pages at fixed positions, the first one being page 2. Each of them
+//! holds an entry for each of the pages following it, up to the next pointer-map page:
+//! | page 0 (header) | page 1 | map | page | page | ... | map | page | page | ... |
+//! Like in SQLite, page 1 has no entry and never moves, as it holds the root of the catalog
+//! An entry is laid out as | kind (1) | parent page (4) |
+
+use std::io;
+
+use crate::pager::PageNumber;
+
+pub const FIRST_POINTER_MAP_PAGE: PageNumber = 2;
+pub const ENTRY_SIZE: usize = 5;
+
+const ROOT: u8 = 1;
+const FREE: u8 = 2;
+const FIRST_OVERFLOW: u8 = 3;
+const OVERFLOW: u8 = 4;
+const CHILD: u8 = 5;
+
+// What a page is used for, along with the page pointing to it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PointerMapEntry {
+    // Root page of a tree, which nothing points to
+    Root,
+    Free,
+    // First page of an overflow chain, pointed to by the leaf holding the cell it belongs to
+    FirstOverflow(PageNumber),
+    // Other page of an overflow chain, pointed to by the previous page of the chain
+    Overflow(PageNumber),
+    // Page of a tree other than its root, pointed to by its parent
+    Child(PageNumber),
+}
+
+impl PointerMapEntry {
+    pub fn to_bytes(self) -> [u8; ENTRY_SIZE] {
+        let (kind, parent) = match self {
+            PointerMapEntry::Root => (ROOT, 0),
+            PointerMapEntry::Free => (FREE, 0),
+            PointerMapEntry::FirstOverflow(parent) => (FIRST_OVERFLOW, parent),
+            PointerMapEntry::Overflow(parent) => (OVERFLOW, parent),
+            PointerMapEntry::Child(parent) => (CHILD, parent),
+        };
+
+        let mut bytes = [0; ENTRY_SIZE];
+        bytes[0] = kind;
+        bytes[1..].copy_from_slice(&parent.to_le_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(page_no: PageNumber, bytes: &[u8]) -> io::Result<Self> {
+        let parent =
+            PageNumber::from_le_bytes(bytes[1..ENTRY_SIZE].try_into().expect("Invalid size"));
+        match bytes[0] {
+            ROOT => Ok(PointerMapEntry::Root),
+            FREE => Ok(PointerMapEntry::Free),
+            FIRST_OVERFLOW => Ok(PointerMapEntry::FirstOverflow(parent)),
+            OVERFLOW => Ok(PointerMapEntry::Overflow(parent)),
+            CHILD => Ok(PointerMapEntry::Child(parent)),
+            kind => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid pointer map entry of kind {kind} for page {page_no}"),
+            )),
+        }
+    }
+}
+
+// Pointer-map page holding the entry of `page_no`, along with the offset of the entry in that page. Pages 0 and 1, as
+// well as pointer-map pages themselves, have none
+pub fn locate(page_no: PageNumber, usable_size: usize) -> Option<(PageNumber, usize)> {
+    let map = map_page(page_no, usable_size)?;
+    match page_no - map {
+        0 => None,
+        index => Some((map, (index as usize - 1) * ENTRY_SIZE)),
+    }
+}
+
+pub fn is_map_page(page_no: PageNumber, usable_size: usize) -> bool {
+    map_page(page_no, usable_size) == Some(page_no)
+}
+
+// Pointer-map pages among the first `page_count` pages of the database
+pub fn map_page_count(page_count: PageNumber, usable_size: usize) -> u32 {
+    match page_count.checked_sub(FIRST_POINTER_MAP_PAGE) {
+        Some(pages) => pages.div_ceil(group_size(usable_size)),
+        None => 0,
+    }
+}
+
+// Pointer-map page that `page_no` falls behind
+fn map_page(page_no: PageNumber, usable_size: usize) -> Option<PageNumber> {
+    let index = page_no.checked_sub(FIRST_POINTER_MAP_PAGE)?;
+    let group = group_size(usable_size);
+    Some(index / group * group + FIRST_POINTER_MAP_PAGE)
+}
+
+// A pointer-map page followed by the pages it has entries for
+fn group_size(usable_size: usize) -> u32 {
+    (usable_size / ENTRY_SIZE) as u32 + 1
+}
@@ -0,0 +1,276 @@
+//! Records, the format of the rows stored in tables and of the keys of indexes. Like in SQLite, a record is a header
+//! giving the serial type of each column, followed by the content of the columns in the same order:
+//! | header size (varint) | serial type (varint) ... | content ... |
+//! The header size counts its own bytes. Serial types tell both the type of a value and the size of its content:
+//! | serial type    | content                                                     |
+//! |              0 | NULL, no content                                            |
+//! |         1 to 6 | integer of 1, 2, 3, 4, 6 or 8 bytes, big-endian             |
+//! |              7 | real, as a big-endian IEEE 754 double                       |
+//! |           8, 9 | the integers 0 and 1, no content                            |
+//! |         10, 11 | reserved                                                    |
+//! | N >= 12, even  | blob of (N - 12) / 2 bytes                                  |
+//! | N >= 13, odd   | UTF-8 text of (N - 13) / 2 bytes                            |
+//! Integers always take the smallest serial type that holds them, so a list of values has a single encoding
+//! Varints are big-endian and take 1 to 9 bytes: each byte contributes its 7 low bits, the high bit telling whether
+//! another byte follows, except for the 9th byte which contributes all 8 of its bits
+
+use std::{cmp::Ordering, io, mem};
+
+use crate::value::{Value, ValueRef};
+
+const NULL: u64 = 0;
+const REAL: u64 = 7;
+const ZERO: u64 = 8;
+const ONE: u64 = 9;
+const BLOB: u64 = 12;
+const TEXT: u64 = 13;
+// Largest value that fits in 8 bytes of 7 bits
+const MAX_SHORT_VARINT: u64 = (1 << 56) - 1;
+pub const MAX_VARINT_SIZE: usize = 9;
+
+// Encodes a row or a key. Text and blobs longer than 2^62 bytes cannot be encoded
+pub fn encode(values: &[Value]) -> Vec<u8> {
+    encode_refs(values.iter().map(Value::as_ref))
+}
+
+pub fn encode_refs<'a, I>(values: I) -> Vec<u8>
+where
+    I: IntoIterator<Item = ValueRef<'a>>,
+{
+    let values: Vec<ValueRef> = values.into_iter().collect();
+    let types: Vec<u64> = values.iter().map(serial_type).collect();
+
+    // The size of the header depends on the size of its own varint
+    let types_size: usize = types.iter().map(|&serial| varint_size(serial)).sum();
+    let mut header_size = types_size + 1;
+    while types_size + varint_size(header_size as u64) != header_size {
+        header_size = types_size + varint_size(header_size as u64);
+    }
+    let body_size: usize = types.iter().map(|&serial| content_size(serial)).sum();
+
+    let mut record = Vec::with_capacity(header_size + body_size);
+    put_varint(&mut record, header_size as u64);
+    for &serial in &types {
+        put_varint(&mut record, serial);
+    }
+    for (value, serial) in values.iter().zip(types) {
+        match *value {
+            ValueRef::Null => {}
+            ValueRef::Integer(integer) => {
+                let size = content_size(serial);
+                record.extend_from_slice(&integer.to_be_bytes()[mem::size_of::<i64>() - size..]);
+            }
+            ValueRef::Real(real) => record.extend_from_slice(&real.to_be_bytes()),
+            ValueRef::Text(bytes) | ValueRef::Blob(bytes) => record.extend_from_slice(bytes),
+        }
+    }
+
+    record
+}
+
+pub fn decode(record: &[u8]) -> io::Result<Vec<Value>> {
+    Ok(Record::parse(record)?
+        .values()
+        .map(|value| value.to_owned())
+        .collect())
+}
+
+// A record whose header was read, giving access to its values without copying them
+#[derive(Debug, Clone)]
+pub struct Record<'a> {
+    data: &'a [u8],
+    // Serial type and content offset of each column
+    columns: Vec<(u64, usize)>,
+}
+
+impl<'a> Record<'a> {
+    pub fn parse(data: &'a [u8]) -> io::Result<Self> {
+        let mut fields = Fields::new(data)?;
+        let columns = fields.by_ref().collect::<io::Result<_>>()?;
+        if fields.content != data.len() {
+            return Err(malformed());
+        }
+
+        Ok(Self { data, columns })
+    }
+
+    pub fn len(&self) -> usize {
+        self.columns.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.columns.is_empty()
+    }
+
+    pub fn column(&self, index: usize) -> ValueRef<'a> {
+        let (serial, offset) = self.columns[index];
+        value(self.data, serial, offset)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = ValueRef<'a>> + '_ {
+        (0..self.len()).map(|index| self.column(index))
+    }
+}
+
+// Compares two records value by value, without decoding them first. A record whose values are the first values of the
+// other compares equal to it
+pub fn compare(a: &[u8], b: &[u8]) -> io::Result<Ordering> {
+    for fields in Fields::new(a)?.zip(Fields::new(b)?) {
+        let ((a_serial, a_offset), (b_serial, b_offset)) = (fields.0?, fields.1?);
+        let ordering = value(a, a_serial, a_offset).compare(&value(b, b_serial, b_offset));
+        if ordering.is_ne() {
+            return Ok(ordering);
+        }
+    }
+
+    Ok(Ordering::Equal)
+}
+
+// Walks through the header of a record, yielding the serial type and content offset of each column
+struct Fields<'a> {
+    data: &'a [u8],
+    header_size: usize,
+    offset: usize,
+    content: usize,
+}
+
+impl<'a> Fields<'a> {
+    fn new(data: &'a [u8]) -> io::Result<Self> {
+        let (header_size, offset) = read_varint(data).ok_or_else(malformed)?;
+        let header_size = usize::try_from(header_size).map_err(|_| malformed())?;
+        if header_size < offset || header_size > data.len() {
+            return Err(malformed());
+        }
+
+        Ok(Self {
+            data,
+            header_size,
+            offset,
+            content: header_size,
+        })
+    }
+}
+
+impl Iterator for Fields<'_> {
+    type Item = io::Result<(u64, usize)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.offset >= self.header_size {
+            return None;
+        }
+
+        let field = read_varint(&self.data[self.offset..self.header_size])
+            .filter(|&(serial, _)| serial != 10 && serial != 11)
+            .and_then(|(serial, size)| {
+                let end = self.content.checked_add(content_size(serial))?;
+                (end <= self.data.len()).then_some((serial, size, end))
+            });
+        let Some((serial, size, end)) = field else {
+            // Nothing can be read past a malformed field
+            self.offset = self.header_size;
+            return Some(Err(malformed()));
+        };
+
+        let content = self.content;
+        self.offset += size;
+        self.content = end;
+        Some(Ok((serial, content)))
+    }
+}
+
+// Value of serial type `serial` whose content starts at `offset`, which must lie within `data`
+fn value(data: &[u8], serial: u64, offset: usize) -> ValueRef<'_> {
+    let content = &data[offset..offset + content_size(serial)];
+
+    match serial {
+        NULL => ValueRef::Null,
+        ZERO => ValueRef::Integer(0),
+        ONE => ValueRef::Integer(1),
+        REAL => ValueRef::Real(f64::from_be_bytes(
+            content.try_into().expect("Invalid size"),
+        )),
+        1..=6 => {
+            // Sign extension
+            let mut bytes = match content[0] & 0x80 {
+                0 => [0; mem::size_of::<i64>()],
+                _ => [0xff; mem::size_of::<i64>()],
+            };
+            bytes[mem::size_of::<i64>() - content.len()..].copy_from_slice(content);
+            ValueRef::Integer(i64::from_be_bytes(bytes))
+        }
+        serial if serial % 2 == 0 => ValueRef::Blob(content),
+        _ => ValueRef::Text(content),
+    }
+}
+
+pub fn put_varint(buf: &mut Vec<u8>, value: u64) {
+    if value > MAX_SHORT_VARINT {
+        for shift in (1..MAX_VARINT_SIZE).rev().map(|index| 8 + 7 * (index - 1)) {
+            buf.push((value >> shift) as u8 | 0x80);
+        }
+        buf.push(value as u8);
+        return;
+    }
+
+    let size = varint_size(value);
+    for index in (0..size).rev() {
+        let byte = (value >> (7 * index)) as u8 & 0x7f;
+        buf.push(if index == 0 { byte } else { byte | 0x80 });
+    }
+}
+
+// Returns the value along with the number of bytes it took, or `None` when `buf` ends in the middle of it
+pub fn read_varint(buf: &[u8]) -> Option<(u64, usize)> {
+    let mut value = 0;
+    for (index, &byte) in buf.iter().take(MAX_VARINT_SIZE).enumerate() {
+        if index == MAX_VARINT_SIZE - 1 {
+            return Some(((value << 8) | byte as u64, MAX_VARINT_SIZE));
+        }
+        value = (value << 7) | (byte & 0x7f) as u64;
+        if byte & 0x80 == 0 {
+            return Some((value, index + 1));
+        }
+    }
+
+    None
+}
+
+pub fn varint_size(value: u64) -> usize {
+    match value {
+        0..=MAX_SHORT_VARINT => (u64::BITS - value.leading_zeros()).max(1).div_ceil(7) as usize,
+        _ => MAX_VARINT_SIZE,
+    }
+}
+
+fn serial_type(value: &ValueRef) -> u64 {
+    match *value {
+        ValueRef::Null => NULL,
+        ValueRef::Integer(0) => ZERO,
+        ValueRef::Integer(1) => ONE,
+        ValueRef::Integer(integer) => match integer {
+            -0x80..=0x7f => 1,
+            -0x8000..=0x7fff => 2,
+            -0x80_0000..=0x7f_ffff => 3,
+            -0x8000_0000..=0x7fff_ffff => 4,
+            -0x8000_0000_0000..=0x7fff_ffff_ffff => 5,
+            _ => 6,
+        },
+        ValueRef::Real(_) => REAL,
+        ValueRef::Blob(blob) => BLOB + 2 * blob.len() as u64,
+        ValueRef::Text(text) => TEXT + 2 * text.len() as u64,
+    }
+}
+
+fn content_size(serial: u64) -> usize {
+    match serial {
+        NULL | ZERO | ONE | 10 | 11 => 0,
+        1..=4 => serial as usize,
+        5 => 6,
+        6 | REAL => 8,
+        serial => ((serial - BLOB) / 2) as usize,
+    }
+}
+
+fn malformed() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "Malformed record")
+}
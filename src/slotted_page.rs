@@ -0,0 +1,308 @@
+//! In-page format used to store variable-length records (cells) inside a fixed-size page
+//! A slotted page starts with a small header followed by the slot directory, which grows towards the end of the page.
+//! Cells are written from the end of the page backwards, so the free space sits between the last slot and the first
+//! cell:
+//! | header | slot 0 | slot 1 | ... | free space | ... | cell 1 | cell 0 |
+//! Each slot holds the offset and length of its cell. Slots are kept in the order given by the caller, which lets
+//! users such as B-trees keep their cells sorted by moving slots around rather than cells.
+//! Deleting or shrinking a cell leaves a hole in the cell area. Holes are only accounted for and get reclaimed by
+//! compacting the page once an insertion no longer fits in the contiguous free space
+
+use std::{cmp::Reverse, error::Error, fmt, mem};
+
+// | page type (1) | flags (1) | slot count (2) | cell content start (2) | fragmented bytes (2) | right pointer (4) |
+pub const PAGE_HEADER_SIZE: usize = 12;
+// | cell offset (2) | cell length (2) |
+pub const SLOT_SIZE: usize = 2 * mem::size_of::<u16>();
+
+const PAGE_TYPE_OFFSET: usize = 0;
+const FLAGS_OFFSET: usize = 1;
+const SLOT_COUNT_OFFSET: usize = 2;
+const CONTENT_START_OFFSET: usize = 4;
+const FRAGMENTED_OFFSET: usize = 6;
+const RIGHT_POINTER_OFFSET: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageFull;
+impl fmt::Display for PageFull {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Not enough free space in page")
+    }
+}
+impl Error for PageFull {}
+
+#[derive(Debug)]
+pub struct SlottedPage<B> {
+    buf: B,
+}
+
+impl<B: AsRef<[u8]>> SlottedPage<B> {
+    // Wraps a buffer that already holds a slotted page
+    pub fn new(buf: B) -> Self {
+        Self { buf }
+    }
+
+    pub fn into_inner(self) -> B {
+        self.buf
+    }
+
+    pub fn page_type(&self) -> u8 {
+        self.buf.as_ref()[PAGE_TYPE_OFFSET]
+    }
+
+    pub fn flags(&self) -> u8 {
+        self.buf.as_ref()[FLAGS_OFFSET]
+    }
+
+    // Free-form page pointer living in the header, e.g. the right-most child of a B-tree interior page
+    pub fn right_pointer(&self) -> u32 {
+        self.read_u32(RIGHT_POINTER_OFFSET)
+    }
+
+    pub fn len(&self) -> usize {
+        self.read_u16(SLOT_COUNT_OFFSET) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn cell(&self, index: usize) -> &[u8] {
+        let (offset, len) = self.slot(index);
+        &self.buf.as_ref()[offset..offset + len]
+    }
+
+    pub fn cells(&self) -> impl Iterator<Item = &[u8]> {
+        (0..self.len()).map(|index| self.cell(index))
+    }
+
+    // Bytes available for new cells and their slots, holes included
+    pub fn free_space(&self) -> usize {
+        self.contiguous_free_space() + self.fragmented_bytes()
+    }
+
+    pub fn fits(&self, cell_len: usize) -> bool {
+        cell_len + SLOT_SIZE <= self.free_space()
+    }
+
+    // Largest cell that can be stored in an empty page of `page_len` bytes
+    pub fn max_cell_size(page_len: usize) -> usize {
+        page_len - PAGE_HEADER_SIZE - SLOT_SIZE
+    }
+
+    fn contiguous_free_space(&self) -> usize {
+        self.content_start() - self.slots_end()
+    }
+
+    fn fragmented_bytes(&self) -> usize {
+        self.read_u16(FRAGMENTED_OFFSET) as usize
+    }
+
+    fn slots_end(&self) -> usize {
+        PAGE_HEADER_SIZE + self.len() * SLOT_SIZE
+    }
+
+    // A 65536 bytes empty page would need 65536 to be stored here, which is encoded as 0
+    fn content_start(&self) -> usize {
+        match self.read_u16(CONTENT_START_OFFSET) {
+            0 => self.buf.as_ref().len(),
+            start => start as usize,
+        }
+    }
+
+    fn slot(&self, index: usize) -> (usize, usize) {
+        assert!(index < self.len(), "Slot {index} out of bounds");
+        let offset = PAGE_HEADER_SIZE + index * SLOT_SIZE;
+        (
+            self.read_u16(offset) as usize,
+            self.read_u16(offset + mem::size_of::<u16>()) as usize,
+        )
+    }
+
+    fn read_u16(&self, offset: usize) -> u16 {
+        u16::from_le_bytes(
+            self.buf.as_ref()[offset..offset + mem::size_of::<u16>()]
+                .try_into()
+                .expect("Invalid size"),
+        )
+    }
+
+    fn read_u32(&self, offset: usize) -> u32 {
+        u32::from_le_bytes(
+            self.buf.as_ref()[offset..offset + mem::size_of::<u32>()]
+                .try_into()
+                .expect("Invalid size"),
+        )
+    }
+}
+
+impl<B: AsRef<[u8]> + AsMut<[u8]>> SlottedPage<B> {
+    // Formats `buf` as an empty slotted page
+    pub fn init(mut buf: B, page_type: u8) -> Self {
+        let page = buf.as_mut();
+        assert!(
+            page.len() > PAGE_HEADER_SIZE && page.len() <= u16::MAX as usize + 1,
+            "Invalid page size {}",
+            page.len()
+        );
+        page[..PAGE_HEADER_SIZE].fill(0);
+
+        let mut page = Self { buf };
+        page.set_page_type(page_type);
+        let end = page.buf.as_ref().len();
+        page.set_content_start(end);
+        page
+    }
+
+    pub fn set_page_type(&mut self, page_type: u8) {
+        self.buf.as_mut()[PAGE_TYPE_OFFSET] = page_type;
+    }
+
+    pub fn set_flags(&mut self, flags: u8) {
+        self.buf.as_mut()[FLAGS_OFFSET] = flags;
+    }
+
+    pub fn set_right_pointer(&mut self, pointer: u32) {
+        self.write_u32(RIGHT_POINTER_OFFSET, pointer);
+    }
+
+    // Stores `cell` so that it ends up at position `index`, shifting the following cells by one
+    pub fn insert(&mut self, index: usize, cell: &[u8]) -> Result<(), PageFull> {
+        let len = self.len();
+        assert!(index <= len, "Slot {index} out of bounds");
+        if !self.fits(cell.len()) {
+            return Err(PageFull);
+        }
+
+        let offset = self.allocate(cell.len(), SLOT_SIZE);
+        self.buf.as_mut()[offset..offset + cell.len()].copy_from_slice(cell);
+
+        let slots = PAGE_HEADER_SIZE + index * SLOT_SIZE;
+        let slots_end = self.slots_end();
+        self.buf
+            .as_mut()
+            .copy_within(slots..slots_end, slots + SLOT_SIZE);
+        self.write_u16(SLOT_COUNT_OFFSET, len as u16 + 1);
+        self.set_slot(index, offset, cell.len());
+
+        Ok(())
+    }
+
+    pub fn push(&mut self, cell: &[u8]) -> Result<(), PageFull> {
+        self.insert(self.len(), cell)
+    }
+
+    // Removes the cell at `index`, shifting the following cells by one
+    pub fn delete(&mut self, index: usize) {
+        let (offset, len) = self.slot(index);
+        self.release(offset, len);
+
+        let slots = PAGE_HEADER_SIZE + index * SLOT_SIZE;
+        let slots_end = self.slots_end();
+        self.buf
+            .as_mut()
+            .copy_within(slots + SLOT_SIZE..slots_end, slots);
+        self.write_u16(SLOT_COUNT_OFFSET, self.len() as u16 - 1);
+    }
+
+    // Replaces the cell at `index`. A cell that does not grow is rewritten in place, otherwise it is moved
+    pub fn update(&mut self, index: usize, cell: &[u8]) -> Result<(), PageFull> {
+        let (offset, len) = self.slot(index);
+
+        if cell.len() <= len {
+            self.buf.as_mut()[offset..offset + cell.len()].copy_from_slice(cell);
+            self.release(offset + cell.len(), len - cell.len());
+            self.set_slot(index, offset, cell.len());
+            return Ok(());
+        }
+
+        if cell.len() > self.free_space() + len {
+            return Err(PageFull);
+        }
+
+        // The old cell is released first so that a compaction can reuse its space
+        self.release(offset, len);
+        self.set_slot(index, 0, 0);
+        let offset = self.allocate(cell.len(), 0);
+        self.buf.as_mut()[offset..offset + cell.len()].copy_from_slice(cell);
+        self.set_slot(index, offset, cell.len());
+
+        Ok(())
+    }
+
+    // Removes every cell
+    pub fn clear(&mut self) {
+        let end = self.buf.as_ref().len();
+        self.write_u16(SLOT_COUNT_OFFSET, 0);
+        self.write_u16(FRAGMENTED_OFFSET, 0);
+        self.set_content_start(end);
+    }
+
+    // Moves every cell to the end of the page so that all the free space becomes contiguous
+    pub fn compact(&mut self) {
+        let mut slots: Vec<(usize, usize, usize)> = (0..self.len())
+            .map(|index| {
+                let (offset, len) = self.slot(index);
+                (offset, len, index)
+            })
+            .filter(|&(_, len, _)| len > 0)
+            .collect();
+        // Cells are moved starting with the one closest to the end of the page, so a cell is never moved over
+        // another one that has not been moved yet
+        slots.sort_unstable_by_key(|&(offset, _, _)| Reverse(offset));
+
+        let mut end = self.buf.as_ref().len();
+        for (offset, len, index) in slots {
+            end -= len;
+            self.buf.as_mut().copy_within(offset..offset + len, end);
+            self.set_slot(index, end, len);
+        }
+
+        self.set_content_start(end);
+        self.write_u16(FRAGMENTED_OFFSET, 0);
+    }
+
+    // Reserves `len` bytes of cell area while keeping `extra` bytes free for the slot directory
+    fn allocate(&mut self, len: usize, extra: usize) -> usize {
+        if self.contiguous_free_space() < len + extra {
+            self.compact();
+        }
+
+        let offset = self.content_start() - len;
+        self.set_content_start(offset);
+        offset
+    }
+
+    fn release(&mut self, offset: usize, len: usize) {
+        if len == 0 {
+            return;
+        }
+
+        // Cells sitting right at the start of the cell area are given back to the contiguous free space
+        if offset == self.content_start() {
+            self.set_content_start(offset + len);
+        } else {
+            self.write_u16(FRAGMENTED_OFFSET, (self.fragmented_bytes() + len) as u16);
+        }
+    }
+
+    fn set_content_start(&mut self, start: usize) {
+        self.write_u16(CONTENT_START_OFFSET, start as u16);
+    }
+
+    fn set_slot(&mut self, index: usize, offset: usize, len: usize) {
+        let slot = PAGE_HEADER_SIZE + index * SLOT_SIZE;
+        self.write_u16(slot, offset as u16);
+        self.write_u16(slot + mem::size_of::<u16>(), len as u16);
+    }
+
+    fn write_u16(&mut self, offset: usize, value: u16) {
+        self.buf.as_mut()[offset..offset + mem::size_of::<u16>()]
+            .copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn write_u32(&mut self, offset: usize, value: u32) {
+        self.buf.as_mut()[offset..offset + mem::size_of::<u32>()]
+            .copy_from_slice(&value.to_le_bytes());
+    }
+}
@@ -0,0 +1,85 @@
+//! The SQL front end: `lexer` splits the text of statements into tokens, which `parser` turns into the syntax trees of
+//! `ast`. Every token and node of the tree records the span of text it comes from, so that errors can point at it
+//! `compiler` then turns a statement into a `program` of instructions, which the `vm` runs over the pager, yielding its
+//! result rows one at a time
+
+pub mod ast;
+pub mod compiler;
+pub mod functions;
+pub mod lexer;
+pub mod parser;
+pub mod program;
+pub mod schema;
+pub mod vm;
+
+use std::{error::Error, fmt, io};
+
+pub use compiler::compile;
+pub use parser::{parse, parse_statement};
+pub use program::Program;
+pub use vm::Vm;
+
+// Byte range of the text of a token or a node, from `start` included to `end` excluded
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    // The span covering both `self` and `other`, along with anything in between
+    pub fn to(self, other: Span) -> Span {
+        Span::new(self.start.min(other.start), self.end.max(other.end))
+    }
+
+    // The text of the span within `sql`, the text it was taken from
+    pub fn text(self, sql: &str) -> &str {
+        &sql[self.start..self.end]
+    }
+}
+
+// Text that is not valid SQL, or that uses SQL not supported yet. It travels as the payload of an `InvalidInput` error
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntaxError {
+    pub message: String,
+    pub span: Span,
+    // Where the span starts in the text, both counted from 1
+    pub line: usize,
+    pub column: usize,
+}
+
+// Error pointing at `span` of `sql`
+pub(crate) fn syntax_error(sql: &str, span: Span, message: String) -> io::Error {
+    let before = &sql[..span.start];
+    let line = before.matches('\n').count() + 1;
+    let column = match before.rfind('\n') {
+        Some(newline) => before[newline + 1..].chars().count() + 1,
+        None => before.chars().count() + 1,
+    };
+
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        SyntaxError {
+            message,
+            span,
+            line,
+            column,
+        },
+    )
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at line {}, column {}",
+            self.message, self.line, self.column
+        )
+    }
+}
+
+impl Error for SyntaxError {}
@@ -0,0 +1,377 @@
+//! Syntax trees of SQL statements, as produced by `parser`. Names keep the case they were written in, comparing them is
+//! up to the layers above, which treat them as case-insensitive
+//! Nodes record the span of the text they were parsed from, the span of a statement covering the whole statement
+//! without its final semicolon
+
+use std::iter;
+
+use crate::{key::SortOrder, sql::Span, value::Value};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+    CreateTable(CreateTable),
+    CreateIndex(CreateIndex),
+    DropTable(Drop),
+    DropIndex(Drop),
+    Insert(Insert),
+    Select(Box<Select>),
+    Update(Update),
+    Delete(Delete),
+    Explain(Explain),
+}
+
+impl Statement {
+    pub fn span(&self) -> Span {
+        match self {
+            Statement::CreateTable(statement) => statement.span,
+            Statement::CreateIndex(statement) => statement.span,
+            Statement::DropTable(statement) | Statement::DropIndex(statement) => statement.span,
+            Statement::Insert(statement) => statement.span,
+            Statement::Select(statement) => statement.span,
+            Statement::Update(statement) => statement.span,
+            Statement::Delete(statement) => statement.span,
+            Statement::Explain(statement) => statement.span,
+        }
+    }
+}
+
+// EXPLAIN followed by the statement whose program is listed instead of being run
+#[derive(Debug, Clone, PartialEq)]
+pub struct Explain {
+    pub statement: Box<Statement>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ident {
+    pub name: String,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreateTable {
+    pub name: Ident,
+    pub if_not_exists: bool,
+    pub columns: Vec<ColumnDef>,
+    pub constraints: Vec<TableConstraint>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnDef {
+    pub name: Ident,
+    // As written, such as `VARCHAR(20)`. Columns may have no type
+    pub type_name: Option<String>,
+    pub constraints: Vec<ColumnConstraint>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ColumnConstraint {
+    PrimaryKey(SortOrder),
+    NotNull,
+    Unique,
+    Default(Expr),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TableConstraint {
+    PrimaryKey(Vec<IndexedColumn>),
+    Unique(Vec<IndexedColumn>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexedColumn {
+    pub name: Ident,
+    pub order: SortOrder,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CreateIndex {
+    pub name: Ident,
+    pub if_not_exists: bool,
+    pub unique: bool,
+    pub table: Ident,
+    pub columns: Vec<IndexedColumn>,
+    pub span: Span,
+}
+
+// DROP TABLE or DROP INDEX
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Drop {
+    pub name: Ident,
+    pub if_exists: bool,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Insert {
+    pub table: Ident,
+    // Empty when the statement does not list the columns, all of them being given in order
+    pub columns: Vec<Ident>,
+    pub source: InsertSource,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InsertSource {
+    Values(Vec<Vec<Expr>>),
+    Select(Box<Select>),
+    DefaultValues,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Select {
+    pub distinct: bool,
+    pub columns: Vec<ResultColumn>,
+    pub from: Option<FromClause>,
+    pub where_clause: Option<Expr>,
+    pub group_by: Vec<Expr>,
+    pub having: Option<Expr>,
+    pub order_by: Vec<OrderingTerm>,
+    pub limit: Option<Limit>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResultColumn {
+    // `*`
+    All,
+    // `table.*`
+    AllOf(Ident),
+    Expr { expr: Expr, alias: Option<Ident> },
+}
+
+// The tables of a FROM clause, joined from left to right
+#[derive(Debug, Clone, PartialEq)]
+pub struct FromClause {
+    pub first: TableRef,
+    pub joins: Vec<Join>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TableRef {
+    Table {
+        name: Ident,
+        alias: Option<Ident>,
+        span: Span,
+    },
+    Subquery {
+        select: Box<Select>,
+        alias: Option<Ident>,
+        span: Span,
+    },
+}
+
+impl TableRef {
+    pub fn span(&self) -> Span {
+        match self {
+            TableRef::Table { span, .. } | TableRef::Subquery { span, .. } => *span,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Join {
+    pub kind: JoinKind,
+    pub table: TableRef,
+    pub constraint: Option<JoinConstraint>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinKind {
+    // `JOIN`, `INNER JOIN`
+    Inner,
+    // `LEFT JOIN`, `LEFT OUTER JOIN`
+    Left,
+    // `,`, `CROSS JOIN`
+    Cross,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum JoinConstraint {
+    On(Expr),
+    Using(Vec<Ident>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderingTerm {
+    pub expr: Expr,
+    pub order: SortOrder,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Limit {
+    pub limit: Expr,
+    pub offset: Option<Expr>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Update {
+    pub table: Ident,
+    pub assignments: Vec<Assignment>,
+    pub where_clause: Option<Expr>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Assignment {
+    pub column: Ident,
+    pub value: Expr,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Delete {
+    pub table: Ident,
+    pub where_clause: Option<Expr>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+impl Expr {
+    // Operands of the expression, those of its subqueries aside
+    pub fn children(&self) -> Vec<&Expr> {
+        match &self.kind {
+            ExprKind::Literal(_)
+            | ExprKind::Column { .. }
+            | ExprKind::Parameter(_)
+            | ExprKind::Exists(_)
+            | ExprKind::Subquery(_) => vec![],
+            ExprKind::Unary { operand, .. } => vec![operand],
+            ExprKind::Binary { left, right, .. } => vec![left, right],
+            ExprKind::Like { expr, pattern, .. } => vec![expr, pattern],
+            ExprKind::Between {
+                expr, low, high, ..
+            } => vec![expr, low, high],
+            ExprKind::InList { expr, list, .. } => iter::once(&**expr).chain(list).collect(),
+            ExprKind::InSelect { expr, .. } | ExprKind::Cast { expr, .. } => vec![expr],
+            ExprKind::Function { args, .. } => match args {
+                FunctionArgs::Star => vec![],
+                FunctionArgs::List(args) => args.iter().collect(),
+            },
+            ExprKind::Case {
+                operand,
+                branches,
+                else_result,
+            } => operand
+                .iter()
+                .map(|operand| &**operand)
+                .chain(branches.iter().flat_map(|(when, then)| [when, then]))
+                .chain(else_result.iter().map(|result| &**result))
+                .collect(),
+        }
+    }
+
+    // Number of nodes on the longest path from the expression down to its leaves, subqueries counting as leaves
+    pub fn height(&self) -> usize {
+        1 + self
+            .children()
+            .into_iter()
+            .map(Expr::height)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprKind {
+    Literal(Value),
+    Column {
+        table: Option<Ident>,
+        name: Ident,
+    },
+    // Numbered from 1
+    Parameter(u32),
+    Unary {
+        op: UnaryOp,
+        operand: Box<Expr>,
+    },
+    Binary {
+        op: BinaryOp,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    Like {
+        expr: Box<Expr>,
+        pattern: Box<Expr>,
+        negated: bool,
+    },
+    Between {
+        expr: Box<Expr>,
+        low: Box<Expr>,
+        high: Box<Expr>,
+        negated: bool,
+    },
+    InList {
+        expr: Box<Expr>,
+        list: Vec<Expr>,
+        negated: bool,
+    },
+    InSelect {
+        expr: Box<Expr>,
+        select: Box<Select>,
+        negated: bool,
+    },
+    Exists(Box<Select>),
+    // A subquery whose first column of its first row is the value
+    Subquery(Box<Select>),
+    Function {
+        name: Ident,
+        distinct: bool,
+        args: FunctionArgs,
+    },
+    Case {
+        operand: Option<Box<Expr>>,
+        // WHEN and THEN expressions
+        branches: Vec<(Expr, Expr)>,
+        else_result: Option<Box<Expr>>,
+    },
+    Cast {
+        expr: Box<Expr>,
+        type_name: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FunctionArgs {
+    // As in `count(*)`
+    Star,
+    List(Vec<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Neg,
+    Plus,
+    Not,
+    BitNot,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Or,
+    And,
+    Eq,
+    NotEq,
+    Is,
+    IsNot,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    BitAnd,
+    BitOr,
+    ShiftLeft,
+    ShiftRight,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Concat,
+}
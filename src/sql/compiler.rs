@@ -0,0 +1,2615 @@
+//! Compiles statements into the programs of `program`, against the schema of the catalog
+//! Queries become nested loops, one per table of the FROM clause, the first table being the outermost loop. A loop
+//! walks its whole table unless the WHERE clause compares the rowid, or the first columns of an index, to values known
+//! before the loop starts, in which case it seeks them instead. The WHERE clause is checked in the innermost loop
+//! Rows are sorted, grouped and freed of duplicates with ephemeral cursors. Grouped rows are sorted on their GROUP BY
+//! values before being aggregated group after group
+//! Subqueries are compiled inline, and run again each time their value is needed. UPDATE and DELETE first collect the
+//! rowids of the rows they change, so that the table is not modified while being walked through
+
+use std::{collections::HashMap, io, rc::Rc};
+
+use crate::{
+    btree::TreeKind,
+    catalog::{Catalog, ObjectKind},
+    key::SortOrder,
+    pager::Pager,
+    sql::{
+        ast::*,
+        functions::{Affinity, AggregateFunction, ScalarFunction},
+        program::{CursorId, Insn, KeyInfo, Program, Register, Root},
+        schema::{self, IndexDef, Table},
+        syntax_error, Span,
+    },
+    value::Value,
+};
+
+// Compiles `statement`, parsed from `sql`, against the current schema of the database
+pub fn compile(pager: &mut Pager, sql: &str, statement: &Statement) -> io::Result<Program> {
+    let catalog = pager.catalog()?.clone();
+    let mut compiler = Compiler::new(sql, catalog);
+
+    let (statement, explain) = match statement {
+        Statement::Explain(explain) => (&*explain.statement, true),
+        statement => (statement, false),
+    };
+    let columns = compiler.statement(statement)?;
+    Ok(compiler.finish(columns, explain))
+}
+
+// Position in the program, bound once known
+#[derive(Debug, Clone, Copy)]
+struct Label(usize);
+
+enum ColumnRef {
+    Column(usize),
+    Rowid,
+}
+
+// A table, or subquery, of a FROM clause
+struct Source {
+    // Name its columns are qualified with, its alias if it has one
+    name: String,
+    columns: Vec<String>,
+    cursor: CursorId,
+    // Subqueries have no rowid
+    table: Option<Table>,
+    // Whether it is the right side of a LEFT JOIN, and the condition it is joined on
+    left: bool,
+    on: Vec<Expr>,
+    // Registers its columns, followed by its rowid, were copied to. They are read instead of the cursor once set
+    registers: Option<Register>,
+}
+
+// The values sought come with the affinity they are compared with
+enum Access {
+    Scan,
+    Rowid(Expr, Option<Affinity>),
+    // The index, along with the values of its first columns
+    Index(IndexDef, Vec<(Expr, Option<Affinity>)>),
+}
+
+// Loop over the rows of a source
+struct Loop {
+    // Cursor moved by `Next`, if the loop can have more than one row
+    next: Option<CursorId>,
+    top: Label,
+    // Goes on with the next row
+    cont: Label,
+    exit: Label,
+    left: Option<LeftJoin>,
+}
+
+// State of a loop on the right side of a LEFT JOIN, which goes through a row of NULLs when no row matches
+struct LeftJoin {
+    cursor: CursorId,
+    matched: Register,
+    null_row: Register,
+    body: Label,
+}
+
+// Where the rows of a query go
+#[derive(Clone, Copy)]
+enum Destination {
+    Output,
+    // Added to an ephemeral cursor, sorted on their first `key_count` values
+    Ephemeral { cursor: CursorId, key_count: usize },
+    // Sets `dest` to 1 on the first row, and jumps to `done`
+    Exists { dest: Register, done: Label },
+    // Copies the first column of the first row to `dest`, and jumps to `done`
+    Scalar { dest: Register, done: Label },
+}
+
+// How the result rows of a query are produced
+struct Output<'a> {
+    results: &'a [Expr],
+    names: &'a [String],
+    order_by: &'a [OrderingTerm],
+    sorter: Option<CursorId>,
+    distinct: Option<CursorId>,
+    limit: Option<Register>,
+    offset: Option<Register>,
+    destination: Destination,
+    end: Label,
+}
+
+struct Compiler<'a> {
+    sql: &'a str,
+    catalog: Catalog,
+    insns: Vec<Insn>,
+    // Addresses of the labels, the targets of jumps being labels until `finish`
+    labels: Vec<Option<usize>>,
+    registers: usize,
+    cursors: usize,
+    aggregates: usize,
+    parameters: u32,
+    // Sources of the queries being compiled, from the outermost
+    scopes: Vec<Vec<Source>>,
+    // Registers holding the values of aggregate calls, once computed
+    aggregate_results: HashMap<*const Expr, Register>,
+    // Names of the result columns of the innermost query, along with their expressions. Columns that are not found
+    // in its sources may be one of them
+    aliases: Option<(Vec<String>, Rc<Vec<Expr>>)>,
+}
+
+impl<'a> Compiler<'a> {
+    fn new(sql: &'a str, catalog: Catalog) -> Self {
+        let mut compiler = Self {
+            sql,
+            catalog,
+            insns: vec![],
+            labels: vec![],
+            registers: 0,
+            cursors: 0,
+            aggregates: 0,
+            parameters: 0,
+            scopes: vec![],
+            aggregate_results: HashMap::new(),
+            aliases: None,
+        };
+        // Bound in `finish`
+        compiler.label();
+        compiler.insns.push(Insn::Init { target: 0 });
+        compiler
+    }
+
+    fn finish(mut self, columns: Vec<String>, explain: bool) -> Program {
+        self.emit(Insn::Halt);
+        self.bind(Label(0));
+        self.emit(Insn::VerifyCookie {
+            cookie: self.catalog.schema_cookie(),
+        });
+        self.emit(Insn::Goto { target: 1 });
+        let goto = self.insns.len() - 1;
+
+        for (addr, insn) in self.insns.iter_mut().enumerate() {
+            if addr == goto {
+                continue;
+            }
+            if let Some(target) = insn.target_mut() {
+                *target = self.labels[*target].expect("Every label is bound");
+            }
+        }
+
+        Program {
+            insns: self.insns,
+            registers: self.registers,
+            cursors: self.cursors,
+            aggregates: self.aggregates,
+            parameters: self.parameters,
+            columns,
+            explain,
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) -> io::Result<Vec<String>> {
+        match statement {
+            Statement::CreateTable(create) => self.create_table(create)?,
+            Statement::CreateIndex(create) => self.create_index(create)?,
+            Statement::DropTable(drop) => self.drop_table(drop)?,
+            Statement::DropIndex(drop) => self.drop_index(drop)?,
+            Statement::Insert(insert) => self.insert(insert)?,
+            Statement::Select(select) => return self.select(select, Destination::Output),
+            Statement::Update(update) => self.update(update)?,
+            Statement::Delete(delete) => self.delete(delete)?,
+            Statement::Explain(explain) => {
+                return Err(self.error(explain.span, "EXPLAIN can only start a statement"))
+            }
+        }
+
+        Ok(vec![])
+    }
+
+    fn create_table(&mut self, create: &CreateTable) -> io::Result<()> {
+        let name = &create.name.name;
+        if let Some(object) = self.catalog.get(name) {
+            if create.if_not_exists && object.kind == ObjectKind::Table {
+                return Ok(());
+            }
+            return Err(self.error(
+                create.name.span,
+                &format!("there is already {} named {name}", article(object.kind)),
+            ));
+        }
+        let definition = schema::define(self.sql, create)?;
+
+        let root = self.register();
+        self.emit(Insn::CreateTree {
+            kind: TreeKind::Table,
+            dest: root,
+        });
+        self.emit(Insn::AddToCatalog {
+            kind: ObjectKind::Table,
+            name: name.clone(),
+            table: name.clone(),
+            root: Some(root),
+            sql: create.span.text(self.sql).to_string(),
+        });
+        for number in 1..=definition.automatic_indexes.len() {
+            self.emit(Insn::CreateTree {
+                kind: TreeKind::Index,
+                dest: root,
+            });
+            self.emit(Insn::AddToCatalog {
+                kind: ObjectKind::Index,
+                name: schema::automatic_index_name(name, number),
+                table: name.clone(),
+                root: Some(root),
+                sql: String::new(),
+            });
+        }
+
+        Ok(())
+    }
+
+    fn create_index(&mut self, create: &CreateIndex) -> io::Result<()> {
+        let name = &create.name.name;
+        if let Some(object) = self.catalog.get(name) {
+            if create.if_not_exists && object.kind == ObjectKind::Index {
+                return Ok(());
+            }
+            return Err(self.error(
+                create.name.span,
+                &format!("there is already {} named {name}", article(object.kind)),
+            ));
+        }
+        let table = self.table(&create.table)?;
+        let index = IndexDef {
+            name: name.clone(),
+            root: 0,
+            unique: create.unique,
+            columns: schema::indexed_columns(self.sql, &table.columns, &create.columns)?,
+            orders: create.columns.iter().map(|column| column.order).collect(),
+            automatic: false,
+        };
+
+        let root = self.register();
+        self.emit(Insn::CreateTree {
+            kind: TreeKind::Index,
+            dest: root,
+        });
+        self.emit(Insn::AddToCatalog {
+            kind: ObjectKind::Index,
+            name: name.clone(),
+            table: table.name.clone(),
+            root: Some(root),
+            sql: create.span.text(self.sql).to_string(),
+        });
+
+        // Indexes the rows already in the table
+        let index_cursor = self.cursor();
+        self.emit(Insn::OpenWrite {
+            cursor: index_cursor,
+            root: Root::Register(root),
+            key_info: Some(key_info(&index)),
+        });
+        let conflict = unique_conflict(&table, &index.columns);
+        let cursor = self.open_table(&table, false);
+        self.scopes
+            .push(vec![self.table_source(table, cursor, None)]);
+
+        let (top, done) = (self.label(), self.label());
+        self.emit(Insn::Rewind {
+            cursor,
+            target: done.0,
+        });
+        self.bind(top);
+        let row = self.copy_row(0);
+        let columns = self.scopes.last().expect("Scope of the table")[0]
+            .columns
+            .len();
+        let key = self.index_key(&index, None, row, row + columns);
+        self.emit(Insn::IdxInsert {
+            cursor: index_cursor,
+            start: key,
+            count: index.columns.len(),
+            rowid: row + columns,
+            conflict,
+        });
+        self.emit(Insn::Next {
+            cursor,
+            target: top.0,
+        });
+        self.bind(done);
+
+        self.scopes.pop();
+        Ok(())
+    }
+
+    fn drop_table(&mut self, drop: &Drop) -> io::Result<()> {
+        let Some(table) = schema::table(&self.catalog, &drop.name.name)? else {
+            if drop.if_exists {
+                return Ok(());
+            }
+            return Err(self.error(
+                drop.name.span,
+                &format!("no such table: {}", drop.name.name),
+            ));
+        };
+
+        for index in &table.indexes {
+            self.emit(Insn::DestroyTree { root: index.root });
+            self.emit(Insn::RemoveFromCatalog {
+                name: index.name.clone(),
+            });
+        }
+        self.emit(Insn::DestroyTree { root: table.root });
+        self.emit(Insn::RemoveFromCatalog { name: table.name });
+        Ok(())
+    }
+
+    fn drop_index(&mut self, drop: &Drop) -> io::Result<()> {
+        let index = match self.catalog.get(&drop.name.name) {
+            Some(object) if object.kind == ObjectKind::Index => object.clone(),
+            _ if drop.if_exists => return Ok(()),
+            _ => {
+                return Err(self.error(
+                    drop.name.span,
+                    &format!("no such index: {}", drop.name.name),
+                ))
+            }
+        };
+        if index.sql.is_empty() {
+            return Err(self.error(
+                drop.name.span,
+                "index associated with UNIQUE or PRIMARY KEY constraint cannot be dropped",
+            ));
+        }
+
+        self.emit(Insn::DestroyTree { root: index.root });
+        self.emit(Insn::RemoveFromCatalog { name: index.name });
+        Ok(())
+    }
+
+    fn insert(&mut self, insert: &Insert) -> io::Result<()> {
+        let table = self.table(&insert.table)?;
+        let targets = match insert.columns.is_empty() {
+            true => (0..table.columns.len()).collect(),
+            false => insert
+                .columns
+                .iter()
+                .map(|column| {
+                    table.column(&column.name).ok_or_else(|| {
+                        self.error(
+                            column.span,
+                            &format!("table {} has no column named {}", table.name, column.name),
+                        )
+                    })
+                })
+                .collect::<io::Result<Vec<usize>>>()?,
+        };
+        let mut provided = vec![false; table.columns.len()];
+        for &target in &targets {
+            provided[target] = true;
+        }
+        let count_error = |compiler: &Self, count: usize, span: Span| {
+            let message = match insert.columns.is_empty() {
+                true => format!(
+                    "table {} has {} columns but {count} values were supplied",
+                    table.name,
+                    targets.len()
+                ),
+                false => format!("{count} values for {} columns", targets.len()),
+            };
+            compiler.error(span, &message)
+        };
+
+        let cursor = self.open_table(&table, true);
+        let index_cursors = self.open_indexes(&table);
+        let columns = self.registers(table.columns.len());
+
+        match &insert.source {
+            InsertSource::Values(rows) => {
+                for row in rows {
+                    if row.len() != targets.len() {
+                        let span = row[0].span.to(row[row.len() - 1].span);
+                        return Err(count_error(self, row.len(), span));
+                    }
+                    for (expr, &target) in row.iter().zip(&targets) {
+                        self.expr(expr, columns + target)?;
+                    }
+                    self.insert_row(&table, cursor, &index_cursors, columns, &provided)?;
+                }
+            }
+            InsertSource::Select(select) => {
+                // The rows are all selected before the first one is inserted, as they may come from the same table
+                let rows = self.cursor();
+                self.emit(Insn::OpenEphemeral {
+                    cursor: rows,
+                    orders: vec![],
+                });
+                let names = self.select(
+                    select,
+                    Destination::Ephemeral {
+                        cursor: rows,
+                        key_count: 0,
+                    },
+                )?;
+                if names.len() != targets.len() {
+                    return Err(count_error(self, names.len(), select.span));
+                }
+
+                let (top, done) = (self.label(), self.label());
+                self.emit(Insn::Rewind {
+                    cursor: rows,
+                    target: done.0,
+                });
+                self.bind(top);
+                for (column, &target) in targets.iter().enumerate() {
+                    self.emit(Insn::Column {
+                        cursor: rows,
+                        column,
+                        dest: columns + target,
+                    });
+                }
+                self.insert_row(&table, cursor, &index_cursors, columns, &provided)?;
+                self.emit(Insn::Next {
+                    cursor: rows,
+                    target: top.0,
+                });
+                self.bind(done);
+            }
+            InsertSource::DefaultValues => {
+                let provided = vec![false; table.columns.len()];
+                self.insert_row(&table, cursor, &index_cursors, columns, &provided)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Inserts the row whose values are in the registers from `columns`, those that are not `provided` taking their
+    // default value
+    fn insert_row(
+        &mut self,
+        table: &Table,
+        cursor: CursorId,
+        index_cursors: &[CursorId],
+        columns: Register,
+        provided: &[bool],
+    ) -> io::Result<()> {
+        for (position, column) in table.columns.iter().enumerate() {
+            if provided[position] {
+                continue;
+            }
+            match &column.default {
+                Some(default) => self.expr(default, columns + position)?,
+                None => self.null(columns + position),
+            }
+        }
+
+        let rowid = self.register();
+        match table.rowid_alias {
+            Some(alias) => {
+                let (given, ok) = (self.label(), self.label());
+                self.emit(Insn::NotNull {
+                    reg: columns + alias,
+                    target: given.0,
+                });
+                self.emit(Insn::NewRowid {
+                    cursor,
+                    dest: rowid,
+                });
+                self.emit(Insn::Goto { target: ok.0 });
+                self.bind(given);
+                self.emit(Insn::Copy {
+                    source: columns + alias,
+                    dest: rowid,
+                    count: 1,
+                });
+                self.emit(Insn::MustBeInt { reg: rowid });
+                self.emit(Insn::SeekRowid {
+                    cursor,
+                    rowid,
+                    target: ok.0,
+                });
+                self.unique_failed(table, alias);
+                self.bind(ok);
+                self.null(columns + alias);
+            }
+            None => {
+                self.emit(Insn::NewRowid {
+                    cursor,
+                    dest: rowid,
+                });
+            }
+        }
+
+        self.check_row(table, columns);
+        for (index, &index_cursor) in table.indexes.iter().zip(index_cursors) {
+            let key = self.index_key(index, table.rowid_alias, columns, rowid);
+            self.emit(Insn::IdxInsert {
+                cursor: index_cursor,
+                start: key,
+                count: index.columns.len(),
+                rowid,
+                conflict: unique_conflict(table, &index.columns),
+            });
+        }
+        let record = self.register();
+        self.emit(Insn::MakeRecord {
+            start: columns,
+            count: table.columns.len(),
+            dest: record,
+        });
+        self.emit(Insn::Insert {
+            cursor,
+            record,
+            rowid,
+        });
+
+        Ok(())
+    }
+
+    // Applies the affinities of the columns to the values about to be stored, then checks their NOT NULL constraints
+    fn check_row(&mut self, table: &Table, columns: Register) {
+        self.emit(Insn::Affinity {
+            start: columns,
+            affinities: table.columns.iter().map(|column| column.affinity).collect(),
+        });
+
+        for (position, column) in table.columns.iter().enumerate() {
+            if !column.not_null || table.rowid_alias == Some(position) {
+                continue;
+            }
+            let ok = self.label();
+            self.emit(Insn::NotNull {
+                reg: columns + position,
+                target: ok.0,
+            });
+            self.emit(Insn::Abort {
+                kind: io::ErrorKind::InvalidInput,
+                message: format!("NOT NULL constraint failed: {}.{}", table.name, column.name),
+            });
+            self.bind(ok);
+        }
+    }
+
+    fn unique_failed(&mut self, table: &Table, column: usize) {
+        self.emit(Insn::Abort {
+            kind: io::ErrorKind::AlreadyExists,
+            message: unique_conflict(table, &[column]),
+        });
+    }
+
+    fn update(&mut self, update: &Update) -> io::Result<()> {
+        let table = self.table(&update.table)?;
+        let mut assigned: Vec<Option<&Expr>> = vec![None; table.columns.len()];
+        for assignment in &update.assignments {
+            let position = table.column(&assignment.column.name).ok_or_else(|| {
+                self.error(
+                    assignment.column.span,
+                    &format!("no such column: {}", assignment.column.name),
+                )
+            })?;
+            assigned[position] = Some(&assignment.value);
+        }
+
+        let cursor = self.open_table(&table, true);
+        let index_cursors = self.open_indexes(&table);
+        self.scopes
+            .push(vec![self.table_source(table.clone(), cursor, None)]);
+        let rowids = self.collect_rowids(update.where_clause.as_ref())?;
+
+        let (top, next, done) = (self.label(), self.label(), self.label());
+        self.emit(Insn::Rewind {
+            cursor: rowids,
+            target: done.0,
+        });
+        self.bind(top);
+        let old_rowid = self.register();
+        self.emit(Insn::Column {
+            cursor: rowids,
+            column: 0,
+            dest: old_rowid,
+        });
+        self.emit(Insn::SeekRowid {
+            cursor,
+            rowid: old_rowid,
+            target: next.0,
+        });
+
+        let old = self.copy_row(0);
+        let new = self.registers(table.columns.len());
+        for (position, value) in assigned.iter().enumerate() {
+            match value {
+                Some(value) => self.expr(value, new + position)?,
+                None => self.emit_copy(old + position, new + position),
+            }
+        }
+
+        let new_rowid = self.register();
+        match table.rowid_alias {
+            Some(alias) if assigned[alias].is_some() => {
+                self.emit_copy(new + alias, new_rowid);
+                self.emit(Insn::MustBeInt { reg: new_rowid });
+
+                // The new rowid must not be taken by another row
+                let (same, moved) = (self.label(), self.label());
+                let unchanged = self.register();
+                self.emit(Insn::Binary {
+                    op: BinaryOp::Eq,
+                    left: new_rowid,
+                    right: old_rowid,
+                    dest: unchanged,
+                });
+                self.emit(Insn::If {
+                    reg: unchanged,
+                    target: same.0,
+                    jump_if_null: false,
+                });
+                self.emit(Insn::SeekRowid {
+                    cursor,
+                    rowid: new_rowid,
+                    target: moved.0,
+                });
+                self.unique_failed(&table, alias);
+                self.bind(moved);
+                self.emit(Insn::SeekRowid {
+                    cursor,
+                    rowid: old_rowid,
+                    target: next.0,
+                });
+                self.bind(same);
+            }
+            _ => self.emit_copy(old_rowid, new_rowid),
+        }
+        if let Some(alias) = table.rowid_alias {
+            self.null(new + alias);
+        }
+        self.check_row(&table, new);
+
+        let keys: Vec<Register> = table
+            .indexes
+            .iter()
+            .map(|index| self.index_key(index, table.rowid_alias, old, old_rowid))
+            .collect();
+        for ((index, &index_cursor), key) in table.indexes.iter().zip(&index_cursors).zip(keys) {
+            self.emit(Insn::IdxDelete {
+                cursor: index_cursor,
+                start: key,
+                count: index.columns.len(),
+                rowid: old_rowid,
+            });
+        }
+        self.emit(Insn::Delete { cursor });
+
+        let record = self.register();
+        self.emit(Insn::MakeRecord {
+            start: new,
+            count: table.columns.len(),
+            dest: record,
+        });
+        self.emit(Insn::Insert {
+            cursor,
+            record,
+            rowid: new_rowid,
+        });
+        for (index, &index_cursor) in table.indexes.iter().zip(&index_cursors) {
+            let key = self.index_key(index, table.rowid_alias, new, new_rowid);
+            self.emit(Insn::IdxInsert {
+                cursor: index_cursor,
+                start: key,
+                count: index.columns.len(),
+                rowid: new_rowid,
+                conflict: unique_conflict(&table, &index.columns),
+            });
+        }
+
+        self.bind(next);
+        self.emit(Insn::Next {
+            cursor: rowids,
+            target: top.0,
+        });
+        self.bind(done);
+
+        self.scopes.pop();
+        Ok(())
+    }
+
+    fn delete(&mut self, delete: &Delete) -> io::Result<()> {
+        let table = self.table(&delete.table)?;
+
+        let Some(where_clause) = &delete.where_clause else {
+            self.emit(Insn::Clear { root: table.root });
+            for index in &table.indexes {
+                self.emit(Insn::Clear { root: index.root });
+            }
+            return Ok(());
+        };
+
+        let cursor = self.open_table(&table, true);
+        let index_cursors = self.open_indexes(&table);
+        self.scopes
+            .push(vec![self.table_source(table.clone(), cursor, None)]);
+        let rowids = self.collect_rowids(Some(where_clause))?;
+
+        let (top, next, done) = (self.label(), self.label(), self.label());
+        self.emit(Insn::Rewind {
+            cursor: rowids,
+            target: done.0,
+        });
+        self.bind(top);
+        let rowid = self.register();
+        self.emit(Insn::Column {
+            cursor: rowids,
+            column: 0,
+            dest: rowid,
+        });
+        self.emit(Insn::SeekRowid {
+            cursor,
+            rowid,
+            target: next.0,
+        });
+        let row = self.copy_row(0);
+        for (index, &index_cursor) in table.indexes.iter().zip(&index_cursors) {
+            let key = self.index_key(index, None, row, rowid);
+            self.emit(Insn::IdxDelete {
+                cursor: index_cursor,
+                start: key,
+                count: index.columns.len(),
+                rowid,
+            });
+        }
+        self.emit(Insn::Delete { cursor });
+        self.bind(next);
+        self.emit(Insn::Next {
+            cursor: rowids,
+            target: top.0,
+        });
+        self.bind(done);
+
+        self.scopes.pop();
+        Ok(())
+    }
+
+    // Adds the rowids of the rows of the table in scope that match `where_clause` to a new ephemeral cursor
+    fn collect_rowids(&mut self, where_clause: Option<&Expr>) -> io::Result<CursorId> {
+        let rowids = self.cursor();
+        self.emit(Insn::OpenEphemeral {
+            cursor: rowids,
+            orders: vec![],
+        });
+
+        let loops = self.open_loops(where_clause)?;
+        let next = loops.last().expect("Loop over the table").cont;
+        if let Some(where_clause) = where_clause {
+            self.jump_if(where_clause, false, true, next)?;
+        }
+        let rowid = self.register();
+        let cursor = self.scopes.last().expect("Scope of the table")[0].cursor;
+        self.emit(Insn::Rowid {
+            cursor,
+            dest: rowid,
+        });
+        self.emit(Insn::EphemeralInsert {
+            cursor: rowids,
+            start: rowid,
+            count: 1,
+            key_count: 1,
+        });
+        self.close_loops(loops);
+
+        Ok(rowids)
+    }
+
+    // Compiles a query whose rows go to `destination`, and returns the names of its columns
+    fn select(&mut self, select: &Select, destination: Destination) -> io::Result<Vec<String>> {
+        let mut sources = vec![];
+        if let Some(from) = &select.from {
+            sources.push(self.source(&from.first, false, vec![])?);
+            for join in &from.joins {
+                let on = match &join.constraint {
+                    Some(JoinConstraint::On(on)) => vec![on.clone()],
+                    Some(JoinConstraint::Using(columns)) => {
+                        let name = match &join.table {
+                            TableRef::Table { name, alias, .. } => alias.as_ref().unwrap_or(name),
+                            TableRef::Subquery { alias, .. } => {
+                                alias.as_ref().ok_or_else(|| {
+                                    self.error(
+                                        join.table.span(),
+                                        "USING needs the subquery to be named",
+                                    )
+                                })?
+                            }
+                        };
+                        self.using(&sources, name, columns)?
+                    }
+                    None => vec![],
+                };
+                let left = join.kind == JoinKind::Left;
+                sources.push(self.source(&join.table, left, on)?);
+            }
+        }
+
+        self.scopes.push(sources);
+        let outer = self.aliases.take();
+        let names = self.query(select, destination);
+        self.aliases = outer;
+        self.scopes.pop();
+        names
+    }
+
+    fn source(&mut self, table_ref: &TableRef, left: bool, on: Vec<Expr>) -> io::Result<Source> {
+        match table_ref {
+            TableRef::Table { name, alias, .. } => {
+                let table = self.table(name)?;
+                let cursor = self.open_table(&table, false);
+                let mut source = self.table_source(table, cursor, alias.as_ref());
+                source.left = left;
+                source.on = on;
+                Ok(source)
+            }
+            TableRef::Subquery { select, alias, .. } => {
+                let cursor = self.cursor();
+                self.emit(Insn::OpenEphemeral {
+                    cursor,
+                    orders: vec![],
+                });
+                let columns = self.select(
+                    select,
+                    Destination::Ephemeral {
+                        cursor,
+                        key_count: 0,
+                    },
+                )?;
+
+                Ok(Source {
+                    name: alias
+                        .as_ref()
+                        .map(|alias| alias.name.clone())
+                        .unwrap_or_default(),
+                    columns,
+                    cursor,
+                    table: None,
+                    left,
+                    on,
+                    registers: None,
+                })
+            }
+        }
+    }
+
+    // Equalities between the columns of USING, from the first of the earlier sources having them to `table`
+    fn using(&self, sources: &[Source], table: &Ident, columns: &[Ident]) -> io::Result<Vec<Expr>> {
+        columns
+            .iter()
+            .map(|column| {
+                let source = sources
+                    .iter()
+                    .find(|source| {
+                        source
+                            .columns
+                            .iter()
+                            .any(|name| name.eq_ignore_ascii_case(&column.name))
+                    })
+                    .ok_or_else(|| {
+                        self.error(
+                            column.span,
+                            &format!(
+                                "cannot join using column {} - column not present in both tables",
+                                column.name
+                            ),
+                        )
+                    })?;
+                let qualified = |table: &str| Expr {
+                    kind: ExprKind::Column {
+                        table: Some(Ident {
+                            name: table.to_string(),
+                            span: column.span,
+                        }),
+                        name: column.clone(),
+                    },
+                    span: column.span,
+                };
+
+                Ok(Expr {
+                    kind: ExprKind::Binary {
+                        op: BinaryOp::Eq,
+                        left: Box::new(qualified(&source.name)),
+                        right: Box::new(qualified(&table.name)),
+                    },
+                    span: column.span,
+                })
+            })
+            .collect()
+    }
+
+    // Compiles a query whose sources are the last scope
+    fn query(&mut self, select: &Select, destination: Destination) -> io::Result<Vec<String>> {
+        let (results, names) = self.result_columns(select)?;
+        let results = Rc::new(results);
+        self.aliases = Some((names.clone(), Rc::clone(&results)));
+
+        let mut calls = vec![];
+        for expr in results
+            .iter()
+            .chain(&select.having)
+            .chain(select.order_by.iter().map(|term| &term.expr))
+        {
+            self.collect_aggregates(expr, &mut calls)?;
+        }
+        let aggregate = !select.group_by.is_empty() || !calls.is_empty();
+        if let (Some(having), false) = (&select.having, aggregate) {
+            return Err(self.error(having.span, "a GROUP BY clause is required before HAVING"));
+        }
+
+        let end = self.label();
+        let (mut limit, mut offset) = (None, None);
+        if let Some(clause) = &select.limit {
+            let reg = self.register();
+            self.expr(&clause.limit, reg)?;
+            self.emit(Insn::MustBeInt { reg });
+            self.emit(Insn::IfNot {
+                reg,
+                target: end.0,
+                jump_if_null: false,
+            });
+            limit = Some(reg);
+
+            if let Some(expr) = &clause.offset {
+                let reg = self.register();
+                self.expr(expr, reg)?;
+                self.emit(Insn::MustBeInt { reg });
+                offset = Some(reg);
+            }
+        }
+
+        let sorted =
+            !select.order_by.is_empty() && !matches!(destination, Destination::Exists { .. });
+        let sorter = sorted.then(|| {
+            let cursor = self.cursor();
+            self.emit(Insn::OpenEphemeral {
+                cursor,
+                orders: select.order_by.iter().map(|term| term.order).collect(),
+            });
+            cursor
+        });
+        let distinct = select.distinct.then(|| {
+            let cursor = self.cursor();
+            self.emit(Insn::OpenEphemeral {
+                cursor,
+                orders: vec![],
+            });
+            cursor
+        });
+
+        let output = Output {
+            results: &results,
+            names: &names,
+            order_by: if sorted { &select.order_by } else { &[] },
+            sorter,
+            distinct,
+            limit,
+            offset,
+            destination,
+            end,
+        };
+        match aggregate {
+            true => self.aggregate_query(select, &calls, &output)?,
+            false => {
+                let loops = self.open_loops(select.where_clause.as_ref())?;
+                let next = match loops.last() {
+                    Some(innermost) => innermost.cont,
+                    None => self.label(),
+                };
+                if let Some(where_clause) = &select.where_clause {
+                    self.jump_if(where_clause, false, true, next)?;
+                }
+                self.emit_result(&output, next)?;
+                if loops.is_empty() {
+                    self.bind(next);
+                }
+                self.close_loops(loops);
+            }
+        }
+        self.sorted_output(&output)?;
+        self.bind(end);
+
+        Ok(names)
+    }
+
+    // The expressions of the result columns, `*` being expanded, along with the names of the columns
+    fn result_columns(&self, select: &Select) -> io::Result<(Vec<Expr>, Vec<String>)> {
+        let sources = self.scopes.last().expect("Scope of the query");
+        let mut results = vec![];
+        let mut names = vec![];
+        let expand =
+            |source: &Source, span: Span, results: &mut Vec<Expr>, names: &mut Vec<String>| {
+                for column in &source.columns {
+                    let ident = |name: &str| Ident {
+                        name: name.to_string(),
+                        span,
+                    };
+                    results.push(Expr {
+                        kind: ExprKind::Column {
+                            table: Some(ident(&source.name)),
+                            name: ident(column),
+                        },
+                        span,
+                    });
+                    names.push(column.clone());
+                }
+            };
+
+        for column in &select.columns {
+            match column {
+                ResultColumn::All => {
+                    if sources.is_empty() {
+                        return Err(self.error(select.span, "no tables specified"));
+                    }
+                    for source in sources {
+                        expand(source, select.span, &mut results, &mut names);
+                    }
+                }
+                ResultColumn::AllOf(table) => {
+                    let source = sources
+                        .iter()
+                        .find(|source| source.name.eq_ignore_ascii_case(&table.name))
+                        .ok_or_else(|| {
+                            self.error(table.span, &format!("no such table: {}", table.name))
+                        })?;
+                    expand(source, table.span, &mut results, &mut names);
+                }
+                ResultColumn::Expr { expr, alias } => {
+                    let name = match (alias, &expr.kind) {
+                        (Some(alias), _) => alias.name.clone(),
+                        (None, ExprKind::Column { name, .. }) => name.name.clone(),
+                        (None, _) => expr.span.text(self.sql).to_string(),
+                    };
+                    results.push(expr.clone());
+                    names.push(name);
+                }
+            }
+        }
+
+        Ok((results, names))
+    }
+
+    // Aggregates the rows of the query, in a single group or in the groups of GROUP BY. Every column of the sources is
+    // copied out of the cursors along with the arguments of the aggregates, as the result columns are computed after
+    // the last row of a group was read
+    fn aggregate_query(
+        &mut self,
+        select: &Select,
+        calls: &[&Expr],
+        output: &Output,
+    ) -> io::Result<()> {
+        let slots: Vec<usize> = calls
+            .iter()
+            .map(|_| {
+                self.aggregates += 1;
+                self.aggregates - 1
+            })
+            .collect();
+        let widths: Vec<usize> = self
+            .scopes
+            .last()
+            .expect("Scope of the query")
+            .iter()
+            .map(|source| source.columns.len() + 1)
+            .collect();
+        let width: usize = widths.iter().sum();
+        let row = self.registers(width);
+        let bases: Vec<Register> = widths
+            .iter()
+            .scan(row, |base, width| {
+                *base += width;
+                Some(*base - width)
+            })
+            .collect();
+
+        for &slot in &slots {
+            self.emit(Insn::AggReset { slot });
+        }
+
+        if select.group_by.is_empty() {
+            // A query without rows still has a group, whose columns are NULL
+            self.emit(Insn::Null {
+                dest: row,
+                count: width,
+            });
+            let loops = self.open_loops(select.where_clause.as_ref())?;
+            let next = match loops.last() {
+                Some(innermost) => innermost.cont,
+                None => self.label(),
+            };
+            if let Some(where_clause) = &select.where_clause {
+                self.jump_if(where_clause, false, true, next)?;
+            }
+            for (position, &base) in bases.iter().enumerate() {
+                let copied = self.copy_row(position);
+                self.emit(Insn::Copy {
+                    source: copied,
+                    dest: base,
+                    count: widths[position],
+                });
+            }
+            self.set_row_registers(Some(&bases));
+            self.aggregate_step(calls, &slots)?;
+            self.set_row_registers(None);
+            if loops.is_empty() {
+                self.bind(next);
+            }
+            self.close_loops(loops);
+
+            self.set_row_registers(Some(&bases));
+            let done = self.label();
+            self.aggregate_output(select, calls, &slots, output, done)?;
+            self.bind(done);
+            self.set_row_registers(None);
+            return Ok(());
+        }
+
+        // Rows are sorted on their GROUP BY values, followed by the columns of the sources
+        let keys = select.group_by.len();
+        let sorter = self.cursor();
+        self.emit(Insn::OpenEphemeral {
+            cursor: sorter,
+            orders: vec![SortOrder::Ascending; keys],
+        });
+        let loops = self.open_loops(select.where_clause.as_ref())?;
+        let next = match loops.last() {
+            Some(innermost) => innermost.cont,
+            None => self.label(),
+        };
+        if let Some(where_clause) = &select.where_clause {
+            self.jump_if(where_clause, false, true, next)?;
+        }
+        let entry = self.registers(keys + width);
+        for (offset, term) in select.group_by.iter().enumerate() {
+            // A number stands for a result column
+            let expr = match &term.kind {
+                ExprKind::Literal(Value::Integer(number)) => match usize::try_from(*number) {
+                    Ok(number @ 1..) if number <= output.results.len() => {
+                        &output.results[number - 1]
+                    }
+                    _ => {
+                        return Err(self.error(
+                            term.span,
+                            &format!(
+                                "GROUP BY term out of range - should be between 1 and {}",
+                                output.results.len()
+                            ),
+                        ))
+                    }
+                },
+                _ => term,
+            };
+            if self.contains_aggregate(expr) {
+                return Err(self.error(
+                    expr.span,
+                    "aggregate functions are not allowed in the GROUP BY clause",
+                ));
+            }
+            self.expr(expr, entry + offset)?;
+        }
+        for (position, &base) in bases.iter().enumerate() {
+            let copied = self.copy_row(position);
+            self.emit(Insn::Copy {
+                source: copied,
+                dest: entry + keys + (base - row),
+                count: widths[position],
+            });
+        }
+        self.emit(Insn::EphemeralInsert {
+            cursor: sorter,
+            start: entry,
+            count: keys + width,
+            key_count: keys,
+        });
+        if loops.is_empty() {
+            self.bind(next);
+        }
+        self.close_loops(loops);
+
+        // Groups start where the GROUP BY values change. Each one is output by a subroutine once its last row was read
+        let (first, ret) = (self.register(), self.register());
+        let (previous, current) = (self.registers(keys), self.registers(keys));
+        let (top, changed, load, input_done) =
+            (self.label(), self.label(), self.label(), self.label());
+        let (subroutine, group_done, after) = (self.label(), self.label(), self.label());
+
+        self.emit(Insn::Integer {
+            value: 1,
+            dest: first,
+        });
+        self.emit(Insn::Rewind {
+            cursor: sorter,
+            target: input_done.0,
+        });
+        self.bind(top);
+        for column in 0..keys {
+            self.emit(Insn::Column {
+                cursor: sorter,
+                column,
+                dest: current + column,
+            });
+        }
+        self.emit(Insn::IfPos {
+            reg: first,
+            target: load.0,
+        });
+        let different = self.register();
+        for column in 0..keys {
+            self.emit(Insn::Binary {
+                op: BinaryOp::IsNot,
+                left: current + column,
+                right: previous + column,
+                dest: different,
+            });
+            self.emit(Insn::If {
+                reg: different,
+                target: changed.0,
+                jump_if_null: false,
+            });
+        }
+        self.emit(Insn::Goto { target: load.0 });
+        self.bind(changed);
+        self.emit(Insn::Gosub {
+            ret,
+            target: subroutine.0,
+        });
+        for &slot in &slots {
+            self.emit(Insn::AggReset { slot });
+        }
+        self.bind(load);
+        self.emit(Insn::Copy {
+            source: current,
+            dest: previous,
+            count: keys,
+        });
+        for column in 0..width {
+            self.emit(Insn::Column {
+                cursor: sorter,
+                column: keys + column,
+                dest: row + column,
+            });
+        }
+        self.set_row_registers(Some(&bases));
+        self.aggregate_step(calls, &slots)?;
+        self.emit(Insn::Next {
+            cursor: sorter,
+            target: top.0,
+        });
+        self.bind(input_done);
+        self.emit(Insn::If {
+            reg: first,
+            target: after.0,
+            jump_if_null: false,
+        });
+        self.emit(Insn::Gosub {
+            ret,
+            target: subroutine.0,
+        });
+        self.emit(Insn::Goto { target: after.0 });
+
+        self.bind(subroutine);
+        self.aggregate_output(select, calls, &slots, output, group_done)?;
+        self.bind(group_done);
+        self.emit(Insn::Return { ret });
+        self.bind(after);
+        self.set_row_registers(None);
+
+        Ok(())
+    }
+
+    fn aggregate_step(&mut self, calls: &[&Expr], slots: &[usize]) -> io::Result<()> {
+        for (call, &slot) in calls.iter().zip(slots) {
+            let ExprKind::Function {
+                name,
+                distinct,
+                args,
+            } = &call.kind
+            else {
+                unreachable!()
+            };
+            let args: &[Expr] = match args {
+                FunctionArgs::Star => &[],
+                FunctionArgs::List(args) => args,
+            };
+            let function = self.aggregate_function(call)?;
+            if *distinct && args.len() != 1 {
+                return Err(self.error(
+                    call.span,
+                    &format!(
+                        "DISTINCT aggregates must have exactly one argument: {}",
+                        name.name
+                    ),
+                ));
+            }
+
+            let start = self.registers(args.len());
+            for (offset, arg) in args.iter().enumerate() {
+                self.expr(arg, start + offset)?;
+            }
+            self.emit(Insn::AggStep {
+                function,
+                distinct: *distinct,
+                args: start,
+                count: args.len(),
+                slot,
+            });
+        }
+
+        Ok(())
+    }
+
+    // Outputs the row of a group, unless HAVING rejects it
+    fn aggregate_output(
+        &mut self,
+        select: &Select,
+        calls: &[&Expr],
+        slots: &[usize],
+        output: &Output,
+        next: Label,
+    ) -> io::Result<()> {
+        for (&call, &slot) in calls.iter().zip(slots) {
+            let dest = self.register();
+            let function = self.aggregate_function(call)?;
+            self.emit(Insn::AggFinal {
+                function,
+                slot,
+                dest,
+            });
+            self.aggregate_results.insert(call as *const Expr, dest);
+        }
+
+        let result = match &select.having {
+            Some(having) => self.jump_if(having, false, true, next),
+            None => Ok(()),
+        }
+        .and_then(|()| self.emit_result(output, next));
+
+        for &call in calls {
+            self.aggregate_results.remove(&(call as *const Expr));
+        }
+        result
+    }
+
+    // Computes a result row, and sends it on its way to the destination. Jumps to `next` if it is left out
+    fn emit_result(&mut self, output: &Output, next: Label) -> io::Result<()> {
+        let count = output.results.len();
+        let keys = output.order_by.len();
+        let start = self.registers(keys + count);
+        for (offset, expr) in output.results.iter().enumerate() {
+            self.expr(expr, start + keys + offset)?;
+        }
+        for (offset, term) in output.order_by.iter().enumerate() {
+            match self.result_reference(&term.expr, output)? {
+                Some(column) => self.emit_copy(start + keys + column, start + offset),
+                None => self.expr(&term.expr, start + offset)?,
+            }
+        }
+
+        if let Some(distinct) = output.distinct {
+            self.emit(Insn::Found {
+                cursor: distinct,
+                start: start + keys,
+                count,
+                target: next.0,
+            });
+            self.emit(Insn::EphemeralInsert {
+                cursor: distinct,
+                start: start + keys,
+                count,
+                key_count: count,
+            });
+        }
+
+        match output.sorter {
+            Some(sorter) => {
+                self.emit(Insn::EphemeralInsert {
+                    cursor: sorter,
+                    start,
+                    count: keys + count,
+                    key_count: keys,
+                });
+            }
+            None => self.deliver(output, start + keys, next),
+        }
+        Ok(())
+    }
+
+    // The result column an ORDER BY term refers to, by its number or by its alias
+    fn result_reference(&self, expr: &Expr, output: &Output) -> io::Result<Option<usize>> {
+        match &expr.kind {
+            ExprKind::Literal(Value::Integer(number)) => match usize::try_from(*number) {
+                Ok(number @ 1..) if number <= output.results.len() => Ok(Some(number - 1)),
+                _ => Err(self.error(
+                    expr.span,
+                    &format!(
+                        "ORDER BY term out of range - should be between 1 and {}",
+                        output.results.len()
+                    ),
+                )),
+            },
+            ExprKind::Column { table: None, name } => Ok(output
+                .names
+                .iter()
+                .zip(output.results)
+                .position(|(result, expr)| {
+                    result.eq_ignore_ascii_case(&name.name)
+                        && !matches!(&expr.kind, ExprKind::Column { .. })
+                })),
+            _ => Ok(None),
+        }
+    }
+
+    // Sends the result row in the registers from `start` to the destination, once past the offset and within the
+    // limit
+    fn deliver(&mut self, output: &Output, start: Register, next: Label) {
+        if let Some(offset) = output.offset {
+            self.emit(Insn::IfPos {
+                reg: offset,
+                target: next.0,
+            });
+        }
+
+        let count = output.results.len();
+        match output.destination {
+            Destination::Output => {
+                self.emit(Insn::ResultRow { start, count });
+            }
+            Destination::Ephemeral { cursor, key_count } => {
+                self.emit(Insn::EphemeralInsert {
+                    cursor,
+                    start,
+                    count,
+                    key_count,
+                });
+            }
+            Destination::Exists { dest, done } => {
+                self.emit(Insn::Integer { value: 1, dest });
+                self.emit(Insn::Goto { target: done.0 });
+            }
+            Destination::Scalar { dest, done } => {
+                self.emit_copy(start, dest);
+                self.emit(Insn::Goto { target: done.0 });
+            }
+        }
+
+        if let Some(limit) = output.limit {
+            self.emit(Insn::DecrJumpZero {
+                reg: limit,
+                target: output.end.0,
+            });
+        }
+    }
+
+    // Delivers the rows gathered by the ORDER BY sorter, in order
+    fn sorted_output(&mut self, output: &Output) -> io::Result<()> {
+        let Some(sorter) = output.sorter else {
+            return Ok(());
+        };
+
+        let keys = output.order_by.len();
+        let count = output.results.len();
+        let (top, next, done) = (self.label(), self.label(), self.label());
+        let start = self.registers(count);
+        self.emit(Insn::Rewind {
+            cursor: sorter,
+            target: done.0,
+        });
+        self.bind(top);
+        for column in 0..count {
+            self.emit(Insn::Column {
+                cursor: sorter,
+                column: keys + column,
+                dest: start + column,
+            });
+        }
+        self.deliver(output, start, next);
+        self.bind(next);
+        self.emit(Insn::Next {
+            cursor: sorter,
+            target: top.0,
+        });
+        self.bind(done);
+
+        Ok(())
+    }
+
+    // Starts the loops over the sources of the last scope, the accesses being chosen from the terms of
+    // `where_clause`
+    fn open_loops(&mut self, where_clause: Option<&Expr>) -> io::Result<Vec<Loop>> {
+        let mut terms = vec![];
+        if let Some(where_clause) = where_clause {
+            conjuncts(where_clause, &mut terms);
+        }
+
+        let count = self.scopes.last().expect("Scope of the query").len();
+        let mut loops = vec![];
+        for position in 0..count {
+            let source = &self.scopes.last().expect("Scope of the query")[position];
+            let (cursor, left) = (source.cursor, source.left);
+            let on = source.on.clone();
+            let access = match left {
+                true => Access::Scan,
+                false => self.access(position, &terms),
+            };
+
+            let left = left.then(|| LeftJoin {
+                cursor,
+                matched: self.register(),
+                null_row: self.register(),
+                body: self.label(),
+            });
+            if let Some(left) = &left {
+                for reg in [left.matched, left.null_row] {
+                    self.emit(Insn::Integer {
+                        value: 0,
+                        dest: reg,
+                    });
+                }
+            }
+
+            let (top, cont, exit) = (self.label(), self.label(), self.label());
+            let next = match access {
+                Access::Scan => {
+                    self.emit(Insn::Rewind {
+                        cursor,
+                        target: exit.0,
+                    });
+                    self.bind(top);
+                    Some(cursor)
+                }
+                Access::Rowid(expr, affinity) => {
+                    let rowid = self.register();
+                    self.expr(&expr, rowid)?;
+                    self.convert(rowid, affinity);
+                    self.emit(Insn::SeekRowid {
+                        cursor,
+                        rowid,
+                        target: exit.0,
+                    });
+                    None
+                }
+                Access::Index(index, exprs) => {
+                    let index_cursor = self.cursor();
+                    self.emit(Insn::OpenRead {
+                        cursor: index_cursor,
+                        root: index.root,
+                        key_info: Some(key_info(&index)),
+                    });
+                    let start = self.registers(exprs.len());
+                    for (offset, (expr, affinity)) in exprs.iter().enumerate() {
+                        self.expr(expr, start + offset)?;
+                        self.convert(start + offset, *affinity);
+                        // NULL equals nothing
+                        self.emit(Insn::IsNull {
+                            reg: start + offset,
+                            target: exit.0,
+                        });
+                    }
+                    let count = exprs.len();
+                    self.emit(Insn::SeekGe {
+                        cursor: index_cursor,
+                        start,
+                        count,
+                        target: exit.0,
+                    });
+                    self.bind(top);
+                    self.emit(Insn::IdxGt {
+                        cursor: index_cursor,
+                        start,
+                        count,
+                        target: exit.0,
+                    });
+                    let rowid = self.register();
+                    self.emit(Insn::Rowid {
+                        cursor: index_cursor,
+                        dest: rowid,
+                    });
+                    self.emit(Insn::SeekRowid {
+                        cursor,
+                        rowid,
+                        target: cont.0,
+                    });
+                    Some(index_cursor)
+                }
+            };
+
+            for expr in &on {
+                self.jump_if(expr, false, true, cont)?;
+            }
+            if let Some(left) = &left {
+                self.emit(Insn::Integer {
+                    value: 1,
+                    dest: left.matched,
+                });
+                self.bind(left.body);
+            }
+
+            loops.push(Loop {
+                next,
+                top,
+                cont,
+                exit,
+                left,
+            });
+        }
+
+        Ok(loops)
+    }
+
+    fn close_loops(&mut self, loops: Vec<Loop>) {
+        for lp in loops.into_iter().rev() {
+            self.bind(lp.cont);
+            let after = self.label();
+            if let Some(left) = &lp.left {
+                self.emit(Insn::If {
+                    reg: left.null_row,
+                    target: after.0,
+                    jump_if_null: false,
+                });
+            }
+            if let Some(cursor) = lp.next {
+                self.emit(Insn::Next {
+                    cursor,
+                    target: lp.top.0,
+                });
+            }
+            self.bind(lp.exit);
+
+            // Without a match, the row goes on once with NULLs for the columns of the source
+            if let Some(left) = &lp.left {
+                self.emit(Insn::If {
+                    reg: left.matched,
+                    target: after.0,
+                    jump_if_null: false,
+                });
+                self.emit(Insn::NullRow {
+                    cursor: left.cursor,
+                });
+                for reg in [left.matched, left.null_row] {
+                    self.emit(Insn::Integer {
+                        value: 1,
+                        dest: reg,
+                    });
+                }
+                self.emit(Insn::Goto {
+                    target: left.body.0,
+                });
+            }
+            self.bind(after);
+        }
+    }
+
+    // How to go through the rows of a source: the terms of the WHERE clause comparing its rowid, or the first columns
+    // of one of its indexes, to values that do not depend on it or on the sources after it let it seek them
+    fn access(&self, position: usize, terms: &[&Expr]) -> Access {
+        let sources = self.scopes.last().expect("Scope of the query");
+        let Some(table) = &sources[position].table else {
+            return Access::Scan;
+        };
+
+        let mut equal: Vec<Option<(&Expr, Option<Affinity>)>> = vec![None; table.columns.len()];
+        for term in terms {
+            let ExprKind::Binary {
+                op: BinaryOp::Eq,
+                left,
+                right,
+            } = &term.kind
+            else {
+                continue;
+            };
+            for (operand, value) in [(left, right), (right, left)] {
+                let ExprKind::Column {
+                    table: qualifier,
+                    name,
+                } = &operand.kind
+                else {
+                    continue;
+                };
+                let depth = self.scopes.len() - 1;
+                let Ok((column_depth, column_position, column)) =
+                    self.resolve(qualifier.as_ref(), name)
+                else {
+                    continue;
+                };
+                if column_depth != depth
+                    || column_position != position
+                    || !self.usable(value, position)
+                {
+                    continue;
+                }
+                // Values of the column converted for the comparison are out of the order of its tree
+                let (column_affinity, affinity) =
+                    comparison_affinities(self.affinity(operand), self.affinity(value));
+                if column_affinity.is_some() {
+                    continue;
+                }
+
+                match column {
+                    ColumnRef::Rowid => return Access::Rowid((**value).clone(), affinity),
+                    ColumnRef::Column(column) if table.rowid_alias == Some(column) => {
+                        return Access::Rowid((**value).clone(), affinity)
+                    }
+                    ColumnRef::Column(column) => {
+                        equal[column].get_or_insert((value, affinity));
+                    }
+                }
+            }
+        }
+
+        table
+            .indexes
+            .iter()
+            .map(|index| {
+                let values: Vec<(Expr, Option<Affinity>)> = index
+                    .columns
+                    .iter()
+                    .map_while(|&column| {
+                        equal[column].map(|(value, affinity)| (value.clone(), affinity))
+                    })
+                    .collect();
+                (index, values)
+            })
+            .filter(|(_, values)| !values.is_empty())
+            .max_by_key(|(_, values)| values.len())
+            .map_or(Access::Scan, |(index, values)| {
+                Access::Index(index.clone(), values)
+            })
+    }
+
+    // Whether `expr` can be computed before the loop over the source at `position` of the last scope starts
+    fn usable(&self, expr: &Expr, position: usize) -> bool {
+        match &expr.kind {
+            ExprKind::Column { table, name } => match self.resolve(table.as_ref(), name) {
+                Ok((depth, source, _)) => depth < self.scopes.len() - 1 || source < position,
+                Err(_) => false,
+            },
+            ExprKind::InSelect { .. } | ExprKind::Exists(_) | ExprKind::Subquery(_) => false,
+            _ if self.contains_aggregate(expr) => false,
+            _ => expr
+                .children()
+                .into_iter()
+                .all(|child| self.usable(child, position)),
+        }
+    }
+
+    fn collect_aggregates<'e>(&self, expr: &'e Expr, calls: &mut Vec<&'e Expr>) -> io::Result<()> {
+        if self.is_aggregate(expr) {
+            for arg in expr.children() {
+                if self.contains_aggregate(arg) {
+                    return Err(self.misuse(arg));
+                }
+            }
+            calls.push(expr);
+            return Ok(());
+        }
+
+        for child in expr.children() {
+            self.collect_aggregates(child, calls)?;
+        }
+        Ok(())
+    }
+
+    fn is_aggregate(&self, expr: &Expr) -> bool {
+        match &expr.kind {
+            ExprKind::Function { name, args, .. } => {
+                AggregateFunction::find(&name.name, argument_count(args)).is_some()
+            }
+            _ => false,
+        }
+    }
+
+    fn contains_aggregate(&self, expr: &Expr) -> bool {
+        self.is_aggregate(expr)
+            || expr
+                .children()
+                .into_iter()
+                .any(|child| self.contains_aggregate(child))
+    }
+
+    fn aggregate_function(&self, call: &Expr) -> io::Result<AggregateFunction> {
+        let ExprKind::Function { name, args, .. } = &call.kind else {
+            unreachable!()
+        };
+        match AggregateFunction::find(&name.name, argument_count(args)) {
+            Some(Ok(function)) => Ok(function),
+            Some(Err(message)) => Err(self.error(call.span, &message)),
+            None => unreachable!(),
+        }
+    }
+
+    fn misuse(&self, expr: &Expr) -> io::Error {
+        let mut call = expr;
+        while !self.is_aggregate(call) {
+            call = call
+                .children()
+                .into_iter()
+                .find(|child| self.contains_aggregate(child))
+                .expect("Expression with an aggregate");
+        }
+        let ExprKind::Function { name, .. } = &call.kind else {
+            unreachable!()
+        };
+        self.error(
+            call.span,
+            &format!("misuse of aggregate function {}()", name.name),
+        )
+    }
+
+    // Makes the columns of the sources of the last scope read from the registers from `bases`, or from their cursors
+    // again
+    fn set_row_registers(&mut self, bases: Option<&[Register]>) {
+        let sources = self.scopes.last_mut().expect("Scope of the query");
+        for (position, source) in sources.iter_mut().enumerate() {
+            source.registers = bases.map(|bases| bases[position]);
+        }
+    }
+
+    // Copies the columns of the current row of the source at `position` of the last scope, followed by its rowid, to
+    // new registers
+    fn copy_row(&mut self, position: usize) -> Register {
+        let depth = self.scopes.len() - 1;
+        let columns = self.scopes[depth][position].columns.len();
+        let start = self.registers(columns + 1);
+        for column in 0..columns {
+            self.column(depth, position, ColumnRef::Column(column), start + column);
+        }
+        match self.scopes[depth][position].table.is_some() {
+            true => self.column(depth, position, ColumnRef::Rowid, start + columns),
+            false => self.null(start + columns),
+        }
+        start
+    }
+
+    // Copies the values an index has for a row to new registers, from the values of the row's columns from `columns`.
+    // The column that is the rowid, NULL in records, takes the value of `rowid`
+    fn index_key(
+        &mut self,
+        index: &IndexDef,
+        alias: Option<usize>,
+        columns: Register,
+        rowid: Register,
+    ) -> Register {
+        let start = self.registers(index.columns.len());
+        for (offset, &column) in index.columns.iter().enumerate() {
+            match alias == Some(column) {
+                true => self.emit_copy(rowid, start + offset),
+                false => self.emit_copy(columns + column, start + offset),
+            }
+        }
+        start
+    }
+
+    fn expr(&mut self, expr: &Expr, dest: Register) -> io::Result<()> {
+        match &expr.kind {
+            ExprKind::Literal(value) => self.value(value, dest),
+            ExprKind::Column { table, name } => match self.resolve(table.as_ref(), name) {
+                Ok((depth, position, column)) => self.column(depth, position, column, dest),
+                Err(err) => {
+                    let Some((names, results)) = self.aliases.take().filter(|_| table.is_none())
+                    else {
+                        return Err(err);
+                    };
+                    let Some(position) = names
+                        .iter()
+                        .position(|alias| alias.eq_ignore_ascii_case(&name.name))
+                    else {
+                        self.aliases = Some((names, results));
+                        return Err(err);
+                    };
+                    // The aliases stay out of reach meanwhile, as an alias cannot refer to itself
+                    let result = self.expr(&results[position], dest);
+                    self.aliases = Some((names, results));
+                    result?;
+                }
+            },
+            ExprKind::Parameter(parameter) => {
+                self.parameters = self.parameters.max(*parameter);
+                self.emit(Insn::Variable {
+                    parameter: *parameter,
+                    dest,
+                });
+            }
+            ExprKind::Unary {
+                op: UnaryOp::Plus,
+                operand,
+            } => self.expr(operand, dest)?,
+            ExprKind::Unary { op, operand } => {
+                self.expr(operand, dest)?;
+                self.emit(Insn::Unary {
+                    op: *op,
+                    source: dest,
+                    dest,
+                });
+            }
+            ExprKind::Binary { op, left, right } => {
+                let (mut left_reg, mut right_reg) = (self.register(), self.register());
+                self.expr(left, left_reg)?;
+                self.expr(right, right_reg)?;
+                if is_comparison(*op) {
+                    (left_reg, right_reg) = self.comparison(left, left_reg, right, right_reg);
+                }
+                self.emit(Insn::Binary {
+                    op: *op,
+                    left: left_reg,
+                    right: right_reg,
+                    dest,
+                });
+            }
+            ExprKind::Like {
+                expr: value,
+                pattern,
+                negated,
+            } => {
+                let (value_reg, pattern_reg) = (self.register(), self.register());
+                self.expr(value, value_reg)?;
+                self.expr(pattern, pattern_reg)?;
+                self.emit(Insn::Like {
+                    value: value_reg,
+                    pattern: pattern_reg,
+                    dest,
+                });
+                self.negate(*negated, dest);
+            }
+            ExprKind::Between {
+                expr: value,
+                low,
+                high,
+                negated,
+            } => {
+                let start = self.registers(3);
+                self.expr(value, start)?;
+                self.expr(low, start + 1)?;
+                self.expr(high, start + 2)?;
+                let (above, below) = (self.register(), self.register());
+                let (left, right) = self.comparison(value, start, low, start + 1);
+                self.emit(Insn::Binary {
+                    op: BinaryOp::GtEq,
+                    left,
+                    right,
+                    dest: above,
+                });
+                let (left, right) = self.comparison(value, start, high, start + 2);
+                self.emit(Insn::Binary {
+                    op: BinaryOp::LtEq,
+                    left,
+                    right,
+                    dest: below,
+                });
+                self.emit(Insn::Binary {
+                    op: BinaryOp::And,
+                    left: above,
+                    right: below,
+                    dest,
+                });
+                self.negate(*negated, dest);
+            }
+            ExprKind::InList {
+                expr: value,
+                list,
+                negated,
+            } => {
+                // The OR of the equalities, which is NULL when none holds but one is NULL
+                let done = self.label();
+                let (value_reg, item, equal) = (self.register(), self.register(), self.register());
+                self.emit(Insn::Integer { value: 0, dest });
+                self.expr(value, value_reg)?;
+                for expr in list {
+                    self.expr(expr, item)?;
+                    let (left, right) = self.comparison(value, value_reg, expr, item);
+                    self.emit(Insn::Binary {
+                        op: BinaryOp::Eq,
+                        left,
+                        right,
+                        dest: equal,
+                    });
+                    self.emit(Insn::Binary {
+                        op: BinaryOp::Or,
+                        left: dest,
+                        right: equal,
+                        dest,
+                    });
+                    self.emit(Insn::If {
+                        reg: dest,
+                        target: done.0,
+                        jump_if_null: false,
+                    });
+                }
+                self.bind(done);
+                self.negate(*negated, dest);
+            }
+            ExprKind::InSelect {
+                expr: value,
+                select,
+                negated,
+            } => {
+                let rows = self.cursor();
+                self.emit(Insn::OpenEphemeral {
+                    cursor: rows,
+                    orders: vec![],
+                });
+                let columns = self.select(
+                    select,
+                    Destination::Ephemeral {
+                        cursor: rows,
+                        key_count: 1,
+                    },
+                )?;
+                self.single_column(select, columns.len())?;
+
+                let (found, done) = (self.label(), self.label());
+                let value_reg = self.register();
+                self.expr(value, value_reg)?;
+                self.emit(Insn::Integer { value: 0, dest });
+                self.emit(Insn::Rewind {
+                    cursor: rows,
+                    target: done.0,
+                });
+                self.null(dest);
+                self.emit(Insn::IsNull {
+                    reg: value_reg,
+                    target: done.0,
+                });
+                self.emit(Insn::Found {
+                    cursor: rows,
+                    start: value_reg,
+                    count: 1,
+                    target: found.0,
+                });
+                // Not found, which gives NULL if the rows have a NULL
+                let null = self.register();
+                self.null(null);
+                self.emit(Insn::Found {
+                    cursor: rows,
+                    start: null,
+                    count: 1,
+                    target: done.0,
+                });
+                self.emit(Insn::Integer { value: 0, dest });
+                self.emit(Insn::Goto { target: done.0 });
+                self.bind(found);
+                self.emit(Insn::Integer { value: 1, dest });
+                self.bind(done);
+                self.negate(*negated, dest);
+            }
+            ExprKind::Exists(select) => {
+                let done = self.label();
+                self.emit(Insn::Integer { value: 0, dest });
+                self.select(select, Destination::Exists { dest, done })?;
+                self.bind(done);
+            }
+            ExprKind::Subquery(select) => {
+                let done = self.label();
+                self.null(dest);
+                let columns = self.select(select, Destination::Scalar { dest, done })?;
+                self.single_column(select, columns.len())?;
+                self.bind(done);
+            }
+            ExprKind::Function {
+                name,
+                distinct,
+                args,
+            } => {
+                if self.is_aggregate(expr) {
+                    let Some(&result) = self.aggregate_results.get(&(expr as *const Expr)) else {
+                        return Err(self.misuse(expr));
+                    };
+                    self.emit_copy(result, dest);
+                    return Ok(());
+                }
+
+                let args = match args {
+                    FunctionArgs::List(args) => args.as_slice(),
+                    FunctionArgs::Star => &[],
+                };
+                let function = match ScalarFunction::find(&name.name, args.len()) {
+                    Some(Ok(function)) => function,
+                    Some(Err(message)) => return Err(self.error(expr.span, &message)),
+                    None => {
+                        return Err(
+                            self.error(name.span, &format!("no such function: {}", name.name))
+                        )
+                    }
+                };
+                if *distinct {
+                    return Err(self.error(
+                        expr.span,
+                        &format!(
+                            "DISTINCT is only allowed in aggregate functions, not {}()",
+                            name.name
+                        ),
+                    ));
+                }
+
+                let start = self.registers(args.len());
+                for (offset, arg) in args.iter().enumerate() {
+                    self.expr(arg, start + offset)?;
+                }
+                self.emit(Insn::Function {
+                    function,
+                    args: start,
+                    count: args.len(),
+                    dest,
+                });
+            }
+            ExprKind::Case {
+                operand,
+                branches,
+                else_result,
+            } => {
+                let done = self.label();
+                let operand_reg = match operand {
+                    Some(operand) => {
+                        let reg = self.register();
+                        self.expr(operand, reg)?;
+                        Some(reg)
+                    }
+                    None => None,
+                };
+
+                for (when, then) in branches {
+                    let next = self.label();
+                    match operand_reg {
+                        Some(operand_reg) => {
+                            let (value, equal) = (self.register(), self.register());
+                            self.expr(when, value)?;
+                            let operand = operand.as_ref().expect("CASE operand");
+                            let (left, right) = self.comparison(operand, operand_reg, when, value);
+                            self.emit(Insn::Binary {
+                                op: BinaryOp::Eq,
+                                left,
+                                right,
+                                dest: equal,
+                            });
+                            self.emit(Insn::IfNot {
+                                reg: equal,
+                                target: next.0,
+                                jump_if_null: true,
+                            });
+                        }
+                        None => self.jump_if(when, false, true, next)?,
+                    }
+                    self.expr(then, dest)?;
+                    self.emit(Insn::Goto { target: done.0 });
+                    self.bind(next);
+                }
+                match else_result {
+                    Some(else_result) => self.expr(else_result, dest)?,
+                    None => self.null(dest),
+                }
+                self.bind(done);
+            }
+            ExprKind::Cast { expr, type_name } => {
+                self.expr(expr, dest)?;
+                self.emit(Insn::Cast {
+                    source: dest,
+                    affinity: Affinity::from_type(Some(type_name)),
+                    dest,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    // Jumps to `target` if `expr` is `when`, or NULL when `jump_if_null` is set. AND and OR skip their second operand
+    // when the first one decides
+    fn jump_if(
+        &mut self,
+        expr: &Expr,
+        when: bool,
+        jump_if_null: bool,
+        target: Label,
+    ) -> io::Result<()> {
+        match &expr.kind {
+            ExprKind::Unary {
+                op: UnaryOp::Not,
+                operand,
+            } => self.jump_if(operand, !when, jump_if_null, target),
+            // False if either one is, true if both are
+            ExprKind::Binary {
+                op: BinaryOp::And,
+                left,
+                right,
+            } if !when || !jump_if_null => {
+                if when {
+                    let skip = self.label();
+                    self.jump_if(left, false, true, skip)?;
+                    self.jump_if(right, true, false, target)?;
+                    self.bind(skip);
+                } else {
+                    self.jump_if(left, false, jump_if_null, target)?;
+                    self.jump_if(right, false, jump_if_null, target)?;
+                }
+                Ok(())
+            }
+            // True if either one is, false if both are
+            ExprKind::Binary {
+                op: BinaryOp::Or,
+                left,
+                right,
+            } if when || !jump_if_null => {
+                if when {
+                    self.jump_if(left, true, jump_if_null, target)?;
+                    self.jump_if(right, true, jump_if_null, target)?;
+                } else {
+                    let skip = self.label();
+                    self.jump_if(left, true, true, skip)?;
+                    self.jump_if(right, false, false, target)?;
+                    self.bind(skip);
+                }
+                Ok(())
+            }
+            _ => {
+                let reg = self.register();
+                self.expr(expr, reg)?;
+                let target = target.0;
+                self.emit(match when {
+                    true => Insn::If {
+                        reg,
+                        target,
+                        jump_if_null,
+                    },
+                    false => Insn::IfNot {
+                        reg,
+                        target,
+                        jump_if_null,
+                    },
+                });
+                Ok(())
+            }
+        }
+    }
+
+    // Affinity of the values of `expr`, if it has one: that of the column it reads, or of the type it is cast to
+    fn affinity(&self, expr: &Expr) -> Option<Affinity> {
+        let affinity = match &expr.kind {
+            ExprKind::Column { table, name } => {
+                let (depth, position, column) = self.resolve(table.as_ref(), name).ok()?;
+                let table = self.scopes[depth][position].table.as_ref()?;
+                match column {
+                    ColumnRef::Column(column) if table.rowid_alias != Some(column) => {
+                        table.columns[column].affinity
+                    }
+                    _ => Affinity::Integer,
+                }
+            }
+            ExprKind::Cast { type_name, .. } => Affinity::from_type(Some(type_name)),
+            _ => return None,
+        };
+        (affinity != Affinity::Blob).then_some(affinity)
+    }
+
+    // Registers holding the operands of a comparison, converted as their affinities require. Registers are copied
+    // before being converted
+    fn comparison(
+        &mut self,
+        left: &Expr,
+        left_reg: Register,
+        right: &Expr,
+        right_reg: Register,
+    ) -> (Register, Register) {
+        let (left_affinity, right_affinity) =
+            comparison_affinities(self.affinity(left), self.affinity(right));
+        let mut converted = |reg: Register, affinity: Option<Affinity>| match affinity {
+            Some(affinity) => {
+                let copy = self.register();
+                self.emit_copy(reg, copy);
+                self.convert(copy, Some(affinity));
+                copy
+            }
+            None => reg,
+        };
+        (
+            converted(left_reg, left_affinity),
+            converted(right_reg, right_affinity),
+        )
+    }
+
+    fn convert(&mut self, reg: Register, affinity: Option<Affinity>) {
+        if let Some(affinity) = affinity {
+            self.emit(Insn::Affinity {
+                start: reg,
+                affinities: vec![affinity],
+            });
+        }
+    }
+
+    // The column of a source, taken from its registers once set
+    fn column(&mut self, depth: usize, position: usize, column: ColumnRef, dest: Register) {
+        let source = &self.scopes[depth][position];
+        let cursor = source.cursor;
+        let insn = match (source.registers, column) {
+            (Some(start), ColumnRef::Column(column)) => Insn::Copy {
+                source: start + column,
+                dest,
+                count: 1,
+            },
+            (Some(start), ColumnRef::Rowid) => Insn::Copy {
+                source: start + source.columns.len(),
+                dest,
+                count: 1,
+            },
+            (None, ColumnRef::Column(column))
+                if source
+                    .table
+                    .as_ref()
+                    .is_some_and(|table| table.rowid_alias == Some(column)) =>
+            {
+                Insn::Rowid { cursor, dest }
+            }
+            (None, ColumnRef::Column(column)) => Insn::Column {
+                cursor,
+                column,
+                dest,
+            },
+            (None, ColumnRef::Rowid) => Insn::Rowid { cursor, dest },
+        };
+        self.emit(insn);
+    }
+
+    // Finds the source of a column, from the innermost query to the outermost. Tables also have a `rowid` column,
+    // unless one of their columns has that name
+    fn resolve(
+        &self,
+        table: Option<&Ident>,
+        name: &Ident,
+    ) -> io::Result<(usize, usize, ColumnRef)> {
+        for (depth, sources) in self.scopes.iter().enumerate().rev() {
+            let mut found = None;
+            for (position, source) in sources.iter().enumerate() {
+                if table.is_some_and(|table| !table.name.eq_ignore_ascii_case(&source.name)) {
+                    continue;
+                }
+                let column = match source
+                    .columns
+                    .iter()
+                    .position(|column| column.eq_ignore_ascii_case(&name.name))
+                {
+                    Some(column) => ColumnRef::Column(column),
+                    None if source.table.is_some()
+                        && ["rowid", "oid", "_rowid_"]
+                            .iter()
+                            .any(|rowid| rowid.eq_ignore_ascii_case(&name.name)) =>
+                    {
+                        ColumnRef::Rowid
+                    }
+                    None => continue,
+                };
+                if found.is_some() {
+                    return Err(
+                        self.error(name.span, &format!("ambiguous column name: {}", name.name))
+                    );
+                }
+                found = Some((depth, position, column));
+            }
+            if let Some(found) = found {
+                return Ok(found);
+            }
+        }
+
+        let qualified = match table {
+            Some(table) => format!("{}.{}", table.name, name.name),
+            None => name.name.clone(),
+        };
+        Err(self.error(name.span, &format!("no such column: {qualified}")))
+    }
+
+    fn single_column(&self, select: &Select, count: usize) -> io::Result<()> {
+        match count {
+            1 => Ok(()),
+            _ => Err(self.error(
+                select.span,
+                &format!("sub-select returns {count} columns - expected 1"),
+            )),
+        }
+    }
+
+    fn table(&self, name: &Ident) -> io::Result<Table> {
+        schema::table(&self.catalog, &name.name)?
+            .ok_or_else(|| self.error(name.span, &format!("no such table: {}", name.name)))
+    }
+
+    fn table_source(&self, table: Table, cursor: CursorId, alias: Option<&Ident>) -> Source {
+        Source {
+            name: alias.map_or_else(|| table.name.clone(), |alias| alias.name.clone()),
+            columns: table
+                .columns
+                .iter()
+                .map(|column| column.name.clone())
+                .collect(),
+            cursor,
+            table: Some(table),
+            left: false,
+            on: vec![],
+            registers: None,
+        }
+    }
+
+    fn open_table(&mut self, table: &Table, write: bool) -> CursorId {
+        let cursor = self.cursor();
+        self.emit(match write {
+            true => Insn::OpenWrite {
+                cursor,
+                root: Root::Page(table.root),
+                key_info: None,
+            },
+            false => Insn::OpenRead {
+                cursor,
+                root: table.root,
+                key_info: None,
+            },
+        });
+        cursor
+    }
+
+    fn open_indexes(&mut self, table: &Table) -> Vec<CursorId> {
+        table
+            .indexes
+            .iter()
+            .map(|index| {
+                let cursor = self.cursor();
+                self.emit(Insn::OpenWrite {
+                    cursor,
+                    root: Root::Page(index.root),
+                    key_info: Some(key_info(index)),
+                });
+                cursor
+            })
+            .collect()
+    }
+
+    fn value(&mut self, value: &Value, dest: Register) {
+        self.emit(match value {
+            Value::Null => Insn::Null { dest, count: 1 },
+            Value::Integer(value) => Insn::Integer {
+                value: *value,
+                dest,
+            },
+            Value::Real(value) => Insn::Real {
+                value: *value,
+                dest,
+            },
+            Value::Text(value) => Insn::String {
+                value: value.clone(),
+                dest,
+            },
+            Value::Blob(value) => Insn::Blob {
+                value: value.clone(),
+                dest,
+            },
+        });
+    }
+
+    fn negate(&mut self, negated: bool, reg: Register) {
+        if negated {
+            self.emit(Insn::Unary {
+                op: UnaryOp::Not,
+                source: reg,
+                dest: reg,
+            });
+        }
+    }
+
+    fn null(&mut self, dest: Register) {
+        self.emit(Insn::Null { dest, count: 1 });
+    }
+
+    fn emit_copy(&mut self, source: Register, dest: Register) {
+        self.emit(Insn::Copy {
+            source,
+            dest,
+            count: 1,
+        });
+    }
+
+    fn emit(&mut self, insn: Insn) {
+        self.insns.push(insn);
+    }
+
+    fn label(&mut self) -> Label {
+        self.labels.push(None);
+        Label(self.labels.len() - 1)
+    }
+
+    // Makes `label` point to the next instruction
+    fn bind(&mut self, label: Label) {
+        self.labels[label.0] = Some(self.insns.len());
+    }
+
+    fn register(&mut self) -> Register {
+        self.registers(1)
+    }
+
+    fn registers(&mut self, count: usize) -> Register {
+        self.registers += count;
+        self.registers - count
+    }
+
+    fn cursor(&mut self) -> CursorId {
+        self.cursors += 1;
+        self.cursors - 1
+    }
+
+    fn error(&self, span: Span, message: &str) -> io::Error {
+        syntax_error(self.sql, span, message.to_string())
+    }
+}
+
+// Message of the error raised when a row repeats the values of unique columns
+fn unique_conflict(table: &Table, columns: &[usize]) -> String {
+    let names: Vec<String> = columns
+        .iter()
+        .map(|&column| format!("{}.{}", table.name, table.columns[column].name))
+        .collect();
+    format!("UNIQUE constraint failed: {}", names.join(", "))
+}
+
+fn key_info(index: &IndexDef) -> KeyInfo {
+    KeyInfo {
+        unique: index.unique,
+        orders: index.orders.clone(),
+    }
+}
+
+fn article(kind: ObjectKind) -> &'static str {
+    match kind {
+        ObjectKind::Table => "a table",
+        ObjectKind::Index => "an index",
+        ObjectKind::View => "a view",
+        ObjectKind::Trigger => "a trigger",
+    }
+}
+
+fn argument_count(args: &FunctionArgs) -> Option<usize> {
+    match args {
+        FunctionArgs::Star => None,
+        FunctionArgs::List(args) => Some(args.len()),
+    }
+}
+
+fn is_comparison(op: BinaryOp) -> bool {
+    matches!(
+        op,
+        BinaryOp::Eq
+            | BinaryOp::NotEq
+            | BinaryOp::Is
+            | BinaryOp::IsNot
+            | BinaryOp::Lt
+            | BinaryOp::LtEq
+            | BinaryOp::Gt
+            | BinaryOp::GtEq
+    )
+}
+
+// Affinities the operands of a comparison are converted to, left then right: a column of numbers makes the other
+// operand numeric, unless it is one too, and a column of text makes text of an operand that has no affinity
+fn comparison_affinities(
+    left: Option<Affinity>,
+    right: Option<Affinity>,
+) -> (Option<Affinity>, Option<Affinity>) {
+    let numeric = |affinity: Option<Affinity>| {
+        matches!(
+            affinity,
+            Some(Affinity::Integer | Affinity::Real | Affinity::Numeric)
+        )
+    };
+    match (left, right) {
+        _ if numeric(left) && !numeric(right) => (None, Some(Affinity::Numeric)),
+        _ if numeric(right) && !numeric(left) => (Some(Affinity::Numeric), None),
+        (Some(Affinity::Text), None) => (None, Some(Affinity::Text)),
+        (None, Some(Affinity::Text)) => (Some(Affinity::Text), None),
+        _ => (None, None),
+    }
+}
+
+// The terms of the AND of `expr`
+fn conjuncts<'e>(expr: &'e Expr, terms: &mut Vec<&'e Expr>) {
+    match &expr.kind {
+        ExprKind::Binary {
+            op: BinaryOp::And,
+            left,
+            right,
+        } => {
+            conjuncts(left, terms);
+            conjuncts(right, terms);
+        }
+        _ => terms.push(expr),
+    }
+}
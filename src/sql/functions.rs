@@ -0,0 +1,630 @@
+//! What SQL does with values: the operators, type conversions, and the scalar and aggregate functions the VM calls
+//! Values convert the way they do in SQLite. Text used as a number is read up to the longest prefix that makes one, 0
+//! when there is none, and operators give NULL whenever one of their operands is NULL, AND and OR aside
+
+use std::{cmp::Ordering, collections::HashSet, fmt, io};
+
+use crate::{
+    key,
+    sql::ast::{BinaryOp, UnaryOp},
+    value::Value,
+};
+
+// Preferred type of a column, applied to the values stored in it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Affinity {
+    Integer,
+    Real,
+    Numeric,
+    Text,
+    // No conversion at all
+    Blob,
+}
+
+impl Affinity {
+    // Affinity of a column declared with `type_name`, by SQLite's rules: the first of INT, CHAR/CLOB/TEXT, BLOB (or no
+    // type at all) and REAL/FLOA/DOUB found in the name decides, NUMERIC being the default
+    pub fn from_type(type_name: Option<&str>) -> Self {
+        let Some(type_name) = type_name else {
+            return Self::Blob;
+        };
+        let upper = type_name.to_ascii_uppercase();
+        if upper.contains("INT") {
+            Self::Integer
+        } else if ["CHAR", "CLOB", "TEXT"]
+            .iter()
+            .any(|name| upper.contains(name))
+        {
+            Self::Text
+        } else if upper.contains("BLOB") {
+            Self::Blob
+        } else if ["REAL", "FLOA", "DOUB"]
+            .iter()
+            .any(|name| upper.contains(name))
+        {
+            Self::Real
+        } else {
+            Self::Numeric
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Integer => "INTEGER",
+            Self::Real => "REAL",
+            Self::Numeric => "NUMERIC",
+            Self::Text => "TEXT",
+            Self::Blob => "BLOB",
+        }
+    }
+}
+
+// Converts a value about to be stored in a column. Unlike a cast, text is only turned into a number when the whole of
+// it is one, and nothing is lost along the way
+pub fn apply_affinity(value: Value, affinity: Affinity) -> Value {
+    match (affinity, value) {
+        (Affinity::Text, value @ (Value::Integer(_) | Value::Real(_))) => {
+            Value::Text(to_text(&value))
+        }
+        (Affinity::Integer | Affinity::Numeric, Value::Text(text)) => match parse_number(&text) {
+            Some(number) => integral(number),
+            None => Value::Text(text),
+        },
+        (Affinity::Integer | Affinity::Numeric, Value::Real(real)) => integral(Value::Real(real)),
+        (Affinity::Real, Value::Integer(integer)) => Value::Real(integer as f64),
+        (Affinity::Real, Value::Text(text)) => match parse_number(&text) {
+            Some(number) => Value::Real(to_real(&number)),
+            None => Value::Text(text),
+        },
+        (_, value) => value,
+    }
+}
+
+// CAST(value AS type), where `affinity` is that of the type
+pub fn cast(value: Value, affinity: Affinity) -> Value {
+    match (affinity, value) {
+        (_, Value::Null) => Value::Null,
+        (Affinity::Integer, value) => Value::Integer(to_integer(&value)),
+        (Affinity::Real, value) => Value::Real(to_real(&value)),
+        (Affinity::Numeric, value @ (Value::Integer(_) | Value::Real(_))) => integral(value),
+        (Affinity::Numeric, value) => integral(numeric(&value)),
+        (Affinity::Text, Value::Text(text)) => Value::Text(text),
+        (Affinity::Text, value) => Value::Text(to_text(&value)),
+        (Affinity::Blob, Value::Blob(blob)) => Value::Blob(blob),
+        (Affinity::Blob, value) => Value::Blob(to_text(&value).into_bytes()),
+    }
+}
+
+// Reals without a fractional part become integers, when they fit
+fn integral(value: Value) -> Value {
+    match value {
+        Value::Real(real) if real.fract() == 0.0 && (-9.2e18..=9.2e18).contains(&real) => {
+            Value::Integer(real as i64)
+        }
+        value => value,
+    }
+}
+
+// Number made of the whole of `text`, leading and trailing spaces aside
+fn parse_number(text: &str) -> Option<Value> {
+    let text = text.trim();
+    match number_prefix(text) {
+        Some(len) if len == text.len() => Some(parse_prefix(text)),
+        _ => None,
+    }
+}
+
+// Length of the longest prefix of `text` that reads as a number, if any
+fn number_prefix(text: &str) -> Option<usize> {
+    let bytes = text.as_bytes();
+    let digits = |mut index: usize| {
+        while index < bytes.len() && bytes[index].is_ascii_digit() {
+            index += 1;
+        }
+        index
+    };
+
+    let mut end = match bytes.first() {
+        Some(b'+' | b'-') => 1,
+        _ => 0,
+    };
+    let integral = digits(end);
+    let mut has_digits = integral > end;
+    end = integral;
+    if bytes.get(end) == Some(&b'.') {
+        let fraction = digits(end + 1);
+        has_digits |= fraction > end + 1;
+        end = fraction;
+    }
+    if !has_digits {
+        return None;
+    }
+
+    if matches!(bytes.get(end), Some(b'e' | b'E')) {
+        let mut exponent = end + 1;
+        if matches!(bytes.get(exponent), Some(b'+' | b'-')) {
+            exponent += 1;
+        }
+        let exponent_end = digits(exponent);
+        if exponent_end > exponent {
+            end = exponent_end;
+        }
+    }
+
+    Some(end)
+}
+
+// `text` has to be a valid number
+fn parse_prefix(text: &str) -> Value {
+    match text.parse::<i64>() {
+        Ok(integer) => Value::Integer(integer),
+        Err(_) => Value::Real(text.parse().unwrap_or(0.0)),
+    }
+}
+
+// The value as a number, an integer or a real
+pub fn numeric(value: &Value) -> Value {
+    match value {
+        Value::Integer(_) | Value::Real(_) => value.clone(),
+        Value::Null => Value::Integer(0),
+        Value::Text(text) => text_number(text),
+        Value::Blob(blob) => text_number(&String::from_utf8_lossy(blob)),
+    }
+}
+
+fn text_number(text: &str) -> Value {
+    let text = text.trim_start();
+    match number_prefix(text) {
+        Some(len) => parse_prefix(&text[..len]),
+        None => Value::Integer(0),
+    }
+}
+
+pub fn to_integer(value: &Value) -> i64 {
+    match numeric(value) {
+        Value::Integer(integer) => integer,
+        // Saturates, NaN giving 0
+        Value::Real(real) => real as i64,
+        _ => unreachable!(),
+    }
+}
+
+pub fn to_real(value: &Value) -> f64 {
+    match numeric(value) {
+        Value::Integer(integer) => integer as f64,
+        Value::Real(real) => real,
+        _ => unreachable!(),
+    }
+}
+
+// Reals always show a fractional part or an exponent, so that they read back as reals
+pub fn to_text(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::Integer(integer) => integer.to_string(),
+        Value::Real(real) if real.fract() == 0.0 && real.abs() < 1e15 => format!("{real:.1}"),
+        Value::Real(real) if real.is_finite() && (real.abs() < 1e15 && real.abs() >= 1e-4) => {
+            real.to_string()
+        }
+        Value::Real(real) => {
+            // As in `1.5e+20`
+            let text = format!("{real:e}");
+            match text.split_once('e') {
+                Some((mantissa, exponent)) => {
+                    let point = if mantissa.contains('.') { "" } else { ".0" };
+                    let sign = if exponent.starts_with('-') { "" } else { "+" };
+                    format!("{mantissa}{point}e{sign}{exponent}")
+                }
+                None => text,
+            }
+        }
+        Value::Text(text) => text.clone(),
+        Value::Blob(blob) => String::from_utf8_lossy(blob).into_owned(),
+    }
+}
+
+// Truth value of a condition, `None` standing for NULL
+pub fn truth(value: &Value) -> Option<bool> {
+    match value {
+        Value::Null => None,
+        Value::Integer(integer) => Some(*integer != 0),
+        Value::Real(real) => Some(*real != 0.0),
+        value => Some(to_real(value) != 0.0),
+    }
+}
+
+fn boolean(value: Option<bool>) -> Value {
+    match value {
+        Some(value) => Value::Integer(value as i64),
+        None => Value::Null,
+    }
+}
+
+pub fn unary(op: UnaryOp, value: &Value) -> Value {
+    match (op, value) {
+        (UnaryOp::Plus, value) => value.clone(),
+        (_, Value::Null) => Value::Null,
+        (UnaryOp::Neg, value) => match numeric(value) {
+            Value::Integer(integer) => integer
+                .checked_neg()
+                .map_or(Value::Real(-(integer as f64)), Value::Integer),
+            Value::Real(real) => Value::Real(-real),
+            _ => unreachable!(),
+        },
+        (UnaryOp::Not, value) => boolean(truth(value).map(|value| !value)),
+        (UnaryOp::BitNot, value) => Value::Integer(!to_integer(value)),
+    }
+}
+
+pub fn binary(op: BinaryOp, left: &Value, right: &Value) -> Value {
+    match op {
+        BinaryOp::And => boolean(match (truth(left), truth(right)) {
+            (Some(false), _) | (_, Some(false)) => Some(false),
+            (Some(true), Some(true)) => Some(true),
+            _ => None,
+        }),
+        BinaryOp::Or => boolean(match (truth(left), truth(right)) {
+            (Some(true), _) | (_, Some(true)) => Some(true),
+            (Some(false), Some(false)) => Some(false),
+            _ => None,
+        }),
+        BinaryOp::Is => boolean(Some(left.compare(right) == Ordering::Equal)),
+        BinaryOp::IsNot => boolean(Some(left.compare(right) != Ordering::Equal)),
+        _ if left.is_null() || right.is_null() => Value::Null,
+        BinaryOp::Eq => boolean(Some(left.compare(right) == Ordering::Equal)),
+        BinaryOp::NotEq => boolean(Some(left.compare(right) != Ordering::Equal)),
+        BinaryOp::Lt => boolean(Some(left.compare(right) == Ordering::Less)),
+        BinaryOp::LtEq => boolean(Some(left.compare(right) != Ordering::Greater)),
+        BinaryOp::Gt => boolean(Some(left.compare(right) == Ordering::Greater)),
+        BinaryOp::GtEq => boolean(Some(left.compare(right) != Ordering::Less)),
+        BinaryOp::Concat => Value::Text(to_text(left) + &to_text(right)),
+        BinaryOp::BitAnd => Value::Integer(to_integer(left) & to_integer(right)),
+        BinaryOp::BitOr => Value::Integer(to_integer(left) | to_integer(right)),
+        BinaryOp::ShiftLeft => Value::Integer(shift_left(to_integer(left), to_integer(right))),
+        BinaryOp::ShiftRight => Value::Integer(shift_left(
+            to_integer(left),
+            to_integer(right).saturating_neg(),
+        )),
+        BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem => {
+            arithmetic(op, numeric(left), numeric(right))
+        }
+    }
+}
+
+// Shifts right when `amount` is negative. Shifting by 64 bits or more gives 0, or -1 for negative values shifted right
+fn shift_left(value: i64, amount: i64) -> i64 {
+    match amount {
+        64.. => 0,
+        0..=63 => value << amount,
+        -63..=-1 => value >> -amount,
+        _ if value < 0 => -1,
+        _ => 0,
+    }
+}
+
+// Integers overflowing fall back to reals. Dividing by zero gives NULL
+fn arithmetic(op: BinaryOp, left: Value, right: Value) -> Value {
+    if let (Value::Integer(a), Value::Integer(b)) = (&left, &right) {
+        let (a, b) = (*a, *b);
+        let result = match op {
+            BinaryOp::Add => a.checked_add(b),
+            BinaryOp::Sub => a.checked_sub(b),
+            BinaryOp::Mul => a.checked_mul(b),
+            BinaryOp::Div if b == 0 => return Value::Null,
+            BinaryOp::Div => a.checked_div(b),
+            BinaryOp::Rem if b == 0 => return Value::Null,
+            _ => Some(a.checked_rem(b).unwrap_or(0)),
+        };
+        if let Some(result) = result {
+            return Value::Integer(result);
+        }
+    }
+
+    let (a, b) = (to_real(&left), to_real(&right));
+    match op {
+        BinaryOp::Add => Value::Real(a + b),
+        BinaryOp::Sub => Value::Real(a - b),
+        BinaryOp::Mul => Value::Real(a * b),
+        _ if b == 0.0 => Value::Null,
+        BinaryOp::Div => Value::Real(a / b),
+        _ => Value::Real(a % b),
+    }
+}
+
+// `value LIKE pattern`, where `%` matches any sequence of characters and `_` any single one. ASCII letters match
+// regardless of their case
+pub fn like(value: &Value, pattern: &Value) -> Value {
+    if value.is_null() || pattern.is_null() {
+        return Value::Null;
+    }
+    let value: Vec<char> = to_text(value).chars().collect();
+    let pattern: Vec<char> = to_text(pattern).chars().collect();
+
+    // Greedy matching that backtracks to the latest `%` on a mismatch
+    let (mut v, mut p) = (0, 0);
+    let mut backtrack = None;
+    while v < value.len() {
+        match pattern.get(p) {
+            Some('%') => {
+                p += 1;
+                backtrack = Some((p, v));
+            }
+            Some('_') => {
+                p += 1;
+                v += 1;
+            }
+            Some(c) if c.eq_ignore_ascii_case(&value[v]) => {
+                p += 1;
+                v += 1;
+            }
+            _ => match backtrack {
+                Some((pattern_at, value_at)) => {
+                    p = pattern_at;
+                    v = value_at + 1;
+                    backtrack = Some((pattern_at, value_at + 1));
+                }
+                None => return boolean(Some(false)),
+            },
+        }
+    }
+
+    boolean(Some(pattern[p..].iter().all(|&c| c == '%')))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScalarFunction {
+    Abs,
+    Coalesce,
+    IfNull,
+    NullIf,
+    Length,
+    Lower,
+    Upper,
+    Typeof,
+    // `min` and `max` with several arguments, with one they are aggregates
+    Min,
+    Max,
+}
+
+impl ScalarFunction {
+    // The function called `name`, if any. Gives an error when it does not take `count` arguments
+    pub fn find(name: &str, count: usize) -> Option<Result<Self, String>> {
+        let (function, valid) = match name.to_ascii_lowercase().as_str() {
+            "abs" => (Self::Abs, count == 1),
+            "coalesce" => (Self::Coalesce, count >= 2),
+            "ifnull" => (Self::IfNull, count == 2),
+            "nullif" => (Self::NullIf, count == 2),
+            "length" => (Self::Length, count == 1),
+            "lower" => (Self::Lower, count == 1),
+            "upper" => (Self::Upper, count == 1),
+            "typeof" => (Self::Typeof, count == 1),
+            "min" if count >= 2 => (Self::Min, true),
+            "max" if count >= 2 => (Self::Max, true),
+            _ => return None,
+        };
+
+        Some(match valid {
+            true => Ok(function),
+            false => Err(format!("wrong number of arguments to function {name}()")),
+        })
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Abs => "abs",
+            Self::Coalesce => "coalesce",
+            Self::IfNull => "ifnull",
+            Self::NullIf => "nullif",
+            Self::Length => "length",
+            Self::Lower => "lower",
+            Self::Upper => "upper",
+            Self::Typeof => "typeof",
+            Self::Min => "min",
+            Self::Max => "max",
+        }
+    }
+
+    // `args` has as many values as were checked by `find`
+    pub fn call(self, args: &[Value]) -> Value {
+        match self {
+            Self::Abs => match &args[0] {
+                Value::Null => Value::Null,
+                value => match numeric(value) {
+                    Value::Integer(integer) => integer
+                        .checked_abs()
+                        .map_or(Value::Real(-(integer as f64)), Value::Integer),
+                    Value::Real(real) => Value::Real(real.abs()),
+                    _ => unreachable!(),
+                },
+            },
+            Self::Coalesce | Self::IfNull => args
+                .iter()
+                .find(|arg| !arg.is_null())
+                .cloned()
+                .unwrap_or(Value::Null),
+            Self::NullIf => match args[0].compare(&args[1]) {
+                Ordering::Equal => Value::Null,
+                _ => args[0].clone(),
+            },
+            Self::Length => match &args[0] {
+                Value::Null => Value::Null,
+                Value::Blob(blob) => Value::Integer(blob.len() as i64),
+                value => Value::Integer(to_text(value).chars().count() as i64),
+            },
+            Self::Lower | Self::Upper => match &args[0] {
+                Value::Null => Value::Null,
+                value if self == Self::Lower => Value::Text(to_text(value).to_lowercase()),
+                value => Value::Text(to_text(value).to_uppercase()),
+            },
+            Self::Typeof => Value::Text(
+                match &args[0] {
+                    Value::Null => "null",
+                    Value::Integer(_) => "integer",
+                    Value::Real(_) => "real",
+                    Value::Text(_) => "text",
+                    Value::Blob(_) => "blob",
+                }
+                .to_string(),
+            ),
+            Self::Min | Self::Max if args.iter().any(Value::is_null) => Value::Null,
+            // Of equal values, min() takes the last one and max() the first one
+            Self::Min => args
+                .iter()
+                .rev()
+                .min_by(|a, b| a.compare(b))
+                .cloned()
+                .unwrap_or(Value::Null),
+            Self::Max => args
+                .iter()
+                .rev()
+                .max_by(|a, b| a.compare(b))
+                .cloned()
+                .unwrap_or(Value::Null),
+        }
+    }
+}
+
+impl fmt::Display for ScalarFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AggregateFunction {
+    // `count(*)`, counting rows rather than values
+    CountRows,
+    Count,
+    Sum,
+    Total,
+    Avg,
+    Min,
+    Max,
+}
+
+impl AggregateFunction {
+    // The aggregate called `name`, if any, as for `ScalarFunction::find`. `count` is `None` for `(*)`
+    pub fn find(name: &str, count: Option<usize>) -> Option<Result<Self, String>> {
+        let function = match (name.to_ascii_lowercase().as_str(), count) {
+            ("count", None) => Self::CountRows,
+            ("count", _) => Self::Count,
+            ("sum", _) => Self::Sum,
+            ("total", _) => Self::Total,
+            ("avg", _) => Self::Avg,
+            ("min", Some(1) | None) => Self::Min,
+            ("max", Some(1) | None) => Self::Max,
+            _ => return None,
+        };
+
+        Some(match (function, count) {
+            (Self::CountRows, None) | (_, Some(1)) => Ok(function),
+            _ => Err(format!("wrong number of arguments to function {name}()")),
+        })
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::CountRows | Self::Count => "count",
+            Self::Sum => "sum",
+            Self::Total => "total",
+            Self::Avg => "avg",
+            Self::Min => "min",
+            Self::Max => "max",
+        }
+    }
+}
+
+impl fmt::Display for AggregateFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// State of an aggregate over the rows of a group
+#[derive(Debug, Default)]
+pub struct Accumulator {
+    count: i64,
+    // Sum of the values while they are all integers and it does not overflow
+    integer_sum: Option<i64>,
+    real_sum: f64,
+    overflowed: bool,
+    has_real: bool,
+    // Smallest or largest value so far
+    extreme: Option<Value>,
+    // Keys of the values seen so far, for DISTINCT aggregates
+    seen: HashSet<Vec<u8>>,
+}
+
+impl Accumulator {
+    // Adds the arguments of a row, which `CountRows` has none of
+    pub fn step(
+        &mut self,
+        function: AggregateFunction,
+        args: &[Value],
+        distinct: bool,
+    ) -> io::Result<()> {
+        let Some(value) = args.first() else {
+            self.count += 1;
+            return Ok(());
+        };
+        if value.is_null() || (distinct && !self.seen.insert(key::encode(args, &[]))) {
+            return Ok(());
+        }
+        self.count += 1;
+
+        match function {
+            AggregateFunction::Sum | AggregateFunction::Total | AggregateFunction::Avg => {
+                let number = numeric(value);
+                if let Value::Integer(integer) = number {
+                    if !self.overflowed {
+                        self.integer_sum = self.integer_sum.unwrap_or(0).checked_add(integer);
+                        self.overflowed = self.integer_sum.is_none();
+                    }
+                } else {
+                    self.has_real = true;
+                }
+                self.real_sum += to_real(&number);
+
+                if function == AggregateFunction::Sum && self.overflowed && !self.has_real {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "integer overflow",
+                    ));
+                }
+            }
+            AggregateFunction::Min | AggregateFunction::Max => {
+                let wanted = match function {
+                    AggregateFunction::Min => Ordering::Less,
+                    _ => Ordering::Greater,
+                };
+                if self
+                    .extreme
+                    .as_ref()
+                    .is_none_or(|extreme| value.compare(extreme) == wanted)
+                {
+                    self.extreme = Some(value.clone());
+                }
+            }
+            AggregateFunction::CountRows | AggregateFunction::Count => {}
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(&self, function: AggregateFunction) -> Value {
+        match function {
+            AggregateFunction::CountRows | AggregateFunction::Count => Value::Integer(self.count),
+            AggregateFunction::Sum if self.count == 0 => Value::Null,
+            AggregateFunction::Sum => match (self.has_real, self.integer_sum) {
+                (false, Some(sum)) => Value::Integer(sum),
+                _ => Value::Real(self.real_sum),
+            },
+            AggregateFunction::Total => Value::Real(self.real_sum),
+            AggregateFunction::Avg if self.count == 0 => Value::Null,
+            AggregateFunction::Avg => Value::Real(self.real_sum / self.count as f64),
+            AggregateFunction::Min | AggregateFunction::Max => {
+                self.extreme.clone().unwrap_or(Value::Null)
+            }
+        }
+    }
+}
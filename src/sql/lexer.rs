@@ -0,0 +1,610 @@
+//! Splits SQL text into tokens. Whitespace and comments (`-- ...` up to the end of the line, `/* ... */`) separate
+//! tokens and are dropped
+//! Keywords are case-insensitive and reserved: a keyword can only be used as a name when quoted. Names are quoted with
+//! double quotes, backticks or square brackets, and strings with single quotes, the quote being doubled to appear
+//! inside. Blobs are written as `X'...'` with two hexadecimal digits per byte
+//! Integers that do not fit in 64 bits are read as reals. The sign of a number is a separate token
+
+use std::io;
+
+use crate::sql::{syntax_error, Span};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    Keyword(Keyword),
+    Identifier(String),
+    // Unsigned, the parser tells whether it fits in an `i64` once it knows the sign
+    Integer(u64),
+    Real(f64),
+    String(String),
+    Blob(Vec<u8>),
+    // `?` without a number is `None`, and takes the number after the largest one used before it
+    Parameter(Option<u32>),
+    LeftParen,
+    RightParen,
+    Comma,
+    Semicolon,
+    Dot,
+    Star,
+    Plus,
+    Minus,
+    Slash,
+    Percent,
+    // `||`
+    Concat,
+    // `=` or `==`
+    Eq,
+    // `!=` or `<>`
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    BitAnd,
+    BitOr,
+    BitNot,
+    ShiftLeft,
+    ShiftRight,
+    Eof,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Keyword {
+    All,
+    And,
+    As,
+    Asc,
+    Between,
+    By,
+    Case,
+    Cast,
+    Create,
+    Cross,
+    Default,
+    Delete,
+    Desc,
+    Distinct,
+    Drop,
+    Else,
+    End,
+    Exists,
+    Explain,
+    From,
+    Group,
+    Having,
+    If,
+    In,
+    Index,
+    Inner,
+    Insert,
+    Into,
+    Is,
+    Join,
+    Key,
+    Left,
+    Like,
+    Limit,
+    Not,
+    Null,
+    Offset,
+    On,
+    Or,
+    Order,
+    Outer,
+    Primary,
+    Select,
+    Set,
+    Table,
+    Then,
+    Unique,
+    Update,
+    Using,
+    Values,
+    When,
+    Where,
+}
+
+const KEYWORDS: &[(&str, Keyword)] = &[
+    ("ALL", Keyword::All),
+    ("AND", Keyword::And),
+    ("AS", Keyword::As),
+    ("ASC", Keyword::Asc),
+    ("BETWEEN", Keyword::Between),
+    ("BY", Keyword::By),
+    ("CASE", Keyword::Case),
+    ("CAST", Keyword::Cast),
+    ("CREATE", Keyword::Create),
+    ("CROSS", Keyword::Cross),
+    ("DEFAULT", Keyword::Default),
+    ("DELETE", Keyword::Delete),
+    ("DESC", Keyword::Desc),
+    ("DISTINCT", Keyword::Distinct),
+    ("DROP", Keyword::Drop),
+    ("ELSE", Keyword::Else),
+    ("END", Keyword::End),
+    ("EXISTS", Keyword::Exists),
+    ("EXPLAIN", Keyword::Explain),
+    ("FROM", Keyword::From),
+    ("GROUP", Keyword::Group),
+    ("HAVING", Keyword::Having),
+    ("IF", Keyword::If),
+    ("IN", Keyword::In),
+    ("INDEX", Keyword::Index),
+    ("INNER", Keyword::Inner),
+    ("INSERT", Keyword::Insert),
+    ("INTO", Keyword::Into),
+    ("IS", Keyword::Is),
+    ("JOIN", Keyword::Join),
+    ("KEY", Keyword::Key),
+    ("LEFT", Keyword::Left),
+    ("LIKE", Keyword::Like),
+    ("LIMIT", Keyword::Limit),
+    ("NOT", Keyword::Not),
+    ("NULL", Keyword::Null),
+    ("OFFSET", Keyword::Offset),
+    ("ON", Keyword::On),
+    ("OR", Keyword::Or),
+    ("ORDER", Keyword::Order),
+    ("OUTER", Keyword::Outer),
+    ("PRIMARY", Keyword::Primary),
+    ("SELECT", Keyword::Select),
+    ("SET", Keyword::Set),
+    ("TABLE", Keyword::Table),
+    ("THEN", Keyword::Then),
+    ("UNIQUE", Keyword::Unique),
+    ("UPDATE", Keyword::Update),
+    ("USING", Keyword::Using),
+    ("VALUES", Keyword::Values),
+    ("WHEN", Keyword::When),
+    ("WHERE", Keyword::Where),
+];
+
+impl Keyword {
+    pub fn from_word(word: &str) -> Option<Self> {
+        KEYWORDS
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(word))
+            .map(|&(_, keyword)| keyword)
+    }
+
+    pub fn as_str(self) -> &'static str {
+        KEYWORDS
+            .iter()
+            .find(|&&(_, keyword)| keyword == self)
+            .map(|&(name, _)| name)
+            .expect("Every keyword is listed")
+    }
+}
+
+// Splits the whole of `sql` into tokens, the last one being `Eof`
+pub fn tokenize(sql: &str) -> io::Result<Vec<Token>> {
+    let mut lexer = Lexer::new(sql);
+    let mut tokens = vec![];
+    loop {
+        let token = lexer.next_token()?;
+        let eof = token.kind == TokenKind::Eof;
+        tokens.push(token);
+        if eof {
+            return Ok(tokens);
+        }
+    }
+}
+
+pub struct Lexer<'a> {
+    sql: &'a str,
+    offset: usize,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(sql: &'a str) -> Self {
+        Self { sql, offset: 0 }
+    }
+
+    // Returns `Eof` at the end of the text, and every time it is called after that
+    pub fn next_token(&mut self) -> io::Result<Token> {
+        self.skip_whitespace_and_comments()?;
+
+        let start = self.offset;
+        let Some(c) = self.peek() else {
+            return Ok(Token {
+                kind: TokenKind::Eof,
+                span: Span::new(start, start),
+            });
+        };
+
+        let kind = match c {
+            'x' | 'X' if self.peek_at(1) == Some('\'') => self.blob()?,
+            c if c.is_ascii_alphabetic() || c == '_' || !c.is_ascii() => self.word(),
+            c if c.is_ascii_digit() => self.number()?,
+            '.' if self.peek_at(1).is_some_and(|c| c.is_ascii_digit()) => self.number()?,
+            '\'' => TokenKind::String(self.quoted('\'')?),
+            '"' => TokenKind::Identifier(self.quoted('"')?),
+            '`' => TokenKind::Identifier(self.quoted('`')?),
+            '[' => {
+                self.bump();
+                let end = self.sql[self.offset..]
+                    .find(']')
+                    .ok_or_else(|| self.error(start, "Unterminated name".to_string()))?;
+                let name = self.sql[self.offset..self.offset + end].to_string();
+                self.offset += end + 1;
+                TokenKind::Identifier(name)
+            }
+            '?' => {
+                self.bump();
+                let digits = self.take_while(|c| c.is_ascii_digit());
+                match digits {
+                    "" => TokenKind::Parameter(None),
+                    digits => match digits.parse() {
+                        Ok(number) if number > 0 => TokenKind::Parameter(Some(number)),
+                        _ => {
+                            return Err(
+                                self.error(start, format!("Invalid parameter number ?{digits}"))
+                            )
+                        }
+                    },
+                }
+            }
+            _ => {
+                self.bump();
+                let next = self.peek();
+                let (kind, two_chars) = match (c, next) {
+                    ('(', _) => (TokenKind::LeftParen, false),
+                    (')', _) => (TokenKind::RightParen, false),
+                    (',', _) => (TokenKind::Comma, false),
+                    (';', _) => (TokenKind::Semicolon, false),
+                    ('.', _) => (TokenKind::Dot, false),
+                    ('*', _) => (TokenKind::Star, false),
+                    ('+', _) => (TokenKind::Plus, false),
+                    ('-', _) => (TokenKind::Minus, false),
+                    ('/', _) => (TokenKind::Slash, false),
+                    ('%', _) => (TokenKind::Percent, false),
+                    ('~', _) => (TokenKind::BitNot, false),
+                    ('&', _) => (TokenKind::BitAnd, false),
+                    ('|', Some('|')) => (TokenKind::Concat, true),
+                    ('|', _) => (TokenKind::BitOr, false),
+                    ('=', Some('=')) => (TokenKind::Eq, true),
+                    ('=', _) => (TokenKind::Eq, false),
+                    ('!', Some('=')) => (TokenKind::NotEq, true),
+                    ('<', Some('>')) => (TokenKind::NotEq, true),
+                    ('<', Some('=')) => (TokenKind::LtEq, true),
+                    ('<', Some('<')) => (TokenKind::ShiftLeft, true),
+                    ('<', _) => (TokenKind::Lt, false),
+                    ('>', Some('=')) => (TokenKind::GtEq, true),
+                    ('>', Some('>')) => (TokenKind::ShiftRight, true),
+                    ('>', _) => (TokenKind::Gt, false),
+                    _ => return Err(self.error(start, format!("Unexpected character '{c}'"))),
+                };
+                if two_chars {
+                    self.bump();
+                }
+                kind
+            }
+        };
+
+        Ok(Token {
+            kind,
+            span: Span::new(start, self.offset),
+        })
+    }
+
+    fn skip_whitespace_and_comments(&mut self) -> io::Result<()> {
+        loop {
+            self.take_while(char::is_whitespace);
+
+            let rest = &self.sql[self.offset..];
+            if rest.starts_with("--") {
+                self.offset += rest.find('\n').unwrap_or(rest.len());
+            } else if let Some(comment) = rest.strip_prefix("/*") {
+                let end = comment
+                    .find("*/")
+                    .ok_or_else(|| self.error(self.offset, "Unterminated comment".to_string()))?;
+                self.offset += end + 4;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn word(&mut self) -> TokenKind {
+        let word =
+            self.take_while(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$' || !c.is_ascii());
+        match Keyword::from_word(word) {
+            Some(keyword) => TokenKind::Keyword(keyword),
+            None => TokenKind::Identifier(word.to_string()),
+        }
+    }
+
+    fn number(&mut self) -> io::Result<TokenKind> {
+        let start = self.offset;
+        let mut real = false;
+
+        self.take_while(|c| c.is_ascii_digit());
+        if self.peek() == Some('.') {
+            real = true;
+            self.bump();
+            self.take_while(|c| c.is_ascii_digit());
+        }
+        if let Some('e' | 'E') = self.peek() {
+            real = true;
+            self.bump();
+            if let Some('+' | '-') = self.peek() {
+                self.bump();
+            }
+            if self.take_while(|c| c.is_ascii_digit()).is_empty() {
+                return Err(self.error(start, "Malformed number".to_string()));
+            }
+        }
+        // Such as `12abc`
+        if self
+            .peek()
+            .is_some_and(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            return Err(self.error(start, "Malformed number".to_string()));
+        }
+
+        let text = &self.sql[start..self.offset];
+        let real_value = || {
+            text.parse()
+                .map_err(|_| self.error(start, "Malformed number".to_string()))
+        };
+        match real {
+            true => Ok(TokenKind::Real(real_value()?)),
+            false => match text.parse() {
+                Ok(integer) => Ok(TokenKind::Integer(integer)),
+                Err(_) => Ok(TokenKind::Real(real_value()?)),
+            },
+        }
+    }
+
+    fn blob(&mut self) -> io::Result<TokenKind> {
+        let start = self.offset;
+        self.bump();
+        let hex = self.quoted('\'')?;
+        if hex.len() % 2 != 0 || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            return Err(self.error(start, "Malformed blob literal".to_string()));
+        }
+
+        let blob = (0..hex.len())
+            .step_by(2)
+            .map(|index| u8::from_str_radix(&hex[index..index + 2], 16).expect("Valid hex digits"))
+            .collect();
+        Ok(TokenKind::Blob(blob))
+    }
+
+    // Text between `quote` and the next single `quote`, a doubled quote standing for one quote
+    fn quoted(&mut self, quote: char) -> io::Result<String> {
+        let start = self.offset;
+        self.bump();
+
+        let mut text = String::new();
+        loop {
+            match self.bump() {
+                Some(c) if c == quote && self.peek() == Some(quote) => {
+                    self.bump();
+                    text.push(quote);
+                }
+                Some(c) if c == quote => return Ok(text),
+                Some(c) => text.push(c),
+                None => {
+                    let what = match quote {
+                        '\'' => "string",
+                        _ => "name",
+                    };
+                    return Err(self.error(start, format!("Unterminated {what}")));
+                }
+            }
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.sql[self.offset..].chars().next()
+    }
+
+    fn peek_at(&self, n: usize) -> Option<char> {
+        self.sql[self.offset..].chars().nth(n)
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.offset += c.len_utf8();
+        Some(c)
+    }
+
+    fn take_while(&mut self, predicate: impl Fn(char) -> bool) -> &'a str {
+        let start = self.offset;
+        while self.peek().is_some_and(&predicate) {
+            self.bump();
+        }
+        &self.sql[start..self.offset]
+    }
+
+    fn error(&self, start: usize, message: String) -> io::Error {
+        syntax_error(self.sql, Span::new(start, self.offset), message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::SyntaxError;
+
+    fn kinds(sql: &str) -> Vec<TokenKind> {
+        let mut tokens = tokenize(sql).unwrap();
+        assert_eq!(tokens.pop().map(|token| token.kind), Some(TokenKind::Eof));
+        tokens.into_iter().map(|token| token.kind).collect()
+    }
+
+    fn syntax_error_of(sql: &str) -> SyntaxError {
+        let err = tokenize(sql).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        *err.into_inner().unwrap().downcast().unwrap()
+    }
+
+    fn identifier(name: &str) -> TokenKind {
+        TokenKind::Identifier(name.to_string())
+    }
+
+    #[test]
+    fn words_and_spans() {
+        let sql = "select Name_1 FROM\tt$ -- comment\n /* multi\nline */ éte";
+        let last = sql.find('é').unwrap();
+        let tokens = tokenize(sql).unwrap();
+        let spans: Vec<(TokenKind, Span)> = tokens
+            .into_iter()
+            .map(|token| (token.kind, token.span))
+            .collect();
+        assert_eq!(
+            spans,
+            [
+                (TokenKind::Keyword(Keyword::Select), Span::new(0, 6)),
+                (identifier("Name_1"), Span::new(7, 13)),
+                (TokenKind::Keyword(Keyword::From), Span::new(14, 18)),
+                (identifier("t$"), Span::new(19, 21)),
+                (identifier("éte"), Span::new(last, sql.len())),
+                (TokenKind::Eof, Span::new(sql.len(), sql.len())),
+            ]
+        );
+    }
+
+    #[test]
+    fn quoting() {
+        assert_eq!(
+            kinds(r#"'it''s' "a""b" [a "b"] `c``d` "select" '' """#),
+            [
+                TokenKind::String("it's".to_string()),
+                identifier("a\"b"),
+                identifier("a \"b\""),
+                identifier("c`d"),
+                // Quoted keywords are names
+                identifier("select"),
+                TokenKind::String(String::new()),
+                identifier(""),
+            ]
+        );
+    }
+
+    #[test]
+    fn blobs() {
+        assert_eq!(
+            kinds("X'00fF7a' x'' xy"),
+            [
+                TokenKind::Blob(vec![0x00, 0xff, 0x7a]),
+                TokenKind::Blob(vec![]),
+                identifier("xy"),
+            ]
+        );
+
+        for sql in ["X'abc'", "X'zz'"] {
+            let err = syntax_error_of(sql);
+            assert_eq!(err.message, "Malformed blob literal");
+            assert_eq!(err.span, Span::new(0, sql.len()));
+        }
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(
+            kinds("0 42 3.5 .5 1. 1e3 2.5E-2 18446744073709551615 18446744073709551616"),
+            [
+                TokenKind::Integer(0),
+                TokenKind::Integer(42),
+                TokenKind::Real(3.5),
+                TokenKind::Real(0.5),
+                TokenKind::Real(1.0),
+                TokenKind::Real(1000.0),
+                TokenKind::Real(0.025),
+                TokenKind::Integer(u64::MAX),
+                // Too large for any integer
+                TokenKind::Real(18446744073709551616.0),
+            ]
+        );
+        // The sign is a token of its own
+        assert_eq!(kinds("-1"), [TokenKind::Minus, TokenKind::Integer(1)]);
+
+        for sql in ["12abc", "1e", "1e+"] {
+            assert_eq!(syntax_error_of(sql).message, "Malformed number");
+        }
+    }
+
+    #[test]
+    fn parameters() {
+        assert_eq!(
+            kinds("? ?1 ?42"),
+            [
+                TokenKind::Parameter(None),
+                TokenKind::Parameter(Some(1)),
+                TokenKind::Parameter(Some(42)),
+            ]
+        );
+        assert_eq!(syntax_error_of("?0").message, "Invalid parameter number ?0");
+        assert_eq!(
+            syntax_error_of("?99999999999").message,
+            "Invalid parameter number ?99999999999"
+        );
+    }
+
+    #[test]
+    fn operators() {
+        assert_eq!(
+            kinds("|| | == = != <> <= << < >= >> > & ~ * / % + - . , ; ( )"),
+            [
+                TokenKind::Concat,
+                TokenKind::BitOr,
+                TokenKind::Eq,
+                TokenKind::Eq,
+                TokenKind::NotEq,
+                TokenKind::NotEq,
+                TokenKind::LtEq,
+                TokenKind::ShiftLeft,
+                TokenKind::Lt,
+                TokenKind::GtEq,
+                TokenKind::ShiftRight,
+                TokenKind::Gt,
+                TokenKind::BitAnd,
+                TokenKind::BitNot,
+                TokenKind::Star,
+                TokenKind::Slash,
+                TokenKind::Percent,
+                TokenKind::Plus,
+                TokenKind::Minus,
+                TokenKind::Dot,
+                TokenKind::Comma,
+                TokenKind::Semicolon,
+                TokenKind::LeftParen,
+                TokenKind::RightParen,
+            ]
+        );
+
+        let err = syntax_error_of("SELECT 1 !");
+        assert_eq!(err.message, "Unexpected character '!'");
+        assert_eq!((err.line, err.column), (1, 10));
+    }
+
+    #[test]
+    fn unterminated() {
+        let err = syntax_error_of("SELECT 'abc");
+        assert_eq!(err.message, "Unterminated string");
+        assert_eq!(err.span, Span::new(7, 11));
+        assert_eq!((err.line, err.column), (1, 8));
+
+        for sql in ["\"abc", "`abc", "[abc"] {
+            let err = syntax_error_of(sql);
+            assert_eq!(err.message, "Unterminated name");
+            assert_eq!(err.span.start, 0);
+        }
+
+        let err = syntax_error_of("SELECT 1\n  /* comment * /");
+        assert_eq!(err.message, "Unterminated comment");
+        assert_eq!(err.span.start, 11);
+        assert_eq!((err.line, err.column), (2, 3));
+
+        // A line comment may end the text
+        assert_eq!(kinds("1 -- no newline"), [TokenKind::Integer(1)]);
+    }
+}
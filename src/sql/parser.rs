@@ -0,0 +1,1568 @@
+//! Recursive-descent parser turning tokens into the syntax trees of `ast`, one function per rule of the grammar
+//! Operators bind as in SQLite, from the loosest to the tightest, operators of the same level associating to the left:
+//! - OR
+//! - AND
+//! - NOT (prefix)
+//! - `=`, `!=`, IS, IS NOT, IN, LIKE, BETWEEN
+//! - `<`, `<=`, `>`, `>=`
+//! - `&`, `|`, `<<`, `>>`
+//! - `+`, `-`
+//! - `*`, `/`, `%`
+//! - `||`
+//! - `-`, `+`, `~` (prefix)
+
+use std::io;
+
+use crate::{
+    key::SortOrder,
+    sql::{
+        ast::*,
+        lexer::{self, Keyword, Token, TokenKind},
+        syntax_error, Span,
+    },
+    value::Value,
+};
+
+// Expressions and subqueries the parser can be inside of at once. Each level takes a few stack frames of the recursive
+// descent, which must not run out of stack
+const MAX_NESTING: usize = 100;
+// Height of expression trees, which the layers above walk recursively, as in SQLite. Operators chained at the same
+// level of nesting build trees as high as the chain is long
+const MAX_DEPTH: usize = 1000;
+
+// Levels of the binary operators between comparison and concatenation, from the loosest to the tightest
+const BINARY_LEVELS: &[&[(TokenKind, BinaryOp)]] = &[
+    &[
+        (TokenKind::Lt, BinaryOp::Lt),
+        (TokenKind::LtEq, BinaryOp::LtEq),
+        (TokenKind::Gt, BinaryOp::Gt),
+        (TokenKind::GtEq, BinaryOp::GtEq),
+    ],
+    &[
+        (TokenKind::BitAnd, BinaryOp::BitAnd),
+        (TokenKind::BitOr, BinaryOp::BitOr),
+        (TokenKind::ShiftLeft, BinaryOp::ShiftLeft),
+        (TokenKind::ShiftRight, BinaryOp::ShiftRight),
+    ],
+    &[
+        (TokenKind::Plus, BinaryOp::Add),
+        (TokenKind::Minus, BinaryOp::Sub),
+    ],
+    &[
+        (TokenKind::Star, BinaryOp::Mul),
+        (TokenKind::Slash, BinaryOp::Div),
+        (TokenKind::Percent, BinaryOp::Rem),
+    ],
+    &[(TokenKind::Concat, BinaryOp::Concat)],
+];
+
+// Parses the statements of `sql`, separated by semicolons
+pub fn parse(sql: &str) -> io::Result<Vec<Statement>> {
+    let mut parser = Parser::new(sql)?;
+    let mut statements = vec![];
+    while let Some(statement) = parser.next_statement()? {
+        statements.push(statement);
+    }
+
+    Ok(statements)
+}
+
+// Parses `sql` as a single statement, optionally followed by a semicolon
+pub fn parse_statement(sql: &str) -> io::Result<Statement> {
+    let mut parser = Parser::new(sql)?;
+    let statement = parser
+        .next_statement()?
+        .ok_or_else(|| syntax_error(sql, Span::new(0, sql.len()), "Empty statement".to_string()))?;
+    if parser.peek() != &TokenKind::Eof {
+        return Err(parser.error("Expected the end of the statement"));
+    }
+
+    Ok(statement)
+}
+
+pub struct Parser<'a> {
+    sql: &'a str,
+    tokens: Vec<Token>,
+    position: usize,
+    // Largest parameter number used so far, which the next `?` follows
+    parameters: u32,
+    // Expressions and subqueries being parsed, nested into each other
+    nesting: usize,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(sql: &'a str) -> io::Result<Self> {
+        Ok(Self {
+            sql,
+            tokens: lexer::tokenize(sql)?,
+            position: 0,
+            parameters: 0,
+            nesting: 0,
+        })
+    }
+
+    // Parses the next statement along with the semicolon ending it, if any. Returns `None` at the end of the text
+    pub fn next_statement(&mut self) -> io::Result<Option<Statement>> {
+        while self.eat(&TokenKind::Semicolon) {}
+        if self.peek() == &TokenKind::Eof {
+            return Ok(None);
+        }
+
+        let statement = self.statement()?;
+        if !self.eat(&TokenKind::Semicolon) && self.peek() != &TokenKind::Eof {
+            return Err(self.error("Expected ';' or the end of the statement"));
+        }
+
+        Ok(Some(statement))
+    }
+
+    // Number of the largest parameter found so far
+    pub fn parameter_count(&self) -> u32 {
+        self.parameters
+    }
+
+    fn statement(&mut self) -> io::Result<Statement> {
+        match self.peek() {
+            TokenKind::Keyword(Keyword::Create) => self.create(),
+            TokenKind::Keyword(Keyword::Drop) => self.drop(),
+            TokenKind::Keyword(Keyword::Insert) => self.insert().map(Statement::Insert),
+            TokenKind::Keyword(Keyword::Select) => self
+                .select()
+                .map(|select| Statement::Select(Box::new(select))),
+            TokenKind::Keyword(Keyword::Update) => self.update().map(Statement::Update),
+            TokenKind::Keyword(Keyword::Delete) => self.delete().map(Statement::Delete),
+            TokenKind::Keyword(Keyword::Explain) => self.explain().map(Statement::Explain),
+            _ => Err(self.error("Expected a statement")),
+        }
+    }
+
+    fn explain(&mut self) -> io::Result<Explain> {
+        let start = self.start();
+        self.expect_keyword(Keyword::Explain)?;
+        if self.peek() == &TokenKind::Keyword(Keyword::Explain) {
+            return Err(self.error("Expected a statement to explain"));
+        }
+        let statement = Box::new(self.statement()?);
+
+        Ok(Explain {
+            statement,
+            span: self.span_from(start),
+        })
+    }
+
+    fn create(&mut self) -> io::Result<Statement> {
+        let start = self.start();
+        self.expect_keyword(Keyword::Create)?;
+
+        let unique = self.eat_keyword(Keyword::Unique);
+        if unique || self.peek() == &TokenKind::Keyword(Keyword::Index) {
+            return self.create_index(start, unique).map(Statement::CreateIndex);
+        }
+        self.expect_keyword(Keyword::Table)?;
+        let if_not_exists = self.if_not_exists()?;
+        let name = self.ident()?;
+
+        self.expect(TokenKind::LeftParen)?;
+        let mut columns = vec![];
+        let mut constraints = vec![];
+        loop {
+            match self.peek() {
+                TokenKind::Keyword(Keyword::Primary) => {
+                    self.advance();
+                    self.expect_keyword(Keyword::Key)?;
+                    constraints.push(TableConstraint::PrimaryKey(self.indexed_columns()?));
+                }
+                TokenKind::Keyword(Keyword::Unique) => {
+                    self.advance();
+                    constraints.push(TableConstraint::Unique(self.indexed_columns()?));
+                }
+                // Like in SQLite, columns come before the constraints of the table
+                _ if constraints.is_empty() => columns.push(self.column_def()?),
+                _ => return Err(self.error("Expected a table constraint")),
+            }
+            if !self.eat(&TokenKind::Comma) {
+                break;
+            }
+        }
+        self.expect(TokenKind::RightParen)?;
+
+        Ok(Statement::CreateTable(CreateTable {
+            name,
+            if_not_exists,
+            columns,
+            constraints,
+            span: self.span_from(start),
+        }))
+    }
+
+    fn column_def(&mut self) -> io::Result<ColumnDef> {
+        let start = self.start();
+        let name = self.ident()?;
+        let type_name = self.type_name()?;
+
+        let mut constraints = vec![];
+        loop {
+            let constraint = match self.peek() {
+                TokenKind::Keyword(Keyword::Primary) => {
+                    self.advance();
+                    self.expect_keyword(Keyword::Key)?;
+                    ColumnConstraint::PrimaryKey(self.sort_order())
+                }
+                TokenKind::Keyword(Keyword::Not) => {
+                    self.advance();
+                    self.expect_keyword(Keyword::Null)?;
+                    ColumnConstraint::NotNull
+                }
+                TokenKind::Keyword(Keyword::Unique) => {
+                    self.advance();
+                    ColumnConstraint::Unique
+                }
+                // Only literals, signed numbers and expressions between parentheses, which keeps `DEFAULT 0 NOT NULL`
+                // from being read as a comparison
+                TokenKind::Keyword(Keyword::Default) => {
+                    self.advance();
+                    ColumnConstraint::Default(self.unary()?)
+                }
+                _ => break,
+            };
+            constraints.push(constraint);
+        }
+
+        Ok(ColumnDef {
+            name,
+            type_name,
+            constraints,
+            span: self.span_from(start),
+        })
+    }
+
+    // A sequence of names, optionally followed by one or two signed numbers between parentheses, such as
+    // `DOUBLE PRECISION` or `DECIMAL(10, 2)`
+    fn type_name(&mut self) -> io::Result<Option<String>> {
+        if !matches!(self.peek(), TokenKind::Identifier(_)) {
+            return Ok(None);
+        }
+
+        let start = self.start();
+        while matches!(self.peek(), TokenKind::Identifier(_)) {
+            self.advance();
+        }
+        if self.eat(&TokenKind::LeftParen) {
+            loop {
+                if !self.eat(&TokenKind::Plus) {
+                    self.eat(&TokenKind::Minus);
+                }
+                match self.peek() {
+                    TokenKind::Integer(_) | TokenKind::Real(_) => self.advance(),
+                    _ => return Err(self.error("Expected a number")),
+                };
+                if !self.eat(&TokenKind::Comma) {
+                    break;
+                }
+            }
+            self.expect(TokenKind::RightParen)?;
+        }
+
+        Ok(Some(self.span_from(start).text(self.sql).to_string()))
+    }
+
+    fn create_index(&mut self, start: usize, unique: bool) -> io::Result<CreateIndex> {
+        self.expect_keyword(Keyword::Index)?;
+        let if_not_exists = self.if_not_exists()?;
+        let name = self.ident()?;
+        self.expect_keyword(Keyword::On)?;
+        let table = self.ident()?;
+        let columns = self.indexed_columns()?;
+
+        Ok(CreateIndex {
+            name,
+            if_not_exists,
+            unique,
+            table,
+            columns,
+            span: self.span_from(start),
+        })
+    }
+
+    fn indexed_columns(&mut self) -> io::Result<Vec<IndexedColumn>> {
+        self.expect(TokenKind::LeftParen)?;
+        let columns = self.comma_separated(|parser| {
+            Ok(IndexedColumn {
+                name: parser.ident()?,
+                order: parser.sort_order(),
+            })
+        })?;
+        self.expect(TokenKind::RightParen)?;
+
+        Ok(columns)
+    }
+
+    fn if_not_exists(&mut self) -> io::Result<bool> {
+        if !self.eat_keyword(Keyword::If) {
+            return Ok(false);
+        }
+        self.expect_keyword(Keyword::Not)?;
+        self.expect_keyword(Keyword::Exists)?;
+
+        Ok(true)
+    }
+
+    fn drop(&mut self) -> io::Result<Statement> {
+        let start = self.start();
+        self.expect_keyword(Keyword::Drop)?;
+
+        let index = match self.peek() {
+            TokenKind::Keyword(Keyword::Table) => false,
+            TokenKind::Keyword(Keyword::Index) => true,
+            _ => return Err(self.error("Expected TABLE or INDEX")),
+        };
+        self.advance();
+        let if_exists = self.eat_keyword(Keyword::If);
+        if if_exists {
+            self.expect_keyword(Keyword::Exists)?;
+        }
+        let drop = Drop {
+            name: self.ident()?,
+            if_exists,
+            span: self.span_from(start),
+        };
+
+        Ok(match index {
+            true => Statement::DropIndex(drop),
+            false => Statement::DropTable(drop),
+        })
+    }
+
+    fn insert(&mut self) -> io::Result<Insert> {
+        let start = self.start();
+        self.expect_keyword(Keyword::Insert)?;
+        self.expect_keyword(Keyword::Into)?;
+        let table = self.ident()?;
+
+        let mut columns = vec![];
+        if self.eat(&TokenKind::LeftParen) {
+            columns = self.comma_separated(Self::ident)?;
+            self.expect(TokenKind::RightParen)?;
+        }
+
+        let source = match self.peek() {
+            TokenKind::Keyword(Keyword::Values) => {
+                self.advance();
+                InsertSource::Values(self.comma_separated(|parser| {
+                    parser.expect(TokenKind::LeftParen)?;
+                    let row = parser.comma_separated(Self::expr)?;
+                    parser.expect(TokenKind::RightParen)?;
+                    Ok(row)
+                })?)
+            }
+            TokenKind::Keyword(Keyword::Select) => InsertSource::Select(self.subquery()?),
+            TokenKind::Keyword(Keyword::Default) if columns.is_empty() => {
+                self.advance();
+                self.expect_keyword(Keyword::Values)?;
+                InsertSource::DefaultValues
+            }
+            _ => return Err(self.error("Expected VALUES or SELECT")),
+        };
+
+        Ok(Insert {
+            table,
+            columns,
+            source,
+            span: self.span_from(start),
+        })
+    }
+
+    fn select(&mut self) -> io::Result<Select> {
+        self.nested(Self::select_body)
+    }
+
+    fn select_body(&mut self) -> io::Result<Select> {
+        let start = self.start();
+        self.expect_keyword(Keyword::Select)?;
+
+        let distinct = self.eat_keyword(Keyword::Distinct);
+        if !distinct {
+            self.eat_keyword(Keyword::All);
+        }
+        let columns = self.comma_separated(Self::result_column)?;
+
+        let from = match self.eat_keyword(Keyword::From) {
+            true => Some(self.tables()?),
+            false => None,
+        };
+        let where_clause = self.optional_where()?;
+
+        let mut group_by = vec![];
+        let mut having = None;
+        if self.eat_keyword(Keyword::Group) {
+            self.expect_keyword(Keyword::By)?;
+            group_by = self.comma_separated(Self::expr)?;
+            if self.eat_keyword(Keyword::Having) {
+                having = Some(self.expr()?);
+            }
+        }
+
+        let mut order_by = vec![];
+        if self.eat_keyword(Keyword::Order) {
+            self.expect_keyword(Keyword::By)?;
+            order_by = self.comma_separated(|parser| {
+                Ok(OrderingTerm {
+                    expr: parser.expr()?,
+                    order: parser.sort_order(),
+                })
+            })?;
+        }
+
+        let mut limit = None;
+        if self.eat_keyword(Keyword::Limit) {
+            let first = self.expr()?;
+            // `LIMIT offset, limit`, the other way around
+            limit = Some(match self.peek() {
+                TokenKind::Keyword(Keyword::Offset) => {
+                    self.advance();
+                    Limit {
+                        limit: first,
+                        offset: Some(self.expr()?),
+                    }
+                }
+                TokenKind::Comma => {
+                    self.advance();
+                    Limit {
+                        limit: self.expr()?,
+                        offset: Some(first),
+                    }
+                }
+                _ => Limit {
+                    limit: first,
+                    offset: None,
+                },
+            });
+        }
+
+        Ok(Select {
+            distinct,
+            columns,
+            from,
+            where_clause,
+            group_by,
+            having,
+            order_by,
+            limit,
+            span: self.span_from(start),
+        })
+    }
+
+    // A SELECT nested in another statement. The tree is boxed right away, so that it does not take room in the frames
+    // of the callers, which recursion stacks up
+    fn subquery(&mut self) -> io::Result<Box<Select>> {
+        self.select().map(Box::new)
+    }
+
+    fn result_column(&mut self) -> io::Result<ResultColumn> {
+        if self.eat(&TokenKind::Star) {
+            return Ok(ResultColumn::All);
+        }
+        if let (TokenKind::Identifier(_), TokenKind::Dot, TokenKind::Star) =
+            (self.peek(), self.peek_at(1), self.peek_at(2))
+        {
+            let table = self.ident()?;
+            self.advance();
+            self.advance();
+            return Ok(ResultColumn::AllOf(table));
+        }
+
+        let expr = self.expr()?;
+        Ok(ResultColumn::Expr {
+            expr,
+            alias: self.alias()?,
+        })
+    }
+
+    // `AS name`, or just `name`
+    fn alias(&mut self) -> io::Result<Option<Ident>> {
+        if self.eat_keyword(Keyword::As) {
+            return self.ident().map(Some);
+        }
+        match self.peek() {
+            TokenKind::Identifier(_) => self.ident().map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    fn tables(&mut self) -> io::Result<FromClause> {
+        let first = self.table_ref()?;
+
+        let mut joins = vec![];
+        loop {
+            let kind = match self.peek() {
+                TokenKind::Comma => JoinKind::Cross,
+                TokenKind::Keyword(Keyword::Join) => JoinKind::Inner,
+                TokenKind::Keyword(Keyword::Inner) => {
+                    self.advance();
+                    JoinKind::Inner
+                }
+                TokenKind::Keyword(Keyword::Cross) => {
+                    self.advance();
+                    JoinKind::Cross
+                }
+                TokenKind::Keyword(Keyword::Left) => {
+                    self.advance();
+                    self.eat_keyword(Keyword::Outer);
+                    JoinKind::Left
+                }
+                _ => break,
+            };
+            if !self.eat(&TokenKind::Comma) {
+                self.expect_keyword(Keyword::Join)?;
+            }
+
+            let table = self.table_ref()?;
+            let constraint = match self.peek() {
+                TokenKind::Keyword(Keyword::On) => {
+                    self.advance();
+                    Some(JoinConstraint::On(self.expr()?))
+                }
+                TokenKind::Keyword(Keyword::Using) => {
+                    self.advance();
+                    self.expect(TokenKind::LeftParen)?;
+                    let columns = self.comma_separated(Self::ident)?;
+                    self.expect(TokenKind::RightParen)?;
+                    Some(JoinConstraint::Using(columns))
+                }
+                _ => None,
+            };
+            joins.push(Join {
+                kind,
+                table,
+                constraint,
+            });
+        }
+
+        Ok(FromClause { first, joins })
+    }
+
+    fn table_ref(&mut self) -> io::Result<TableRef> {
+        let start = self.start();
+        if self.eat(&TokenKind::LeftParen) {
+            let select = self.subquery()?;
+            self.expect(TokenKind::RightParen)?;
+            let alias = self.alias()?;
+            return Ok(TableRef::Subquery {
+                select,
+                alias,
+                span: self.span_from(start),
+            });
+        }
+
+        let name = self.ident()?;
+        let alias = self.alias()?;
+        Ok(TableRef::Table {
+            name,
+            alias,
+            span: self.span_from(start),
+        })
+    }
+
+    fn update(&mut self) -> io::Result<Update> {
+        let start = self.start();
+        self.expect_keyword(Keyword::Update)?;
+        let table = self.ident()?;
+        self.expect_keyword(Keyword::Set)?;
+        let assignments = self.comma_separated(|parser| {
+            let column = parser.ident()?;
+            parser.expect(TokenKind::Eq)?;
+            Ok(Assignment {
+                column,
+                value: parser.expr()?,
+            })
+        })?;
+        let where_clause = self.optional_where()?;
+
+        Ok(Update {
+            table,
+            assignments,
+            where_clause,
+            span: self.span_from(start),
+        })
+    }
+
+    fn delete(&mut self) -> io::Result<Delete> {
+        let start = self.start();
+        self.expect_keyword(Keyword::Delete)?;
+        self.expect_keyword(Keyword::From)?;
+        let table = self.ident()?;
+        let where_clause = self.optional_where()?;
+
+        Ok(Delete {
+            table,
+            where_clause,
+            span: self.span_from(start),
+        })
+    }
+
+    fn optional_where(&mut self) -> io::Result<Option<Expr>> {
+        match self.eat_keyword(Keyword::Where) {
+            true => self.expr().map(Some),
+            false => Ok(None),
+        }
+    }
+
+    fn sort_order(&mut self) -> SortOrder {
+        if self.eat_keyword(Keyword::Desc) {
+            return SortOrder::Descending;
+        }
+        self.eat_keyword(Keyword::Asc);
+        SortOrder::Ascending
+    }
+
+    pub fn expr(&mut self) -> io::Result<Expr> {
+        self.nested(Self::or)
+    }
+
+    fn or(&mut self) -> io::Result<Expr> {
+        let mut left = self.and()?;
+        let mut height = None;
+        while self.eat_keyword(Keyword::Or) {
+            let right = self.and()?;
+            left = binary(BinaryOp::Or, left, right);
+            self.check_height(&mut height, &left)?;
+        }
+
+        Ok(left)
+    }
+
+    fn and(&mut self) -> io::Result<Expr> {
+        let mut left = self.not()?;
+        let mut height = None;
+        while self.eat_keyword(Keyword::And) {
+            let right = self.not()?;
+            left = binary(BinaryOp::And, left, right);
+            self.check_height(&mut height, &left)?;
+        }
+
+        Ok(left)
+    }
+
+    fn not(&mut self) -> io::Result<Expr> {
+        let start = self.start();
+        if !self.eat_keyword(Keyword::Not) {
+            return self.comparison();
+        }
+
+        let operand = Box::new(self.nested(Self::not)?);
+        Ok(Expr {
+            kind: ExprKind::Unary {
+                op: UnaryOp::Not,
+                operand,
+            },
+            span: self.span_from(start),
+        })
+    }
+
+    // Equality and the operators at the same level: IS, IN, LIKE and BETWEEN, the last three possibly negated
+    fn comparison(&mut self) -> io::Result<Expr> {
+        let start = self.start();
+        let mut left = self.binary(0)?;
+        let mut height = None;
+
+        loop {
+            let negated = self.peek() == &TokenKind::Keyword(Keyword::Not)
+                && matches!(
+                    self.peek_at(1),
+                    TokenKind::Keyword(Keyword::In | Keyword::Like | Keyword::Between)
+                );
+            if negated {
+                self.advance();
+            }
+
+            let kind = match self.peek() {
+                TokenKind::Eq | TokenKind::NotEq => {
+                    let op = match self.advance().kind {
+                        TokenKind::Eq => BinaryOp::Eq,
+                        _ => BinaryOp::NotEq,
+                    };
+                    let right = self.binary(0)?;
+                    left = binary(op, left, right);
+                    self.check_height(&mut height, &left)?;
+                    continue;
+                }
+                TokenKind::Keyword(Keyword::Is) => {
+                    self.advance();
+                    let op = match self.eat_keyword(Keyword::Not) {
+                        true => BinaryOp::IsNot,
+                        false => BinaryOp::Is,
+                    };
+                    let right = self.binary(0)?;
+                    left = binary(op, left, right);
+                    self.check_height(&mut height, &left)?;
+                    continue;
+                }
+                TokenKind::Keyword(Keyword::In) => {
+                    self.advance();
+                    self.expect(TokenKind::LeftParen)?;
+                    let expr = Box::new(left);
+                    let kind = match self.peek() {
+                        TokenKind::Keyword(Keyword::Select) => ExprKind::InSelect {
+                            expr,
+                            select: self.subquery()?,
+                            negated,
+                        },
+                        TokenKind::RightParen => ExprKind::InList {
+                            expr,
+                            list: vec![],
+                            negated,
+                        },
+                        _ => ExprKind::InList {
+                            expr,
+                            list: self.comma_separated(Self::expr)?,
+                            negated,
+                        },
+                    };
+                    self.expect(TokenKind::RightParen)?;
+                    kind
+                }
+                TokenKind::Keyword(Keyword::Like) => {
+                    self.advance();
+                    ExprKind::Like {
+                        expr: Box::new(left),
+                        pattern: Box::new(self.binary(0)?),
+                        negated,
+                    }
+                }
+                TokenKind::Keyword(Keyword::Between) => {
+                    self.advance();
+                    let low = Box::new(self.binary(0)?);
+                    self.expect_keyword(Keyword::And)?;
+                    ExprKind::Between {
+                        expr: Box::new(left),
+                        low,
+                        high: Box::new(self.binary(0)?),
+                        negated,
+                    }
+                }
+                _ => return Ok(left),
+            };
+            left = Expr {
+                kind,
+                span: self.span_from(start),
+            };
+            self.check_height(&mut height, &left)?;
+        }
+    }
+
+    // Binary operators of `BINARY_LEVELS[level]` and tighter. Operators of a tighter level take their operands first,
+    // each right operand being made of operators tighter than its own
+    fn binary(&mut self, level: usize) -> io::Result<Expr> {
+        let mut left = self.unary()?;
+        let mut height = None;
+        while let Some((level, op)) = self.binary_op(level) {
+            self.advance();
+            let right = self.binary(level + 1)?;
+            left = binary(op, left, right);
+            self.check_height(&mut height, &left)?;
+        }
+
+        Ok(left)
+    }
+
+    // The operator of the current token along with its level, if it is a binary operator of `level` or tighter
+    fn binary_op(&self, level: usize) -> Option<(usize, BinaryOp)> {
+        BINARY_LEVELS[level..]
+            .iter()
+            .zip(level..)
+            .find_map(|(operators, level)| {
+                let &(_, op) = operators.iter().find(|(kind, _)| kind == self.peek())?;
+                Some((level, op))
+            })
+    }
+
+    fn unary(&mut self) -> io::Result<Expr> {
+        let start = self.start();
+        let op = match self.peek() {
+            TokenKind::Minus => UnaryOp::Neg,
+            TokenKind::Plus => UnaryOp::Plus,
+            TokenKind::BitNot => UnaryOp::BitNot,
+            _ => return self.primary(),
+        };
+        self.advance();
+
+        // The only integer whose absolute value does not fit in an `i64`
+        if op == UnaryOp::Neg && self.peek() == &TokenKind::Integer(i64::MIN.unsigned_abs()) {
+            self.advance();
+            return Ok(Expr {
+                kind: ExprKind::Literal(Value::Integer(i64::MIN)),
+                span: self.span_from(start),
+            });
+        }
+
+        let operand = Box::new(self.nested(Self::unary)?);
+        Ok(Expr {
+            kind: ExprKind::Unary { op, operand },
+            span: self.span_from(start),
+        })
+    }
+
+    fn primary(&mut self) -> io::Result<Expr> {
+        let start = self.start();
+        let token = self.advance();
+
+        let kind = match token.kind {
+            TokenKind::Integer(integer) => ExprKind::Literal(match i64::try_from(integer) {
+                Ok(integer) => Value::Integer(integer),
+                Err(_) => Value::Real(integer as f64),
+            }),
+            TokenKind::Real(real) => ExprKind::Literal(Value::Real(real)),
+            TokenKind::String(text) => ExprKind::Literal(Value::Text(text)),
+            TokenKind::Blob(blob) => ExprKind::Literal(Value::Blob(blob)),
+            TokenKind::Keyword(Keyword::Null) => ExprKind::Literal(Value::Null),
+            TokenKind::Parameter(number) => {
+                let number = number.unwrap_or(self.parameters + 1);
+                self.parameters = self.parameters.max(number);
+                ExprKind::Parameter(number)
+            }
+            TokenKind::LeftParen => {
+                let kind = match self.peek() {
+                    TokenKind::Keyword(Keyword::Select) => ExprKind::Subquery(self.subquery()?),
+                    _ => self.expr()?.kind,
+                };
+                self.expect(TokenKind::RightParen)?;
+                kind
+            }
+            TokenKind::Keyword(Keyword::Exists) => {
+                self.expect(TokenKind::LeftParen)?;
+                let select = self.subquery()?;
+                self.expect(TokenKind::RightParen)?;
+                ExprKind::Exists(select)
+            }
+            TokenKind::Keyword(Keyword::Case) => self.case()?,
+            TokenKind::Keyword(Keyword::Cast) => {
+                self.expect(TokenKind::LeftParen)?;
+                let expr = Box::new(self.expr()?);
+                self.expect_keyword(Keyword::As)?;
+                let type_name = self
+                    .type_name()?
+                    .ok_or_else(|| self.error("Expected a type name"))?;
+                self.expect(TokenKind::RightParen)?;
+                ExprKind::Cast { expr, type_name }
+            }
+            TokenKind::Identifier(name) => {
+                let name = Ident {
+                    name,
+                    span: token.span,
+                };
+                match self.peek() {
+                    TokenKind::LeftParen => self.function(name)?,
+                    TokenKind::Dot => {
+                        self.advance();
+                        ExprKind::Column {
+                            table: Some(name),
+                            name: self.ident()?,
+                        }
+                    }
+                    _ => ExprKind::Column { table: None, name },
+                }
+            }
+            kind => {
+                // The error points at the unexpected token, which `advance` does not move past if it is `Eof`
+                if kind != TokenKind::Eof {
+                    self.position -= 1;
+                }
+                return Err(self.error("Expected an expression"));
+            }
+        };
+
+        Ok(Expr {
+            kind,
+            span: self.span_from(start),
+        })
+    }
+
+    fn function(&mut self, name: Ident) -> io::Result<ExprKind> {
+        self.expect(TokenKind::LeftParen)?;
+
+        let mut distinct = false;
+        let args = match self.peek() {
+            TokenKind::Star => {
+                self.advance();
+                FunctionArgs::Star
+            }
+            TokenKind::RightParen => FunctionArgs::List(vec![]),
+            _ => {
+                distinct = self.eat_keyword(Keyword::Distinct);
+                FunctionArgs::List(self.comma_separated(Self::expr)?)
+            }
+        };
+        self.expect(TokenKind::RightParen)?;
+
+        Ok(ExprKind::Function {
+            name,
+            distinct,
+            args,
+        })
+    }
+
+    // After CASE
+    fn case(&mut self) -> io::Result<ExprKind> {
+        let operand = match self.peek() {
+            TokenKind::Keyword(Keyword::When) => None,
+            _ => Some(Box::new(self.expr()?)),
+        };
+
+        let mut branches = vec![];
+        while self.eat_keyword(Keyword::When) {
+            let condition = self.expr()?;
+            self.expect_keyword(Keyword::Then)?;
+            branches.push((condition, self.expr()?));
+        }
+        if branches.is_empty() {
+            return Err(self.error("Expected WHEN"));
+        }
+        let else_result = match self.eat_keyword(Keyword::Else) {
+            true => Some(Box::new(self.expr()?)),
+            false => None,
+        };
+        self.expect_keyword(Keyword::End)?;
+
+        Ok(ExprKind::Case {
+            operand,
+            branches,
+            else_result,
+        })
+    }
+
+    fn comma_separated<T>(
+        &mut self,
+        mut item: impl FnMut(&mut Self) -> io::Result<T>,
+    ) -> io::Result<Vec<T>> {
+        let mut items = vec![item(self)?];
+        while self.eat(&TokenKind::Comma) {
+            items.push(item(self)?);
+        }
+
+        Ok(items)
+    }
+
+    fn ident(&mut self) -> io::Result<Ident> {
+        let TokenKind::Identifier(name) = self.peek() else {
+            return Err(self.error("Expected a name"));
+        };
+        let name = name.clone();
+        let span = self.advance().span;
+
+        Ok(Ident { name, span })
+    }
+
+    fn peek(&self) -> &TokenKind {
+        self.peek_at(0)
+    }
+
+    fn peek_at(&self, n: usize) -> &TokenKind {
+        // The last token is always `Eof`
+        let index = (self.position + n).min(self.tokens.len() - 1);
+        &self.tokens[index].kind
+    }
+
+    // Moves past the current token and returns it. `Eof` is never moved past
+    fn advance(&mut self) -> Token {
+        let token = self.tokens[self.position].clone();
+        if token.kind != TokenKind::Eof {
+            self.position += 1;
+        }
+        token
+    }
+
+    fn eat(&mut self, kind: &TokenKind) -> bool {
+        let matches = self.peek() == kind;
+        if matches {
+            self.advance();
+        }
+        matches
+    }
+
+    fn eat_keyword(&mut self, keyword: Keyword) -> bool {
+        self.eat(&TokenKind::Keyword(keyword))
+    }
+
+    fn expect(&mut self, kind: TokenKind) -> io::Result<()> {
+        if self.eat(&kind) {
+            return Ok(());
+        }
+
+        let expected = match kind {
+            TokenKind::LeftParen => "'('",
+            TokenKind::RightParen => "')'",
+            TokenKind::Eq => "'='",
+            _ => unreachable!("Not expected on its own"),
+        };
+        Err(self.error(&format!("Expected {expected}")))
+    }
+
+    fn expect_keyword(&mut self, keyword: Keyword) -> io::Result<()> {
+        match self.eat_keyword(keyword) {
+            true => Ok(()),
+            false => Err(self.error(&format!("Expected {}", keyword.as_str()))),
+        }
+    }
+
+    // Runs `parse` one level of nesting deeper
+    fn nested<T>(&mut self, parse: impl FnOnce(&mut Self) -> io::Result<T>) -> io::Result<T> {
+        if self.nesting == MAX_NESTING {
+            return Err(syntax_error(
+                self.sql,
+                self.tokens[self.position].span,
+                format!("Expressions and subqueries are nested too deeply, the maximum is {MAX_NESTING} levels"),
+            ));
+        }
+
+        self.nesting += 1;
+        let result = parse(self);
+        self.nesting -= 1;
+        result
+    }
+
+    // Checks the height of `node`, just built by a chain of operators on top of its first operand. `height` is that of
+    // the first operand, then of `node`, so that the chain is not walked down again for each of its operators.
+    // Nodes on top of the chain are one per level of nesting at most, which the height left for it accounts for
+    fn check_height(&self, height: &mut Option<usize>, node: &Expr) -> io::Result<()> {
+        let children = node.children();
+        let first = height.unwrap_or_else(|| children[0].height());
+        let grown = 1 + children[1..]
+            .iter()
+            .map(|child| child.height())
+            .fold(first, usize::max);
+        if grown + self.nesting > MAX_DEPTH {
+            return Err(syntax_error(
+                self.sql,
+                node.span,
+                format!("Expression tree is too large, the maximum depth is {MAX_DEPTH}"),
+            ));
+        }
+
+        *height = Some(grown);
+        Ok(())
+    }
+
+    // Offset of the current token, where the node being parsed starts
+    fn start(&self) -> usize {
+        self.tokens[self.position].span.start
+    }
+
+    // From `start` to the end of the last token moved past
+    fn span_from(&self, start: usize) -> Span {
+        let end = match self.position {
+            0 => start,
+            position => self.tokens[position - 1].span.end,
+        };
+        Span::new(start, end.max(start))
+    }
+
+    // Error pointing at the current token, which was not expected
+    fn error(&self, expected: &str) -> io::Error {
+        let token = &self.tokens[self.position];
+        let found = match token.kind {
+            TokenKind::Eof => "the end of the text".to_string(),
+            _ => format!("\"{}\"", token.span.text(self.sql)),
+        };
+        syntax_error(self.sql, token.span, format!("{expected}, found {found}"))
+    }
+}
+
+fn binary(op: BinaryOp, left: Expr, right: Expr) -> Expr {
+    let span = left.span.to(right.span);
+    Expr {
+        kind: ExprKind::Binary {
+            op,
+            left: Box::new(left),
+            right: Box::new(right),
+        },
+        span,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::SyntaxError;
+
+    fn syntax_error_of(sql: &str) -> SyntaxError {
+        let err = parse(sql).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        *err.into_inner().unwrap().downcast().unwrap()
+    }
+
+    fn statement(sql: &str) -> Statement {
+        parse_statement(sql).unwrap()
+    }
+
+    fn select(sql: &str) -> Select {
+        match statement(sql) {
+            Statement::Select(select) => *select,
+            statement => panic!("Not a SELECT: {statement:?}"),
+        }
+    }
+
+    // The first result column of `SELECT <sql>`
+    fn expr(sql: &str) -> Expr {
+        match select(&format!("SELECT {sql}")).columns.remove(0) {
+            ResultColumn::Expr { expr, .. } => expr,
+            column => panic!("Not an expression: {column:?}"),
+        }
+    }
+
+    // Operators and their operands between parentheses, to check how they bind
+    fn render(expr: &Expr) -> String {
+        match &expr.kind {
+            ExprKind::Literal(Value::Integer(integer)) => integer.to_string(),
+            ExprKind::Column { name, .. } => name.name.clone(),
+            ExprKind::Unary { op, operand } => format!("({op:?} {})", render(operand)),
+            ExprKind::Binary { op, left, right } => {
+                format!("({} {op:?} {})", render(left), render(right))
+            }
+            ExprKind::InList {
+                expr,
+                list,
+                negated,
+            } => format!(
+                "({} {}In {})",
+                render(expr),
+                if *negated { "Not" } else { "" },
+                list.iter().map(render).collect::<Vec<_>>().join(" ")
+            ),
+            ExprKind::Between {
+                expr,
+                low,
+                high,
+                negated,
+            } => format!(
+                "({} {}Between {} {})",
+                render(expr),
+                if *negated { "Not" } else { "" },
+                render(low),
+                render(high)
+            ),
+            kind => format!("{kind:?}"),
+        }
+    }
+
+    #[test]
+    fn create_table() {
+        let Statement::CreateTable(create) = statement(
+            "CREATE TABLE IF NOT EXISTS \"t 1\" (id INTEGER PRIMARY KEY DESC, name VARCHAR(20) NOT NULL UNIQUE, \
+             price DECIMAL(10, 2) DEFAULT -1.5, flag DEFAULT (1 + 1) NOT NULL, raw, UNIQUE (name, raw DESC))",
+        ) else {
+            panic!("Not a CREATE TABLE");
+        };
+        assert!(create.if_not_exists);
+        assert_eq!(create.name.name, "t 1");
+        let columns: Vec<(&str, Option<&str>, &[ColumnConstraint])> = create
+            .columns
+            .iter()
+            .map(|column| {
+                (
+                    &column.name.name[..],
+                    column.type_name.as_deref(),
+                    &column.constraints[..],
+                )
+            })
+            .collect();
+        assert_eq!(columns[0].0, "id");
+        assert_eq!(columns[0].1, Some("INTEGER"));
+        assert_eq!(
+            columns[0].2,
+            [ColumnConstraint::PrimaryKey(SortOrder::Descending)]
+        );
+        assert_eq!(columns[1].1, Some("VARCHAR(20)"));
+        assert_eq!(
+            columns[1].2,
+            [ColumnConstraint::NotNull, ColumnConstraint::Unique]
+        );
+        assert_eq!(columns[2].1, Some("DECIMAL(10, 2)"));
+        assert!(matches!(
+            columns[2].2,
+            [ColumnConstraint::Default(Expr {
+                kind: ExprKind::Unary {
+                    op: UnaryOp::Neg,
+                    ..
+                },
+                ..
+            })]
+        ));
+        assert_eq!(columns[3].1, None);
+        assert!(matches!(
+            columns[3].2,
+            [ColumnConstraint::Default(_), ColumnConstraint::NotNull]
+        ));
+        assert_eq!(columns[4], ("raw", None, &[][..]));
+        let [TableConstraint::Unique(unique)] = &create.constraints[..] else {
+            panic!("Expected a UNIQUE constraint");
+        };
+        assert_eq!(
+            unique
+                .iter()
+                .map(|column| (&column.name.name[..], column.order))
+                .collect::<Vec<_>>(),
+            [
+                ("name", SortOrder::Ascending),
+                ("raw", SortOrder::Descending)
+            ]
+        );
+
+        let err = syntax_error_of("CREATE TABLE t (UNIQUE (a), b)");
+        assert_eq!(err.message, "Expected a table constraint, found \"b\"");
+    }
+
+    #[test]
+    fn create_and_drop_index() {
+        let Statement::CreateIndex(index) =
+            statement("CREATE UNIQUE INDEX IF NOT EXISTS i ON t (a DESC, b)")
+        else {
+            panic!("Not a CREATE INDEX");
+        };
+        assert!(index.unique && index.if_not_exists);
+        assert!(index.name.name == "i" && index.table.name == "t");
+        assert_eq!(index.columns.len(), 2);
+        assert_eq!(index.columns[0].order, SortOrder::Descending);
+        assert!(matches!(
+            statement("CREATE INDEX i ON t (a)"),
+            Statement::CreateIndex(CreateIndex {
+                unique: false,
+                if_not_exists: false,
+                ..
+            })
+        ));
+
+        let Statement::DropTable(drop) = statement("DROP TABLE IF EXISTS t") else {
+            panic!("Not a DROP TABLE");
+        };
+        assert!(drop.if_exists && drop.name.name == "t");
+        let Statement::DropIndex(drop) = statement("DROP INDEX i") else {
+            panic!("Not a DROP INDEX");
+        };
+        assert!(!drop.if_exists && drop.name.name == "i");
+        assert_eq!(
+            syntax_error_of("DROP VIEW v").message,
+            "Expected TABLE or INDEX, found \"VIEW\""
+        );
+    }
+
+    #[test]
+    fn insert() {
+        let Statement::Insert(insert) = statement("INSERT INTO t (a, b) VALUES (1, 2), (3, ?)")
+        else {
+            panic!("Not an INSERT");
+        };
+        assert_eq!(insert.columns.len(), 2);
+        let InsertSource::Values(rows) = &insert.source else {
+            panic!("Expected VALUES");
+        };
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[1][1].kind, ExprKind::Parameter(1));
+
+        let Statement::Insert(insert) = statement("INSERT INTO t SELECT * FROM u") else {
+            panic!("Not an INSERT");
+        };
+        assert!(insert.columns.is_empty());
+        assert!(matches!(insert.source, InsertSource::Select(_)));
+        assert!(matches!(
+            statement("INSERT INTO t DEFAULT VALUES"),
+            Statement::Insert(Insert {
+                source: InsertSource::DefaultValues,
+                ..
+            })
+        ));
+        // Columns without values to go with them
+        assert!(parse("INSERT INTO t (a) DEFAULT VALUES").is_err());
+    }
+
+    #[test]
+    fn select_clauses() {
+        let select = select(
+            "SELECT DISTINCT t.*, a AS x, count(*) FROM t LEFT OUTER JOIN u USING (a), v CROSS JOIN \
+             (SELECT 1) AS w INNER JOIN z ON z.b = t.b WHERE a > 1 GROUP BY a, 2 HAVING count(*) > 1 \
+             ORDER BY x DESC, 1 LIMIT 10 OFFSET 5",
+        );
+        assert!(select.distinct);
+        assert!(matches!(&select.columns[0], ResultColumn::AllOf(table) if table.name == "t"));
+        assert!(
+            matches!(&select.columns[1], ResultColumn::Expr { alias: Some(alias), .. } if alias.name == "x")
+        );
+        assert!(matches!(
+            &select.columns[2],
+            ResultColumn::Expr {
+                expr: Expr {
+                    kind: ExprKind::Function {
+                        args: FunctionArgs::Star,
+                        ..
+                    },
+                    ..
+                },
+                alias: None
+            }
+        ));
+
+        let from = select.from.unwrap();
+        assert!(
+            matches!(&from.first, TableRef::Table { name, alias: None, .. } if name.name == "t")
+        );
+        let joins: Vec<JoinKind> = from.joins.iter().map(|join| join.kind).collect();
+        assert_eq!(
+            joins,
+            [
+                JoinKind::Left,
+                JoinKind::Cross,
+                JoinKind::Cross,
+                JoinKind::Inner
+            ]
+        );
+        assert!(matches!(
+            &from.joins[0].constraint,
+            Some(JoinConstraint::Using(names)) if names.len() == 1
+        ));
+        assert!(matches!(
+            &from.joins[2].table,
+            TableRef::Subquery { alias: Some(alias), .. } if alias.name == "w"
+        ));
+        assert!(matches!(
+            from.joins[3].constraint,
+            Some(JoinConstraint::On(_))
+        ));
+
+        assert!(select.where_clause.is_some());
+        assert_eq!(select.group_by.len(), 2);
+        assert!(select.having.is_some());
+        let orders: Vec<SortOrder> = select.order_by.iter().map(|term| term.order).collect();
+        assert_eq!(orders, [SortOrder::Descending, SortOrder::Ascending]);
+        let limit = select.limit.unwrap();
+        assert_eq!(render(&limit.limit), "10");
+        assert_eq!(
+            limit.offset.map(|offset| render(&offset)).as_deref(),
+            Some("5")
+        );
+
+        // `LIMIT offset, limit`
+        let limit = self::select("SELECT 1 LIMIT 5, 10").limit.unwrap();
+        assert_eq!(render(&limit.limit), "10");
+        assert_eq!(
+            limit.offset.map(|offset| render(&offset)).as_deref(),
+            Some("5")
+        );
+    }
+
+    #[test]
+    fn update_delete_explain() {
+        let Statement::Update(update) = statement("UPDATE t SET a = a + 1, b = NULL WHERE id = 3")
+        else {
+            panic!("Not an UPDATE");
+        };
+        let columns: Vec<&str> = update
+            .assignments
+            .iter()
+            .map(|assignment| &assignment.column.name[..])
+            .collect();
+        assert_eq!(columns, ["a", "b"]);
+        assert_eq!(render(&update.where_clause.unwrap()), "(id Eq 3)");
+
+        let Statement::Delete(delete) = statement("DELETE FROM t") else {
+            panic!("Not a DELETE");
+        };
+        assert!(delete.where_clause.is_none());
+
+        let Statement::Explain(explain) = statement("EXPLAIN DELETE FROM t WHERE a") else {
+            panic!("Not an EXPLAIN");
+        };
+        assert!(matches!(*explain.statement, Statement::Delete(_)));
+        assert_eq!(explain.statement.span(), Span::new(8, 29));
+        assert_eq!(
+            syntax_error_of("EXPLAIN EXPLAIN SELECT 1").message,
+            "Expected a statement to explain, found \"EXPLAIN\""
+        );
+    }
+
+    #[test]
+    fn precedence() {
+        for (sql, expected) in [
+            ("1 + 2 * 3 - 4", "((1 Add (2 Mul 3)) Sub 4)"),
+            ("1 - 2 - 3", "((1 Sub 2) Sub 3)"),
+            ("1 || 2 * 3", "((1 Concat 2) Mul 3)"),
+            ("1 << 2 + 3 & 4", "((1 ShiftLeft (2 Add 3)) BitAnd 4)"),
+            ("1 < 2 = 3 > 4", "((1 Lt 2) Eq (3 Gt 4))"),
+            ("a OR b AND NOT c = d", "(a Or (b And (Not (c Eq d))))"),
+            ("- a * ~ b", "((Neg a) Mul (BitNot b))"),
+            ("a IS NOT b + 1", "(a IsNot (b Add 1))"),
+            ("a NOT IN (1, 2) = b", "((a NotIn 1 2) Eq b)"),
+            (
+                "a BETWEEN 1 + 1 AND 2 OR c",
+                "((a Between (1 Add 1) 2) Or c)",
+            ),
+            ("a NOT BETWEEN 1 AND 2 AND c", "((a NotBetween 1 2) And c)"),
+            ("(1 + 2) * 3", "((1 Add 2) Mul 3)"),
+        ] {
+            assert_eq!(render(&expr(sql)), expected, "{sql}");
+        }
+    }
+
+    #[test]
+    fn literals_and_parameters() {
+        let values: Vec<ExprKind> = [
+            "9223372036854775807",
+            "9223372036854775808",
+            "-9223372036854775808",
+            "18446744073709551616",
+            "'text'",
+            "X'0102'",
+            "NULL",
+        ]
+        .into_iter()
+        .map(|sql| expr(sql).kind)
+        .collect();
+        assert_eq!(
+            values,
+            [
+                ExprKind::Literal(Value::Integer(i64::MAX)),
+                // Out of range integers become reals
+                ExprKind::Literal(Value::Real(9223372036854775808.0)),
+                ExprKind::Literal(Value::Integer(i64::MIN)),
+                ExprKind::Literal(Value::Real(18446744073709551616.0)),
+                ExprKind::Literal(Value::Text("text".to_string())),
+                ExprKind::Literal(Value::Blob(vec![1, 2])),
+                ExprKind::Literal(Value::Null),
+            ]
+        );
+        assert_eq!(
+            render(&expr("-9223372036854775809")),
+            format!(
+                "(Neg {:?})",
+                ExprKind::Literal(Value::Real(9223372036854775809.0))
+            )
+        );
+
+        // `?` follows the largest number used before it
+        let mut parser = Parser::new("SELECT ?, ?5, ?, ?2, ?").unwrap();
+        let Some(Statement::Select(select)) = parser.next_statement().unwrap() else {
+            panic!("Not a SELECT");
+        };
+        let numbers: Vec<ExprKind> = select
+            .columns
+            .into_iter()
+            .map(|column| match column {
+                ResultColumn::Expr { expr, .. } => expr.kind,
+                column => panic!("Not an expression: {column:?}"),
+            })
+            .collect();
+        assert_eq!(numbers, [1, 5, 6, 2, 7].map(ExprKind::Parameter));
+        assert_eq!(parser.parameter_count(), 7);
+    }
+
+    #[test]
+    fn spans() {
+        let sql = "  SELECT a + b AS total FROM t ;";
+        let select = select(sql);
+        assert_eq!(select.span.text(sql), "SELECT a + b AS total FROM t");
+        let ResultColumn::Expr { expr, alias } = &select.columns[0] else {
+            panic!("Not an expression");
+        };
+        assert_eq!(expr.span.text(sql), "a + b");
+        assert_eq!(alias.as_ref().unwrap().span.text(sql), "total");
+        assert_eq!(select.from.unwrap().first.span().text(sql), "t");
+
+        let sql = "SELECT -(1),\n  f(x, 2)";
+        let columns: Vec<String> = self::select(sql)
+            .columns
+            .iter()
+            .map(|column| match column {
+                ResultColumn::Expr { expr, .. } => expr.span.text(sql).to_string(),
+                column => panic!("Not an expression: {column:?}"),
+            })
+            .collect();
+        assert_eq!(columns, ["-(1)", "f(x, 2)"]);
+
+        let statements = parse("SELECT 1; ;DELETE FROM t;").unwrap();
+        let spans: Vec<Span> = statements.iter().map(Statement::span).collect();
+        assert_eq!(spans, [Span::new(0, 8), Span::new(11, 24)]);
+    }
+
+    #[test]
+    fn errors() {
+        // Lines and columns count characters from 1
+        let err = syntax_error_of("SELECT a,\n  'é', b\n  FROM WHERE");
+        assert_eq!(err.message, "Expected a name, found \"WHERE\"");
+        assert_eq!((err.line, err.column), (3, 8));
+        assert_eq!(
+            err.to_string(),
+            "Expected a name, found \"WHERE\" at line 3, column 8"
+        );
+
+        let err = syntax_error_of("SELECT é +");
+        assert_eq!(
+            err.message,
+            "Expected an expression, found the end of the text"
+        );
+        assert_eq!(err.span, Span::new(11, 11));
+        assert_eq!((err.line, err.column), (1, 11));
+
+        let err = syntax_error_of("SELECT 1 SELECT 2");
+        assert_eq!(
+            err.message,
+            "Expected ';' or the end of the statement, found \"SELECT\""
+        );
+        assert_eq!(err.column, 10);
+
+        assert_eq!(
+            parse_statement("SELECT 1; SELECT 2")
+                .unwrap_err()
+                .to_string(),
+            "Expected the end of the statement, found \"SELECT\" at line 1, column 11"
+        );
+        assert_eq!(
+            parse_statement(" -- nothing").unwrap_err().to_string(),
+            "Empty statement at line 1, column 1"
+        );
+        assert_eq!(
+            syntax_error_of("VACUUM").message,
+            "Expected a statement, found \"VACUUM\""
+        );
+        // Errors of the lexer come out of the parser too
+        assert_eq!(syntax_error_of("SELECT 'a").message, "Unterminated string");
+    }
+
+    // `SELECT` followed by `open` `depth` times, `1` and as many `close`
+    fn nested_select(open: &str, close: &str, depth: usize) -> String {
+        format!("SELECT {}1{}", open.repeat(depth), close.repeat(depth))
+    }
+
+    #[test]
+    fn nesting_limit() {
+        // The statement and its result column take two levels
+        let depth = MAX_NESTING - 2;
+        parse(&nested_select("(", ")", depth)).unwrap();
+
+        let err = syntax_error_of(&nested_select("(", ")", depth + 1));
+        assert!(err.message.contains("nested too deeply"), "{err}");
+        assert_eq!(err.span.start, "SELECT ".len() + depth + 1);
+
+        for (open, close) in [("(SELECT ", ")"), ("- ", ""), ("NOT ", ""), ("abs(", ")")] {
+            let err = syntax_error_of(&nested_select(open, close, MAX_NESTING));
+            assert!(err.message.contains("nested too deeply"), "{err}");
+        }
+    }
+
+    #[test]
+    fn depth_limit() {
+        // Operators chained at the same level build trees as high as the chain is long, without nesting
+        parse(&format!("SELECT 1{}", " + 1".repeat(MAX_DEPTH / 2))).unwrap();
+
+        for operator in [" + 1", " OR 1", " = 1", " BETWEEN 1 AND 1", " || 'a'"] {
+            let err = syntax_error_of(&format!("SELECT 1{}", operator.repeat(MAX_DEPTH)));
+            assert!(err.message.contains("too large"), "{err}");
+        }
+
+        // Chains add up when nested as the first operand of each other
+        let chain = " + 1".repeat(MAX_DEPTH / 4);
+        let mut expr = "1".to_string();
+        for _ in 0..5 {
+            expr = format!("({expr}){chain}");
+        }
+        let err = syntax_error_of(&format!("SELECT {expr}"));
+        assert!(err.message.contains("too large"), "{err}");
+    }
+}
@@ -0,0 +1,932 @@
+//! Bytecode programs run by `vm`, as compiled from statements by `compiler`. Like SQLite's VDBE programs, they work on
+//! numbered registers holding values and on numbered cursors opened on trees, and jump to the addresses of their
+//! instructions. A program starts with `Init`, which jumps to the checks of its last instructions before coming back
+//! A program compiled from EXPLAIN lists its instructions instead of running them, one row each:
+//! | addr | opcode | p1 | p2 | p3 | p4 | comment |
+//! where the operands p1 to p4 are those of the instruction in the order it is written in, and the comment tells what
+//! it does
+
+use std::{fmt, io};
+
+use crate::{
+    btree::TreeKind,
+    catalog::ObjectKind,
+    key::SortOrder,
+    pager::PageNumber,
+    sql::{
+        ast::{BinaryOp, UnaryOp},
+        functions::{Affinity, AggregateFunction, ScalarFunction},
+    },
+    value::Value,
+};
+
+pub type Address = usize;
+pub type Register = usize;
+pub type CursorId = usize;
+
+// What a cursor opened on an index needs to build its keys
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyInfo {
+    pub unique: bool,
+    pub orders: Vec<SortOrder>,
+}
+
+// Root page of the tree a cursor is opened on, known when compiling or only once the tree is created
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Root {
+    Page(PageNumber),
+    Register(Register),
+}
+
+// Ranges of registers are given by their first register and their count. Jumps go to `target`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Insn {
+    // Jumps to the checks at the end of the program, which jump back to the start
+    Init {
+        target: Address,
+    },
+    // Fails when the schema changed since the program was compiled
+    VerifyCookie {
+        cookie: u32,
+    },
+    Goto {
+        target: Address,
+    },
+    // Stores the address of the next instruction in `ret`, and jumps to the subroutine at `target`
+    Gosub {
+        ret: Register,
+        target: Address,
+    },
+    Return {
+        ret: Register,
+    },
+    Halt,
+    // Stops the program with an error
+    Abort {
+        kind: io::ErrorKind,
+        message: String,
+    },
+    Integer {
+        value: i64,
+        dest: Register,
+    },
+    Real {
+        value: f64,
+        dest: Register,
+    },
+    String {
+        value: String,
+        dest: Register,
+    },
+    Blob {
+        value: Vec<u8>,
+        dest: Register,
+    },
+    Null {
+        dest: Register,
+        count: usize,
+    },
+    // Value of the parameter numbered `parameter`, from 1
+    Variable {
+        parameter: u32,
+        dest: Register,
+    },
+    Copy {
+        source: Register,
+        dest: Register,
+        count: usize,
+    },
+    // Turns the value into an integer, failing when it does not hold one
+    MustBeInt {
+        reg: Register,
+    },
+    Affinity {
+        start: Register,
+        affinities: Vec<Affinity>,
+    },
+    Unary {
+        op: UnaryOp,
+        source: Register,
+        dest: Register,
+    },
+    Binary {
+        op: BinaryOp,
+        left: Register,
+        right: Register,
+        dest: Register,
+    },
+    Like {
+        value: Register,
+        pattern: Register,
+        dest: Register,
+    },
+    Cast {
+        source: Register,
+        affinity: Affinity,
+        dest: Register,
+    },
+    Function {
+        function: ScalarFunction,
+        args: Register,
+        count: usize,
+        dest: Register,
+    },
+    If {
+        reg: Register,
+        target: Address,
+        jump_if_null: bool,
+    },
+    IfNot {
+        reg: Register,
+        target: Address,
+        jump_if_null: bool,
+    },
+    IsNull {
+        reg: Register,
+        target: Address,
+    },
+    NotNull {
+        reg: Register,
+        target: Address,
+    },
+    // Decrements the integer in `reg` and jumps if it was positive
+    IfPos {
+        reg: Register,
+        target: Address,
+    },
+    // Decrements the integer in `reg` and jumps if it reached 0
+    DecrJumpZero {
+        reg: Register,
+        target: Address,
+    },
+    // Makes `step` return the values of the registers
+    ResultRow {
+        start: Register,
+        count: usize,
+    },
+    OpenRead {
+        cursor: CursorId,
+        root: PageNumber,
+        key_info: Option<KeyInfo>,
+    },
+    OpenWrite {
+        cursor: CursorId,
+        root: Root,
+        key_info: Option<KeyInfo>,
+    },
+    // Opens a cursor on rows kept in memory, sorted on their first values with `orders`
+    OpenEphemeral {
+        cursor: CursorId,
+        orders: Vec<SortOrder>,
+    },
+    // Moves to the first entry, or jumps if there is none
+    Rewind {
+        cursor: CursorId,
+        target: Address,
+    },
+    // Moves to the next entry, and jumps if there is one
+    Next {
+        cursor: CursorId,
+        target: Address,
+    },
+    // Moves a table cursor to the row whose rowid is in `rowid`, or jumps if there is none
+    SeekRowid {
+        cursor: CursorId,
+        rowid: Register,
+        target: Address,
+    },
+    // Moves an index cursor to the first entry whose values are at least those of the registers, or jumps if there is
+    // none
+    SeekGe {
+        cursor: CursorId,
+        start: Register,
+        count: usize,
+        target: Address,
+    },
+    // Jumps if the values of the entry of an index cursor are beyond those of the registers, these being compared with
+    // as many of the first values of the entry
+    IdxGt {
+        cursor: CursorId,
+        start: Register,
+        count: usize,
+        target: Address,
+    },
+    Column {
+        cursor: CursorId,
+        column: usize,
+        dest: Register,
+    },
+    Rowid {
+        cursor: CursorId,
+        dest: Register,
+    },
+    // Makes the cursor point to a row of NULLs until it moves, for the outer side of LEFT JOINs
+    NullRow {
+        cursor: CursorId,
+    },
+    // A rowid past the largest one of the table
+    NewRowid {
+        cursor: CursorId,
+        dest: Register,
+    },
+    MakeRecord {
+        start: Register,
+        count: usize,
+        dest: Register,
+    },
+    // Adds the record in `record` to a table, replacing the row with the same rowid, if any
+    Insert {
+        cursor: CursorId,
+        record: Register,
+        rowid: Register,
+    },
+    // Removes the row a table cursor points to
+    Delete {
+        cursor: CursorId,
+    },
+    // Fails with `conflict` as message when a unique index already has the values for another row
+    IdxInsert {
+        cursor: CursorId,
+        start: Register,
+        count: usize,
+        rowid: Register,
+        conflict: String,
+    },
+    IdxDelete {
+        cursor: CursorId,
+        start: Register,
+        count: usize,
+        rowid: Register,
+    },
+    // Adds the values of the registers to an ephemeral cursor, sorted on the first `key_count` of them. Rows with the
+    // same key are kept in the order they were added
+    EphemeralInsert {
+        cursor: CursorId,
+        start: Register,
+        count: usize,
+        key_count: usize,
+    },
+    // Jumps if an ephemeral cursor has a row whose key starts with the values of the registers
+    Found {
+        cursor: CursorId,
+        start: Register,
+        count: usize,
+        target: Address,
+    },
+    // Removes every entry of a tree
+    Clear {
+        root: PageNumber,
+    },
+    AggReset {
+        slot: usize,
+    },
+    AggStep {
+        function: AggregateFunction,
+        distinct: bool,
+        args: Register,
+        count: usize,
+        slot: usize,
+    },
+    AggFinal {
+        function: AggregateFunction,
+        slot: usize,
+        dest: Register,
+    },
+    // Allocates the root of a new tree, whose page number goes to `dest`
+    CreateTree {
+        kind: TreeKind,
+        dest: Register,
+    },
+    DestroyTree {
+        root: PageNumber,
+    },
+    // The root page of objects with a tree is in `root`
+    AddToCatalog {
+        kind: ObjectKind,
+        name: String,
+        table: String,
+        root: Option<Register>,
+        sql: String,
+    },
+    RemoveFromCatalog {
+        name: String,
+    },
+}
+
+impl Insn {
+    pub(crate) fn target_mut(&mut self) -> Option<&mut Address> {
+        match self {
+            Insn::Init { target }
+            | Insn::Goto { target }
+            | Insn::Gosub { target, .. }
+            | Insn::If { target, .. }
+            | Insn::IfNot { target, .. }
+            | Insn::IsNull { target, .. }
+            | Insn::NotNull { target, .. }
+            | Insn::IfPos { target, .. }
+            | Insn::DecrJumpZero { target, .. }
+            | Insn::Rewind { target, .. }
+            | Insn::Next { target, .. }
+            | Insn::SeekRowid { target, .. }
+            | Insn::SeekGe { target, .. }
+            | Insn::IdxGt { target, .. }
+            | Insn::Found { target, .. } => Some(target),
+            _ => None,
+        }
+    }
+
+    pub fn opcode(&self) -> &'static str {
+        match self {
+            Insn::Init { .. } => "Init",
+            Insn::VerifyCookie { .. } => "VerifyCookie",
+            Insn::Goto { .. } => "Goto",
+            Insn::Gosub { .. } => "Gosub",
+            Insn::Return { .. } => "Return",
+            Insn::Halt => "Halt",
+            Insn::Abort { .. } => "Abort",
+            Insn::Integer { .. } => "Integer",
+            Insn::Real { .. } => "Real",
+            Insn::String { .. } => "String",
+            Insn::Blob { .. } => "Blob",
+            Insn::Null { .. } => "Null",
+            Insn::Variable { .. } => "Variable",
+            Insn::Copy { .. } => "Copy",
+            Insn::MustBeInt { .. } => "MustBeInt",
+            Insn::Affinity { .. } => "Affinity",
+            Insn::Unary { op, .. } => match op {
+                UnaryOp::Neg => "Negative",
+                UnaryOp::Plus => "Positive",
+                UnaryOp::Not => "Not",
+                UnaryOp::BitNot => "BitNot",
+            },
+            Insn::Binary { op, .. } => match op {
+                BinaryOp::Or => "Or",
+                BinaryOp::And => "And",
+                BinaryOp::Eq => "Eq",
+                BinaryOp::NotEq => "Ne",
+                BinaryOp::Is => "Is",
+                BinaryOp::IsNot => "IsNot",
+                BinaryOp::Lt => "Lt",
+                BinaryOp::LtEq => "Le",
+                BinaryOp::Gt => "Gt",
+                BinaryOp::GtEq => "Ge",
+                BinaryOp::BitAnd => "BitAnd",
+                BinaryOp::BitOr => "BitOr",
+                BinaryOp::ShiftLeft => "ShiftLeft",
+                BinaryOp::ShiftRight => "ShiftRight",
+                BinaryOp::Add => "Add",
+                BinaryOp::Sub => "Subtract",
+                BinaryOp::Mul => "Multiply",
+                BinaryOp::Div => "Divide",
+                BinaryOp::Rem => "Remainder",
+                BinaryOp::Concat => "Concat",
+            },
+            Insn::Like { .. } => "Like",
+            Insn::Cast { .. } => "Cast",
+            Insn::Function { .. } => "Function",
+            Insn::If { .. } => "If",
+            Insn::IfNot { .. } => "IfNot",
+            Insn::IsNull { .. } => "IsNull",
+            Insn::NotNull { .. } => "NotNull",
+            Insn::IfPos { .. } => "IfPos",
+            Insn::DecrJumpZero { .. } => "DecrJumpZero",
+            Insn::ResultRow { .. } => "ResultRow",
+            Insn::OpenRead { .. } => "OpenRead",
+            Insn::OpenWrite { .. } => "OpenWrite",
+            Insn::OpenEphemeral { .. } => "OpenEphemeral",
+            Insn::Rewind { .. } => "Rewind",
+            Insn::Next { .. } => "Next",
+            Insn::SeekRowid { .. } => "SeekRowid",
+            Insn::SeekGe { .. } => "SeekGE",
+            Insn::IdxGt { .. } => "IdxGT",
+            Insn::Column { .. } => "Column",
+            Insn::Rowid { .. } => "Rowid",
+            Insn::NullRow { .. } => "NullRow",
+            Insn::NewRowid { .. } => "NewRowid",
+            Insn::MakeRecord { .. } => "MakeRecord",
+            Insn::Insert { .. } => "Insert",
+            Insn::Delete { .. } => "Delete",
+            Insn::IdxInsert { .. } => "IdxInsert",
+            Insn::IdxDelete { .. } => "IdxDelete",
+            Insn::EphemeralInsert { .. } => "EphemeralInsert",
+            Insn::Found { .. } => "Found",
+            Insn::Clear { .. } => "Clear",
+            Insn::AggReset { .. } => "AggReset",
+            Insn::AggStep { .. } => "AggStep",
+            Insn::AggFinal { .. } => "AggFinal",
+            Insn::CreateTree { .. } => "CreateTree",
+            Insn::DestroyTree { .. } => "DestroyTree",
+            Insn::AddToCatalog { .. } => "AddToCatalog",
+            Insn::RemoveFromCatalog { .. } => "RemoveFromCatalog",
+        }
+    }
+
+    // Operands p1 to p4 and the comment of the EXPLAIN listing
+    fn explain(&self) -> (i64, i64, i64, Value, String) {
+        let text = |text: String| Value::Text(text);
+        let p = |value: usize| value as i64;
+        let range = |start: usize, count: usize| match count {
+            1 => format!("r[{start}]"),
+            _ => format!("r[{start}..{}]", start + count),
+        };
+        let orders = |orders: &[SortOrder]| {
+            let orders: Vec<&str> = orders
+                .iter()
+                .map(|order| match order {
+                    SortOrder::Ascending => "ASC",
+                    SortOrder::Descending => "DESC",
+                })
+                .collect();
+            Value::Text(orders.join(","))
+        };
+        let key_orders = |key_info: &Option<KeyInfo>| match key_info {
+            Some(key_info) => orders(&key_info.orders),
+            None => Value::Null,
+        };
+
+        match self {
+            Insn::Init { target } | Insn::Goto { target } => {
+                (0, p(*target), 0, Value::Null, String::new())
+            }
+            Insn::VerifyCookie { cookie } => (
+                0,
+                *cookie as i64,
+                0,
+                Value::Null,
+                format!("schema cookie {cookie}"),
+            ),
+            Insn::Gosub { ret, target } => (p(*ret), p(*target), 0, Value::Null, String::new()),
+            Insn::Return { ret } => (p(*ret), 0, 0, Value::Null, String::new()),
+            Insn::Halt => (0, 0, 0, Value::Null, String::new()),
+            Insn::Abort { message, .. } => (0, 0, 0, text(message.clone()), String::new()),
+            Insn::Integer { value, dest } => (
+                *value,
+                p(*dest),
+                0,
+                Value::Null,
+                format!("r[{dest}]={value}"),
+            ),
+            Insn::Real { value, dest } => (
+                0,
+                p(*dest),
+                0,
+                Value::Real(*value),
+                format!("r[{dest}]={value}"),
+            ),
+            Insn::String { value, dest } => (
+                0,
+                p(*dest),
+                0,
+                text(value.clone()),
+                format!("r[{dest}]='{value}'"),
+            ),
+            Insn::Blob { value, dest } => (
+                0,
+                p(*dest),
+                0,
+                Value::Blob(value.clone()),
+                format!("r[{dest}]=blob"),
+            ),
+            Insn::Null { dest, count } => (
+                0,
+                p(*dest),
+                p(*count),
+                Value::Null,
+                format!("{}=NULL", range(*dest, *count)),
+            ),
+            Insn::Variable { parameter, dest } => (
+                *parameter as i64,
+                p(*dest),
+                0,
+                Value::Null,
+                format!("r[{dest}]=?{parameter}"),
+            ),
+            Insn::Copy {
+                source,
+                dest,
+                count,
+            } => (
+                p(*source),
+                p(*dest),
+                p(*count),
+                Value::Null,
+                format!("{}={}", range(*dest, *count), range(*source, *count)),
+            ),
+            Insn::MustBeInt { reg } => (p(*reg), 0, 0, Value::Null, String::new()),
+            Insn::Affinity { start, affinities } => {
+                let names: Vec<&str> = affinities
+                    .iter()
+                    .map(|affinity| affinity.as_str())
+                    .collect();
+                (
+                    p(*start),
+                    p(affinities.len()),
+                    0,
+                    text(names.join(",")),
+                    format!("affinity({})", range(*start, affinities.len())),
+                )
+            }
+            Insn::Unary { op, source, dest } => {
+                let symbol = match op {
+                    UnaryOp::Neg => "-",
+                    UnaryOp::Plus => "+",
+                    UnaryOp::Not => "NOT ",
+                    UnaryOp::BitNot => "~",
+                };
+                (
+                    p(*source),
+                    p(*dest),
+                    0,
+                    Value::Null,
+                    format!("r[{dest}]={symbol}r[{source}]"),
+                )
+            }
+            Insn::Binary {
+                op,
+                left,
+                right,
+                dest,
+            } => {
+                let symbol = match op {
+                    BinaryOp::Or => "OR",
+                    BinaryOp::And => "AND",
+                    BinaryOp::Eq => "=",
+                    BinaryOp::NotEq => "!=",
+                    BinaryOp::Is => "IS",
+                    BinaryOp::IsNot => "IS NOT",
+                    BinaryOp::Lt => "<",
+                    BinaryOp::LtEq => "<=",
+                    BinaryOp::Gt => ">",
+                    BinaryOp::GtEq => ">=",
+                    BinaryOp::BitAnd => "&",
+                    BinaryOp::BitOr => "|",
+                    BinaryOp::ShiftLeft => "<<",
+                    BinaryOp::ShiftRight => ">>",
+                    BinaryOp::Add => "+",
+                    BinaryOp::Sub => "-",
+                    BinaryOp::Mul => "*",
+                    BinaryOp::Div => "/",
+                    BinaryOp::Rem => "%",
+                    BinaryOp::Concat => "||",
+                };
+                (
+                    p(*left),
+                    p(*right),
+                    p(*dest),
+                    Value::Null,
+                    format!("r[{dest}]=r[{left}] {symbol} r[{right}]"),
+                )
+            }
+            Insn::Like {
+                value,
+                pattern,
+                dest,
+            } => (
+                p(*value),
+                p(*pattern),
+                p(*dest),
+                Value::Null,
+                format!("r[{dest}]=r[{value}] LIKE r[{pattern}]"),
+            ),
+            Insn::Cast {
+                source,
+                affinity,
+                dest,
+            } => (
+                p(*source),
+                p(*dest),
+                0,
+                text(affinity.as_str().to_string()),
+                format!("r[{dest}]=CAST(r[{source}] AS {})", affinity.as_str()),
+            ),
+            Insn::Function {
+                function,
+                args,
+                count,
+                dest,
+            } => (
+                p(*args),
+                p(*count),
+                p(*dest),
+                text(function.to_string()),
+                format!("r[{dest}]={function}({})", range(*args, *count)),
+            ),
+            Insn::If {
+                reg,
+                target,
+                jump_if_null,
+            }
+            | Insn::IfNot {
+                reg,
+                target,
+                jump_if_null,
+            } => (
+                p(*reg),
+                p(*target),
+                *jump_if_null as i64,
+                Value::Null,
+                String::new(),
+            ),
+            Insn::IsNull { reg, target }
+            | Insn::NotNull { reg, target }
+            | Insn::IfPos { reg, target }
+            | Insn::DecrJumpZero { reg, target } => {
+                (p(*reg), p(*target), 0, Value::Null, String::new())
+            }
+            Insn::ResultRow { start, count } => (
+                p(*start),
+                p(*count),
+                0,
+                Value::Null,
+                format!("output={}", range(*start, *count)),
+            ),
+            Insn::OpenRead {
+                cursor,
+                root,
+                key_info,
+            } => (
+                p(*cursor),
+                *root as i64,
+                0,
+                key_orders(key_info),
+                format!("root={root}"),
+            ),
+            Insn::OpenWrite {
+                cursor,
+                root,
+                key_info,
+            } => match root {
+                Root::Page(root) => (
+                    p(*cursor),
+                    *root as i64,
+                    0,
+                    key_orders(key_info),
+                    format!("root={root}"),
+                ),
+                Root::Register(reg) => (
+                    p(*cursor),
+                    p(*reg),
+                    1,
+                    key_orders(key_info),
+                    format!("root=r[{reg}]"),
+                ),
+            },
+            Insn::OpenEphemeral { cursor, orders: o } => {
+                (p(*cursor), p(o.len()), 0, orders(o), String::new())
+            }
+            Insn::Rewind { cursor, target } | Insn::Next { cursor, target } => {
+                (p(*cursor), p(*target), 0, Value::Null, String::new())
+            }
+            Insn::SeekRowid {
+                cursor,
+                rowid,
+                target,
+            } => (
+                p(*cursor),
+                p(*target),
+                p(*rowid),
+                Value::Null,
+                format!("rowid=r[{rowid}]"),
+            ),
+            Insn::SeekGe {
+                cursor,
+                start,
+                count,
+                target,
+            }
+            | Insn::IdxGt {
+                cursor,
+                start,
+                count,
+                target,
+            }
+            | Insn::Found {
+                cursor,
+                start,
+                count,
+                target,
+            } => (
+                p(*cursor),
+                p(*target),
+                p(*start),
+                Value::Integer(*count as i64),
+                format!("key={}", range(*start, *count)),
+            ),
+            Insn::Column {
+                cursor,
+                column,
+                dest,
+            } => (
+                p(*cursor),
+                p(*column),
+                p(*dest),
+                Value::Null,
+                format!("r[{dest}]=cursor {cursor} column {column}"),
+            ),
+            Insn::Rowid { cursor, dest } | Insn::NewRowid { cursor, dest } => (
+                p(*cursor),
+                p(*dest),
+                0,
+                Value::Null,
+                format!("r[{dest}]=rowid"),
+            ),
+            Insn::NullRow { cursor } | Insn::Delete { cursor } => {
+                (p(*cursor), 0, 0, Value::Null, String::new())
+            }
+            Insn::MakeRecord { start, count, dest } => (
+                p(*start),
+                p(*count),
+                p(*dest),
+                Value::Null,
+                format!("r[{dest}]=mkrec({})", range(*start, *count)),
+            ),
+            Insn::Insert {
+                cursor,
+                record,
+                rowid,
+            } => (
+                p(*cursor),
+                p(*record),
+                p(*rowid),
+                Value::Null,
+                format!("rowid=r[{rowid}]"),
+            ),
+            Insn::IdxInsert {
+                cursor,
+                start,
+                count,
+                rowid,
+                ..
+            }
+            | Insn::IdxDelete {
+                cursor,
+                start,
+                count,
+                rowid,
+            } => (
+                p(*cursor),
+                p(*start),
+                p(*count),
+                Value::Integer(*rowid as i64),
+                format!("key={} rowid=r[{rowid}]", range(*start, *count)),
+            ),
+            Insn::EphemeralInsert {
+                cursor,
+                start,
+                count,
+                key_count,
+            } => (
+                p(*cursor),
+                p(*start),
+                p(*count),
+                Value::Integer(*key_count as i64),
+                format!("row={}", range(*start, *count)),
+            ),
+            Insn::Clear { root } | Insn::DestroyTree { root } => {
+                (*root as i64, 0, 0, Value::Null, format!("root={root}"))
+            }
+            Insn::AggReset { slot } => (p(*slot), 0, 0, Value::Null, String::new()),
+            Insn::AggStep {
+                function,
+                distinct,
+                args,
+                count,
+                slot,
+            } => (
+                p(*args),
+                p(*count),
+                p(*slot),
+                text(function.to_string()),
+                format!(
+                    "accum[{slot}]={function}({}{})",
+                    if *distinct { "DISTINCT " } else { "" },
+                    match count {
+                        0 => "*".to_string(),
+                        _ => range(*args, *count),
+                    }
+                ),
+            ),
+            Insn::AggFinal {
+                function,
+                slot,
+                dest,
+            } => (
+                p(*slot),
+                p(*dest),
+                0,
+                text(function.to_string()),
+                format!("r[{dest}]=accum[{slot}]"),
+            ),
+            Insn::CreateTree { kind, dest } => (
+                p(*dest),
+                0,
+                0,
+                text(
+                    match kind {
+                        TreeKind::Table => "table",
+                        TreeKind::Index => "index",
+                    }
+                    .to_string(),
+                ),
+                format!("r[{dest}]=root"),
+            ),
+            Insn::AddToCatalog {
+                kind,
+                name,
+                table,
+                root,
+                sql,
+            } => (
+                root.map_or(0, p),
+                0,
+                0,
+                text(sql.clone()),
+                format!("{} {name} on {table}", kind.as_str()),
+            ),
+            Insn::RemoveFromCatalog { name } => (0, 0, 0, text(name.clone()), String::new()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    pub(crate) insns: Vec<Insn>,
+    pub(crate) registers: usize,
+    pub(crate) cursors: usize,
+    pub(crate) aggregates: usize,
+    pub(crate) parameters: u32,
+    // Names of the columns of the rows it returns
+    pub(crate) columns: Vec<String>,
+    // Whether the program lists its instructions instead of running them
+    pub(crate) explain: bool,
+}
+
+const EXPLAIN_COLUMNS: &[&str] = &["addr", "opcode", "p1", "p2", "p3", "p4", "comment"];
+
+impl Program {
+    pub fn insns(&self) -> &[Insn] {
+        &self.insns
+    }
+
+    pub fn columns(&self) -> Vec<String> {
+        match self.explain {
+            true => EXPLAIN_COLUMNS
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+            false => self.columns.clone(),
+        }
+    }
+
+    // Number of the largest parameter used by the program
+    pub fn parameter_count(&self) -> u32 {
+        self.parameters
+    }
+
+    pub fn is_explain(&self) -> bool {
+        self.explain
+    }
+
+    // The row of the EXPLAIN listing for the instruction at `addr`
+    pub fn explain_row(&self, addr: Address) -> Option<Vec<Value>> {
+        let insn = self.insns.get(addr)?;
+        let (p1, p2, p3, p4, comment) = insn.explain();
+
+        Some(vec![
+            Value::Integer(addr as i64),
+            Value::Text(insn.opcode().to_string()),
+            Value::Integer(p1),
+            Value::Integer(p2),
+            Value::Integer(p3),
+            p4,
+            Value::Text(comment),
+        ])
+    }
+}
+
+// The EXPLAIN listing, one instruction per line
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "addr  opcode             p1    p2    p3    p4                    comment"
+        )?;
+        for (addr, insn) in self.insns.iter().enumerate() {
+            let (p1, p2, p3, p4, comment) = insn.explain();
+            let p4 = match p4 {
+                Value::Null => String::new(),
+                Value::Integer(integer) => integer.to_string(),
+                Value::Real(real) => real.to_string(),
+                Value::Text(text) => text,
+                Value::Blob(blob) => blob.iter().map(|byte| format!("{byte:02x}")).collect(),
+            };
+            writeln!(
+                f,
+                "{addr:<5} {:<18} {p1:<5} {p2:<5} {p3:<5} {p4:<21} {comment}",
+                insn.opcode()
+            )?;
+        }
+
+        Ok(())
+    }
+}
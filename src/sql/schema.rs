@@ -0,0 +1,255 @@
+//! Tables and indexes as the compiler sees them, read from the SQL text the catalog keeps for them
+//! A column declared `INTEGER PRIMARY KEY` is the rowid of its table, under another name, and is stored as NULL in the
+//! records of the rows. Any other PRIMARY KEY or UNIQUE constraint gets an index of its own, created along with the
+//! table. These automatic indexes have no SQL text, they are named `autoindex_<table>_<n>`, numbered from 1 in the
+//! order their constraints appear in, columns first
+
+use std::io;
+
+use crate::{
+    catalog::{Catalog, ObjectKind, SchemaObject},
+    key::SortOrder,
+    pager::PageNumber,
+    sql::{
+        ast::{ColumnConstraint, CreateTable, Expr, IndexedColumn, Statement, TableConstraint},
+        functions::Affinity,
+        parser, syntax_error,
+    },
+};
+
+#[derive(Debug, Clone)]
+pub struct Column {
+    pub name: String,
+    pub affinity: Affinity,
+    pub not_null: bool,
+    pub default: Option<Expr>,
+}
+
+#[derive(Debug, Clone)]
+pub struct IndexDef {
+    pub name: String,
+    pub root: PageNumber,
+    pub unique: bool,
+    // Positions of the indexed columns in their table
+    pub columns: Vec<usize>,
+    pub orders: Vec<SortOrder>,
+    // Whether it was created for a constraint of its table
+    pub automatic: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Table {
+    pub name: String,
+    pub root: PageNumber,
+    pub columns: Vec<Column>,
+    // Position of the column that is the rowid
+    pub rowid_alias: Option<usize>,
+    pub indexes: Vec<IndexDef>,
+}
+
+impl Table {
+    // Position of the column called `name`
+    pub fn column(&self, name: &str) -> Option<usize> {
+        self.columns
+            .iter()
+            .position(|column| column.name.eq_ignore_ascii_case(name))
+    }
+}
+
+// What a CREATE TABLE statement defines, besides the trees
+pub struct Definition {
+    pub columns: Vec<Column>,
+    pub rowid_alias: Option<usize>,
+    // Columns and sort orders of the automatic indexes, in the order they are numbered in
+    pub automatic_indexes: Vec<(Vec<usize>, Vec<SortOrder>)>,
+}
+
+// Checks the definition of a table, whose text is `sql`
+pub fn define(sql: &str, create: &CreateTable) -> io::Result<Definition> {
+    let mut columns: Vec<Column> = vec![];
+    let mut rowid_alias = None;
+    let mut primary_key = false;
+    let mut automatic_indexes = vec![];
+
+    for def in &create.columns {
+        if columns
+            .iter()
+            .any(|column| column.name.eq_ignore_ascii_case(&def.name.name))
+        {
+            return Err(syntax_error(
+                sql,
+                def.name.span,
+                format!("duplicate column name: {}", def.name.name),
+            ));
+        }
+
+        let position = columns.len();
+        let mut column = Column {
+            name: def.name.name.clone(),
+            affinity: Affinity::from_type(def.type_name.as_deref()),
+            not_null: false,
+            default: None,
+        };
+        for constraint in &def.constraints {
+            match constraint {
+                ColumnConstraint::PrimaryKey(order) => {
+                    if primary_key {
+                        return Err(syntax_error(
+                            sql,
+                            def.span,
+                            format!("table {} has more than one primary key", create.name.name),
+                        ));
+                    }
+                    primary_key = true;
+                    let integer = def
+                        .type_name
+                        .as_deref()
+                        .is_some_and(|type_name| type_name.eq_ignore_ascii_case("INTEGER"));
+                    match integer && *order == SortOrder::Ascending {
+                        true => rowid_alias = Some(position),
+                        false => automatic_indexes.push((vec![position], vec![*order])),
+                    }
+                }
+                ColumnConstraint::NotNull => column.not_null = true,
+                ColumnConstraint::Unique => {
+                    automatic_indexes.push((vec![position], vec![SortOrder::Ascending]))
+                }
+                ColumnConstraint::Default(expr) => column.default = Some(expr.clone()),
+            }
+        }
+        columns.push(column);
+    }
+
+    for constraint in &create.constraints {
+        let (indexed, is_primary_key) = match constraint {
+            TableConstraint::PrimaryKey(indexed) => (indexed, true),
+            TableConstraint::Unique(indexed) => (indexed, false),
+        };
+        let positions = indexed_columns(sql, &columns, indexed)?;
+        let orders = indexed.iter().map(|column| column.order).collect();
+
+        if is_primary_key {
+            if primary_key {
+                return Err(syntax_error(
+                    sql,
+                    indexed[0].name.span,
+                    format!("table {} has more than one primary key", create.name.name),
+                ));
+            }
+            primary_key = true;
+            let def = &create.columns[positions[0]];
+            let integer = def
+                .type_name
+                .as_deref()
+                .is_some_and(|type_name| type_name.eq_ignore_ascii_case("INTEGER"));
+            if positions.len() == 1 && integer && indexed[0].order == SortOrder::Ascending {
+                rowid_alias = Some(positions[0]);
+                continue;
+            }
+        }
+        automatic_indexes.push((positions, orders));
+    }
+
+    Ok(Definition {
+        columns,
+        rowid_alias,
+        automatic_indexes,
+    })
+}
+
+// Positions of the columns listed by an index or a constraint
+pub fn indexed_columns(
+    sql: &str,
+    columns: &[Column],
+    indexed: &[IndexedColumn],
+) -> io::Result<Vec<usize>> {
+    indexed
+        .iter()
+        .map(|indexed| {
+            columns
+                .iter()
+                .position(|column| column.name.eq_ignore_ascii_case(&indexed.name.name))
+                .ok_or_else(|| {
+                    syntax_error(
+                        sql,
+                        indexed.name.span,
+                        format!("no such column: {}", indexed.name.name),
+                    )
+                })
+        })
+        .collect()
+}
+
+pub fn automatic_index_name(table: &str, number: usize) -> String {
+    format!("autoindex_{table}_{number}")
+}
+
+// The table called `name`, if the catalog has one
+pub fn table(catalog: &Catalog, name: &str) -> io::Result<Option<Table>> {
+    let Some(object) = catalog.get(name) else {
+        return Ok(None);
+    };
+    if object.kind != ObjectKind::Table {
+        return Ok(None);
+    }
+
+    let Statement::CreateTable(create) = parse(object)? else {
+        return Err(malformed(object));
+    };
+    let definition = define(&object.sql, &create).map_err(|_| malformed(object))?;
+
+    let mut indexes = vec![];
+    for index in catalog.indexes(&object.name) {
+        if index.sql.is_empty() {
+            let number = (1..=definition.automatic_indexes.len())
+                .find(|&number| index.name == automatic_index_name(&object.name, number))
+                .ok_or_else(|| malformed(index))?;
+            let (columns, orders) = definition.automatic_indexes[number - 1].clone();
+            indexes.push(IndexDef {
+                name: index.name.clone(),
+                root: index.root,
+                unique: true,
+                columns,
+                orders,
+                automatic: true,
+            });
+            continue;
+        }
+
+        let Statement::CreateIndex(create) = parse(index)? else {
+            return Err(malformed(index));
+        };
+        indexes.push(IndexDef {
+            name: index.name.clone(),
+            root: index.root,
+            unique: create.unique,
+            columns: indexed_columns(&index.sql, &definition.columns, &create.columns)
+                .map_err(|_| malformed(index))?,
+            orders: create.columns.iter().map(|column| column.order).collect(),
+            automatic: false,
+        });
+    }
+
+    Ok(Some(Table {
+        name: object.name.clone(),
+        root: object.root,
+        columns: definition.columns,
+        rowid_alias: definition.rowid_alias,
+        indexes,
+    }))
+}
+
+fn parse(object: &SchemaObject) -> io::Result<Statement> {
+    parser::parse_statement(&object.sql).map_err(|_| malformed(object))
+}
+
+fn malformed(object: &SchemaObject) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "Malformed schema: invalid definition of {} {}",
+            object.kind.as_str(),
+            object.name
+        ),
+    )
+}
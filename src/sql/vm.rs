@@ -0,0 +1,749 @@
+//! Register-based virtual machine running the programs of `program`, one instruction after the other, until a row is
+//! produced or the program halts. Like cursors, the VM does not borrow the pager, which is passed to each step, so that
+//! the caller decides which transaction the rows are read in
+//! Besides cursors on the trees of the database, programs use ephemeral cursors on rows kept in memory, for sorting,
+//! removing duplicates and materializing subqueries
+
+use std::{collections::BTreeMap, io, mem, ops::Bound};
+
+use crate::{
+    btree::{self, BTree, Cursor, RowId, TreeKind},
+    catalog::{self, SchemaObject},
+    index::{self, Index},
+    key::{self, SortOrder},
+    pager::{PageNumber, Pager},
+    record,
+    sql::{
+        functions::{self, Accumulator, Affinity},
+        program::{Address, CursorId, Insn, KeyInfo, Program, Register, Root},
+    },
+    value::Value,
+};
+
+enum Tree {
+    Table(BTree),
+    Index(Index),
+}
+
+// Rows kept in memory, sorted on the key of the values they were added with
+struct Ephemeral {
+    orders: Vec<SortOrder>,
+    // Keys end with a sequence number, so that rows with the same values are all kept, in the order they came in
+    rows: BTreeMap<Vec<u8>, Vec<Value>>,
+    sequence: u64,
+    position: Option<Vec<u8>>,
+}
+
+impl Ephemeral {
+    fn insert(&mut self, values: Vec<Value>, key_count: usize) {
+        let mut key = key::encode(&values[..key_count], &self.orders);
+        key.extend_from_slice(&self.sequence.to_be_bytes());
+        self.sequence += 1;
+        self.rows.insert(key, values);
+    }
+
+    fn first(&mut self) -> bool {
+        self.position = self.rows.keys().next().cloned();
+        self.position.is_some()
+    }
+
+    fn next(&mut self) -> bool {
+        self.position = self.position.take().and_then(|position| {
+            self.rows
+                .range::<Vec<u8>, _>((Bound::Excluded(position), Bound::Unbounded))
+                .next()
+                .map(|(key, _)| key.clone())
+        });
+        self.position.is_some()
+    }
+
+    fn current(&self) -> Option<&Vec<Value>> {
+        self.position
+            .as_ref()
+            .and_then(|position| self.rows.get(position))
+    }
+
+    // Whether a row starts with `values`, no value being the prefix of another once encoded
+    fn contains(&self, values: &[Value]) -> bool {
+        let prefix = key::encode(values, &self.orders);
+        self.rows
+            .range(prefix.clone()..)
+            .next()
+            .is_some_and(|(key, _)| key.starts_with(&prefix))
+    }
+}
+
+enum CursorKind {
+    Tree {
+        tree: Tree,
+        cursor: Cursor,
+        write: bool,
+    },
+    Ephemeral(Ephemeral),
+}
+
+struct VmCursor {
+    kind: CursorKind,
+    // Set by `NullRow` until the cursor moves
+    null_row: bool,
+    // Values of the current entry, decoded on the first column read from it
+    row: Option<Vec<Value>>,
+}
+
+impl VmCursor {
+    fn moved(&mut self) {
+        self.null_row = false;
+        self.row = None;
+    }
+
+    fn is_valid(&self) -> bool {
+        match &self.kind {
+            CursorKind::Tree { cursor, .. } => cursor.is_valid(),
+            CursorKind::Ephemeral(ephemeral) => ephemeral.position.is_some(),
+        }
+    }
+}
+
+pub struct Vm<'a> {
+    program: &'a Program,
+    parameters: Vec<Value>,
+    pc: Address,
+    registers: Vec<Value>,
+    cursors: Vec<Option<VmCursor>>,
+    accumulators: Vec<Accumulator>,
+    halted: bool,
+}
+
+impl<'a> Vm<'a> {
+    // Parameters missing from `parameters` are NULL
+    pub fn new(program: &'a Program, parameters: &[Value]) -> Self {
+        Self {
+            program,
+            parameters: parameters.to_vec(),
+            pc: 0,
+            registers: vec![Value::Null; program.registers],
+            cursors: (0..program.cursors).map(|_| None).collect(),
+            accumulators: (0..program.aggregates)
+                .map(|_| Accumulator::default())
+                .collect(),
+            halted: false,
+        }
+    }
+
+    // Runs the program up to its next row. Returns `None` once it halted, which it also does after an error
+    pub fn step(&mut self, pager: &mut Pager) -> io::Result<Option<Vec<Value>>> {
+        if self.halted {
+            return Ok(None);
+        }
+
+        let result = match self.program.explain {
+            true => {
+                self.pc += 1;
+                Ok(self.program.explain_row(self.pc - 1))
+            }
+            false => self.execute(pager),
+        };
+        if !matches!(result, Ok(Some(_))) {
+            self.halted = true;
+            self.cursors.clear();
+        }
+        result
+    }
+
+    // Runs the program to its end, returning all of its rows
+    pub fn run(&mut self, pager: &mut Pager) -> io::Result<Vec<Vec<Value>>> {
+        let mut rows = vec![];
+        while let Some(row) = self.step(pager)? {
+            rows.push(row);
+        }
+
+        Ok(rows)
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    fn execute(&mut self, pager: &mut Pager) -> io::Result<Option<Vec<Value>>> {
+        let program = self.program;
+
+        while let Some(insn) = program.insns.get(self.pc) {
+            self.pc += 1;
+
+            match insn {
+                Insn::Init { target } | Insn::Goto { target } => self.pc = *target,
+                Insn::VerifyCookie { cookie } => {
+                    if pager.schema_cookie() != *cookie {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            "The schema changed since the statement was compiled",
+                        ));
+                    }
+                }
+                Insn::Gosub { ret, target } => {
+                    self.registers[*ret] = Value::Integer(self.pc as i64);
+                    self.pc = *target;
+                }
+                Insn::Return { ret } => match self.registers[*ret] {
+                    Value::Integer(addr) => self.pc = addr as Address,
+                    _ => return Err(invalid_program("Return without Gosub")),
+                },
+                Insn::Halt => return Ok(None),
+                Insn::Abort { kind, message } => {
+                    return Err(io::Error::new(*kind, message.clone()))
+                }
+                Insn::Integer { value, dest } => self.registers[*dest] = Value::Integer(*value),
+                Insn::Real { value, dest } => self.registers[*dest] = Value::Real(*value),
+                Insn::String { value, dest } => self.registers[*dest] = Value::Text(value.clone()),
+                Insn::Blob { value, dest } => self.registers[*dest] = Value::Blob(value.clone()),
+                Insn::Null { dest, count } => {
+                    self.registers[*dest..*dest + *count].fill(Value::Null)
+                }
+                Insn::Variable { parameter, dest } => {
+                    self.registers[*dest] = self
+                        .parameters
+                        .get(*parameter as usize - 1)
+                        .cloned()
+                        .unwrap_or(Value::Null);
+                }
+                Insn::Copy {
+                    source,
+                    dest,
+                    count,
+                } => {
+                    for offset in 0..*count {
+                        self.registers[dest + offset] = self.registers[source + offset].clone();
+                    }
+                }
+                Insn::MustBeInt { reg } => {
+                    let value = mem::replace(&mut self.registers[*reg], Value::Null);
+                    match functions::apply_affinity(value, Affinity::Integer) {
+                        Value::Integer(integer) => self.registers[*reg] = Value::Integer(integer),
+                        _ => {
+                            return Err(io::Error::new(
+                                io::ErrorKind::InvalidInput,
+                                "datatype mismatch",
+                            ))
+                        }
+                    }
+                }
+                Insn::Affinity { start, affinities } => {
+                    for (offset, affinity) in affinities.iter().enumerate() {
+                        let value = mem::replace(&mut self.registers[start + offset], Value::Null);
+                        self.registers[start + offset] =
+                            functions::apply_affinity(value, *affinity);
+                    }
+                }
+                Insn::Unary { op, source, dest } => {
+                    self.registers[*dest] = functions::unary(*op, &self.registers[*source]);
+                }
+                Insn::Binary {
+                    op,
+                    left,
+                    right,
+                    dest,
+                } => {
+                    self.registers[*dest] =
+                        functions::binary(*op, &self.registers[*left], &self.registers[*right]);
+                }
+                Insn::Like {
+                    value,
+                    pattern,
+                    dest,
+                } => {
+                    self.registers[*dest] =
+                        functions::like(&self.registers[*value], &self.registers[*pattern]);
+                }
+                Insn::Cast {
+                    source,
+                    affinity,
+                    dest,
+                } => {
+                    self.registers[*dest] =
+                        functions::cast(self.registers[*source].clone(), *affinity);
+                }
+                Insn::Function {
+                    function,
+                    args,
+                    count,
+                    dest,
+                } => {
+                    self.registers[*dest] = function.call(&self.registers[*args..*args + *count]);
+                }
+                Insn::If {
+                    reg,
+                    target,
+                    jump_if_null,
+                } => {
+                    if functions::truth(&self.registers[*reg]).unwrap_or(*jump_if_null) {
+                        self.pc = *target;
+                    }
+                }
+                Insn::IfNot {
+                    reg,
+                    target,
+                    jump_if_null,
+                } => {
+                    if !functions::truth(&self.registers[*reg]).unwrap_or(!*jump_if_null) {
+                        self.pc = *target;
+                    }
+                }
+                Insn::IsNull { reg, target } => {
+                    if self.registers[*reg].is_null() {
+                        self.pc = *target;
+                    }
+                }
+                Insn::NotNull { reg, target } => {
+                    if !self.registers[*reg].is_null() {
+                        self.pc = *target;
+                    }
+                }
+                Insn::IfPos { reg, target } => {
+                    if let Value::Integer(value @ 1..) = &mut self.registers[*reg] {
+                        *value -= 1;
+                        self.pc = *target;
+                    }
+                }
+                Insn::DecrJumpZero { reg, target } => {
+                    if let Value::Integer(value) = &mut self.registers[*reg] {
+                        *value = value.saturating_sub(1);
+                        if *value == 0 {
+                            self.pc = *target;
+                        }
+                    }
+                }
+                Insn::ResultRow { start, count } => {
+                    return Ok(Some(self.registers[*start..*start + *count].to_vec()));
+                }
+                Insn::OpenRead {
+                    cursor,
+                    root,
+                    key_info,
+                } => self.open(pager, *cursor, *root, key_info.as_ref(), false)?,
+                Insn::OpenWrite {
+                    cursor,
+                    root,
+                    key_info,
+                } => {
+                    let root = match root {
+                        Root::Page(root) => *root,
+                        Root::Register(reg) => self.page_number(*reg)?,
+                    };
+                    self.open(pager, *cursor, root, key_info.as_ref(), true)?;
+                }
+                Insn::OpenEphemeral { cursor, orders } => {
+                    self.cursors[*cursor] = Some(VmCursor {
+                        kind: CursorKind::Ephemeral(Ephemeral {
+                            orders: orders.clone(),
+                            rows: BTreeMap::new(),
+                            sequence: 0,
+                            position: None,
+                        }),
+                        null_row: false,
+                        row: None,
+                    });
+                }
+                Insn::Rewind { cursor, target } => {
+                    let cursor = self.cursor(*cursor)?;
+                    cursor.moved();
+                    let found = match &mut cursor.kind {
+                        CursorKind::Tree { cursor, .. } => cursor.first(pager)?,
+                        CursorKind::Ephemeral(ephemeral) => ephemeral.first(),
+                    };
+                    if !found {
+                        self.pc = *target;
+                    }
+                }
+                Insn::Next { cursor, target } => {
+                    let cursor = self.cursor(*cursor)?;
+                    cursor.moved();
+                    let found = match &mut cursor.kind {
+                        CursorKind::Tree { cursor, .. } => cursor.next(pager)?,
+                        CursorKind::Ephemeral(ephemeral) => ephemeral.next(),
+                    };
+                    if found {
+                        self.pc = *target;
+                    }
+                }
+                Insn::SeekRowid {
+                    cursor,
+                    rowid,
+                    target,
+                } => {
+                    let rowid = match self.registers[*rowid] {
+                        Value::Integer(rowid) => Some(rowid),
+                        _ => None,
+                    };
+                    self.cursor(*cursor)?.moved();
+                    let (_, cursor) = self.table(*cursor)?;
+                    let found = match rowid {
+                        Some(rowid) => {
+                            cursor.seek(pager, &btree::rowid_key(rowid))? && cursor.rowid() == rowid
+                        }
+                        None => false,
+                    };
+                    if !found {
+                        self.pc = *target;
+                    }
+                }
+                Insn::SeekGe {
+                    cursor,
+                    start,
+                    count,
+                    target,
+                } => {
+                    let values = self.registers[*start..*start + *count].to_vec();
+                    self.cursor(*cursor)?.moved();
+                    let (index, cursor) = self.index(*cursor)?;
+                    let key = key::encode(&values, index.orders());
+                    if !cursor.seek(pager, &key)? {
+                        self.pc = *target;
+                    }
+                }
+                Insn::IdxGt {
+                    cursor,
+                    start,
+                    count,
+                    target,
+                } => {
+                    let values = self.registers[*start..*start + *count].to_vec();
+                    let (index, cursor) = self.index(*cursor)?;
+                    let key = key::encode(&values, index.orders());
+                    if !cursor.is_valid()
+                        || index::compare_keys(cursor.key(), &key) == std::cmp::Ordering::Greater
+                    {
+                        self.pc = *target;
+                    }
+                }
+                Insn::Column {
+                    cursor,
+                    column,
+                    dest,
+                } => self.registers[*dest] = self.column(pager, *cursor, *column)?,
+                Insn::Rowid { cursor, dest } => {
+                    let cursor = self.cursor(*cursor)?;
+                    self.registers[*dest] = match &cursor.kind {
+                        _ if cursor.null_row || !cursor.is_valid() => Value::Null,
+                        CursorKind::Tree { tree, cursor, .. } => Value::Integer(match tree {
+                            Tree::Table(_) => cursor.rowid(),
+                            Tree::Index(_) => index_rowid(cursor.key())?,
+                        }),
+                        CursorKind::Ephemeral(_) => Value::Null,
+                    };
+                }
+                Insn::NullRow { cursor } => {
+                    let cursor = self.cursor(*cursor)?;
+                    cursor.null_row = true;
+                    cursor.row = None;
+                }
+                Insn::NewRowid { cursor, dest } => {
+                    let (tree, _) = self.table(*cursor)?;
+                    let rowid = match tree.max_rowid(pager)? {
+                        Some(rowid) => rowid.checked_add(1).ok_or_else(|| {
+                            io::Error::new(io::ErrorKind::StorageFull, "No rowid left in the table")
+                        })?,
+                        None => 1,
+                    };
+                    self.registers[*dest] = Value::Integer(rowid);
+                }
+                Insn::MakeRecord { start, count, dest } => {
+                    self.registers[*dest] =
+                        Value::Blob(record::encode(&self.registers[*start..*start + *count]));
+                }
+                Insn::Insert {
+                    cursor,
+                    record,
+                    rowid,
+                } => {
+                    let rowid = self.rowid(*rowid)?;
+                    let Value::Blob(record) =
+                        mem::replace(&mut self.registers[*record], Value::Null)
+                    else {
+                        return Err(invalid_program("Insert of a value that is not a record"));
+                    };
+                    let tree = self.writable_table(*cursor)?;
+                    tree.insert(pager, rowid, &record)?;
+                }
+                Insn::Delete { cursor } => {
+                    let tree = self.writable_table(*cursor)?;
+                    let (_, cursor) = self.table(*cursor)?;
+                    if !cursor.is_valid() {
+                        return Err(invalid_program("Delete from a cursor without a row"));
+                    }
+                    tree.delete(pager, cursor.rowid())?;
+                }
+                Insn::IdxInsert {
+                    cursor,
+                    start,
+                    count,
+                    rowid,
+                    conflict,
+                } => {
+                    let rowid = self.rowid(*rowid)?;
+                    let values = self.registers[*start..*start + *count].to_vec();
+                    self.writable_index(*cursor)?
+                        .insert(pager, &values, rowid)
+                        .map_err(|err| match err.kind() {
+                            io::ErrorKind::AlreadyExists => {
+                                io::Error::new(io::ErrorKind::AlreadyExists, conflict.clone())
+                            }
+                            _ => err,
+                        })?;
+                }
+                Insn::IdxDelete {
+                    cursor,
+                    start,
+                    count,
+                    rowid,
+                } => {
+                    let rowid = self.rowid(*rowid)?;
+                    let values = self.registers[*start..*start + *count].to_vec();
+                    self.writable_index(*cursor)?
+                        .delete(pager, &values, rowid)?;
+                }
+                Insn::EphemeralInsert {
+                    cursor,
+                    start,
+                    count,
+                    key_count,
+                } => {
+                    let values = self.registers[*start..*start + *count].to_vec();
+                    self.ephemeral(*cursor)?.insert(values, *key_count);
+                }
+                Insn::Found {
+                    cursor,
+                    start,
+                    count,
+                    target,
+                } => {
+                    let values = self.registers[*start..*start + *count].to_vec();
+                    if self.ephemeral(*cursor)?.contains(&values) {
+                        self.pc = *target;
+                    }
+                }
+                Insn::Clear { root } => BTree::open(pager, *root)?.clear(pager)?,
+                Insn::AggReset { slot } => self.accumulators[*slot] = Accumulator::default(),
+                Insn::AggStep {
+                    function,
+                    distinct,
+                    args,
+                    count,
+                    slot,
+                } => self.accumulators[*slot].step(
+                    *function,
+                    &self.registers[*args..*args + *count],
+                    *distinct,
+                )?,
+                Insn::AggFinal {
+                    function,
+                    slot,
+                    dest,
+                } => self.registers[*dest] = self.accumulators[*slot].finish(*function),
+                Insn::CreateTree { kind, dest } => {
+                    let root = BTree::create(pager, *kind)?.root_page();
+                    self.registers[*dest] = Value::Integer(root as i64);
+                }
+                Insn::DestroyTree { root } => BTree::open(pager, *root)?.destroy(pager)?,
+                Insn::AddToCatalog {
+                    kind,
+                    name,
+                    table,
+                    root,
+                    sql,
+                } => {
+                    let root = match root {
+                        Some(reg) => self.page_number(*reg)?,
+                        None => 0,
+                    };
+                    catalog::add(
+                        pager,
+                        SchemaObject {
+                            kind: *kind,
+                            name: name.clone(),
+                            table: table.clone(),
+                            root,
+                            sql: sql.clone(),
+                        },
+                    )?;
+                }
+                Insn::RemoveFromCatalog { name } => {
+                    catalog::remove(pager, name)?;
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn open(
+        &mut self,
+        pager: &mut Pager,
+        cursor: CursorId,
+        root: PageNumber,
+        key_info: Option<&KeyInfo>,
+        write: bool,
+    ) -> io::Result<()> {
+        let tree = match key_info {
+            Some(key_info) => Tree::Index(Index::open_with(
+                pager,
+                root,
+                key_info.unique,
+                &key_info.orders,
+            )?),
+            None => {
+                let tree = BTree::open(pager, root)?;
+                if tree.kind() != TreeKind::Table {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Page {root} is not the root of a table"),
+                    ));
+                }
+                Tree::Table(tree)
+            }
+        };
+        let mut tree_cursor = match &tree {
+            Tree::Table(tree) => tree.cursor(),
+            Tree::Index(index) => BTree::open(pager, index.root_page())?.cursor(),
+        };
+        tree_cursor.set_prefetch(true);
+
+        self.cursors[cursor] = Some(VmCursor {
+            kind: CursorKind::Tree {
+                tree,
+                cursor: tree_cursor,
+                write,
+            },
+            null_row: false,
+            row: None,
+        });
+        Ok(())
+    }
+
+    fn cursor(&mut self, cursor: CursorId) -> io::Result<&mut VmCursor> {
+        self.cursors
+            .get_mut(cursor)
+            .and_then(Option::as_mut)
+            .ok_or_else(|| invalid_program(&format!("Cursor {cursor} is not open")))
+    }
+
+    fn table(&mut self, cursor: CursorId) -> io::Result<(&BTree, &mut Cursor)> {
+        match &mut self.cursor(cursor)?.kind {
+            CursorKind::Tree {
+                tree: Tree::Table(tree),
+                cursor,
+                ..
+            } => Ok((tree, cursor)),
+            _ => Err(invalid_program(&format!(
+                "Cursor {cursor} is not on a table"
+            ))),
+        }
+    }
+
+    fn index(&mut self, cursor: CursorId) -> io::Result<(&Index, &mut Cursor)> {
+        match &mut self.cursor(cursor)?.kind {
+            CursorKind::Tree {
+                tree: Tree::Index(index),
+                cursor,
+                ..
+            } => Ok((index, cursor)),
+            _ => Err(invalid_program(&format!(
+                "Cursor {cursor} is not on an index"
+            ))),
+        }
+    }
+
+    fn ephemeral(&mut self, cursor: CursorId) -> io::Result<&mut Ephemeral> {
+        match &mut self.cursor(cursor)?.kind {
+            CursorKind::Ephemeral(ephemeral) => Ok(ephemeral),
+            _ => Err(invalid_program(&format!(
+                "Cursor {cursor} is not ephemeral"
+            ))),
+        }
+    }
+
+    // The tree of a cursor opened for writing. Its position is lost once the tree is modified
+    fn writable_tree(&mut self, cursor: CursorId) -> io::Result<&Tree> {
+        let vm_cursor = self.cursor(cursor)?;
+        vm_cursor.row = None;
+        match &vm_cursor.kind {
+            CursorKind::Tree {
+                tree, write: true, ..
+            } => Ok(tree),
+            _ => Err(invalid_program(&format!(
+                "Cursor {cursor} is not open for writing"
+            ))),
+        }
+    }
+
+    fn writable_table(&mut self, cursor: CursorId) -> io::Result<BTree> {
+        match self.writable_tree(cursor)? {
+            Tree::Table(tree) => Ok(*tree),
+            Tree::Index(_) => Err(invalid_program(&format!(
+                "Cursor {cursor} is not on a table"
+            ))),
+        }
+    }
+
+    fn writable_index(&mut self, cursor: CursorId) -> io::Result<Index> {
+        match self.writable_tree(cursor)? {
+            Tree::Index(index) => Ok(index.clone()),
+            Tree::Table(_) => Err(invalid_program(&format!(
+                "Cursor {cursor} is not on an index"
+            ))),
+        }
+    }
+
+    fn column(&mut self, pager: &mut Pager, cursor: CursorId, column: usize) -> io::Result<Value> {
+        let cursor = self.cursor(cursor)?;
+        if cursor.null_row || !cursor.is_valid() {
+            return Ok(Value::Null);
+        }
+
+        if cursor.row.is_none() {
+            cursor.row = Some(match &cursor.kind {
+                CursorKind::Tree { cursor, .. } => record::decode(&cursor.read_payload(pager)?)?,
+                CursorKind::Ephemeral(ephemeral) => {
+                    ephemeral.current().cloned().unwrap_or_default()
+                }
+            });
+        }
+        Ok(cursor
+            .row
+            .as_ref()
+            .and_then(|row| row.get(column))
+            .cloned()
+            .unwrap_or(Value::Null))
+    }
+
+    fn rowid(&self, reg: Register) -> io::Result<RowId> {
+        match self.registers[reg] {
+            Value::Integer(rowid) => Ok(rowid),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "datatype mismatch",
+            )),
+        }
+    }
+
+    fn page_number(&self, reg: Register) -> io::Result<PageNumber> {
+        match self.registers[reg] {
+            Value::Integer(root) => PageNumber::try_from(root)
+                .map_err(|_| invalid_program(&format!("Invalid root page {root}"))),
+            _ => Err(invalid_program("Root page that is not an integer")),
+        }
+    }
+}
+
+fn index_rowid(key: &[u8]) -> io::Result<RowId> {
+    key.len()
+        .checked_sub(mem::size_of::<RowId>())
+        .map(|start| btree::key_rowid(&key[start..]))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Index entry without rowid"))
+}
+
+fn invalid_program(message: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("Invalid program: {message}"),
+    )
+}
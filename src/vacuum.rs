@@ -0,0 +1,213 @@
+//! VACUUM rebuilds the whole database into a fresh heap file, then puts it in place of the original one
+//! Trees are copied entry by entry, which packs their pages and lays them out in key order, and free pages are left
+//! behind, so the file shrinks to the pages actually in use. The page size can be changed along the way
+//! A tree is identified by its root page, so roots keep their page number, the other pages filling the space around
+//! them. The trees described by the catalog are kept along with the ones the caller lists, and VACUUM refuses to run
+//! when pages in use do not belong to any of them rather than losing them
+//! The new database is built in a `<db>-vacuum` file, then copied over the heap file under the rollback journal, so
+//! that a crash at any point leaves the database either as it was or vacuumed
+//! Auto-vacuum databases can also be vacuumed incrementally, without rebuilding them: the pages at the end of the file
+//! move into free pages closer to its beginning, the pointer map telling which page points to them, until the free
+//! pages are all at the end of the file, where they are cut off. Roots never move, so a root at the end of the file
+//! stops the process
+
+use std::{cmp::Ordering, collections::BTreeSet, io, path::Path};
+
+use crate::{
+    btree::{self, BTree},
+    overflow,
+    pager::{self, JournalMode, PageNumber, Pager, DEFAULT_CACHE_SIZE},
+    pointer_map::PointerMapEntry,
+    vfs::Vfs,
+};
+
+// Rebuilds the database made of the trees of the catalog and those rooted at `roots`, with pages of `page_size`
+// bytes, or of the current size when `None`. Pending changes are committed along the way
+pub fn vacuum(pager: &mut Pager, roots: &[PageNumber], page_size: Option<u32>) -> io::Result<()> {
+    let mut roots: Vec<PageNumber> = pager
+        .catalog()?
+        .roots()
+        .chain(roots.iter().copied())
+        .collect();
+    roots.sort_unstable();
+    roots.dedup();
+
+    // Page 0 holds the header
+    let mut used = 1 + pager.freelist_count() as u64 + pager.pointer_map_page_count() as u64;
+    let mut trees = Vec::with_capacity(roots.len());
+    for root in roots {
+        let tree = BTree::open(pager, root)?;
+        used += tree.page_count(pager)? as u64;
+        trees.push(tree);
+    }
+    match used.cmp(&(pager.page_count() as u64)) {
+        Ordering::Less => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{} pages in use do not belong to any of the given trees",
+                    pager.page_count() as u64 - used
+                ),
+            ))
+        }
+        Ordering::Greater => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The given trees share pages",
+            ))
+        }
+        Ordering::Equal => {}
+    }
+
+    let vfs = pager.vfs();
+    let path = pager::sidecar_path(pager.path(), "-vacuum");
+    // Left behind by a vacuum that did not finish
+    remove(&*vfs, &path)?;
+
+    let page_size = page_size.unwrap_or(pager.page_size());
+    let result = build(pager, &trees, &path, page_size).and_then(|target| {
+        let mode = pager.journal_mode();
+        pager.set_journal_mode(JournalMode::Rollback)?;
+        let replaced = pager.replace_with(target);
+        // The journal mode is restored even when the database could not be replaced, whose error comes first
+        let restored = pager.set_journal_mode(mode);
+        replaced.and(restored)
+    });
+    let removed = remove(&*vfs, &path);
+
+    result.and(removed)
+}
+
+fn build(pager: &mut Pager, trees: &[BTree], path: &Path, page_size: u32) -> io::Result<Pager> {
+    let mut target = Pager::open_with_vfs(pager.vfs(), path, page_size, DEFAULT_CACHE_SIZE)?;
+    target.set_page_checksums(pager.has_page_checksums());
+    target.init()?;
+    // The file is thrown away if anything goes wrong, it has no need for a journal
+    target.set_journal_mode(JournalMode::Off)?;
+    target.set_auto_vacuum(pager.auto_vacuum())?;
+
+    // Roots are set aside first, and the pages in between freed from the highest to the lowest, which makes the
+    // free-list hand them out in ascending order. The trees fill them before the file grows
+    let last_root = trees.last().map_or(0, BTree::root_page);
+    while target.page_count() <= last_root {
+        target.allocate_page()?;
+    }
+    let mut roots = trees.iter().map(BTree::root_page).rev().peekable();
+    for page_no in (1..=last_root).rev() {
+        match roots.next_if_eq(&page_no) {
+            // Pointer-map pages lie at other positions with another page size
+            Some(root) if target.is_pointer_map_page(root) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "Root page {root} would fall on a pointer map page with pages of {page_size} bytes"
+                    ),
+                ))
+            }
+            Some(root) => target.set_pointer_map_entry(root, PointerMapEntry::Root)?,
+            None if target.is_pointer_map_page(page_no) => {}
+            None => target.free_page(page_no)?,
+        }
+    }
+
+    for tree in trees {
+        tree.copy_to(pager, &mut target)?;
+    }
+    target.flush()?;
+
+    Ok(target)
+}
+
+// Gives up to `pages` free pages back to the file system, and returns how many were. Requires an auto-vacuum database
+pub fn incremental_vacuum(pager: &mut Pager, pages: u32) -> io::Result<u32> {
+    if !pager.has_pointer_map() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Incremental vacuum requires an auto-vacuum database",
+        ));
+    }
+    if pages == 0 || pager.freelist_count() == 0 {
+        return Ok(0);
+    }
+
+    let mut free: BTreeSet<PageNumber> = pager.take_free_pages()?.into_iter().collect();
+    let mut end = pager.page_count();
+    let mut reclaimed = 0;
+    while reclaimed < pages {
+        let last = end - 1;
+        if pager.is_pointer_map_page(last) {
+            end -= 1;
+            continue;
+        }
+        if !free.remove(&last) {
+            // The last page is in use, it moves to the first free page unless it is a root
+            let Some(&to) = free.first() else {
+                break;
+            };
+            if pager.pointer_map_entry(last)? == PointerMapEntry::Root {
+                break;
+            }
+            free.remove(&to);
+            relocate(pager, last, to)?;
+        }
+        end -= 1;
+        reclaimed += 1;
+    }
+    // A pointer-map page is useless without pages after it
+    while end > 1 && pager.is_pointer_map_page(end - 1) {
+        end -= 1;
+    }
+
+    pager.truncate(end)?;
+    // Handed out in ascending order by the free-list
+    for &page_no in free.iter().rev() {
+        pager.free_page(page_no)?;
+    }
+
+    Ok(reclaimed)
+}
+
+// Moves the content of page `from` to the free page `to`, along with the pointers to it and its pointer-map entry
+fn relocate(pager: &mut Pager, from: PageNumber, to: PageNumber) -> io::Result<()> {
+    let entry = pager.pointer_map_entry(from)?;
+    let mut buf = vec![0; pager.page_size() as usize];
+    pager.read(from, &mut buf)?;
+    pager.write(to, &buf)?;
+
+    match entry {
+        PointerMapEntry::Child(parent) => {
+            btree::replace_pointer(pager, parent, from, to)?;
+            btree::update_pointer_map(pager, to)?;
+        }
+        PointerMapEntry::FirstOverflow(parent) => {
+            btree::replace_pointer(pager, parent, from, to)?;
+            update_overflow_parent(pager, to)?;
+        }
+        PointerMapEntry::Overflow(previous) => {
+            overflow::set_next_page(pager, previous, to)?;
+            update_overflow_parent(pager, to)?;
+        }
+        PointerMapEntry::Root | PointerMapEntry::Free => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Page {from} cannot be moved, its pointer map entry is {entry:?}"),
+            ))
+        }
+    }
+
+    pager.set_pointer_map_entry(to, entry)
+}
+
+fn update_overflow_parent(pager: &mut Pager, page_no: PageNumber) -> io::Result<()> {
+    match overflow::next_page(pager, page_no)? {
+        0 => Ok(()),
+        next => pager.set_pointer_map_entry(next, PointerMapEntry::Overflow(page_no)),
+    }
+}
+
+fn remove(vfs: &dyn Vfs, path: &Path) -> io::Result<()> {
+    match vfs.exists(path)? {
+        true => vfs.delete(path),
+        false => Ok(()),
+    }
+}
@@ -0,0 +1,104 @@
+//! Values stored in table columns
+//! Values of different types compare the way SQLite does: NULL comes first, then numbers (integers and reals compare
+//! by their numeric value), then text, then blobs
+
+use std::cmp::Ordering;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+// Borrowed counterpart of `Value`, used to look at values without copying them out of a page
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ValueRef<'a> {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(&'a [u8]),
+    Blob(&'a [u8]),
+}
+
+impl Value {
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+
+    pub fn as_ref(&self) -> ValueRef<'_> {
+        match self {
+            Value::Null => ValueRef::Null,
+            Value::Integer(integer) => ValueRef::Integer(*integer),
+            Value::Real(real) => ValueRef::Real(*real),
+            Value::Text(text) => ValueRef::Text(text.as_bytes()),
+            Value::Blob(blob) => ValueRef::Blob(blob),
+        }
+    }
+
+    pub fn compare(&self, other: &Value) -> Ordering {
+        self.as_ref().compare(&other.as_ref())
+    }
+}
+
+impl ValueRef<'_> {
+    pub fn to_owned(&self) -> Value {
+        match *self {
+            ValueRef::Null => Value::Null,
+            ValueRef::Integer(integer) => Value::Integer(integer),
+            ValueRef::Real(real) => Value::Real(real),
+            ValueRef::Text(text) => Value::Text(String::from_utf8_lossy(text).into_owned()),
+            ValueRef::Blob(blob) => Value::Blob(blob.to_vec()),
+        }
+    }
+
+    // Text compares byte-wise, which for UTF-8 is the same as comparing code points
+    pub fn compare(&self, other: &ValueRef) -> Ordering {
+        match (*self, *other) {
+            (ValueRef::Integer(a), ValueRef::Integer(b)) => a.cmp(&b),
+            (ValueRef::Real(a), ValueRef::Real(b)) => compare_reals(a, b),
+            (ValueRef::Integer(a), ValueRef::Real(b)) => compare_integer_real(a, b),
+            (ValueRef::Real(a), ValueRef::Integer(b)) => compare_integer_real(b, a).reverse(),
+            (ValueRef::Text(a), ValueRef::Text(b)) => a.cmp(b),
+            (ValueRef::Blob(a), ValueRef::Blob(b)) => a.cmp(b),
+            _ => self.type_rank().cmp(&other.type_rank()),
+        }
+    }
+
+    fn type_rank(&self) -> u8 {
+        match self {
+            ValueRef::Null => 0,
+            ValueRef::Integer(_) | ValueRef::Real(_) => 1,
+            ValueRef::Text(_) => 2,
+            ValueRef::Blob(_) => 3,
+        }
+    }
+}
+
+// NaN is never stored, but sorts before every other real if it ever shows up
+fn compare_reals(a: f64, b: f64) -> Ordering {
+    a.partial_cmp(&b)
+        .unwrap_or_else(|| a.is_nan().cmp(&b.is_nan()).reverse())
+}
+
+// Converting the integer to a real would lose precision past 2^53, so the real is split into its integral and
+// fractional parts instead
+fn compare_integer_real(integer: i64, real: f64) -> Ordering {
+    if real.is_nan() {
+        return Ordering::Greater;
+    }
+    if real < i64::MIN as f64 {
+        return Ordering::Greater;
+    }
+    if real >= i64::MAX as f64 {
+        return Ordering::Less;
+    }
+
+    let floor = real.floor();
+    match integer.cmp(&(floor as i64)) {
+        Ordering::Equal if real > floor => Ordering::Less,
+        ordering => ordering,
+    }
+}
@@ -0,0 +1,291 @@
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+use super::{LockMode, Vfs, VfsFile};
+
+// Granularity at which a write interrupted by a power loss can be torn
+pub const SECTOR_SIZE: usize = 512;
+
+// Wraps another VFS to simulate the failures a database has to survive. Writes are passed through, but the wrapper
+// remembers which of them have not been synced yet, so that `crash` can simulate a power loss: every unsynced write
+// is then kept, lost or torn at a sector boundary, at random. Operations can also be made to fail with an I/O error
+// Clones share the same state
+#[derive(Debug, Clone)]
+pub struct FaultVfs {
+    inner: Arc<dyn Vfs>,
+    state: Arc<Mutex<FaultState>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FaultConfig {
+    // On a crash, unsynced writes may be lost
+    pub drop_unsynced: bool,
+    // On a crash, unsynced writes may only be partially applied, sector by sector
+    pub tear_writes: bool,
+    // Every sync fails without making anything durable
+    pub fail_sync: bool,
+    // Every operation after this many fails with an I/O error
+    pub fail_after: Option<u64>,
+}
+
+#[derive(Debug)]
+struct FaultState {
+    config: FaultConfig,
+    rng: Rng,
+    operations: u64,
+    // Bumped by every crash. Handles opened before it stop working, like the process they belonged to
+    generation: u64,
+    files: HashMap<PathBuf, FileState>,
+}
+
+#[derive(Debug, Default)]
+struct FileState {
+    // Content as of the last sync
+    durable: Vec<u8>,
+    unsynced: Vec<PendingWrite>,
+}
+
+#[derive(Debug)]
+enum PendingWrite {
+    Write { offset: u64, data: Vec<u8> },
+    Truncate(u64),
+}
+
+impl FaultVfs {
+    pub fn new(inner: Arc<dyn Vfs>, seed: u64) -> Self {
+        Self {
+            inner,
+            state: Arc::new(Mutex::new(FaultState {
+                config: FaultConfig::default(),
+                rng: Rng::new(seed),
+                operations: 0,
+                generation: 0,
+                files: HashMap::new(),
+            })),
+        }
+    }
+
+    pub fn config(&self) -> FaultConfig {
+        lock(&self.state).config
+    }
+
+    // Operations are counted from the moment the configuration is set
+    pub fn set_config(&self, config: FaultConfig) {
+        let mut state = lock(&self.state);
+        state.config = config;
+        state.operations = 0;
+    }
+
+    // Simulates a power loss. Files end up with their synced content, plus whatever part of the unsynced writes the
+    // configuration lets survive. Every handle opened so far fails from now on
+    pub fn crash(&self) -> io::Result<()> {
+        let mut state = lock(&self.state);
+        let state = &mut *state;
+        state.generation += 1;
+        state.operations = 0;
+
+        for (path, file) in &mut state.files {
+            let mut content = file.durable.clone();
+            for write in file.unsynced.drain(..) {
+                if state.config.drop_unsynced && state.rng.below(2) == 0 {
+                    continue;
+                }
+
+                match write {
+                    PendingWrite::Truncate(len) => content.resize(len as usize, 0),
+                    PendingWrite::Write { offset, data } => {
+                        let torn = state.config.tear_writes && state.rng.below(2) == 0;
+                        for (i, sector) in data.chunks(SECTOR_SIZE).enumerate() {
+                            if torn && state.rng.below(2) == 0 {
+                                continue;
+                            }
+                            let start = offset as usize + i * SECTOR_SIZE;
+                            if content.len() < start + sector.len() {
+                                content.resize(start + sector.len(), 0);
+                            }
+                            content[start..start + sector.len()].copy_from_slice(sector);
+                        }
+                    }
+                }
+            }
+
+            let handle = self.inner.open(path)?;
+            handle.truncate(0)?;
+            handle.write_all_at(&content, 0)?;
+            handle.sync()?;
+            file.durable = content;
+        }
+
+        Ok(())
+    }
+}
+
+impl Vfs for FaultVfs {
+    fn open(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+        let mut state = lock(&self.state);
+        state.operate()?;
+
+        let existed = self.inner.exists(path)?;
+        let file = self.inner.open(path)?;
+        if !existed || !state.files.contains_key(path) {
+            let durable = read_all(&*file)?;
+            state.files.insert(
+                path.to_path_buf(),
+                FileState {
+                    durable,
+                    unsynced: vec![],
+                },
+            );
+        }
+
+        Ok(Box::new(FaultFile {
+            path: path.to_path_buf(),
+            inner: file,
+            state: self.state.clone(),
+            generation: state.generation,
+        }))
+    }
+
+    fn delete(&self, path: &Path) -> io::Result<()> {
+        let mut state = lock(&self.state);
+        state.operate()?;
+
+        self.inner.delete(path)?;
+        state.files.remove(path);
+        Ok(())
+    }
+
+    fn exists(&self, path: &Path) -> io::Result<bool> {
+        lock(&self.state).operate()?;
+        self.inner.exists(path)
+    }
+}
+
+#[derive(Debug)]
+struct FaultFile {
+    path: PathBuf,
+    inner: Box<dyn VfsFile>,
+    state: Arc<Mutex<FaultState>>,
+    generation: u64,
+}
+
+impl FaultFile {
+    // Counts an operation on this handle, failing it if faults say so. Returns the state to record the operation in
+    fn operate(&self) -> io::Result<MutexGuard<'_, FaultState>> {
+        let mut state = lock(&self.state);
+        if state.generation != self.generation {
+            return Err(io::Error::other("The file was opened before a crash"));
+        }
+        state.operate()?;
+
+        Ok(state)
+    }
+
+    fn record(&self, state: &mut FaultState, write: PendingWrite) {
+        if let Some(file) = state.files.get_mut(&self.path) {
+            file.unsynced.push(write);
+        }
+    }
+}
+
+impl VfsFile for FaultFile {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        let _state = self.operate()?;
+        self.inner.read_at(buf, offset)
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<usize> {
+        let mut state = self.operate()?;
+        let written = self.inner.write_at(buf, offset)?;
+        self.record(
+            &mut state,
+            PendingWrite::Write {
+                offset,
+                data: buf[..written].to_vec(),
+            },
+        );
+
+        Ok(written)
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        let mut state = self.operate()?;
+        if state.config.fail_sync {
+            return Err(io::Error::other("Injected sync failure"));
+        }
+
+        self.inner.sync()?;
+        let durable = read_all(&*self.inner)?;
+        if let Some(file) = state.files.get_mut(&self.path) {
+            file.durable = durable;
+            file.unsynced.clear();
+        }
+
+        Ok(())
+    }
+
+    fn truncate(&self, len: u64) -> io::Result<()> {
+        let mut state = self.operate()?;
+        self.inner.truncate(len)?;
+        self.record(&mut state, PendingWrite::Truncate(len));
+
+        Ok(())
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        let _state = self.operate()?;
+        self.inner.size()
+    }
+
+    // Locks are not subject to faults, so that a crashed handle can still release its lock
+    fn lock(&self, mode: LockMode) -> io::Result<()> {
+        self.inner.lock(mode)
+    }
+}
+
+impl FaultState {
+    fn operate(&mut self) -> io::Result<()> {
+        self.operations += 1;
+        match self.config.fail_after {
+            Some(limit) if self.operations > limit => Err(io::Error::other("Injected I/O error")),
+            _ => Ok(()),
+        }
+    }
+}
+
+// Small deterministic pseudo-random generator (xorshift64*), so that a failing run can be replayed from its seed
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // The state must never be 0
+        Self(seed ^ 0x9e37_79b9_7f4a_7c15 | 1)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    // Uniform in `0..bound`, `bound` must not be 0
+    pub fn below(&mut self, bound: u64) -> u64 {
+        self.next_u64() % bound
+    }
+}
+
+fn read_all(file: &dyn VfsFile) -> io::Result<Vec<u8>> {
+    let mut content = vec![0; file.size()? as usize];
+    file.read_exact_at(&mut content, 0)?;
+    Ok(content)
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
@@ -0,0 +1,146 @@
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+use super::{LockMode, Vfs, VfsFile};
+
+// Files held in memory. Clones share the same files, which live as long as one of them or an open handle does
+#[derive(Debug, Clone, Default)]
+pub struct MemoryVfs {
+    files: Arc<Mutex<HashMap<PathBuf, Arc<Inode>>>>,
+}
+
+impl MemoryVfs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Vfs for MemoryVfs {
+    fn open(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+        let inode = lock(&self.files)
+            .entry(path.to_path_buf())
+            .or_default()
+            .clone();
+
+        Ok(Box::new(MemoryFile {
+            inode,
+            held: Mutex::new(LockMode::Unlocked),
+        }))
+    }
+
+    // Like unlinking a file, handles that are still open keep working on the removed content
+    fn delete(&self, path: &Path) -> io::Result<()> {
+        match lock(&self.files).remove(path) {
+            Some(_) => Ok(()),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No such file: {}", path.display()),
+            )),
+        }
+    }
+
+    fn exists(&self, path: &Path) -> io::Result<bool> {
+        Ok(lock(&self.files).contains_key(path))
+    }
+}
+
+#[derive(Debug, Default)]
+struct Inode {
+    data: Mutex<Vec<u8>>,
+    locks: Mutex<Locks>,
+}
+
+#[derive(Debug, Default)]
+struct Locks {
+    shared: usize,
+    exclusive: bool,
+}
+
+#[derive(Debug)]
+struct MemoryFile {
+    inode: Arc<Inode>,
+    held: Mutex<LockMode>,
+}
+
+impl VfsFile for MemoryFile {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        let data = lock(&self.inode.data);
+        let start = (offset as usize).min(data.len());
+        let len = buf.len().min(data.len() - start);
+        buf[..len].copy_from_slice(&data[start..start + len]);
+
+        Ok(len)
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<usize> {
+        let mut data = lock(&self.inode.data);
+        let end = offset as usize + buf.len();
+        if data.len() < end {
+            data.resize(end, 0);
+        }
+        data[offset as usize..end].copy_from_slice(buf);
+
+        Ok(buf.len())
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn truncate(&self, len: u64) -> io::Result<()> {
+        lock(&self.inode.data).resize(len as usize, 0);
+        Ok(())
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(lock(&self.inode.data).len() as u64)
+    }
+
+    fn lock(&self, mode: LockMode) -> io::Result<()> {
+        let mut locks = lock(&self.inode.locks);
+        let mut held = lock(&self.held);
+
+        // The lock this handle holds does not conflict with the one it asks for
+        match *held {
+            LockMode::Unlocked => {}
+            LockMode::Shared => locks.shared -= 1,
+            LockMode::Exclusive => locks.exclusive = false,
+        }
+        let available = match mode {
+            LockMode::Unlocked => true,
+            LockMode::Shared => !locks.exclusive,
+            LockMode::Exclusive => !locks.exclusive && locks.shared == 0,
+        };
+        if available {
+            *held = mode;
+        }
+        match *held {
+            LockMode::Unlocked => {}
+            LockMode::Shared => locks.shared += 1,
+            LockMode::Exclusive => locks.exclusive = true,
+        }
+
+        match available {
+            true => Ok(()),
+            false => Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                "The file is locked by someone else",
+            )),
+        }
+    }
+}
+
+impl Drop for MemoryFile {
+    fn drop(&mut self) {
+        let _ = self.lock(LockMode::Unlocked);
+    }
+}
+
+// A panic while holding one of the locks cannot leave the data in an inconsistent state, so poisoning is ignored
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
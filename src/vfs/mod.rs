@@ -0,0 +1,164 @@
+//! Virtual file system. Every file the database touches, the heap file as well as its write-ahead log and rollback
+//! journal, goes through the `Vfs` trait, so that the database can run on something else than the local file system
+//! `UnixVfs` is the default, backed by real files that it can also map into memory. `MemoryVfs` keeps files in RAM,
+//! which is handy for tests and for databases that do not need to outlive the process
+//! With the `io-uring` feature, `UringVfs` is a Linux variant of `UnixVfs` that submits batches of page reads and
+//! writes through a single io_uring instead of one system call per page
+//! Files accessed with direct I/O bypass the OS page cache, and require their buffers to be `AlignedBuf`s
+//! `FaultVfs` wraps another VFS to inject I/O errors and simulate power losses, see `src/bin/crash_test/` and the
+//! `crash_test` module. It is only built for tests and with the `fault-injection` feature
+
+mod aligned;
+#[cfg(any(test, feature = "fault-injection"))]
+mod fault;
+mod memory;
+mod unix;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+mod uring;
+
+use std::{fmt, io, path::Path};
+
+pub use aligned::{AlignedBuf, BUFFER_ALIGNMENT};
+#[cfg(any(test, feature = "fault-injection"))]
+pub use fault::{FaultConfig, FaultVfs, Rng, SECTOR_SIZE};
+pub use memory::MemoryVfs;
+pub use unix::UnixVfs;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+pub use uring::UringVfs;
+
+// Locks are advisory and only taken by the pager, to keep other connections away from a database in use
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockMode {
+    Unlocked,
+    // Any number of handles can hold a shared lock at the same time
+    Shared,
+    // Held by a single handle, and only when no other handle holds any lock
+    Exclusive,
+}
+
+pub trait Vfs: fmt::Debug + Send + Sync {
+    // Opens the file at `path` for reading and writing, creating it if needed. The creation is durable once `open`
+    // returns
+    fn open(&self, path: &Path) -> io::Result<Box<dyn VfsFile>>;
+
+    // Removes the file at `path`. The removal is durable once `delete` returns
+    fn delete(&self, path: &Path) -> io::Result<()>;
+
+    fn exists(&self, path: &Path) -> io::Result<bool>;
+}
+
+pub trait VfsFile: fmt::Debug + Send + Sync {
+    // Reads up to `buf.len()` bytes at `offset`. Returns the number of bytes read, 0 past the end of the file
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize>;
+
+    // Writes up to `buf.len()` bytes at `offset`, growing the file if needed. Returns the number of bytes written
+    fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<usize>;
+
+    // Waits for the disk to acknowledge every write made so far
+    fn sync(&self) -> io::Result<()>;
+
+    // Resizes the file to `len` bytes, filling it with zeroes when it grows
+    fn truncate(&self, len: u64) -> io::Result<()>;
+
+    fn size(&self) -> io::Result<u64>;
+
+    // Changes the lock held by this handle without waiting: a lock held by another handle makes it fail with
+    // `ErrorKind::WouldBlock`. Locks are released when the handle is dropped
+    fn lock(&self, mode: LockMode) -> io::Result<()>;
+
+    // Fills each buffer with the bytes at its offset, bytes past the end of the file reading as zeroes. Backends able
+    // to have several reads in flight at once override it
+    fn read_batch(&self, reads: &mut [(u64, &mut [u8])]) -> io::Result<()> {
+        for (offset, buf) in reads {
+            let mut read = 0;
+            while read < buf.len() {
+                match self.read_at(&mut buf[read..], *offset + read as u64) {
+                    Ok(0) => break,
+                    Ok(n) => read += n,
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                    Err(err) => return Err(err),
+                }
+            }
+            buf[read..].fill(0);
+        }
+
+        Ok(())
+    }
+
+    // Writes each buffer at its offset. Backends able to have several writes in flight at once override it
+    fn write_batch(&self, writes: &[(u64, &[u8])]) -> io::Result<()> {
+        for &(offset, buf) in writes {
+            self.write_all_at(buf, offset)?;
+        }
+
+        Ok(())
+    }
+
+    // Alignment that the buffers, offsets and lengths of every read and write must respect once direct I/O is enabled.
+    // Returns `None` when the file cannot be accessed with direct I/O
+    fn direct_io_alignment(&self) -> io::Result<Option<usize>> {
+        Ok(None)
+    }
+
+    // Makes reads and writes bypass the OS page cache, or go through it again
+    fn set_direct_io(&self, _enabled: bool) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Direct I/O is not supported by this file system",
+        ))
+    }
+
+    // Maps the first `len` bytes of the file into memory, which must not go past its end. Returns `None` when the
+    // backend cannot map files, in which case callers fall back to `read_at`
+    fn map(&self, _len: u64) -> io::Result<Option<Box<dyn VfsMap>>> {
+        Ok(None)
+    }
+
+    fn read_exact_at(&self, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+        while !buf.is_empty() {
+            match self.read_at(buf, offset) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "failed to fill whole buffer",
+                    ))
+                }
+                Ok(n) => {
+                    buf = &mut buf[n..];
+                    offset += n as u64;
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_all_at(&self, mut buf: &[u8], mut offset: u64) -> io::Result<()> {
+        while !buf.is_empty() {
+            match self.write_at(buf, offset) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::WriteZero,
+                        "failed to write whole buffer",
+                    ))
+                }
+                Ok(n) => {
+                    buf = &buf[n..];
+                    offset += n as u64;
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Read-only view of the beginning of a file. Later writes to the file show through it, but the file must not shrink
+// below the length of the mapping while it is alive
+pub trait VfsMap: fmt::Debug + Send + Sync {
+    fn bytes(&self) -> &[u8];
+}
@@ -0,0 +1,205 @@
+use std::{
+    ffi::c_void,
+    fs::{self, File, OpenOptions, TryLockError},
+    io,
+    os::{fd::AsRawFd, unix::fs::FileExt},
+    path::Path,
+    ptr, slice,
+};
+
+use super::{LockMode, Vfs, VfsFile, VfsMap};
+
+// Files of the local file system
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UnixVfs;
+
+impl Vfs for UnixVfs {
+    fn open(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+        Ok(Box::new(UnixFile::open(path)?))
+    }
+
+    fn delete(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path)?;
+        sync_parent_dir(path)
+    }
+
+    fn exists(&self, path: &Path) -> io::Result<bool> {
+        path.try_exists()
+    }
+}
+
+#[derive(Debug)]
+pub(super) struct UnixFile {
+    pub(super) file: File,
+}
+
+impl UnixFile {
+    pub(super) fn open(path: &Path) -> io::Result<Self> {
+        let existed = path.exists();
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .truncate(false)
+            .write(true)
+            .open(path)?;
+        if !existed {
+            sync_parent_dir(path)?;
+        }
+
+        Ok(Self { file })
+    }
+}
+
+impl VfsFile for UnixFile {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        self.file.read_at(buf, offset)
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<usize> {
+        self.file.write_at(buf, offset)
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        self.file.sync_data()
+    }
+
+    fn truncate(&self, len: u64) -> io::Result<()> {
+        self.file.set_len(len)
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.file.metadata()?.len())
+    }
+
+    fn lock(&self, mode: LockMode) -> io::Result<()> {
+        let locked = match mode {
+            LockMode::Unlocked => return self.file.unlock(),
+            LockMode::Shared => self.file.try_lock_shared(),
+            LockMode::Exclusive => self.file.try_lock(),
+        };
+
+        match locked {
+            Ok(()) => Ok(()),
+            Err(TryLockError::WouldBlock) => Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                "The file is locked by someone else",
+            )),
+            Err(TryLockError::Error(err)) => Err(err),
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn direct_io_alignment(&self) -> io::Result<Option<usize>> {
+        // SAFETY: `statx` is plain old data, which the kernel fills
+        let mut stat: libc::statx = unsafe { std::mem::zeroed() };
+        // SAFETY: an empty path with `AT_EMPTY_PATH` targets the open file itself
+        let result = unsafe {
+            libc::statx(
+                self.file.as_raw_fd(),
+                c"".as_ptr(),
+                libc::AT_EMPTY_PATH,
+                libc::STATX_DIOALIGN,
+                &mut stat,
+            )
+        };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // Kernels older than 6.1 do not report the alignment, the file system block size is a safe guess as it is a
+        // multiple of the logical block size of the device
+        if stat.stx_mask & libc::STATX_DIOALIGN == 0 {
+            return Ok(Some(stat.stx_blksize as usize));
+        }
+        match stat.stx_dio_offset_align {
+            0 => Ok(None),
+            offset_align => Ok(Some(offset_align.max(stat.stx_dio_mem_align) as usize)),
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn set_direct_io(&self, enabled: bool) -> io::Result<()> {
+        let fd = self.file.as_raw_fd();
+        // SAFETY: plain system calls on a file descriptor owned by `self.file`
+        unsafe {
+            let flags = libc::fcntl(fd, libc::F_GETFL);
+            if flags < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let flags = match enabled {
+                true => flags | libc::O_DIRECT,
+                false => flags & !libc::O_DIRECT,
+            };
+            if libc::fcntl(fd, libc::F_SETFL, flags) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn map(&self, len: u64) -> io::Result<Option<Box<dyn VfsMap>>> {
+        let len = usize::try_from(len)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Mapping too large"))?;
+        if len == 0 {
+            return Ok(Some(Box::new(UnixMap {
+                addr: ptr::null_mut(),
+                len,
+            })));
+        }
+
+        // SAFETY: a fresh read-only shared mapping of our own file, which does not alias any Rust object
+        let addr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_SHARED,
+                self.file.as_raw_fd(),
+                0,
+            )
+        };
+        if addr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Some(Box::new(UnixMap { addr, len })))
+    }
+}
+
+#[derive(Debug)]
+struct UnixMap {
+    addr: *mut c_void,
+    len: usize,
+}
+
+// SAFETY: the mapping is read-only and owned by this struct, so sharing it between threads is fine
+unsafe impl Send for UnixMap {}
+unsafe impl Sync for UnixMap {}
+
+impl VfsMap for UnixMap {
+    fn bytes(&self) -> &[u8] {
+        if self.len == 0 {
+            return &[];
+        }
+        // SAFETY: `addr` points to `len` readable bytes for as long as the mapping is alive
+        unsafe { slice::from_raw_parts(self.addr as *const u8, self.len) }
+    }
+}
+
+impl Drop for UnixMap {
+    fn drop(&mut self) {
+        if self.len != 0 {
+            // SAFETY: `addr` and `len` describe a mapping created by `mmap` and not unmapped yet
+            unsafe { libc::munmap(self.addr, self.len) };
+        }
+    }
+}
+
+// Makes the creation or the removal of the file at `path` durable
+fn sync_parent_dir(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => File::open(dir)?.sync_all(),
+        _ => File::open(".")?.sync_all(),
+    }
+}
@@ -0,0 +1,447 @@
+use std::{
+    ffi::c_void,
+    io,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    path::Path,
+    ptr,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Mutex, MutexGuard, PoisonError,
+    },
+};
+
+use super::{unix::UnixFile, LockMode, UnixVfs, Vfs, VfsFile, VfsMap};
+
+// Operations in flight at once, larger batches are split
+const RING_ENTRIES: u32 = 64;
+
+// From the kernel's io_uring.h
+const IORING_OP_READ: u8 = 22;
+const IORING_OP_WRITE: u8 = 23;
+const IORING_ENTER_GETEVENTS: u32 = 1;
+const IORING_OFF_SQ_RING: libc::off_t = 0;
+const IORING_OFF_CQ_RING: libc::off_t = 0x8000000;
+const IORING_OFF_SQES: libc::off_t = 0x10000000;
+
+// Files of the local file system, like `UnixVfs`, with batches of reads and writes going through an io_uring. Files
+// are opened without a ring on kernels that do not support io_uring or forbid it, and then behave like `UnixVfs` ones.
+// A ring that fails is torn down, and its file goes on without it
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UringVfs;
+
+impl Vfs for UringVfs {
+    fn open(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+        Ok(Box::new(UringFile {
+            file: UnixFile::open(path)?,
+            ring: Mutex::new(Ring::new(RING_ENTRIES).ok()),
+        }))
+    }
+
+    fn delete(&self, path: &Path) -> io::Result<()> {
+        UnixVfs.delete(path)
+    }
+
+    fn exists(&self, path: &Path) -> io::Result<bool> {
+        UnixVfs.exists(path)
+    }
+}
+
+#[derive(Debug)]
+struct UringFile {
+    file: UnixFile,
+    ring: Mutex<Option<Ring>>,
+}
+
+impl VfsFile for UringFile {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        self.file.read_at(buf, offset)
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<usize> {
+        self.file.write_at(buf, offset)
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        self.file.sync()
+    }
+
+    fn truncate(&self, len: u64) -> io::Result<()> {
+        self.file.truncate(len)
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        self.file.size()
+    }
+
+    fn lock(&self, mode: LockMode) -> io::Result<()> {
+        self.file.lock(mode)
+    }
+
+    fn read_batch(&self, reads: &mut [(u64, &mut [u8])]) -> io::Result<()> {
+        let mut ring = lock(&self.ring);
+        let Some(entries) = ring.as_ref().map(|ring| ring.entries) else {
+            return self.file.read_batch(reads);
+        };
+
+        for batch in reads.chunks_mut(entries as usize) {
+            let Some(uring) = ring.as_mut() else {
+                self.file.read_batch(batch)?;
+                continue;
+            };
+            let ops: Vec<Op> = batch
+                .iter_mut()
+                .map(|(offset, buf)| Op {
+                    opcode: IORING_OP_READ,
+                    addr: buf.as_mut_ptr() as u64,
+                    len: buf.len() as u32,
+                    offset: *offset,
+                })
+                .collect();
+            // SAFETY: the buffers outlive the operations, which are all completed when `submit` returns
+            let Ok(results) = (unsafe { uring.submit(self.file.file.as_raw_fd(), &ops) }) else {
+                // Reads are done again without the ring, as it is not known which of them completed
+                *ring = None;
+                self.file.read_batch(batch)?;
+                continue;
+            };
+
+            for ((offset, buf), result) in batch.iter_mut().zip(results) {
+                let read = transferred(result)?;
+                // Short reads happen at the end of the file, or when interrupted, and are finished synchronously
+                if read < buf.len() {
+                    self.file
+                        .read_batch(&mut [(*offset + read as u64, &mut buf[read..])])?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_batch(&self, writes: &[(u64, &[u8])]) -> io::Result<()> {
+        let mut ring = lock(&self.ring);
+        let Some(entries) = ring.as_ref().map(|ring| ring.entries) else {
+            return self.file.write_batch(writes);
+        };
+
+        for batch in writes.chunks(entries as usize) {
+            let Some(uring) = ring.as_mut() else {
+                self.file.write_batch(batch)?;
+                continue;
+            };
+            let ops: Vec<Op> = batch
+                .iter()
+                .map(|&(offset, buf)| Op {
+                    opcode: IORING_OP_WRITE,
+                    addr: buf.as_ptr() as u64,
+                    len: buf.len() as u32,
+                    offset,
+                })
+                .collect();
+            // SAFETY: the buffers outlive the operations, which are all completed when `submit` returns
+            let Ok(results) = (unsafe { uring.submit(self.file.file.as_raw_fd(), &ops) }) else {
+                // Writing the same bytes again is harmless, whichever of them completed
+                *ring = None;
+                self.file.write_batch(batch)?;
+                continue;
+            };
+
+            for (&(offset, buf), result) in batch.iter().zip(results) {
+                let written = transferred(result)?;
+                if written < buf.len() {
+                    self.file
+                        .write_all_at(&buf[written..], offset + written as u64)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn direct_io_alignment(&self) -> io::Result<Option<usize>> {
+        self.file.direct_io_alignment()
+    }
+
+    fn set_direct_io(&self, enabled: bool) -> io::Result<()> {
+        self.file.set_direct_io(enabled)
+    }
+
+    fn map(&self, len: u64) -> io::Result<Option<Box<dyn VfsMap>>> {
+        self.file.map(len)
+    }
+}
+
+// Bytes transferred by a completed operation, which reports errors as negated errno values
+fn transferred(result: i32) -> io::Result<usize> {
+    match result {
+        0.. => Ok(result as usize),
+        _ => Err(io::Error::from_raw_os_error(-result)),
+    }
+}
+
+struct Op {
+    opcode: u8,
+    addr: u64,
+    len: u32,
+    offset: u64,
+}
+
+// The kernel's `io_uring_sqe`, restricted to the fields used by plain reads and writes
+#[repr(C)]
+#[derive(Default)]
+struct Sqe {
+    opcode: u8,
+    flags: u8,
+    ioprio: u16,
+    fd: i32,
+    off: u64,
+    addr: u64,
+    len: u32,
+    rw_flags: u32,
+    user_data: u64,
+    buf_index: u16,
+    personality: u16,
+    splice_fd_in: i32,
+    addr3: u64,
+    pad: u64,
+}
+
+#[repr(C)]
+struct Cqe {
+    user_data: u64,
+    res: i32,
+    flags: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct SqRingOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    flags: u32,
+    dropped: u32,
+    array: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct CqRingOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    overflow: u32,
+    cqes: u32,
+    flags: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct Params {
+    sq_entries: u32,
+    cq_entries: u32,
+    flags: u32,
+    sq_thread_cpu: u32,
+    sq_thread_idle: u32,
+    features: u32,
+    wq_fd: u32,
+    resv: [u32; 3],
+    sq_off: SqRingOffsets,
+    cq_off: CqRingOffsets,
+}
+
+// An io_uring instance: a submission queue of operations for the kernel and a completion queue of their results,
+// both shared with the kernel through memory mappings
+#[derive(Debug)]
+struct Ring {
+    fd: OwnedFd,
+    entries: u32,
+    sq: RingMap,
+    cq: RingMap,
+    sqes: RingMap,
+    sq_off: SqRingOffsets,
+    cq_off: CqRingOffsets,
+}
+
+// SAFETY: the mappings are only touched through `&mut Ring`, and the ring itself can be used from any thread
+unsafe impl Send for Ring {}
+unsafe impl Sync for Ring {}
+
+impl Ring {
+    fn new(entries: u32) -> io::Result<Self> {
+        let mut params = Params::default();
+        // SAFETY: `params` is a valid `io_uring_params` for the kernel to fill
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_io_uring_setup,
+                entries,
+                &mut params as *mut Params,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: the file descriptor was just returned by the kernel and is owned by nobody else
+        let fd = unsafe { OwnedFd::from_raw_fd(fd as RawFd) };
+
+        let sq_len = params.sq_off.array as usize + params.sq_entries as usize * 4;
+        let cq_len = params.cq_off.cqes as usize + params.cq_entries as usize * size_of::<Cqe>();
+        let sqes_len = params.sq_entries as usize * size_of::<Sqe>();
+
+        Ok(Self {
+            sq: RingMap::new(&fd, sq_len, IORING_OFF_SQ_RING)?,
+            cq: RingMap::new(&fd, cq_len, IORING_OFF_CQ_RING)?,
+            sqes: RingMap::new(&fd, sqes_len, IORING_OFF_SQES)?,
+            fd,
+            entries: params.sq_entries,
+            sq_off: params.sq_off,
+            cq_off: params.cq_off,
+        })
+    }
+
+    // Submits `ops` on `fd` and waits for all of them to complete. Returns their results, in the same order
+    // On error, the entries the kernel did not take are left in the submission queue, so the ring must not be used
+    // anymore
+    // SAFETY: the buffers of `ops` must stay valid until the call returns, and there can be at most `entries` of them
+    unsafe fn submit(&mut self, fd: RawFd, ops: &[Op]) -> io::Result<Vec<i32>> {
+        let count = ops.len() as u32;
+        assert!(count <= self.entries, "Too many operations for the ring");
+
+        // The kernel consumes every submitted entry before completing it, so the submission queue never fills up
+        let sq_tail = self.sq.atomic(self.sq_off.tail);
+        let sq_mask = *self.sq.at::<u32>(self.sq_off.ring_mask);
+        let sq_array = self.sq.at::<u32>(self.sq_off.array);
+        let sqes = self.sqes.at::<Sqe>(0);
+
+        let first = sq_tail.load(Ordering::Acquire);
+        for (i, op) in ops.iter().enumerate() {
+            let index = first.wrapping_add(i as u32) & sq_mask;
+            sqes.add(index as usize).write(Sqe {
+                opcode: op.opcode,
+                fd,
+                off: op.offset,
+                addr: op.addr,
+                len: op.len,
+                user_data: i as u64,
+                ..Sqe::default()
+            });
+            sq_array.add(index as usize).write(index);
+        }
+        // Publishes the entries to the kernel
+        sq_tail.store(first.wrapping_add(count), Ordering::Release);
+
+        let cq_head = self.cq.atomic(self.cq_off.head);
+        let cq_tail = self.cq.atomic(self.cq_off.tail);
+        let cq_mask = *self.cq.at::<u32>(self.cq_off.ring_mask);
+        let cqes = self.cq.at::<Cqe>(self.cq_off.cqes);
+
+        let mut results = vec![0; ops.len()];
+        let mut to_submit = count;
+        let mut completed = 0;
+        // Once submitting fails, the entries the kernel already took may still be using their buffers. They are waited
+        // for before returning the error
+        let mut failure = None;
+        loop {
+            let expected = match failure {
+                Some(_) => count - to_submit,
+                None => count,
+            };
+            if completed >= expected {
+                break;
+            }
+
+            let submitted = libc::syscall(
+                libc::SYS_io_uring_enter,
+                self.fd.as_raw_fd(),
+                if failure.is_some() { 0 } else { to_submit },
+                expected - completed,
+                IORING_ENTER_GETEVENTS,
+                ptr::null::<c_void>(),
+                0,
+            );
+            if submitted < 0 {
+                let err = io::Error::last_os_error();
+                match err.raw_os_error() {
+                    Some(libc::EINTR | libc::EAGAIN | libc::EBUSY) => {}
+                    _ if failure.is_none() => failure = Some(err),
+                    // Waiting failed too, the caller tears the ring down
+                    _ => return Err(err),
+                }
+            } else {
+                to_submit -= submitted as u32;
+            }
+
+            let mut head = cq_head.load(Ordering::Relaxed);
+            let tail = cq_tail.load(Ordering::Acquire);
+            while head != tail {
+                let cqe = &*cqes.add((head & cq_mask) as usize);
+                results[cqe.user_data as usize] = cqe.res;
+                head = head.wrapping_add(1);
+                completed += 1;
+            }
+            // Hands the completion entries back to the kernel
+            cq_head.store(head, Ordering::Release);
+        }
+
+        match failure {
+            Some(err) => Err(err),
+            None => Ok(results),
+        }
+    }
+}
+
+// Memory shared with the kernel by an io_uring
+#[derive(Debug)]
+struct RingMap {
+    addr: *mut c_void,
+    len: usize,
+}
+
+impl RingMap {
+    fn new(fd: &OwnedFd, len: usize, offset: libc::off_t) -> io::Result<Self> {
+        // SAFETY: a fresh shared mapping of the ring, which does not alias any Rust object
+        let addr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_POPULATE,
+                fd.as_raw_fd(),
+                offset,
+            )
+        };
+        if addr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self { addr, len })
+    }
+
+    // SAFETY: `offset` must be where the kernel said a `T` lives
+    unsafe fn at<T>(&self, offset: u32) -> *mut T {
+        self.addr.add(offset as usize) as *mut T
+    }
+
+    // Ring indices are updated concurrently by the kernel
+    // SAFETY: `offset` must be where the kernel said the index lives
+    unsafe fn atomic(&self, offset: u32) -> &AtomicU32 {
+        &*self.at::<AtomicU32>(offset)
+    }
+}
+
+impl Drop for RingMap {
+    fn drop(&mut self) {
+        // SAFETY: `addr` and `len` describe a mapping created by `mmap` and not unmapped yet
+        unsafe { libc::munmap(self.addr, self.len) };
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
@@ -0,0 +1,362 @@
+//! Write-ahead log. In WAL mode, pages are never overwritten in the heap file when they are written back: they are
+//! appended as frames to a `<db>-wal` file next to it, and only copied to the heap file by checkpoints
+//! The log starts with a header followed by frames, each holding one page:
+//! header: | magic (4) | version (4) | page size (4) | checkpoint sequence (4) | salt (2 * 4) | checksum (2 * 4) |
+//! frame:  | page number (4) | db size (4) | salt (2 * 4) | checksum (2 * 4) | page data |
+//! A frame whose db size is not 0 marks the end of a commit and records the page count of the database at that point.
+//! Frames that come after the last commit frame are ignored when the log is reopened, which makes commits atomic
+//! Checksums are cumulative, each frame's one covering the previous one, and salts change every time the log is reset.
+//! Both let us tell valid frames from garbage left by a torn write or by an earlier generation of the log
+//! The WAL index maps every page found in the log to its latest frame. As we are the only process using the database,
+//! it lives in memory and is rebuilt from the log when opening it, instead of sitting in shared memory like SQLite's
+
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    fmt,
+    hash::{BuildHasher, Hasher},
+    io, mem,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{
+    pager::PageNumber,
+    vfs::{AlignedBuf, Vfs, VfsFile},
+};
+
+pub const WAL_MAGIC_NUMBER: u32 = 0x50484457;
+pub const WAL_VERSION: u32 = 1;
+pub const WAL_HEADER_SIZE: usize = 32;
+pub const FRAME_HEADER_SIZE: usize = 24;
+// Number of frames after which a commit triggers a passive checkpoint
+pub const DEFAULT_AUTOCHECKPOINT: u32 = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckpointMode {
+    // Copies the committed frames that are not in the heap file yet, and leaves the log as it is
+    Passive,
+    // Copies every committed frame, then resets the log so that new frames are written from its beginning
+    Full,
+    // Like `Full`, and also truncates the log file to zero bytes
+    Truncate,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CheckpointResult {
+    // Frames in the log before the checkpoint
+    pub log_frames: u32,
+    // Frames that have been copied to the heap file, by this checkpoint or earlier ones
+    pub checkpointed_frames: u32,
+}
+
+pub struct Wal {
+    vfs: Arc<dyn Vfs>,
+    file: Box<dyn VfsFile>,
+    path: PathBuf,
+    page_size: usize,
+    checkpoint_seq: u32,
+    salt: [u32; 2],
+    // Checksum of the last frame written, which the next one builds upon
+    checksum: [u32; 2],
+    // Checksum of the last commit frame, which a rollback goes back to
+    committed_checksum: [u32; 2],
+    // Page held by each frame, committed or not
+    frames: Vec<PageNumber>,
+    // Number of frames that belong to committed transactions
+    committed: u32,
+    // Number of committed frames already copied to the heap file
+    backfilled: u32,
+    // Page count of the database as of the last commit
+    db_size: u32,
+    index: HashMap<PageNumber, u32>,
+}
+
+impl Wal {
+    // Opens the log at `path`, replaying the frames of the transactions it holds, or creates an empty one
+    pub fn open(vfs: Arc<dyn Vfs>, path: &Path, page_size: usize) -> io::Result<Self> {
+        let file = vfs.open(path)?;
+
+        let mut wal = Self {
+            vfs,
+            file,
+            path: path.to_path_buf(),
+            page_size,
+            checkpoint_seq: 0,
+            salt: [0; 2],
+            checksum: [0; 2],
+            committed_checksum: [0; 2],
+            frames: vec![],
+            committed: 0,
+            backfilled: 0,
+            db_size: 0,
+            index: HashMap::new(),
+        };
+
+        if !wal.recover()? {
+            wal.reset()?;
+        }
+
+        Ok(wal)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Frame holding the latest version of `page_no`, if the log has one
+    pub fn find(&self, page_no: PageNumber) -> Option<u32> {
+        self.index.get(&page_no).copied()
+    }
+
+    pub fn frame_count(&self) -> u32 {
+        self.frames.len() as u32
+    }
+
+    // Page count of the database as of the last commit, 0 when nothing was committed since the last reset
+    pub fn db_size(&self) -> u32 {
+        self.db_size
+    }
+
+    // Whether frames were appended since the last commit
+    pub fn has_uncommitted_frames(&self) -> bool {
+        self.frames.len() as u32 > self.committed
+    }
+
+    pub fn read_frame(&self, frame: u32, buf: &mut [u8]) -> io::Result<()> {
+        self.file
+            .read_exact_at(buf, self.frame_offset(frame) + FRAME_HEADER_SIZE as u64)
+    }
+
+    // Appends a frame holding `data` as the new content of `page_no`. A non-zero `db_size` commits the transaction,
+    // although it is only durable once the log has been synced
+    pub fn append(&mut self, page_no: PageNumber, data: &[u8], db_size: u32) -> io::Result<()> {
+        // Once every frame has been checkpointed, the log restarts from its beginning instead of growing forever
+        if !self.frames.is_empty() && self.backfilled == self.frames.len() as u32 {
+            self.checkpoint_seq = self.checkpoint_seq.wrapping_add(1);
+            self.reset()?;
+        }
+        // A truncating checkpoint leaves the file without a header, which has to be back before the first frame
+        if self.frames.is_empty() && self.file.size()? < WAL_HEADER_SIZE as u64 {
+            self.reset()?;
+        }
+
+        let frame = self.frames.len() as u32;
+        let mut header = [0_u8; FRAME_HEADER_SIZE];
+        header[0..4].copy_from_slice(&page_no.to_le_bytes());
+        header[4..8].copy_from_slice(&db_size.to_le_bytes());
+        header[8..12].copy_from_slice(&self.salt[0].to_le_bytes());
+        header[12..16].copy_from_slice(&self.salt[1].to_le_bytes());
+
+        let checksum = checksum(data, checksum(&header[..8], self.checksum));
+        header[16..20].copy_from_slice(&checksum[0].to_le_bytes());
+        header[20..24].copy_from_slice(&checksum[1].to_le_bytes());
+
+        let offset = self.frame_offset(frame);
+        self.file.write_all_at(&header, offset)?;
+        self.file
+            .write_all_at(data, offset + FRAME_HEADER_SIZE as u64)?;
+
+        self.checksum = checksum;
+        self.frames.push(page_no);
+        self.index.insert(page_no, frame);
+        if db_size != 0 {
+            self.committed = self.frames.len() as u32;
+            self.committed_checksum = checksum;
+            self.db_size = db_size;
+        }
+
+        Ok(())
+    }
+
+    pub fn sync(&self) -> io::Result<()> {
+        self.file.sync()
+    }
+
+    // Forgets the frames appended since the last commit. The next frames overwrite them in the file
+    pub fn rollback(&mut self) {
+        self.frames.truncate(self.committed as usize);
+        self.checksum = self.committed_checksum;
+        self.index.clear();
+        for (frame, &page_no) in self.frames.iter().enumerate() {
+            self.index.insert(page_no, frame as u32);
+        }
+    }
+
+    // Copies the latest committed version of every page in the log to `db`. Uncommitted frames are left alone
+    pub fn checkpoint(
+        &mut self,
+        db: &dyn VfsFile,
+        mode: CheckpointMode,
+    ) -> io::Result<CheckpointResult> {
+        let log_frames = self.frames.len() as u32;
+        let mut latest = HashMap::new();
+        for frame in self.backfilled..self.committed {
+            latest.insert(self.frames[frame as usize], frame);
+        }
+        let mut latest: Vec<(PageNumber, u32)> = latest.into_iter().collect();
+        latest.sort_unstable();
+
+        // The log was synced when its frames were committed, so it can be trusted before the heap file is touched
+        let mut buf = AlignedBuf::zeroed(self.page_size);
+        for (page_no, frame) in latest {
+            self.read_frame(frame, &mut buf)?;
+            db.write_all_at(&buf, page_no as u64 * self.page_size as u64)?;
+        }
+        if self.committed > self.backfilled && self.db_size != 0 {
+            db.truncate(self.db_size as u64 * self.page_size as u64)?;
+        }
+        db.sync()?;
+        self.backfilled = self.committed;
+
+        let result = CheckpointResult {
+            log_frames,
+            checkpointed_frames: self.backfilled,
+        };
+
+        if mode != CheckpointMode::Passive && !self.has_uncommitted_frames() {
+            self.checkpoint_seq = self.checkpoint_seq.wrapping_add(1);
+            self.reset()?;
+            if mode == CheckpointMode::Truncate {
+                self.file.truncate(0)?;
+                self.file.sync()?;
+            }
+        }
+
+        Ok(result)
+    }
+
+    // Removes the log file. Its frames must have been checkpointed beforehand
+    pub fn delete(self) -> io::Result<()> {
+        self.vfs.delete(&self.path)
+    }
+
+    fn frame_offset(&self, frame: u32) -> u64 {
+        WAL_HEADER_SIZE as u64 + frame as u64 * (FRAME_HEADER_SIZE + self.page_size) as u64
+    }
+
+    // Starts a new generation of the log. New salts make the frames of the previous one invalid
+    fn reset(&mut self) -> io::Result<()> {
+        let random = RandomState::new().build_hasher().finish();
+        self.salt = [random as u32, (random >> 32) as u32];
+
+        let mut header = [0_u8; WAL_HEADER_SIZE];
+        header[0..4].copy_from_slice(&WAL_MAGIC_NUMBER.to_le_bytes());
+        header[4..8].copy_from_slice(&WAL_VERSION.to_le_bytes());
+        header[8..12].copy_from_slice(&(self.page_size as u32).to_le_bytes());
+        header[12..16].copy_from_slice(&self.checkpoint_seq.to_le_bytes());
+        header[16..20].copy_from_slice(&self.salt[0].to_le_bytes());
+        header[20..24].copy_from_slice(&self.salt[1].to_le_bytes());
+        self.checksum = checksum(&header[..24], [0, 0]);
+        self.committed_checksum = self.checksum;
+        header[24..28].copy_from_slice(&self.checksum[0].to_le_bytes());
+        header[28..32].copy_from_slice(&self.checksum[1].to_le_bytes());
+
+        self.file.write_all_at(&header, 0)?;
+        self.file.sync()?;
+
+        self.frames.clear();
+        self.committed = 0;
+        self.backfilled = 0;
+        self.db_size = 0;
+        self.index.clear();
+
+        Ok(())
+    }
+
+    // Reads back the frames of the committed transactions. Returns false when the log has no valid header
+    fn recover(&mut self) -> io::Result<bool> {
+        let len = self.file.size()?;
+        if len < WAL_HEADER_SIZE as u64 {
+            return Ok(false);
+        }
+
+        let mut header = [0_u8; WAL_HEADER_SIZE];
+        self.file.read_exact_at(&mut header, 0)?;
+        let word = |offset: usize| {
+            u32::from_le_bytes(header[offset..offset + 4].try_into().expect("Invalid size"))
+        };
+        let header_checksum = checksum(&header[..24], [0, 0]);
+        if word(0) != WAL_MAGIC_NUMBER
+            || word(4) != WAL_VERSION
+            || word(8) as usize != self.page_size
+            || [word(24), word(28)] != header_checksum
+        {
+            return Ok(false);
+        }
+
+        self.checkpoint_seq = word(12);
+        self.salt = [word(16), word(20)];
+        self.checksum = header_checksum;
+        self.committed_checksum = header_checksum;
+
+        let mut frames = vec![];
+        let mut index = HashMap::new();
+        let mut frame_header = [0_u8; FRAME_HEADER_SIZE];
+        let mut data = vec![0; self.page_size];
+        let mut checksum_so_far = self.checksum;
+
+        loop {
+            let frame = frames.len() as u32;
+            let offset = self.frame_offset(frame);
+            if offset + (FRAME_HEADER_SIZE + self.page_size) as u64 > len {
+                break;
+            }
+            self.file.read_exact_at(&mut frame_header, offset)?;
+            self.file
+                .read_exact_at(&mut data, offset + FRAME_HEADER_SIZE as u64)?;
+
+            let word = |offset: usize| {
+                u32::from_le_bytes(
+                    frame_header[offset..offset + 4]
+                        .try_into()
+                        .expect("Invalid size"),
+                )
+            };
+            let expected = checksum(&data, checksum(&frame_header[..8], checksum_so_far));
+            if [word(8), word(12)] != self.salt || [word(16), word(20)] != expected {
+                break;
+            }
+
+            checksum_so_far = expected;
+            frames.push(word(0));
+            index.insert(word(0), frame);
+
+            // Frames only become visible once the commit frame closing their transaction is found
+            if word(4) != 0 {
+                self.frames.extend_from_slice(&frames[self.frames.len()..]);
+                self.index.extend(index.drain());
+                self.committed = frames.len() as u32;
+                self.db_size = word(4);
+                self.checksum = checksum_so_far;
+                self.committed_checksum = checksum_so_far;
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+impl fmt::Debug for Wal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Wal")
+            .field("path", &self.path)
+            .field("frames", &self.frames.len())
+            .field("committed", &self.committed)
+            .field("backfilled", &self.backfilled)
+            .finish()
+    }
+}
+
+// Fletcher-like checksum used by SQLite for its WAL, computed over 32-bit little-endian words and chained from `seed`
+pub(crate) fn checksum(data: &[u8], seed: [u32; 2]) -> [u32; 2] {
+    let [mut s0, mut s1] = seed;
+
+    for chunk in data.chunks_exact(2 * mem::size_of::<u32>()) {
+        let x0 = u32::from_le_bytes(chunk[0..4].try_into().expect("Invalid size"));
+        let x1 = u32::from_le_bytes(chunk[4..8].try_into().expect("Invalid size"));
+        s0 = s0.wrapping_add(x0).wrapping_add(s1);
+        s1 = s1.wrapping_add(x1).wrapping_add(s0);
+    }
+
+    [s0, s1]
+}
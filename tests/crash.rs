@@ -0,0 +1,59 @@
+use phdb::{
+    crash_test,
+    vfs::{FaultConfig, Rng},
+};
+
+// Covers both journal modes, with and without auto-vacuum
+const SEEDS: std::ops::Range<u64> = 0..4;
+
+// Fails an operation of about half of the steps, so that the power loss can also hit in the middle of a transaction
+fn interrupt(rng: &mut Rng) -> Option<u64> {
+    match rng.below(2) {
+        0 => Some(rng.below(200)),
+        _ => None,
+    }
+}
+
+fn run(faults: impl Fn(&mut Rng) -> FaultConfig + Copy) {
+    for seed in SEEDS {
+        if let Err(err) = crash_test::run(seed, faults) {
+            panic!("seed {seed}: {err}");
+        }
+    }
+}
+
+#[test]
+fn dropped_unsynced_writes() {
+    run(|rng| FaultConfig {
+        drop_unsynced: true,
+        fail_after: interrupt(rng),
+        ..FaultConfig::default()
+    });
+}
+
+#[test]
+fn torn_writes() {
+    run(|rng| FaultConfig {
+        tear_writes: true,
+        fail_after: interrupt(rng),
+        ..FaultConfig::default()
+    });
+}
+
+// Writes a failed sync was meant to make durable are lost in the following power loss
+#[test]
+fn failed_sync() {
+    run(|rng| FaultConfig {
+        drop_unsynced: true,
+        fail_sync: rng.below(3) == 0,
+        ..FaultConfig::default()
+    });
+}
+
+#[test]
+fn io_error_after_operations() {
+    run(|rng| FaultConfig {
+        fail_after: Some(rng.below(200)),
+        ..FaultConfig::default()
+    });
+}
@@ -0,0 +1,146 @@
+use std::{io, sync::Arc};
+
+use phdb::{
+    btree::{BTree, TreeKind},
+    connection::Connection,
+    integrity,
+    pager::{AutoVacuum, Pager},
+    pointer_map::PointerMapEntry,
+    value::Value,
+    vfs::MemoryVfs,
+};
+
+const PAGE_SIZE: u32 = 512;
+
+fn open(auto_vacuum: AutoVacuum) -> Pager {
+    let mut pager =
+        Pager::open_with_vfs(Arc::new(MemoryVfs::new()), "test.db", PAGE_SIZE, 16).unwrap();
+    pager.init().unwrap();
+    pager.set_auto_vacuum(auto_vacuum).unwrap();
+    pager
+}
+
+// A tree of several levels, with records spilling over to overflow pages
+fn fill(pager: &mut Pager) -> BTree {
+    let tree = BTree::create(pager, TreeKind::Table).unwrap();
+    for rowid in 0..300 {
+        let len = match rowid % 10 {
+            0 => 2000,
+            _ => 40,
+        };
+        tree.insert(pager, rowid, &vec![rowid as u8; len]).unwrap();
+    }
+    for rowid in (0..300).step_by(3) {
+        tree.delete(pager, rowid).unwrap();
+    }
+    tree
+}
+
+fn error_of(pager: &mut Pager, roots: &[u32]) -> String {
+    let err = integrity::check(pager, roots).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{err}");
+    err.to_string()
+}
+
+#[test]
+fn sound_databases() {
+    for auto_vacuum in [AutoVacuum::None, AutoVacuum::Full] {
+        let mut pager = open(auto_vacuum);
+        let tree = fill(&mut pager);
+        assert!(pager.freelist_count() > 0);
+        integrity::check(&mut pager, &[tree.root_page()]).unwrap();
+        pager.flush().unwrap();
+        integrity::check(&mut pager, &[tree.root_page()]).unwrap();
+    }
+
+    // Trees of the catalog are found without being listed
+    let mut connection =
+        Connection::open_with_vfs(Arc::new(MemoryVfs::new()), "test.db", 1024, 16).unwrap();
+    connection
+        .execute(
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT UNIQUE)",
+            &[],
+        )
+        .unwrap();
+    for i in 0..500 {
+        connection
+            .execute(
+                "INSERT INTO t (name) VALUES (?)",
+                &[Value::Text(format!("{i:0>100}"))],
+            )
+            .unwrap();
+    }
+    integrity::check(connection.pager_mut(), &[]).unwrap();
+}
+
+#[test]
+fn unreachable_and_shared_pages() {
+    let mut pager = open(AutoVacuum::None);
+    let tree = fill(&mut pager);
+    let root = tree.root_page();
+
+    // Pages of a tree that is not listed belong to nothing
+    assert_eq!(
+        error_of(&mut pager, &[]),
+        format!("Page {root} is neither in a tree nor free")
+    );
+
+    let lost = pager.allocate_page().unwrap();
+    assert_eq!(
+        error_of(&mut pager, &[root]),
+        format!("Page {lost} is neither in a tree nor free")
+    );
+    pager.free_page(lost).unwrap();
+    integrity::check(&mut pager, &[root]).unwrap();
+
+    // A page of the tree that is also free
+    let page_no = tree.check(&mut pager).unwrap()[1].0;
+    pager.free_page(page_no).unwrap();
+    assert_eq!(
+        error_of(&mut pager, &[root]),
+        format!("Page {page_no} is used twice")
+    );
+}
+
+#[test]
+fn broken_trees_and_pointer_maps() {
+    let mut pager = open(AutoVacuum::Full);
+    let tree = fill(&mut pager);
+    let root = tree.root_page();
+    let pages = tree.check(&mut pager).unwrap();
+
+    // The pointer map must name the actual parent of each page
+    let (page_no, entry) = pages[1];
+    assert!(matches!(entry, PointerMapEntry::Child(_)));
+    pager
+        .set_pointer_map_entry(page_no, PointerMapEntry::Child(root + 100))
+        .unwrap();
+    let err = error_of(&mut pager, &[root]);
+    assert!(
+        err.starts_with(&format!("The pointer map records Child({})", root + 100)),
+        "{err}"
+    );
+    pager.set_pointer_map_entry(page_no, entry).unwrap();
+    integrity::check(&mut pager, &[root]).unwrap();
+
+    // Overflow chains must be as long as their record needs
+    let (first, _) = *pages
+        .iter()
+        .find(|(_, entry)| matches!(entry, PointerMapEntry::FirstOverflow(_)))
+        .unwrap();
+    let mut next = [0_u8; 4];
+    pager.read(first, &mut next).unwrap();
+    pager.write(first, &[0; 4]).unwrap();
+    let err = error_of(&mut pager, &[root]);
+    assert!(err.contains("Overflow chain of 1 pages"), "{err}");
+    pager.write(first, &next).unwrap();
+    integrity::check(&mut pager, &[root]).unwrap();
+
+    // As must child pages be pages of the tree
+    pager.write(page_no, &vec![0; pager.usable_size()]).unwrap();
+    let err = error_of(&mut pager, &[root]);
+    assert!(
+        err.contains(&format!("Corrupted B-tree page {page_no}")),
+        "{err}"
+    );
+}
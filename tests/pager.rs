@@ -0,0 +1,362 @@
+use std::{io, path::Path, sync::Arc};
+
+use phdb::{
+    header::{self, DbHeader, MAGIC_NUMBER},
+    pager::{JournalMode, Pager},
+    vfs::{MemoryVfs, Vfs},
+};
+
+const PAGE_SIZE: u32 = 1024;
+
+fn open(vfs: &MemoryVfs) -> Pager {
+    // A tiny cache, so that dirty pages get written back before committing
+    let mut pager = Pager::open_with_vfs(Arc::new(vfs.clone()), "test.db", PAGE_SIZE, 4).unwrap();
+    pager.init().unwrap();
+    pager
+}
+
+fn file_pages(vfs: &MemoryVfs) -> u64 {
+    vfs.open(Path::new("test.db")).unwrap().size().unwrap() / PAGE_SIZE as u64
+}
+
+fn write_pages(pager: &mut Pager, pages: &[u32], fill: u8) {
+    // The end of pages is reserved for their checksum
+    let usable_size = pager.usable_size();
+    for &page_no in pages {
+        pager.write(page_no, &vec![fill; usable_size]).unwrap();
+    }
+}
+
+fn allocate_pages(pager: &mut Pager, count: usize, fill: u8) -> Vec<u32> {
+    let pages: Vec<u32> = (0..count).map(|_| pager.allocate_page().unwrap()).collect();
+    write_pages(pager, &pages, fill);
+    pages
+}
+
+// The first pages written back by the transaction are all appended to the file, before any page it had is overwritten
+#[test]
+fn hot_journal_rollback_after_append_only_write_back() {
+    let vfs = MemoryVfs::new();
+    let mut pager = open(&vfs);
+    pager.set_journal_mode(JournalMode::Rollback).unwrap();
+    let pages = allocate_pages(&mut pager, 20, 1);
+    pager.flush().unwrap();
+    let committed = file_pages(&vfs);
+
+    allocate_pages(&mut pager, 10, 2);
+    assert!(file_pages(&vfs) > committed);
+    write_pages(&mut pager, &pages, 3);
+    // Crashes without committing, leaving a hot journal
+    drop(pager);
+
+    let mut pager = open(&vfs);
+    assert_eq!(file_pages(&vfs), committed);
+    assert_eq!(pager.page_count() as u64, committed);
+    let mut buf = vec![0; pager.usable_size()];
+    for &page_no in &pages {
+        pager.read(page_no, &mut buf).unwrap();
+        assert!(buf.iter().all(|&byte| byte == 1));
+    }
+}
+
+// A transaction that only appended pages so far leaves them past the committed end of the file when it crashes
+#[test]
+fn hot_journal_rollback_of_appended_pages() {
+    let vfs = MemoryVfs::new();
+    let mut pager = open(&vfs);
+    pager.set_journal_mode(JournalMode::Rollback).unwrap();
+    allocate_pages(&mut pager, 20, 1);
+    pager.flush().unwrap();
+    let committed = file_pages(&vfs);
+
+    // Each allocation writes the header, so that page 0 stays cached while the new pages are written back
+    for _ in 0..10 {
+        let page_no = pager.allocate_page().unwrap();
+        write_pages(&mut pager, &[page_no], 2);
+    }
+    assert!(file_pages(&vfs) > committed);
+    drop(pager);
+
+    let pager = open(&vfs);
+    assert_eq!(file_pages(&vfs), committed);
+    assert_eq!(pager.page_count() as u64, committed);
+}
+
+#[test]
+fn rollback_of_appended_pages() {
+    let vfs = MemoryVfs::new();
+    let mut pager = open(&vfs);
+    pager.set_journal_mode(JournalMode::Rollback).unwrap();
+    allocate_pages(&mut pager, 20, 1);
+    pager.flush().unwrap();
+    let committed = file_pages(&vfs);
+
+    allocate_pages(&mut pager, 10, 2);
+    assert!(file_pages(&vfs) > committed);
+    pager.rollback().unwrap();
+    assert_eq!(file_pages(&vfs), committed);
+    assert_eq!(pager.page_count() as u64, committed);
+}
+
+fn max_leaves(pager: &Pager) -> usize {
+    // A trunk holds the next trunk and its leaf count, then 4 bytes per leaf
+    (pager.usable_size() - 8) / 4
+}
+
+#[test]
+fn free_list_round_trip() {
+    let vfs = MemoryVfs::new();
+    let mut pager = open(&vfs);
+    let pages = allocate_pages(&mut pager, 10, 1);
+    let page_count = pager.page_count();
+
+    for &page_no in &pages {
+        pager.free_page(page_no).unwrap();
+    }
+    assert_eq!(pager.freelist_count(), 10);
+    assert_eq!(pager.page_count(), page_count);
+    pager.flush().unwrap();
+    drop(pager);
+
+    let mut pager = open(&vfs);
+    assert_eq!(pager.freelist_count(), 10);
+    let mut reused: Vec<u32> = (0..10).map(|_| pager.allocate_page().unwrap()).collect();
+    reused.sort();
+    assert_eq!(reused, pages);
+    assert_eq!(pager.freelist_count(), 0);
+    assert_eq!(pager.page_count(), page_count);
+    // Reused pages come back zeroed
+    let mut buf = vec![1; pager.usable_size()];
+    for &page_no in &pages {
+        pager.read(page_no, &mut buf).unwrap();
+        assert!(buf.iter().all(|&byte| byte == 0));
+    }
+
+    assert_eq!(pager.allocate_page().unwrap(), page_count);
+    assert_eq!(pager.page_count(), page_count + 1);
+}
+
+// Freeing more pages than a trunk has room for starts a second trunk, which becomes the head of the list
+#[test]
+fn free_list_trunk_overflow() {
+    let vfs = MemoryVfs::new();
+    let mut pager = open(&vfs);
+    let count = max_leaves(&pager) + 50;
+    let pages = allocate_pages(&mut pager, count, 1);
+    let page_count = pager.page_count();
+
+    for &page_no in &pages {
+        pager.free_page(page_no).unwrap();
+    }
+    // The first freed page is the first trunk, and the one freed once it is full the second
+    let second_trunk = pages[1 + max_leaves(&pager)];
+    assert_eq!(pager.header().freelist_head, second_trunk);
+    assert_eq!(pager.freelist_count() as usize, count);
+    pager.flush().unwrap();
+    drop(pager);
+
+    let mut pager = open(&vfs);
+    let mut reused: Vec<u32> = (0..count).map(|_| pager.allocate_page().unwrap()).collect();
+    assert_eq!(pager.header().freelist_head, 0);
+    assert_eq!(pager.freelist_count(), 0);
+    assert_eq!(pager.page_count(), page_count);
+    reused.sort();
+    assert_eq!(reused, pages);
+}
+
+#[test]
+fn free_pages_are_reused_before_the_file_grows() {
+    let vfs = MemoryVfs::new();
+    let mut pager = open(&vfs);
+    let pages = allocate_pages(&mut pager, 30, 1);
+    let page_count = pager.page_count();
+    for &page_no in pages.iter().step_by(3) {
+        pager.free_page(page_no).unwrap();
+    }
+
+    let mut reused: Vec<u32> = (0..10).map(|_| pager.allocate_page().unwrap()).collect();
+    reused.sort();
+    assert_eq!(reused, pages.iter().step_by(3).copied().collect::<Vec<_>>());
+    assert_eq!(pager.page_count(), page_count);
+
+    // Pages freed and allocated within the same transaction are reused as well
+    let page_no = pager.allocate_page().unwrap();
+    assert_eq!(page_no, page_count);
+    pager.free_page(page_no).unwrap();
+    assert_eq!(pager.allocate_page().unwrap(), page_no);
+    assert_eq!(pager.page_count(), page_count + 1);
+}
+
+// A corrupted free-list is refused rather than trusted, so that pages in use are never handed out again
+#[test]
+fn corrupted_free_list() {
+    let open_unchecked = |vfs: &MemoryVfs| {
+        let mut pager =
+            Pager::open_with_vfs(Arc::new(vfs.clone()), "test.db", PAGE_SIZE, 4).unwrap();
+        // Without checksums, the file can be patched behind the pager's back
+        pager.set_page_checksums(false);
+        pager.init().unwrap();
+        pager
+    };
+    let patch = |vfs: &MemoryVfs, offset: u64, bytes: &[u8]| {
+        let file = vfs.open(Path::new("test.db")).unwrap();
+        file.write_all_at(bytes, offset).unwrap();
+    };
+
+    // Header offsets of the free-list head and count, and trunk offset of the leaf count and first leaf
+    const FREELIST_HEAD: u64 = 16;
+    const FREELIST_COUNT: u64 = 20;
+    const LEAF_COUNT: u64 = 4;
+    const FIRST_LEAF: u64 = 8;
+
+    // Offset and bytes to patch, given the first trunk and the page count
+    type Corruption = fn(u32, u32) -> (u64, Vec<u8>);
+    let cases: [(&str, Corruption); 5] = [
+        ("leaf count", |trunk, _| {
+            (
+                trunk as u64 * PAGE_SIZE as u64 + LEAF_COUNT,
+                100_000_u32.to_le_bytes().to_vec(),
+            )
+        }),
+        ("free page count", |_, _| {
+            (FREELIST_COUNT, 0_u32.to_le_bytes().to_vec())
+        }),
+        ("leaf past the end", |trunk, page_count| {
+            (
+                trunk as u64 * PAGE_SIZE as u64 + FIRST_LEAF,
+                page_count.to_le_bytes().to_vec(),
+            )
+        }),
+        ("leaf 0", |trunk, _| {
+            (trunk as u64 * PAGE_SIZE as u64 + FIRST_LEAF, vec![0; 4])
+        }),
+        ("head past the end", |_, page_count| {
+            (FREELIST_HEAD, (page_count + 5).to_le_bytes().to_vec())
+        }),
+    ];
+    for (name, corruption) in cases {
+        let vfs = MemoryVfs::new();
+        let mut pager = open_unchecked(&vfs);
+        let pages = allocate_pages(&mut pager, 5, 1);
+        pager.free_page(pages[1]).unwrap();
+        pager.free_page(pages[3]).unwrap();
+        pager.flush().unwrap();
+        let page_count = pager.page_count();
+        drop(pager);
+
+        let (offset, bytes) = corruption(pages[1], page_count);
+        patch(&vfs, offset, &bytes);
+        let mut pager = open_unchecked(&vfs);
+        let err = pager.allocate_page().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{name}: {err}");
+        assert_eq!(pager.page_count(), page_count, "{name}");
+    }
+}
+
+fn try_open(vfs: &MemoryVfs) -> io::Result<Pager> {
+    let mut pager = Pager::open_with_vfs(Arc::new(vfs.clone()), "test.db", PAGE_SIZE, 4)?;
+    // Without checksums, the header can be patched behind the pager's back
+    pager.set_page_checksums(false);
+    pager.init()?;
+    Ok(pager)
+}
+
+fn patch_header(vfs: &MemoryVfs, offset: u64, bytes: &[u8]) {
+    let file = vfs.open(Path::new("test.db")).unwrap();
+    file.write_all_at(bytes, offset).unwrap();
+}
+
+#[test]
+fn header_round_trip() {
+    let mut header = DbHeader::alloc(PAGE_SIZE);
+    header.page_count = 42;
+    header.freelist_head = 7;
+    header.freelist_count = 3;
+    header.user_version = 0xdead_beef;
+    assert_eq!(DbHeader::from(&header.to_buf()), header);
+    // The largest page size does not fit in 16 bits
+    let header = DbHeader::alloc(65536);
+    assert_eq!(DbHeader::from(&header.to_buf()), header);
+
+    let vfs = MemoryVfs::new();
+    let mut pager = try_open(&vfs).unwrap();
+    let created = pager.header().clone();
+    assert_eq!(created.created_by, header::library_version());
+    pager.set_user_version(42).unwrap();
+    pager.set_application_id(0xabcd).unwrap();
+    pager.bump_schema_cookie().unwrap();
+    allocate_pages(&mut pager, 3, 1);
+    pager.flush().unwrap();
+    let written = pager.header().clone();
+    drop(pager);
+
+    let pager = try_open(&vfs).unwrap();
+    assert_eq!(pager.header(), &written);
+    assert_eq!(pager.header().user_version, 42);
+    assert_eq!(pager.header().application_id, 0xabcd);
+    assert_eq!(pager.header().created_at, created.created_at);
+    assert!(!pager.is_read_only());
+}
+
+#[test]
+fn header_compatibility() {
+    const WRITE_VERSION: u64 = 6;
+    const READ_VERSION: u64 = 7;
+    let create = || {
+        let vfs = MemoryVfs::new();
+        let mut pager = try_open(&vfs).unwrap();
+        let page_no = allocate_pages(&mut pager, 1, 1)[0];
+        pager.flush().unwrap();
+        (vfs, page_no)
+    };
+    let error_of = |vfs: &MemoryVfs| match try_open(vfs) {
+        Ok(_) => panic!("Opened an incompatible database"),
+        Err(err) => err,
+    };
+
+    // Files of a newer write version can only be read
+    let (vfs, page_no) = create();
+    patch_header(&vfs, WRITE_VERSION, &[u8::MAX]);
+    let mut pager = try_open(&vfs).unwrap();
+    assert!(pager.is_read_only());
+    let mut buf = vec![0; pager.usable_size()];
+    pager.read(page_no, &mut buf).unwrap();
+    assert!(buf.iter().all(|&byte| byte == 1));
+    pager.write(page_no, &[2; 16]).unwrap();
+    assert_eq!(
+        pager.flush().unwrap_err().kind(),
+        io::ErrorKind::PermissionDenied
+    );
+
+    // Files of a newer read version cannot be opened at all
+    let (vfs, _) = create();
+    patch_header(&vfs, READ_VERSION, &[u8::MAX]);
+    let err = error_of(&vfs);
+    assert_eq!(err.kind(), io::ErrorKind::Unsupported, "{err}");
+
+    let (vfs, _) = create();
+    patch_header(&vfs, 0, b"SQLi");
+    let err = error_of(&vfs);
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{err}");
+    assert_eq!(err.to_string(), "Not a phdb database");
+
+    // Files shorter than the header are not mistaken for empty ones, and are left alone
+    let vfs = MemoryVfs::new();
+    patch_header(&vfs, 0, &MAGIC_NUMBER.to_le_bytes());
+    let err = error_of(&vfs);
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{err}");
+    let file = vfs.open(Path::new("test.db")).unwrap();
+    assert_eq!(file.size().unwrap(), 4);
+
+    // The first format: magic, page size, page count, free-list head and count, journal mode and reserved size
+    let vfs = MemoryVfs::new();
+    let mut page = vec![0_u8; PAGE_SIZE as usize];
+    page[..4].copy_from_slice(&MAGIC_NUMBER.to_le_bytes());
+    page[4..6].copy_from_slice(&(PAGE_SIZE as u16).to_le_bytes());
+    page[6..10].copy_from_slice(&2_u32.to_le_bytes());
+    patch_header(&vfs, 0, &page);
+    patch_header(&vfs, PAGE_SIZE as u64, &vec![0; PAGE_SIZE as usize]);
+    let err = error_of(&vfs);
+    assert_eq!(err.kind(), io::ErrorKind::Unsupported, "{err}");
+    assert!(err.to_string().contains("older phdb format"), "{err}");
+}
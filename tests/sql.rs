@@ -0,0 +1,564 @@
+use std::{io, sync::Arc};
+
+use phdb::{connection::Connection, sql::Vm, value::Value, vfs::MemoryVfs};
+
+use Value::{Integer, Null, Real};
+
+fn open() -> Connection {
+    Connection::open_with_vfs(Arc::new(MemoryVfs::new()), "test.db", 1024, 64).unwrap()
+}
+
+fn query(connection: &mut Connection, sql: &str) -> Vec<Vec<Value>> {
+    connection
+        .execute(sql, &[])
+        .unwrap_or_else(|err| panic!("{sql}: {err}"))
+}
+
+fn error_of(connection: &mut Connection, sql: &str) -> io::Error {
+    connection.execute(sql, &[]).unwrap_err()
+}
+
+fn text(text: &str) -> Value {
+    Value::Text(text.to_string())
+}
+
+fn column(rows: Vec<Vec<Value>>) -> Vec<Value> {
+    rows.into_iter().map(|mut row| row.remove(0)).collect()
+}
+
+// Two tables whose x columns only partly match, with a NULL that matches nothing
+fn open_with_tables() -> Connection {
+    let mut connection = open();
+    query(
+        &mut connection,
+        "CREATE TABLE a (x INT, y TEXT); CREATE TABLE b (x INT, z REAL); CREATE INDEX bx ON b (x)",
+    );
+    query(
+        &mut connection,
+        "INSERT INTO a VALUES (1, 'one'), (2, 'two'), (3, 'three'), (NULL, 'none')",
+    );
+    query(
+        &mut connection,
+        "INSERT INTO b VALUES (1, 1.5), (1, 2.5), (3, 3.5), (4, 4.5)",
+    );
+    connection
+}
+
+#[test]
+fn joins() {
+    let mut connection = open_with_tables();
+
+    assert_eq!(
+        query(
+            &mut connection,
+            "SELECT a.y, b.z FROM a JOIN b ON a.x = b.x ORDER BY b.z"
+        ),
+        vec![
+            vec![text("one"), Real(1.5)],
+            vec![text("one"), Real(2.5)],
+            vec![text("three"), Real(3.5)],
+        ]
+    );
+    assert_eq!(
+        query(
+            &mut connection,
+            "SELECT y, z FROM a, b WHERE a.x = b.x AND z > 2 ORDER BY z"
+        ),
+        vec![vec![text("one"), Real(2.5)], vec![text("three"), Real(3.5)]]
+    );
+    // Rows of the left table without a match come out once, with NULLs for the right table
+    assert_eq!(
+        query(
+            &mut connection,
+            "SELECT y, z FROM a LEFT JOIN b USING (x) ORDER BY 1, 2"
+        ),
+        vec![
+            vec![text("none"), Null],
+            vec![text("one"), Real(1.5)],
+            vec![text("one"), Real(2.5)],
+            vec![text("three"), Real(3.5)],
+            vec![text("two"), Null],
+        ]
+    );
+    assert_eq!(
+        query(
+            &mut connection,
+            "SELECT a.y FROM a LEFT JOIN b ON a.x = b.x WHERE b.x IS NULL ORDER BY a.y"
+        ),
+        vec![vec![text("none")], vec![text("two")]]
+    );
+    assert_eq!(
+        query(
+            &mut connection,
+            "SELECT count(*) FROM a LEFT JOIN b ON b.x = a.x AND b.z > 3"
+        ),
+        vec![vec![Integer(4)]]
+    );
+}
+
+#[test]
+fn grouping_and_ordering() {
+    let mut connection = open_with_tables();
+
+    assert_eq!(
+        query(
+            &mut connection,
+            "SELECT x, count(*), sum(z) FROM b GROUP BY x HAVING count(*) > 0 ORDER BY 2 DESC, x"
+        ),
+        vec![
+            vec![Integer(1), Integer(2), Real(4.0)],
+            vec![Integer(3), Integer(1), Real(3.5)],
+            vec![Integer(4), Integer(1), Real(4.5)],
+        ]
+    );
+    assert_eq!(
+        query(
+            &mut connection,
+            "SELECT x FROM b GROUP BY x HAVING sum(z) > 3.5"
+        ),
+        vec![vec![Integer(1)], vec![Integer(4)]]
+    );
+    // Aggregates over no rows still give one row
+    assert_eq!(
+        query(
+            &mut connection,
+            "SELECT count(*), max(x) FROM a WHERE x > 10"
+        ),
+        vec![vec![Integer(0), Null]]
+    );
+    assert!(query(
+        &mut connection,
+        "SELECT x, count(*) FROM a WHERE x > 10 GROUP BY x"
+    )
+    .is_empty());
+
+    assert_eq!(
+        column(query(
+            &mut connection,
+            "SELECT DISTINCT x FROM b ORDER BY x DESC"
+        )),
+        vec![Integer(4), Integer(3), Integer(1)]
+    );
+    assert_eq!(
+        query(&mut connection, "SELECT count(DISTINCT x), count(x) FROM b"),
+        vec![vec![Integer(3), Integer(4)]]
+    );
+
+    // NULLs sort first
+    assert_eq!(
+        column(query(&mut connection, "SELECT x FROM a ORDER BY x")),
+        vec![Null, Integer(1), Integer(2), Integer(3)]
+    );
+    assert_eq!(
+        column(query(
+            &mut connection,
+            "SELECT y FROM a ORDER BY y DESC LIMIT 2 OFFSET 1"
+        )),
+        vec![text("three"), text("one")]
+    );
+    assert_eq!(
+        column(query(
+            &mut connection,
+            "SELECT z FROM b ORDER BY z LIMIT 10 OFFSET 3"
+        )),
+        vec![Real(4.5)]
+    );
+    assert!(query(&mut connection, "SELECT z FROM b LIMIT 0").is_empty());
+}
+
+#[test]
+fn subqueries() {
+    let mut connection = open_with_tables();
+
+    assert_eq!(
+        query(
+            &mut connection,
+            "SELECT (SELECT max(z) FROM b), (SELECT z FROM b WHERE x = 9)"
+        ),
+        vec![vec![Real(4.5), Null]]
+    );
+    // Correlated scalar and EXISTS subqueries
+    assert_eq!(
+        query(
+            &mut connection,
+            "SELECT y, (SELECT count(*) FROM b WHERE b.x = a.x) FROM a \
+             WHERE EXISTS (SELECT 1 FROM b WHERE b.x >= a.x) ORDER BY y"
+        ),
+        vec![
+            vec![text("one"), Integer(2)],
+            vec![text("three"), Integer(1)],
+            vec![text("two"), Integer(0)],
+        ]
+    );
+    assert_eq!(
+        column(query(
+            &mut connection,
+            "SELECT y FROM a WHERE NOT EXISTS (SELECT 1 FROM b WHERE b.x = a.x) ORDER BY y"
+        )),
+        vec![text("none"), text("two")]
+    );
+    assert_eq!(
+        column(query(
+            &mut connection,
+            "SELECT y FROM a WHERE x IN (SELECT x FROM b) ORDER BY y"
+        )),
+        vec![text("one"), text("three")]
+    );
+    assert_eq!(
+        column(query(
+            &mut connection,
+            "SELECT y FROM a WHERE x NOT IN (SELECT x FROM b WHERE z > 2)"
+        )),
+        vec![text("two")]
+    );
+    // IN with a NULL on either side is NULL unless there is a match
+    assert_eq!(
+        query(
+            &mut connection,
+            "SELECT NULL IN (1), 1 IN (1, NULL), 2 IN (1, NULL), 2 NOT IN (1, NULL)"
+        ),
+        vec![vec![Null, Integer(1), Null, Null]]
+    );
+    assert_eq!(
+        column(query(
+            &mut connection,
+            "SELECT s.x FROM (SELECT x FROM b WHERE z > 2) AS s WHERE s.x < 4"
+        )),
+        vec![Integer(1), Integer(3)]
+    );
+}
+
+#[test]
+fn update_of_the_integer_primary_key() {
+    let mut connection = open();
+    query(
+        &mut connection,
+        "CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT UNIQUE, age INT); CREATE INDEX t_age ON t (age)",
+    );
+    query(
+        &mut connection,
+        "INSERT INTO t (name, age) VALUES ('ann', 20), ('bob', 30), ('cy', 40)",
+    );
+
+    query(
+        &mut connection,
+        "UPDATE t SET id = 10, age = 31 WHERE id = 2",
+    );
+    assert_eq!(
+        query(&mut connection, "SELECT * FROM t"),
+        vec![
+            vec![Integer(1), text("ann"), Integer(20)],
+            vec![Integer(3), text("cy"), Integer(40)],
+            vec![Integer(10), text("bob"), Integer(31)],
+        ]
+    );
+    // Both indexes point at the new rowid
+    assert!(query(&mut connection, "SELECT * FROM t WHERE id = 2").is_empty());
+    assert_eq!(
+        column(query(
+            &mut connection,
+            "SELECT id FROM t WHERE name = 'bob'"
+        )),
+        vec![Integer(10)]
+    );
+    assert_eq!(
+        column(query(&mut connection, "SELECT id FROM t WHERE age = 31")),
+        vec![Integer(10)]
+    );
+    assert!(query(&mut connection, "SELECT id FROM t WHERE age = 30").is_empty());
+
+    // Shifting every key moves rows onto rowids that other rows are leaving
+    query(&mut connection, "UPDATE t SET id = id + 1");
+    assert_eq!(
+        column(query(&mut connection, "SELECT id FROM t")),
+        vec![Integer(2), Integer(4), Integer(11)]
+    );
+    assert_eq!(
+        column(query(&mut connection, "SELECT name FROM t WHERE id = 4")),
+        vec![text("cy")]
+    );
+
+    let err = error_of(&mut connection, "UPDATE t SET id = 2 WHERE id = 4");
+    assert_eq!(err.kind(), io::ErrorKind::AlreadyExists, "{err}");
+    assert_eq!(err.to_string(), "UNIQUE constraint failed: t.id");
+    // New rows get the rowid after the largest one
+    query(&mut connection, "INSERT INTO t (name) VALUES ('dee')");
+    assert_eq!(
+        column(query(
+            &mut connection,
+            "SELECT id FROM t WHERE name = 'dee'"
+        )),
+        vec![Integer(12)]
+    );
+}
+
+#[test]
+fn delete_with_indexes() {
+    let mut connection = open();
+    query(
+        &mut connection,
+        "CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT UNIQUE, age INT); CREATE INDEX t_age ON t (age)",
+    );
+    connection.begin().unwrap();
+    for i in 0..500 {
+        connection
+            .execute(
+                "INSERT INTO t (name, age) VALUES (?, ?)",
+                &[text(&format!("n{i}")), Integer(i % 10)],
+            )
+            .unwrap();
+    }
+    connection.commit().unwrap();
+
+    query(&mut connection, "DELETE FROM t WHERE age = 3 OR id > 400");
+    assert_eq!(
+        query(&mut connection, "SELECT count(*) FROM t"),
+        vec![vec![Integer(360)]]
+    );
+    assert!(query(&mut connection, "SELECT id FROM t WHERE age = 3").is_empty());
+    assert_eq!(
+        query(&mut connection, "SELECT count(*) FROM t WHERE age = 4"),
+        vec![vec![Integer(40)]]
+    );
+    assert!(query(&mut connection, "SELECT id FROM t WHERE name = 'n450'").is_empty());
+    assert_eq!(
+        column(query(&mut connection, "SELECT id FROM t WHERE name = 'n4'")),
+        vec![Integer(5)]
+    );
+
+    // The deleted names are no longer taken
+    query(
+        &mut connection,
+        "INSERT INTO t (name, age) VALUES ('n13', 3), ('n450', 3)",
+    );
+    assert_eq!(
+        column(query(
+            &mut connection,
+            "SELECT name FROM t WHERE age = 3 ORDER BY name"
+        )),
+        vec![text("n13"), text("n450")]
+    );
+
+    query(&mut connection, "DELETE FROM t");
+    assert!(query(&mut connection, "SELECT * FROM t").is_empty());
+    assert!(query(&mut connection, "SELECT id FROM t WHERE age = 4").is_empty());
+    query(
+        &mut connection,
+        "INSERT INTO t (name, age) VALUES ('n4', 4)",
+    );
+    assert_eq!(
+        column(query(&mut connection, "SELECT name FROM t WHERE age = 4")),
+        vec![text("n4")]
+    );
+}
+
+// A failing statement undoes its own changes, but not those of earlier statements of the transaction
+#[test]
+fn constraint_failures_roll_back_the_statement() {
+    let mut connection = open();
+    query(
+        &mut connection,
+        "CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT NOT NULL, code INT UNIQUE)",
+    );
+    query(
+        &mut connection,
+        "INSERT INTO t (name, code) VALUES ('ann', 1)",
+    );
+
+    connection.begin().unwrap();
+    query(
+        &mut connection,
+        "INSERT INTO t (name, code) VALUES ('bob', 2)",
+    );
+
+    let err = error_of(
+        &mut connection,
+        "INSERT INTO t (name, code) VALUES ('cy', 3), (NULL, 4)",
+    );
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{err}");
+    assert_eq!(err.to_string(), "NOT NULL constraint failed: t.name");
+
+    let err = error_of(
+        &mut connection,
+        "INSERT INTO t (name, code) VALUES ('dee', 5), ('eve', 1)",
+    );
+    assert_eq!(err.kind(), io::ErrorKind::AlreadyExists, "{err}");
+    assert_eq!(err.to_string(), "UNIQUE constraint failed: t.code");
+
+    let err = error_of(
+        &mut connection,
+        "INSERT INTO t (id, name) VALUES (9, 'fay'), (1, 'gus')",
+    );
+    assert_eq!(err.to_string(), "UNIQUE constraint failed: t.id");
+
+    let err = error_of(&mut connection, "UPDATE t SET code = code + 1");
+    assert_eq!(err.kind(), io::ErrorKind::AlreadyExists, "{err}");
+    assert_eq!(err.to_string(), "UNIQUE constraint failed: t.code");
+    let err = error_of(&mut connection, "UPDATE t SET name = NULL WHERE code = 2");
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{err}");
+
+    assert!(connection.in_transaction());
+    connection.commit().unwrap();
+    assert_eq!(
+        query(&mut connection, "SELECT * FROM t"),
+        vec![
+            vec![Integer(1), text("ann"), Integer(1)],
+            vec![Integer(2), text("bob"), Integer(2)],
+        ]
+    );
+    // Neither did the failed statements leave index entries behind
+    for code in [3, 4, 5] {
+        query(
+            &mut connection,
+            &format!("INSERT INTO t (name, code) VALUES ('new', {code})"),
+        );
+    }
+    assert_eq!(
+        query(&mut connection, "SELECT count(*) FROM t"),
+        vec![vec![Integer(5)]]
+    );
+}
+
+#[test]
+fn explain() {
+    let mut connection = open_with_tables();
+
+    let program = connection
+        .prepare("EXPLAIN SELECT z FROM b WHERE x = 3")
+        .unwrap();
+    assert!(program.is_explain());
+    assert_eq!(
+        program.columns(),
+        ["addr", "opcode", "p1", "p2", "p3", "p4", "comment"]
+    );
+
+    let rows = connection.run(&program, &[]).unwrap();
+    assert_eq!(rows.len(), program.insns().len());
+    for (addr, row) in rows.iter().enumerate() {
+        assert_eq!(row.len(), 7);
+        assert_eq!(row[0], Integer(addr as i64));
+    }
+    let opcodes: Vec<&Value> = rows.iter().map(|row| &row[1]).collect();
+    for opcode in ["Init", "SeekGE", "IdxGT", "ResultRow", "Halt"] {
+        assert!(
+            opcodes.contains(&&text(opcode)),
+            "{opcode} missing from {program}"
+        );
+    }
+
+    // The listing has a header and one line per instruction
+    let listing = program.to_string();
+    assert_eq!(listing.lines().count(), rows.len() + 1);
+    assert!(listing.lines().nth(1).unwrap().starts_with("0 "));
+
+    // Explaining a statement doesn't run it
+    query(&mut connection, "EXPLAIN DELETE FROM b");
+    assert_eq!(
+        query(&mut connection, "SELECT count(*) FROM b"),
+        vec![vec![Integer(4)]]
+    );
+}
+
+#[test]
+fn step_wise_and_schema_changes() {
+    let mut connection = open_with_tables();
+
+    let program = connection
+        .prepare("SELECT x, z FROM b WHERE z > 2 ORDER BY z")
+        .unwrap();
+    let mut vm = Vm::new(&program, &[]);
+    connection.begin().unwrap();
+    assert_eq!(
+        vm.step(connection.pager_mut()).unwrap(),
+        Some(vec![Integer(1), Real(2.5)])
+    );
+    assert!(!vm.is_halted());
+    assert_eq!(
+        vm.step(connection.pager_mut()).unwrap(),
+        Some(vec![Integer(3), Real(3.5)])
+    );
+    assert_eq!(
+        vm.step(connection.pager_mut()).unwrap(),
+        Some(vec![Integer(4), Real(4.5)])
+    );
+    assert_eq!(vm.step(connection.pager_mut()).unwrap(), None);
+    assert!(vm.is_halted());
+    connection.commit().unwrap();
+
+    // Parameters are bound when the machine is made
+    let program = connection.prepare("SELECT y FROM a WHERE x = ?").unwrap();
+    let mut vm = Vm::new(&program, &[Integer(2)]);
+    connection.begin().unwrap();
+    assert_eq!(
+        vm.step(connection.pager_mut()).unwrap(),
+        Some(vec![text("two")])
+    );
+    assert_eq!(vm.step(connection.pager_mut()).unwrap(), None);
+    connection.commit().unwrap();
+
+    // Changing the schema between preparing and running makes the program stale
+    query(&mut connection, "CREATE INDEX ay ON a (y)");
+    let err = connection.run(&program, &[Integer(2)]).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(
+        err.to_string(),
+        "The schema changed since the statement was compiled"
+    );
+
+    let mut vm = Vm::new(&program, &[Integer(2)]);
+    connection.begin().unwrap();
+    assert!(vm.step(connection.pager_mut()).is_err());
+    connection.rollback().unwrap();
+
+    // Preparing it again picks up the new schema
+    let program = connection.prepare("SELECT y FROM a WHERE x = ?").unwrap();
+    query(&mut connection, "INSERT INTO a VALUES (2, 'deux')");
+    assert_eq!(
+        connection.run(&program, &[Integer(2)]).unwrap(),
+        vec![vec![text("two")], vec![text("deux")]]
+    );
+    query(&mut connection, "DROP INDEX ay");
+    assert!(connection.run(&program, &[Integer(2)]).is_err());
+}
+
+// Unique indexes and INTEGER PRIMARY KEYs name the columns whose values are repeated the same way
+#[test]
+fn unique_constraint_messages() {
+    let mut connection = open();
+    query(
+        &mut connection,
+        "CREATE TABLE t (id INTEGER PRIMARY KEY, a INT, b TEXT, c INT, UNIQUE (a, b))",
+    );
+    query(
+        &mut connection,
+        "INSERT INTO t (a, b, c) VALUES (1, 'x', 1), (1, 'y', 1), (NULL, 'x', 2), (NULL, 'x', 2)",
+    );
+
+    for (sql, message) in [
+        (
+            "INSERT INTO t (id) VALUES (2)",
+            "UNIQUE constraint failed: t.id",
+        ),
+        (
+            "INSERT INTO t (a, b) VALUES (1, 'y')",
+            "UNIQUE constraint failed: t.a, t.b",
+        ),
+        (
+            "UPDATE t SET b = 'x' WHERE id = 2",
+            "UNIQUE constraint failed: t.a, t.b",
+        ),
+        (
+            "CREATE UNIQUE INDEX tc ON t (c)",
+            "UNIQUE constraint failed: t.c",
+        ),
+    ] {
+        let err = error_of(&mut connection, sql);
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists, "{sql}: {err}");
+        assert_eq!(err.to_string(), message, "{sql}");
+    }
+    assert!(connection.catalog().unwrap().get("tc").is_none());
+    assert_eq!(
+        query(&mut connection, "SELECT count(*) FROM t"),
+        vec![vec![Integer(4)]]
+    );
+}